@@ -0,0 +1,174 @@
+//! Helpers for writing zap plugins. A plugin is a `cdylib` with a safe
+//! registration function; `zap_plugin!` exports the entry points zap looks for:
+//!
+//! ```ignore
+//! use zap_plugin_sdk::{tera::Tera, zap_plugin};
+//!
+//! zap_plugin! {
+//!     name: "shout",
+//!     version: env!("CARGO_PKG_VERSION"),
+//!     description: "Uppercases text",
+//!     functions: ["shout"],
+//!     filters: ["shout"],
+//!     register: register,
+//! }
+//!
+//! fn register(tera: &mut Tera) {
+//!     tera.register_function("shout", shout);
+//!     tera.register_filter("shout", shout_filter);
+//! }
+//! ```
+//!
+//! Rust has no stable ABI, so the plugin has to be built with the same compiler
+//! and `tera` version as zap. Use the `tera` re-exported here rather than your own.
+
+use std::ffi::CString;
+
+pub use tera;
+
+/// Version of the contract between zap and its plugins. zap refuses plugins
+/// built for another one instead of calling into them and crashing.
+pub const ABI_VERSION: u32 = 1;
+
+/// The JSON `zap_plugin_info` returns.
+pub fn info_json(
+    name: &str,
+    version: &str,
+    author: Option<&str>,
+    description: Option<&str>,
+) -> CString {
+    let info = serde_json::json!({
+        "name": name,
+        "version": version,
+        "author": author,
+        "description": description,
+    });
+    CString::new(info.to_string()).expect("JSON has no NUL bytes")
+}
+
+/// The comma-separated list `zap_plugin_functions`, `zap_plugin_filters` and
+/// `zap_plugin_testers` return.
+pub fn name_list(names: &[&str]) -> CString {
+    CString::new(names.join(",")).expect("names have no NUL bytes")
+}
+
+/// Export a plugin's entry points: `register_tera_custom_functions` calling the
+/// given `register` function, and `zap_plugin_abi_version`, `zap_plugin_info`,
+/// `zap_plugin_functions`, `zap_plugin_filters` and `zap_plugin_testers` describing
+/// it. `author`, `description`, `filters` and `testers` are optional.
+#[macro_export]
+macro_rules! zap_plugin {
+    (
+        name: $name:expr,
+        version: $version:expr,
+        $(author: $author:expr,)?
+        $(description: $description:expr,)?
+        functions: [$($function:expr),* $(,)?],
+        $(filters: [$($filter:expr),* $(,)?],)?
+        $(testers: [$($tester:expr),* $(,)?],)?
+        register: $register:path $(,)?
+    ) => {
+        /// # Safety
+        ///
+        /// Called by zap with a valid, exclusive reference to its `Tera` instance.
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn register_tera_custom_functions(tera: &mut $crate::tera::Tera) {
+            $register(tera)
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn zap_plugin_abi_version() -> u32 {
+            $crate::ABI_VERSION
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn zap_plugin_info() -> *const ::std::ffi::c_char {
+            static INFO: ::std::sync::OnceLock<::std::ffi::CString> = ::std::sync::OnceLock::new();
+            INFO.get_or_init(|| {
+                let author: Option<&str> = None $(.or(Some($author)))?;
+                let description: Option<&str> = None $(.or(Some($description)))?;
+                $crate::info_json($name, $version, author, description)
+            })
+            .as_ptr()
+        }
+
+        #[unsafe(no_mangle)]
+        pub extern "C" fn zap_plugin_functions() -> *const ::std::ffi::c_char {
+            static FUNCTIONS: ::std::sync::OnceLock<::std::ffi::CString> =
+                ::std::sync::OnceLock::new();
+            FUNCTIONS
+                .get_or_init(|| $crate::name_list(&[$($function),*]))
+                .as_ptr()
+        }
+
+        $(
+            #[unsafe(no_mangle)]
+            pub extern "C" fn zap_plugin_filters() -> *const ::std::ffi::c_char {
+                static FILTERS: ::std::sync::OnceLock<::std::ffi::CString> =
+                    ::std::sync::OnceLock::new();
+                FILTERS
+                    .get_or_init(|| $crate::name_list(&[$($filter),*]))
+                    .as_ptr()
+            }
+        )?
+
+        $(
+            #[unsafe(no_mangle)]
+            pub extern "C" fn zap_plugin_testers() -> *const ::std::ffi::c_char {
+                static TESTERS: ::std::sync::OnceLock<::std::ffi::CString> =
+                    ::std::sync::OnceLock::new();
+                TESTERS
+                    .get_or_init(|| $crate::name_list(&[$($tester),*]))
+                    .as_ptr()
+            }
+        )?
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    fn register(tera: &mut tera::Tera) {
+        tera.register_function("answer", |_: &_| Ok(tera::Value::from(42)));
+        tera.register_filter("twice", |value: &tera::Value, _: &_| {
+            Ok(tera::Value::from(format!("{value}{value}")))
+        });
+    }
+
+    zap_plugin! {
+        name: "answer",
+        version: "1.0.0",
+        author: "Ada",
+        functions: ["answer"],
+        filters: ["twice"],
+        testers: [],
+        register: register,
+    }
+
+    #[test]
+    fn test_generated_entry_points() {
+        let mut tera = tera::Tera::default();
+        unsafe { register_tera_custom_functions(&mut tera) };
+        assert_eq!(
+            tera.render_str("{{ answer() }}", &tera::Context::new())
+                .unwrap(),
+            "42"
+        );
+        assert_eq!(zap_plugin_abi_version(), ABI_VERSION);
+
+        let info = unsafe { CStr::from_ptr(zap_plugin_info()) };
+        let info: serde_json::Value = serde_json::from_slice(info.to_bytes()).unwrap();
+        assert_eq!(info["name"], "answer");
+        assert_eq!(info["author"], "Ada");
+        assert!(info["description"].is_null());
+
+        let functions = unsafe { CStr::from_ptr(zap_plugin_functions()) };
+        assert_eq!(functions.to_str().unwrap(), "answer");
+        let filters = unsafe { CStr::from_ptr(zap_plugin_filters()) };
+        assert_eq!(filters.to_str().unwrap(), "twice");
+        let testers = unsafe { CStr::from_ptr(zap_plugin_testers()) };
+        assert_eq!(testers.to_str().unwrap(), "");
+    }
+}
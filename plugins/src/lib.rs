@@ -1,8 +1,31 @@
 use std::collections::HashMap;
-use tera::{Function as TeraFunction, Result as TeraResult, Value, to_value};
+use zap_plugin_sdk::tera::{self, Result as TeraResult, Tera, Value, to_value};
+use zap_plugin_sdk::zap_plugin;
+
+zap_plugin! {
+    name: "shout",
+    version: env!("CARGO_PKG_VERSION"),
+    description: "Uppercases text and adds exclamation marks",
+    functions: ["shout"],
+    filters: ["shout"],
+    testers: ["shouting"],
+    register: register,
+}
+
+/// `shout(input="hi")` and `{{ "hi" | shout }}` give `HI!!!`, and
+/// `{% if title is shouting %}` tells whether a text is all caps.
+fn register(tera: &mut Tera) {
+    tera.register_function("shout", ShoutFunction);
+    tera.register_filter("shout", shout_filter);
+    tera.register_tester("shouting", shouting_tester);
+}
+
+fn shout(input: &str) -> String {
+    format!("{}!!!", input.to_uppercase())
+}
 
 struct ShoutFunction;
-impl TeraFunction for ShoutFunction {
+impl tera::Function for ShoutFunction {
     fn call(&self, args: &HashMap<String, Value>) -> TeraResult<Value> {
         let input_val = args
             .get("input")
@@ -10,16 +33,24 @@ impl TeraFunction for ShoutFunction {
         let input_str = input_val
             .as_str()
             .ok_or_else(|| tera::Error::msg("`input` argument for `shout` must be a string"))?;
-        Ok(to_value(format!("{}!!!", input_str.to_uppercase()))
-            .map_err(|e| tera::Error::chain("Failed to convert result to Value", e))?)
+        to_value(shout(input_str))
+            .map_err(|e| tera::Error::chain("Failed to convert result to Value", e))
     }
     fn is_safe(&self) -> bool {
         true
     }
 }
 
-#[unsafe(no_mangle)]
-pub unsafe extern "C" fn register_tera_custom_functions(tera: &mut tera::Tera) {
-    tera.register_function("shout", *Box::new(ShoutFunction));
-    // tera.register_function("another_one", Box::new(AnotherFunc));
+fn shout_filter(value: &Value, _args: &HashMap<String, Value>) -> TeraResult<Value> {
+    let input_str = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("Filter `shout` can only be applied to strings"))?;
+    to_value(shout(input_str))
+        .map_err(|e| tera::Error::chain("Failed to convert result to Value", e))
+}
+
+/// The string has letters and all of them are uppercase.
+fn shouting_tester(value: Option<&Value>, _args: &[Value]) -> TeraResult<bool> {
+    let text = value.and_then(Value::as_str).unwrap_or_default();
+    Ok(text.chars().any(char::is_alphabetic) && !text.chars().any(char::is_lowercase))
 }
@@ -1,36 +1,296 @@
+use chrono_tz::Tz;
 use clap::builder::ArgPredicate;
-use clap::{ArgAction, CommandFactory, Parser};
+use clap::error::ErrorKind;
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
+use std::fs::{self, FileType};
+use std::path::PathBuf;
 
-#[derive(Parser, Debug)]
+use crate::config::Config;
+use crate::errors::ZapError;
+use crate::file_time_util::TimeFormat;
+use crate::frontmatter::Eol;
+use crate::timings::TimingsFormat;
+
+/// Replace every `+name` argument that names an alias from `config.toml` with the
+/// arguments it stands for, split like a shell would, so quotes keep spaces in one
+/// argument. Aliases aren't expanded recursively, and `+` arguments that aren't
+/// aliases are left as they are.
+pub fn expand_aliases(
+    args: impl IntoIterator<Item = String>,
+    aliases: &BTreeMap<String, String>,
+) -> Result<Vec<String>, ZapError> {
+    let mut expanded = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            expanded.push(arg);
+            expanded.extend(args.by_ref());
+            break;
+        }
+        match arg
+            .strip_prefix('+')
+            .and_then(|name| Some((name, aliases.get(name)?)))
+        {
+            Some((name, alias)) => {
+                let words = shell_words::split(alias).map_err(|e| ZapError::InvalidAlias {
+                    name: name.to_string(),
+                    reason: e.to_string(),
+                })?;
+                expanded.extend(words);
+            }
+            None => expanded.push(arg),
+        }
+    }
+
+    Ok(expanded)
+}
+
+/// Replace every `@path` argument with the arguments listed in that file.
+///
+/// The file holds one argument per line; blank lines and lines starting with `#`
+/// are ignored, and surrounding whitespace is trimmed. Arguments read from a file
+/// are taken literally. Everything after `--` is left untouched, so file names
+/// starting with `@` can still be passed.
+pub fn expand_argfiles(args: impl IntoIterator<Item = String>) -> Result<Vec<String>, ZapError> {
+    let mut expanded = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            expanded.push(arg);
+            expanded.extend(args.by_ref());
+            break;
+        }
+        match arg.strip_prefix('@').filter(|path| !path.is_empty()) {
+            Some(path) => {
+                let contents = fs::read_to_string(path).map_err(|source| ZapError::ArgFile {
+                    path: path.to_string(),
+                    source,
+                })?;
+                expanded.extend(
+                    contents
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                        .map(String::from),
+                );
+            }
+            None => expanded.push(arg),
+        }
+    }
+
+    Ok(expanded)
+}
+
+#[derive(Parser, Debug, Default, Clone)]
 #[clap(name = "zap", author, version, about = "touch, but with templates", long_about = None, arg_required_else_help(true))]
-#[clap(disable_help_flag = true)] // We'll handle the help flag manually
+#[clap(disable_help_flag = true)] // -h is --symlink, like in touch
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct ZapCli {
     /// Show help information
-    #[clap(short = 'h', long = "help", action = ArgAction::Help)]
+    #[clap(long = "help", action = ArgAction::Help)]
     pub help: Option<bool>,
-    #[clap(value_parser, required = true, num_args = 1..)]
+    #[clap(
+        value_parser,
+        required_unless_present_any = ["each", "from_csv", "map"],
+        conflicts_with_all = ["each", "from_csv"],
+        num_args = 1..
+    )]
     pub filenames: Vec<String>,
 
     /// Optional template name to pre-populate the file.
-    /// Templates are sourced from ~/.config/zap/<template_name>.
-    #[clap(short = 'T', long, value_name = "TEMPLATE_NAME", verbatim_doc_comment)]
+    /// Templates are sourced from ~/.local/share/zap/templates/<template_name>.
+    /// Without a name (at the end of the command line), pick one from a list
+    #[clap(
+        short = 'T',
+        long,
+        value_name = "TEMPLATE_NAME",
+        num_args = 0..=1,
+        default_missing_value = "",
+        verbatim_doc_comment
+    )]
     pub template: Option<String>,
 
+    /// Pick the template from a searchable list of the available ones
+    #[clap(long, conflicts_with = "template")]
+    pub pick_template: bool,
+
+    /// Use the settings of a [profile.NAME] section of the config file
+    #[clap(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Search DIR for templates before all other locations; may be repeated
+    #[clap(long, value_name = "DIR", global = true)]
+    pub template_path: Vec<PathBuf>,
+
+    /// Don't apply the default template (from .zap.toml, config.toml or ZAP_DEFAULT_TEMPLATE)
+    #[clap(long, conflicts_with = "template")]
+    pub no_template: bool,
+
+    /// Don't pick templates by path or file extension (rule and auto_template in config.toml)
+    #[clap(long)]
+    pub no_auto_template: bool,
+
+    /// Pipe rendered templates through COMMAND before writing them,
+    /// e.g. "rustfmt --edition 2021" (it reads stdin and writes stdout)
+    #[clap(long, value_name = "COMMAND", verbatim_doc_comment)]
+    pub format_with: Option<String>,
+
+    /// Fail on undefined variables, unknown filters, tests and functions anywhere
+    /// in a template, naming them and their line, even in branches not taken
+    #[clap(long, verbatim_doc_comment)]
+    pub strict_template: bool,
+
+    /// Line endings of rendered templates; wins over `eol` in a template's frontmatter
+    #[clap(long, value_enum, value_name = "EOL")]
+    pub eol: Option<Eol>,
+
+    /// Download remote templates (-T github:user/repo/path or -T https://...)
+    /// again instead of using the cached copy
+    #[clap(long, verbatim_doc_comment)]
+    pub refresh: bool,
+
     /// Optional context to use when rendering the template.
     /// should contain key-value pairs in the format `foo=bar,baz=qux`.
+    /// Dotted keys (`author.name=Bob`) build nested values.
+    /// `-` reads key=value lines or a JSON object from stdin.
     #[clap(short = 'C', long, value_name = "CONTEXT", verbatim_doc_comment)]
     pub context: Option<String>,
 
+    /// Context for just FILE, merged over --context, e.g.
+    /// `--context-for notes.md title=Monday`; may be repeated
+    #[clap(
+        long,
+        num_args = 2,
+        value_names = ["FILE", "CONTEXT"],
+        action = clap::ArgAction::Append,
+        verbatim_doc_comment
+    )]
+    pub context_for: Vec<String>,
+
+    /// Add the rendered template (-T) to the end of files that already exist
+    /// instead of offering to overwrite them
+    #[clap(long, conflicts_with = "prepend", verbatim_doc_comment)]
+    pub append: bool,
+
+    /// Add the rendered template (-T) to the start of files that already exist,
+    /// e.g. a license header
+    #[clap(long, verbatim_doc_comment)]
+    pub prepend: bool,
+
+    /// Insert the rendered template (-T) into files that already exist right after
+    /// the line MARKER, e.g. "## TASKS"; skipped if the snippet is already there
+    #[clap(
+        long,
+        value_name = "MARKER",
+        conflicts_with_all = ["append", "prepend"],
+        verbatim_doc_comment
+    )]
+    pub insert_at: Option<String>,
+
+    /// Render existing files as templates in place with the context (-C),
+    /// to complete {{ placeholders }} left in them earlier
+    #[clap(long, conflicts_with_all = ["template", "append", "prepend", "insert_at"], verbatim_doc_comment)]
+    pub fill: bool,
+
+    /// Print the rendered template instead of creating files or changing
+    /// their times, to preview a template
+    #[clap(long, verbatim_doc_comment)]
+    pub stdout: bool,
+
+    /// Create FILE from TEMPLATE instead of the -T template; may be repeated, so
+    /// one run can create e.g. main.rs and README.md from different templates
+    #[clap(
+        long,
+        value_name = "FILE=TEMPLATE",
+        conflicts_with_all = ["each", "from_csv"],
+        verbatim_doc_comment
+    )]
+    pub map: Vec<String>,
+
+    /// Render the template once per entry of a JSON array or CSV file.
+    /// Each entry is available as `item`; use --name for the file names.
+    #[clap(long, value_name = "FILE", requires = "name", verbatim_doc_comment)]
+    pub each: Option<String>,
+
+    /// File name pattern for --each, e.g. '{{ item.name }}.md'
+    #[clap(long, value_name = "PATTERN", requires = "each")]
+    pub name: Option<String>,
+
+    /// Create one file per row of a CSV file (with a header row).
+    /// The row's columns are available to the template by their header names.
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "name_column",
+        conflicts_with = "each",
+        verbatim_doc_comment
+    )]
+    pub from_csv: Option<String>,
+
+    /// Column of the --from-csv file that holds each row's file name
+    #[clap(long, value_name = "COLUMN", requires = "from_csv")]
+    pub name_column: Option<String>,
+
+    /// Read the system clipboard into the context variable KEY,
+    /// e.g. to wrap a copied URL or quote into a new note
+    #[clap(long, value_name = "KEY", verbatim_doc_comment)]
+    pub context_clipboard: Option<String>,
+
     /// always create intermediate directories if they do not exist
     /// (analogous to `mkdir -p`)
     #[clap(short = 'p', long, default_value = "false", verbatim_doc_comment)]
     pub create_intermediate_dirs: bool,
 
+    /// Don't create intermediate directories, even if config.toml or the profile says so
+    #[clap(long, overrides_with = "create_intermediate_dirs")]
+    pub no_create_intermediate_dirs: bool,
+
+    /// Resolve relative file names against DIR instead of the current directory
+    #[clap(long, value_name = "DIR")]
+    pub relative_to: Option<String>,
+
+    /// Turn each file name into a safe slug before creating it,
+    /// e.g. "My Idea!.md" becomes "my-idea.md"
+    #[clap(long, verbatim_doc_comment)]
+    pub slugify_name: bool,
+
+    /// Don't let templates read environment variables (env() and get_env())
+    #[clap(long)]
+    pub no_env: bool,
+
+    /// Let templates read environment variables, even if config.toml sets no_env
+    #[clap(long, overrides_with = "no_env")]
+    pub allow_env: bool,
+
+    /// Don't load any plugins, e.g. when rendering templates you don't trust
+    #[clap(long)]
+    pub no_plugins: bool,
+
+    /// Skip plugins that fail to load, with a warning, instead of failing the run
+    #[clap(long)]
+    pub lenient_plugins: bool,
+
+    /// Fail the run when a plugin fails to load, even if config.toml says lenient
+    #[clap(long, overrides_with = "lenient_plugins")]
+    pub strict_plugins: bool,
+
+    /// Report what zap leaves out, such as plugins skipped by --no-plugins or plugins.allow/deny
+    #[clap(short = 'v', long)]
+    pub verbose: bool,
+
     /// Open the file with your $EDITOR
     #[clap(short = 'o', long)]
     pub open: bool,
 
+    /// Don't open the file, even if config.toml says so
+    #[clap(long, overrides_with = "open")]
+    pub no_open: bool,
+
     /// only update the access time
     #[clap(short = 'a')]
     pub access_time: bool,
@@ -39,26 +299,58 @@ pub struct ZapCli {
     #[clap(short = 'm')]
     pub modification_time: bool,
 
+    /// only update the given time, like GNU touch: access (or atime, use) is -a, modify (or mtime) is -m
+    #[clap(long, value_name = "WORD")]
+    pub time: Option<TimeWord>,
+
     /// Don't create the file if it doesn't exist
     #[clap(
         short = 'c',
         long,
         default_value_if("adjust", ArgPredicate::IsPresent, "true"), // -c implied if -A is used
-        default_value_if("symlink_only", ArgPredicate::IsPresent, "true") // -c implied if -h is used
+        default_value_if("symlink_only", ArgPredicate::IsPresent, "true"), // -c implied if -h is used
+        default_value_if("from_exif", ArgPredicate::IsPresent, "true"), // -c implied if --from-exif is used
+        default_value_if("from_git", ArgPredicate::IsPresent, "true") // -c implied if --from-git is used
     )]
     pub no_create: bool,
 
+    /// Only touch existing files last modified more than DURATION ago (e.g. 30d, 1h30m)
+    #[clap(long, value_name = "DURATION")]
+    pub older_than: Option<String>,
+
+    /// Only touch existing files last modified less than DURATION ago (e.g. 2h)
+    #[clap(long, value_name = "DURATION")]
+    pub newer_than: Option<String>,
+
+    /// Only touch existing files of the given type: f (regular file), d (directory) or l (symlink)
+    #[clap(long, value_name = "TYPE")]
+    pub only_type: Option<FileKind>,
+
+    /// Only touch existing files whose sniffed content type matches, e.g. 'image/*'
+    #[clap(long, value_name = "MIME")]
+    pub mime: Option<String>,
+
     /// If the file is a symbolic link, change the times of the link itself rather than the file that the link points to
     /// Note that this implies -c and thus will not create any new files
-    #[clap(long = "symlink")]
+    #[clap(short = 'h', long = "symlink")]
     pub symlink_only: bool,
 
-    /// pass date as human readable string (RFC3339)
+    /// Keep the access time of existing files: a template write or --fill doesn't move it,
+    /// and only the modification time is set
+    #[clap(long, conflicts_with = "access_time", verbatim_doc_comment)]
+    pub preserve_atime: bool,
+
+    /// Set the birth (creation) time too, to the new modification time or the reference file's
+    /// birth time (Windows and macOS)
+    #[clap(long, verbatim_doc_comment)]
+    pub btime: bool,
+
+    /// pass date as human readable string: RFC3339, or relative like "2 hours ago" or "next monday 09:00"
     #[clap(
         short = 'd',
         long,
         value_name = "DATE",
-        overrides_with_all = ["timestamp", "reference"],
+        overrides_with_all = ["timestamp", "unix", "reference"],
         verbatim_doc_comment
     )]
     pub date: Option<String>,
@@ -68,20 +360,49 @@ pub struct ZapCli {
         short = 't',
         long,
         value_name = "TIMESTAMP",
-        overrides_with_all = ["date", "reference"],
+        overrides_with_all = ["date", "unix", "reference"],
         verbatim_doc_comment
     )]
     pub timestamp: Option<String>,
 
+    /// pass date as seconds since the Unix epoch, with up to 9 decimals: 1735689600.5
+    #[clap(
+        short = 's',
+        long,
+        value_name = "SECONDS",
+        overrides_with_all = ["date", "timestamp", "reference"],
+        allow_hyphen_values = true
+    )]
+    pub unix: Option<String>,
+
     /// Use access and modification times from the specified file
     #[clap(
         short = 'r',
         long,
         value_name = "REFERENCE",
-        overrides_with_all = ["date", "timestamp"],
+        overrides_with_all = ["date", "timestamp", "unix"],
     )]
     pub reference: Option<String>,
-    /// Adjust time [-][[hh]mm]SS
+
+    /// Set the access time to DATE (same formats as -d), whatever -d, -t, -s or -r give
+    #[clap(long, value_name = "DATE")]
+    pub adate: Option<String>,
+
+    /// Set the modification time to DATE (same formats as -d), whatever -d, -t, -s or -r give,
+    /// e.g. `--adate now --mdate 2024-01-01`
+    #[clap(long, value_name = "DATE", verbatim_doc_comment)]
+    pub mdate: Option<String>,
+
+    /// Give every file under the target directories the times of the file at the same
+    /// relative path under SRC_DIR. Files without a counterpart are left alone
+    #[clap(
+        long,
+        value_name = "SRC_DIR",
+        conflicts_with_all = ["date", "timestamp", "unix", "reference", "adate", "mdate", "each", "from_csv"],
+        verbatim_doc_comment
+    )]
+    pub reference_tree: Option<String>,
+    /// Adjust time [-][[hh]mm]SS[.frac], or by a duration like -2d, 1h30m, +1month or PT1H30M
     /// the `-c` flag is implied
     #[clap(
         short = 'A',
@@ -91,43 +412,344 @@ pub struct ZapCli {
         allow_hyphen_values = true
     )]
     pub adjust: Option<String>,
+
+    /// Shift the time being set (-r, -d, -t, -s, or now) by ADJUST before it's written,
+    /// e.g. `-r ref --offset 1h`. Unlike -A, files are still created
+    #[clap(
+        long,
+        value_name = "ADJUST",
+        conflicts_with = "adjust",
+        verbatim_doc_comment,
+        allow_hyphen_values = true
+    )]
+    pub offset: Option<String>,
+
+    /// Put back the times the previous run changed
+    #[clap(long, exclusive = true)]
+    pub undo: bool,
+
+    /// Print each file's access, modification and (where kept) birth time instead of touching it
+    #[clap(long, conflicts_with_all = ["template", "stdout", "fill"], verbatim_doc_comment)]
+    pub show: bool,
+
+    /// How --show prints times: rfc3339 (the default), epoch, or a strftime format like '%F %T'
+    #[clap(long, value_name = "FORMAT", requires = "show")]
+    pub format: Option<TimeFormat>,
+
+    /// Give every file and directory in the target directories, and the directories
+    /// themselves, the newest or oldest modification time found among them
+    #[clap(
+        long,
+        value_name = "WHICH",
+        conflicts_with_all = ["date", "timestamp", "unix", "reference", "adate", "mdate", "reference_tree", "randomize", "each", "from_csv"],
+        verbatim_doc_comment
+    )]
+    pub normalize: Option<NormalizeTo>,
+
+    /// Give each file a random time between START and END (both in any form -d takes),
+    /// like "2023-01-01..2023-06-30"
+    #[clap(
+        long,
+        value_name = "START..END",
+        conflicts_with_all = ["date", "timestamp", "unix", "reference", "adate", "mdate", "reference_tree"],
+        verbatim_doc_comment
+    )]
+    pub randomize: Option<String>,
+
+    /// Give each file INTERVAL more than the one before, starting from the time being set
+    /// (-d, -t, -s, -r or now), e.g. 2s
+    #[clap(
+        long,
+        value_name = "INTERVAL",
+        conflicts_with_all = ["randomize", "reference_tree", "normalize"],
+        allow_hyphen_values = true,
+        verbatim_doc_comment
+    )]
+    pub stagger: Option<String>,
+
+    /// Seed for --randomize, so the same files get the same times every run
+    #[clap(long, value_name = "N", requires = "randomize")]
+    pub seed: Option<u64>,
+
+    /// Set each photo or video to when it was taken: EXIF DateTimeOriginal for JPEG,
+    /// HEIC and RAW files, the creation time for MP4 and QuickTime videos.
+    /// Files without one are skipped. The `-c` flag is implied
+    #[clap(
+        long,
+        conflicts_with_all = ["date", "timestamp", "unix", "reference", "adate", "mdate", "reference_tree", "randomize", "normalize", "stagger", "offset", "adjust", "each", "from_csv"],
+        verbatim_doc_comment
+    )]
+    pub from_exif: bool,
+
+    /// Set each file to the time of the last commit that changed it in its git repository,
+    /// for reproducible builds and make after a fresh clone. Untracked files are skipped.
+    /// The `-c` flag is implied
+    #[clap(
+        long,
+        conflicts_with_all = ["from_exif", "date", "timestamp", "unix", "reference", "adate", "mdate", "reference_tree", "randomize", "normalize", "stagger", "offset", "adjust", "each", "from_csv"],
+        verbatim_doc_comment
+    )]
+    pub from_git: bool,
+
+    /// Never set a time earlier than DATE (same formats as -d)
+    #[clap(long, value_name = "DATE")]
+    pub not_before: Option<String>,
+
+    /// Never set a time later than DATE (same formats as -d), e.g. a release date
+    #[clap(long, value_name = "DATE")]
+    pub not_after: Option<String>,
+
+    /// Round the new times to the nearest multiple of GRANULARITY, like 2s for FAT and zip
+    /// or 1m, after everything else
+    #[clap(long, value_name = "GRANULARITY", verbatim_doc_comment)]
+    pub round: Option<String>,
+
+    /// Like --round, but always round down
+    #[clap(long, value_name = "GRANULARITY", conflicts_with = "round")]
+    pub truncate: Option<String>,
+
+    /// Interpret dates and times without an offset (-d, -t, --now) in this IANA time zone,
+    /// like Europe/Berlin, instead of the local one
+    #[clap(long, value_name = "ZONE", verbatim_doc_comment)]
+    pub tz: Option<Tz>,
+
+    /// Which time to take when a local time given to -d, -t or --now happens twice or not
+    /// at all, as clocks change for daylight saving. Such times are refused by default
+    #[clap(long, value_enum, value_name = "WHICH", verbatim_doc_comment)]
+    pub dst: Option<Dst>,
+
+    /// Treat this date (RFC3339) as the current time, for file times and the
+    /// `now()` template function. Overrides SOURCE_DATE_EPOCH
+    #[clap(long, value_name = "DATE", verbatim_doc_comment)]
+    pub now: Option<String>,
+
+    /// Print how long each phase of the run took to stderr (text or json)
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "text",
+        require_equals = true
+    )]
+    pub timings: Option<TimingsFormat>,
+
+    /// List every Tera function, filter and tester available to templates,
+    /// grouped by the plugin that provides it
+    #[clap(long, exclusive = true, verbatim_doc_comment)]
+    pub list_functions: bool,
+
+    /// List the available templates with their descriptions and variables,
+    /// same as `zap template list`
+    #[clap(long, exclusive = true, verbatim_doc_comment)]
+    pub list_templates: bool,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
 }
 
-impl ZapCli {
-    /// Process command line arguments and check for -h being used for symlink.
-    /// If "-h" is passed without any other arguments, it's treated as help.
-    /// Otherwise, it's treated as the symlink_only flag.
-    pub fn process_h_flag() -> Self {
-        let args: Vec<String> = env::args().collect();
-
-        // Check if we have only "-h" without other arguments
-        if args.len() == 2 && args[1] == "-h" {
-            // Show help and exit
-            let mut app = Self::command();
-            app.print_help().unwrap();
-            std::process::exit(0);
+/// Which time `--time` selects, with the names GNU touch accepts
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeWord {
+    #[value(alias = "atime", alias = "use")]
+    Access,
+    #[value(alias = "mtime")]
+    Modify,
+}
+
+/// How `--dst` reads a local time that a daylight saving change makes ambiguous,
+/// because the clocks went back over it, or skips, because they jumped forward over it
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dst {
+    // The first of the two instants; a skipped time with the offset from after the change
+    Earliest,
+    // The second of the two instants; a skipped time with the offset from before the change
+    Latest,
+    #[default]
+    Error,
+}
+
+/// Which time `--normalize` gives a whole tree
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizeTo {
+    Newest,
+    Oldest,
+}
+
+/// File types that can be selected with `--only-type`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    #[value(name = "f")]
+    File,
+    #[value(name = "d")]
+    Directory,
+    #[value(name = "l")]
+    Symlink,
+}
+
+impl FileKind {
+    /// The kind of a file, or `None` for FIFOs, sockets and devices
+    pub fn of(file_type: &FileType) -> Option<FileKind> {
+        if file_type.is_file() {
+            Some(FileKind::File)
+        } else if file_type.is_dir() {
+            Some(FileKind::Directory)
+        } else if file_type.is_symlink() {
+            Some(FileKind::Symlink)
+        } else {
+            None
         }
+    }
+}
 
-        // Look for "-h" and replace it with "--symlink" for clap processing
-        let processed_args: Vec<String> = args
-            .into_iter()
-            .map(|arg| {
-                if arg == "-h" {
-                    "--symlink".to_string()
-                } else {
-                    arg
-                }
-            })
-            .collect();
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FileKind::File => "regular file",
+            FileKind::Directory => "directory",
+            FileKind::Symlink => "symbolic link",
+        })
+    }
+}
 
-        // Use the processed args
-        Self::parse_from(processed_args)
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Inspect and manage templates
+    Template {
+        #[clap(subcommand)]
+        action: TemplateCommand,
+    },
+    /// Create, inspect and edit config.toml
+    Config {
+        #[clap(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Install plugins
+    Plugin {
+        #[clap(subcommand)]
+        action: PluginCommand,
+    },
+    /// Load a native plugin and call it for the zap process that started this
+    /// one, with `plugins.sandbox`
+    #[clap(name = "plugin-host", hide = true)]
+    PluginHost { path: PathBuf },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum PluginCommand {
+    /// Install a plugin library, script or executable from a path or URL into
+    /// the plugins directory, once it has been checked to load
+    Install {
+        #[clap(value_name = "PATH|URL")]
+        source: String,
+        /// File name to install the plugin as; defaults to the source's
+        #[clap(long)]
+        file: Option<String>,
+        /// Version to record in the plugin index, instead of the one the plugin reports
+        #[clap(long)]
+        version: Option<String>,
+        /// Replace an installed plugin of the same file name
+        #[clap(long)]
+        force: bool,
+    },
+    /// List the plugins with their name, version, author and description
+    List,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Write a config.toml with every setting commented out
+    Init {
+        /// Replace an existing config.toml
+        #[clap(long)]
+        force: bool,
+    },
+    /// Print the settings zap uses, after applying environment variables
+    Show,
+    /// Open config.toml in your $EDITOR, creating it if it doesn't exist
+    Edit,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TemplateCommand {
+    /// Print the file a template name resolves to and every location searched
+    Which {
+        #[clap(value_name = "TEMPLATE_NAME")]
+        name: String,
+    },
+    /// Open a template in your $EDITOR, creating it from a stub if it doesn't exist
+    Edit {
+        #[clap(value_name = "TEMPLATE_NAME")]
+        name: String,
+    },
+    /// List the available templates with their descriptions and variables
+    List,
+    /// Render a template (or every file of a scaffold) in memory and report
+    /// syntax errors, undefined variables and unknown filters
+    Check {
+        #[clap(value_name = "TEMPLATE_NAME")]
+        name: String,
+        /// Context to render with, in the format `foo=bar,baz=qux`
+        #[clap(short = 'C', long, value_name = "CONTEXT")]
+        context: Option<String>,
+    },
+    /// Install a template pack from a git URL or a directory, so its templates
+    /// can be used as `-T PACK/TEMPLATE`. Without a source, installs the packs
+    /// recorded in packs.lock at their recorded revisions
+    Install {
+        #[clap(value_name = "GIT_URL|PATH")]
+        source: Option<String>,
+        /// Name of the pack; defaults to the last part of the source
+        #[clap(long, requires = "source")]
+        name: Option<String>,
+        /// Branch, tag or commit to install instead of the latest commit
+        #[clap(long, value_name = "REV", requires = "source")]
+        rev: Option<String>,
+    },
+    /// Fetch the latest version of one or all installed template packs
+    Update {
+        #[clap(value_name = "PACK")]
+        name: Option<String>,
+    },
+    /// Remove an installed template pack
+    Remove {
+        #[clap(value_name = "PACK")]
+        name: String,
+    },
+}
+
+impl ZapCli {
+    /// The command line zap was started with, after expanding aliases and @argfiles.
+    /// Exits with a message (or the help) when it doesn't parse.
+    pub fn from_env() -> Self {
+        // A broken config file is reported once zap runs; until then there are just no aliases
+        let aliases = Config::load()
+            .map(|config| config.alias)
+            .unwrap_or_default();
+        let args = match expand_aliases(env::args(), &aliases).and_then(expand_argfiles) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+        };
+        Self::parse_args(&args).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Parse `args`, the program name first. `-h` is `--symlink`, like in touch, but on
+    /// its own, with no file to apply to, it asks for the help like `--help` does.
+    pub fn parse_args<T: AsRef<str>>(args: &[T]) -> Result<Self, clap::Error> {
+        let args: Vec<&str> = args.iter().map(AsRef::as_ref).collect();
+        match Self::try_parse_from(&args) {
+            Err(e)
+                if e.kind() == ErrorKind::MissingRequiredArgument
+                    && args.get(1..) == Some(&["-h"][..]) =>
+            {
+                Self::try_parse_from([args[0], "--help"])
+            }
+            parsed => parsed,
+        }
     }
 
-    /// Determine which times should be updated based on the -a and -m flags.
-    /// Following touch command behavior:
-    /// - If neither -a nor -m or both -a and -m are specified: update both times
-    /// - If only either -a or -m are specified: update only the respective times
     /// Convenience method to check if symlink_only is set, and if so, ensure no_create is also set
     pub fn ensure_no_create_if_symlink(&mut self) {
         if self.symlink_only {
@@ -135,8 +757,37 @@ impl ZapCli {
         }
     }
 
+    /// The `--map` entries as (file, template) pairs. Files are split off at the
+    /// last `=`, so their names may contain one.
+    pub fn mapped_files(&self) -> Result<Vec<(String, String)>, ZapError> {
+        self.map
+            .iter()
+            .map(|entry| match entry.rsplit_once('=') {
+                Some((file, template)) if !file.is_empty() && !template.is_empty() => {
+                    Ok((file.to_string(), template.to_string()))
+                }
+                _ => Err(ZapError::InvalidMap(entry.clone())),
+            })
+            .collect()
+    }
+
+    /// The `--context-for` entries as (file, context) pairs.
+    pub fn file_contexts(&self) -> Vec<(&str, &str)> {
+        self.context_for
+            .chunks_exact(2)
+            .map(|pair| (pair[0].as_str(), pair[1].as_str()))
+            .collect()
+    }
+
+    /// Determine which times should be updated based on the -a and -m flags,
+    /// with `--time` counting as the flag it names.
+    /// Following touch command behavior:
+    /// - If neither -a nor -m or both -a and -m are specified: update both times
+    /// - If only either -a or -m are specified: update only the respective times
     pub fn should_update_times(&self) -> (bool, bool) {
-        match (self.access_time, self.modification_time) {
+        let access = self.access_time || self.time == Some(TimeWord::Access);
+        let modification = self.modification_time || self.time == Some(TimeWord::Modify);
+        match (access, modification) {
             (false, false) => (true, true), // Neither specified: update both
             (true, false) => (true, false), // Only -a: update access time only
             (false, true) => (false, true), // Only -m: update modification time only
@@ -167,6 +818,7 @@ mod tests {
             reference: None,
             adjust: None,
             symlink_only: false,
+            ..Default::default()
         };
 
         let (update_access, update_modification) = cli.should_update_times();
@@ -198,6 +850,7 @@ mod tests {
             reference: None,
             adjust: None,
             symlink_only: false,
+            ..Default::default()
         };
 
         let (update_access, update_modification) = cli.should_update_times();
@@ -226,6 +879,7 @@ mod tests {
             reference: None,
             adjust: None,
             symlink_only: false,
+            ..Default::default()
         };
 
         let (update_access, update_modification) = cli.should_update_times();
@@ -257,6 +911,7 @@ mod tests {
             reference: None,
             adjust: None,
             symlink_only: false,
+            ..Default::default()
         };
 
         let (update_access, update_modification) = cli.should_update_times();
@@ -269,4 +924,158 @@ mod tests {
             "Should update modification time when both -a and -m specified"
         );
     }
+
+    #[test]
+    fn test_time_word_selects_like_a_and_m() {
+        let times = |args: &[&str]| {
+            ZapCli::try_parse_from(["zap"].iter().chain(args).chain(&["notes.md"]))
+                .unwrap()
+                .should_update_times()
+        };
+        assert_eq!(times(&["--time=atime"]), (true, false));
+        assert_eq!(times(&["--time", "use"]), (true, false));
+        assert_eq!(times(&["--time=modify"]), (false, true));
+        assert_eq!(times(&["--time=mtime", "-a"]), (true, true));
+        assert!(ZapCli::try_parse_from(["zap", "--time=birth", "notes.md"]).is_err());
+    }
+
+    #[test]
+    fn test_offset_still_creates_files() {
+        let cli =
+            ZapCli::try_parse_from(["zap", "-r", "ref", "--offset", "-1h", "new.md"]).unwrap();
+        assert_eq!(cli.offset.as_deref(), Some("-1h"));
+        assert!(!cli.no_create);
+        let cli = ZapCli::try_parse_from(["zap", "-r", "ref", "-A", "-1h", "new.md"]).unwrap();
+        assert!(cli.no_create);
+        assert!(ZapCli::try_parse_from(["zap", "-A", "1h", "--offset", "1h", "new.md"]).is_err());
+    }
+
+    #[test]
+    fn test_h_is_symlink_unless_alone() {
+        let cli = ZapCli::parse_args(&["zap", "-h", "link"]).unwrap();
+        assert!(cli.symlink_only);
+        assert!(cli.no_create);
+        // Combined short flags and values that look like -h are left alone
+        assert!(
+            ZapCli::parse_args(&["zap", "-ch", "link"])
+                .unwrap()
+                .symlink_only
+        );
+        let cli = ZapCli::parse_args(&["zap", "--offset", "-h", "link"]).unwrap();
+        assert_eq!(cli.offset.as_deref(), Some("-h"));
+        assert!(!cli.symlink_only);
+
+        for help in [&["zap", "-h"][..], &["zap", "--help"]] {
+            let err = ZapCli::parse_args(help).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::DisplayHelp);
+        }
+    }
+
+    #[test]
+    fn test_expand_argfiles_reads_arguments_from_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let argfile = dir.path().join("args.txt");
+        fs::write(&argfile, "# files for today\n-T\nnote\n\n  a.md  \nb.md\n").unwrap();
+
+        let args = expand_argfiles([
+            "zap".to_string(),
+            format!("@{}", argfile.display()),
+            "c.md".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(args, ["zap", "-T", "note", "a.md", "b.md", "c.md"]);
+    }
+
+    #[test]
+    fn test_expand_argfiles_keeps_arguments_after_double_dash() {
+        let args = expand_argfiles(["zap", "--", "@literal"].map(String::from)).unwrap();
+        assert_eq!(args, ["zap", "--", "@literal"]);
+    }
+
+    #[test]
+    fn test_expand_aliases() {
+        let aliases = BTreeMap::from([
+            ("note".to_string(), "-T note  -p -o".to_string()),
+            (
+                "todo".to_string(),
+                "-C 'title=To do' -C \"tags=a b\"".to_string(),
+            ),
+            ("broken".to_string(), "-C 'title=open".to_string()),
+        ]);
+        let args = expand_aliases(
+            ["zap", "+note", "today.md", "+other", "@note", "--", "+note"].map(String::from),
+            &aliases,
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "zap", "-T", "note", "-p", "-o", "today.md", "+other", "@note", "--", "+note"
+            ]
+        );
+
+        let args = expand_aliases(["zap", "+todo"].map(String::from), &aliases).unwrap();
+        assert_eq!(args, ["zap", "-C", "title=To do", "-C", "tags=a b"]);
+
+        let err = expand_aliases(["zap", "+broken"].map(String::from), &aliases).unwrap_err();
+        assert!(matches!(err, ZapError::InvalidAlias { name, .. } if name == "broken"));
+    }
+
+    #[test]
+    fn test_template_without_name_asks_to_pick() {
+        let cli = ZapCli::try_parse_from(["zap", "notes.md", "-T"]).unwrap();
+        assert_eq!(cli.template.as_deref(), Some(""));
+        assert_eq!(cli.filenames, ["notes.md"]);
+
+        let cli = ZapCli::try_parse_from(["zap", "-T", "note", "notes.md"]).unwrap();
+        assert_eq!(cli.template.as_deref(), Some("note"));
+    }
+
+    #[test]
+    fn test_map_pairs_files_with_templates() {
+        let cli =
+            ZapCli::try_parse_from(["zap", "--map", "main.rs=rust-bin", "--map", "a=b.md=note"])
+                .unwrap();
+        assert!(cli.filenames.is_empty());
+        assert_eq!(
+            cli.mapped_files().unwrap(),
+            [
+                ("main.rs".to_string(), "rust-bin".to_string()),
+                ("a=b.md".to_string(), "note".to_string())
+            ]
+        );
+
+        let cli = ZapCli::try_parse_from(["zap", "--map", "main.rs"]).unwrap();
+        assert!(matches!(cli.mapped_files(), Err(ZapError::InvalidMap(_))));
+        assert!(ZapCli::try_parse_from(["zap", "--map", "a=b", "--each", "x.json"]).is_err());
+    }
+
+    #[test]
+    fn test_context_for_takes_file_and_context() {
+        let cli = ZapCli::try_parse_from([
+            "zap",
+            "--context-for",
+            "a.md",
+            "title=Monday",
+            "--context-for",
+            "b.md",
+            "title=Tuesday",
+            "a.md",
+            "b.md",
+        ])
+        .unwrap();
+        assert_eq!(cli.filenames, ["a.md", "b.md"]);
+        assert_eq!(
+            cli.file_contexts(),
+            [("a.md", "title=Monday"), ("b.md", "title=Tuesday")]
+        );
+        assert!(ZapCli::try_parse_from(["zap", "--context-for", "a.md", "--", "a.md"]).is_err());
+    }
+
+    #[test]
+    fn test_expand_argfiles_missing_file() {
+        let err = expand_argfiles(["zap", "@does-not-exist.txt"].map(String::from)).unwrap_err();
+        assert!(matches!(err, ZapError::ArgFile { ref path, .. } if path == "does-not-exist.txt"));
+    }
 }
@@ -0,0 +1,397 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use serde_json::Map;
+use tera::{Context, Tera, Value};
+
+use crate::args::NormalizeTo;
+use crate::errors::ZapError;
+use crate::file_time_util::FileTimeSpec;
+use crate::fileaction::Target;
+
+/// Load the items for `--each`: a JSON array, or a CSV file whose header row
+/// provides the keys of each item.
+pub fn load_items(path: &Path) -> Result<Vec<Value>, ZapError> {
+    let is_csv = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+
+    if is_csv {
+        let csv = load_csv_rows(path)?;
+        return Ok(csv
+            .rows
+            .into_iter()
+            .map(|(_, row)| Value::Object(row))
+            .collect());
+    }
+
+    let content = fs::read_to_string(path)?;
+    match serde_json::from_str(&content) {
+        Ok(Value::Array(items)) => Ok(items),
+        Ok(_) => Err(ZapError::InvalidItems {
+            path: path.to_path_buf(),
+            reason: "expected a JSON array".to_string(),
+        }),
+        Err(e) => Err(ZapError::InvalidItems {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        }),
+    }
+}
+
+/// The rows of a CSV file with a header row.
+pub struct CsvRows {
+    /// Column names, in the order of the header row
+    pub headers: Vec<String>,
+    /// Each row's values by column name, with the line the row starts on
+    pub rows: Vec<(u64, Map<String, Value>)>,
+}
+
+/// Read a CSV file with a header row into one JSON object per row.
+pub fn load_csv_rows(path: &Path) -> Result<CsvRows, ZapError> {
+    let invalid = |e: csv::Error| ZapError::InvalidItems {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    };
+
+    let mut reader = csv::Reader::from_path(path).map_err(invalid)?;
+    let headers = reader.headers().map_err(invalid)?.clone();
+
+    let rows = reader
+        .records()
+        .map(|record| {
+            let record = record.map_err(|e| {
+                let line = e.position().map_or(0, |position| position.line());
+                ZapError::InvalidRow {
+                    path: path.to_path_buf(),
+                    line,
+                    reason: e.to_string(),
+                }
+            })?;
+            let line = record.position().map_or(0, |position| position.line());
+            let row = headers
+                .iter()
+                .zip(record.iter())
+                .map(|(key, value)| (key.to_string(), Value::String(value.to_string())))
+                .collect();
+            Ok((line, row))
+        })
+        .collect::<Result<_, ZapError>>()?;
+    Ok(CsvRows {
+        headers: headers.iter().map(str::to_string).collect(),
+        rows,
+    })
+}
+
+/// One target per row of a CSV file for `--from-csv`. The file name comes from
+/// `name_column`, and every column is exposed to the template under its header name.
+pub fn load_csv_targets(
+    path: &Path,
+    name_column: &str,
+    context: &Context,
+) -> Result<Vec<Target>, ZapError> {
+    let csv = load_csv_rows(path)?;
+    if !csv.headers.iter().any(|header| header == name_column) {
+        return Err(ZapError::InvalidItems {
+            path: path.to_path_buf(),
+            reason: format!(
+                "no column '{name_column}' (columns: {})",
+                csv.headers.join(", ")
+            ),
+        });
+    }
+
+    csv.rows
+        .into_iter()
+        .map(|(line, row)| {
+            let filename = row
+                .get(name_column)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .trim();
+            if filename.is_empty() {
+                return Err(ZapError::InvalidRow {
+                    path: path.to_path_buf(),
+                    line,
+                    reason: format!("column '{name_column}' is empty"),
+                });
+            }
+
+            let mut row_context = context.clone();
+            for (key, value) in &row {
+                row_context.insert(key, value);
+            }
+            Ok(Target {
+                filename: filename.to_string(),
+                context: row_context,
+                template: None,
+                times: None,
+            })
+        })
+        .collect()
+}
+
+/// Fan out one target per item. Each item is exposed to the template as `item`
+/// on top of the shared context, and the file name is rendered from `name_pattern`.
+pub fn expand_each(
+    items: Vec<Value>,
+    name_pattern: &str,
+    context: &Context,
+) -> Result<Vec<Target>, ZapError> {
+    items
+        .into_iter()
+        .map(|item| {
+            let mut item_context = context.clone();
+            item_context.insert("item", &item);
+            let filename = Tera::one_off(name_pattern, &item_context, false)?;
+            Ok(Target {
+                filename,
+                context: item_context,
+                template: None,
+                times: None,
+            })
+        })
+        .collect()
+}
+
+/// One target per file and directory under `target_dir` that has a counterpart at the
+/// same relative path under `source_dir` (`--reference-tree`), carrying the
+/// counterpart's times. Entries come before the directory holding them, and
+/// symlinked directories aren't followed.
+pub fn reference_tree_targets(
+    source_dir: &Path,
+    target_dir: &Path,
+    context: &Context,
+    symlink_only: bool,
+) -> Result<Vec<Target>, ZapError> {
+    for dir in [source_dir, target_dir] {
+        if !dir.is_dir() {
+            return Err(ZapError::NotADirectory(dir.to_path_buf()));
+        }
+    }
+    let mut paths = Vec::new();
+    collect_entries(target_dir, PathBuf::new(), &mut paths)?;
+    paths.push(PathBuf::new());
+
+    let mut targets = Vec::new();
+    for relative in paths {
+        let counterpart = source_dir.join(&relative);
+        let metadata = if symlink_only {
+            fs::symlink_metadata(&counterpart)
+        } else {
+            fs::metadata(&counterpart)
+        };
+        // Files only the target has keep their times
+        let Ok(metadata) = metadata else {
+            continue;
+        };
+        targets.push(Target {
+            filename: target_dir.join(&relative).to_string_lossy().into_owned(),
+            context: context.clone(),
+            template: None,
+            times: Some(FileTimeSpec::from_metadata(&metadata)),
+        });
+    }
+    Ok(targets)
+}
+
+/// One target per file and directory under `dir` and for `dir` itself (`--normalize`),
+/// all carrying the newest or oldest modification time among them.
+pub fn normalize_targets(
+    dir: &Path,
+    normalize: NormalizeTo,
+    context: &Context,
+    symlink_only: bool,
+) -> Result<Vec<Target>, ZapError> {
+    if !dir.is_dir() {
+        return Err(ZapError::NotADirectory(dir.to_path_buf()));
+    }
+    let mut paths = Vec::new();
+    collect_entries(dir, PathBuf::new(), &mut paths)?;
+    paths.push(PathBuf::new());
+
+    let mut mtimes = Vec::with_capacity(paths.len());
+    for relative in &paths {
+        let path = dir.join(relative);
+        let metadata = if symlink_only {
+            fs::symlink_metadata(&path)?
+        } else {
+            fs::metadata(&path)?
+        };
+        mtimes.push(FileTime::from_last_modification_time(&metadata));
+    }
+    let chosen = match normalize {
+        NormalizeTo::Newest => mtimes.into_iter().max(),
+        NormalizeTo::Oldest => mtimes.into_iter().min(),
+    };
+
+    Ok(paths
+        .into_iter()
+        .map(|relative| Target {
+            filename: dir.join(relative).to_string_lossy().into_owned(),
+            context: context.clone(),
+            template: None,
+            times: chosen.map(FileTimeSpec::both),
+        })
+        .collect())
+}
+
+fn collect_entries(
+    root: &Path,
+    relative: PathBuf,
+    paths: &mut Vec<PathBuf>,
+) -> Result<(), ZapError> {
+    let mut entries = fs::read_dir(root.join(&relative))?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = relative.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            collect_entries(root, path.clone(), paths)?;
+        }
+        paths.push(path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_json_items() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("members.json");
+        fs::write(&path, r#"[{"name": "Ada"}, {"name": "Linus"}]"#).unwrap();
+
+        let items = load_items(&path).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[1]["name"], "Linus");
+    }
+
+    #[test]
+    fn test_load_items_rejects_non_arrays() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("members.json");
+        fs::write(&path, r#"{"name": "Ada"}"#).unwrap();
+
+        assert!(matches!(
+            load_items(&path),
+            Err(ZapError::InvalidItems { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_csv_items() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("members.csv");
+        fs::write(&path, "name,role\nAda,admin\nLinus,maintainer\n").unwrap();
+
+        let items = load_items(&path).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["role"], "admin");
+        assert_eq!(items[1]["name"], "Linus");
+    }
+
+    #[test]
+    fn test_reference_tree_targets() {
+        let source = tempdir().unwrap();
+        let target = tempdir().unwrap();
+        for dir in [source.path(), target.path()] {
+            fs::create_dir(dir.join("src")).unwrap();
+            fs::write(dir.join("src/main.rs"), "").unwrap();
+        }
+        fs::write(target.path().join("new.txt"), "").unwrap();
+        let time = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_times(source.path().join("src/main.rs"), time, time).unwrap();
+
+        let targets =
+            reference_tree_targets(source.path(), target.path(), &Context::new(), false).unwrap();
+        let names: Vec<_> = targets
+            .iter()
+            .map(|t| Path::new(&t.filename).strip_prefix(target.path()).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [Path::new("src/main.rs"), Path::new("src"), Path::new("")]
+        );
+        assert_eq!(targets[0].times.unwrap().mtime, Some(time));
+
+        let missing = source.path().join("missing");
+        assert!(matches!(
+            reference_tree_targets(&missing, target.path(), &Context::new(), false),
+            Err(ZapError::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn test_normalize_targets() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        let (old, new) = (dir.path().join("src/old.rs"), dir.path().join("new.rs"));
+        fs::write(&old, "").unwrap();
+        fs::write(&new, "").unwrap();
+        let at = |seconds| FileTime::from_unix_time(seconds, 0);
+        filetime::set_file_times(&old, at(1_500_000_000), at(1_500_000_000)).unwrap();
+        filetime::set_file_times(&new, at(1_900_000_000), at(1_900_000_000)).unwrap();
+        let mtime = |normalize| {
+            let targets = normalize_targets(dir.path(), normalize, &Context::new(), false).unwrap();
+            assert_eq!(targets.len(), 4);
+            assert!(
+                targets
+                    .iter()
+                    .all(|t| t.times.unwrap().mtime == targets[0].times.unwrap().mtime)
+            );
+            targets[0].times.unwrap().mtime.unwrap()
+        };
+
+        assert_eq!(mtime(NormalizeTo::Oldest), at(1_500_000_000));
+        assert_eq!(mtime(NormalizeTo::Newest), at(1_900_000_000));
+    }
+
+    #[test]
+    fn test_load_csv_targets() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("services.csv");
+        fs::write(
+            &path,
+            "service,path\napi,conf/api.toml\nweb,conf/web.toml\n",
+        )
+        .unwrap();
+
+        let targets = load_csv_targets(&path, "path", &Context::new()).unwrap();
+        let names: Vec<_> = targets.iter().map(|t| t.filename.as_str()).collect();
+        assert_eq!(names, vec!["conf/api.toml", "conf/web.toml"]);
+        assert_eq!(targets[1].context.get("service").unwrap(), "web");
+    }
+
+    #[test]
+    fn test_load_csv_targets_reports_rows() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("services.csv");
+        fs::write(&path, "service,path\napi,conf/api.toml\nweb,\n").unwrap();
+
+        let err = load_csv_targets(&path, "path", &Context::new()).unwrap_err();
+        assert!(matches!(err, ZapError::InvalidRow { line: 3, .. }), "{err}");
+
+        let err = load_csv_targets(&path, "file", &Context::new()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("no column 'file' (columns: service, path)")
+        );
+    }
+
+    #[test]
+    fn test_expand_each_renders_names() {
+        let items: Vec<Value> =
+            serde_json::from_str(r#"[{"name": "Ada"}, {"name": "Linus"}]"#).unwrap();
+        let mut context = Context::new();
+        context.insert("dir", "people");
+
+        let targets = expand_each(items, "{{ dir }}/{{ item.name | lower }}.md", &context).unwrap();
+        let names: Vec<_> = targets.iter().map(|t| t.filename.as_str()).collect();
+        assert_eq!(names, vec!["people/ada.md", "people/linus.md"]);
+        assert_eq!(targets[0].context.get("item").unwrap()["name"], "Ada");
+    }
+}
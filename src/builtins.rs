@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use rand::Rng;
+use tera::{Tera, Value};
+
+use crate::render::Registered;
+
+/// Register zap's built-in filters and functions, with `read_file` reading from
+/// `read_file_dirs`, and return their names.
+/// Plugins are loaded afterwards, so they can override any of these.
+pub fn register(tera: &mut Tera, read_file_dirs: Vec<PathBuf>) -> Registered {
+    let mut registered = Registered::default();
+    for (name, convert) in [
+        ("snake_case", to_snake_case as fn(&str) -> String),
+        ("camel_case", to_camel_case),
+        ("pascal_case", to_pascal_case),
+        ("kebab_case", to_kebab_case),
+        ("screaming_snake_case", to_screaming_snake_case),
+        // `slugify` itself is provided by Tera's builtins and uses the same `slug` crate
+        ("sanitize_filename", sanitize_filename),
+    ] {
+        registered.filter(tera, name, string_filter(name, convert));
+    }
+    // Another name for `pascal_case`, reporting errors as that
+    registered.filter(
+        tera,
+        "upper_camel",
+        string_filter("pascal_case", to_pascal_case),
+    );
+    // Replaces Tera's `now` so that `--now` and SOURCE_DATE_EPOCH apply to templates too
+    registered.function(tera, "now", now);
+    registered.function(tera, "uuid4", |_: &HashMap<String, Value>| {
+        Ok(Value::String(uuid::Uuid::new_v4().to_string()))
+    });
+    registered.function(tera, "random_hex", random_hex);
+    registered.function(tera, "format_date", format_date);
+    registered.function(tera, "env", read_env);
+    register_read_file(tera, read_file_dirs);
+    registered.functions.insert("read_file".to_string());
+    registered
+}
+
+/// Register `env(name="HOME", default="...")`, or, if `enabled` is false,
+/// replace both it and Tera's `get_env` with a function that fails. That keeps
+/// templates from reading environment variables (`--no-env`), e.g. when
+/// rendering templates from an untrusted source.
+pub fn register_env(tera: &mut Tera, enabled: bool) {
+    if enabled {
+        tera.register_function("env", read_env);
+    } else {
+        for name in ["env", "get_env"] {
+            tera.register_function(name, move |_: &HashMap<String, Value>| {
+                Err(tera::Error::msg(format!(
+                    "Function `{name}` is disabled (--no-env)"
+                )))
+            });
+        }
+    }
+}
+
+/// The value of the environment variable `name`, or `default` if it isn't set.
+fn read_env(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let name = match args.get("name") {
+        Some(Value::String(name)) => name,
+        Some(value) => {
+            return Err(tera::Error::msg(format!(
+                "Function `env` received name={value} but `name` can only be a string"
+            )));
+        }
+        None => {
+            return Err(tera::Error::msg(
+                "Function `env` didn't receive a `name` argument",
+            ));
+        }
+    };
+    match (env::var(name), args.get("default")) {
+        (Ok(value), _) => Ok(Value::String(value)),
+        (Err(_), Some(default)) => Ok(default.clone()),
+        (Err(_), None) => Err(tera::Error::msg(format!(
+            "Environment variable `{name}` is not set and `env` received no default"
+        ))),
+    }
+}
+
+/// Register `read_file(path="chunks/license.txt")`, which returns a file from the
+/// first of the template directories `dirs` that has it, as it is. Only plain
+/// relative paths are accepted, and the file has to really be inside that
+/// directory, so templates can't read anything else, not even through a symlink.
+pub fn register_read_file(tera: &mut Tera, dirs: Vec<PathBuf>) {
+    tera.register_function("read_file", move |args: &HashMap<String, Value>| {
+        let path = match args.get("path") {
+            Some(Value::String(path)) => path,
+            Some(value) => {
+                return Err(tera::Error::msg(format!(
+                    "Function `read_file` received path={value} but `path` can only be a string"
+                )));
+            }
+            None => {
+                return Err(tera::Error::msg(
+                    "Function `read_file` didn't receive a `path` argument",
+                ));
+            }
+        };
+        read_template_file(&dirs, path)
+            .map(Value::String)
+            .map_err(tera::Error::msg)
+    });
+}
+
+fn read_template_file(dirs: &[PathBuf], path: &str) -> Result<String, String> {
+    let relative = Path::new(path);
+    let plain = !path.is_empty()
+        && relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if !plain {
+        return Err(format!(
+            "Function `read_file` only reads files below the template directories, not `{path}`"
+        ));
+    }
+    for dir in dirs {
+        let candidate = dir.join(relative);
+        if !candidate.is_file() {
+            continue;
+        }
+        let (Ok(dir), Ok(file)) = (dir.canonicalize(), candidate.canonicalize()) else {
+            continue;
+        };
+        if !file.starts_with(&dir) {
+            return Err(format!(
+                "Function `read_file` won't follow `{path}` out of the template directory"
+            ));
+        }
+        return fs::read_to_string(&file)
+            .map_err(|e| format!("Function `read_file` could not read `{path}`: {e}"));
+    }
+    Err(format!(
+        "Function `read_file` found no `{path}` in the template directories"
+    ))
+}
+
+/// Same arguments and output as Tera's `now`, but reading the time from zap's clock.
+fn now(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let flag = |name: &str| match args.get(name) {
+        None => Ok(false),
+        Some(value) => value.as_bool().ok_or_else(|| {
+            tera::Error::msg(format!(
+                "Function `now` received {name}={value} but `{name}` can only be a boolean"
+            ))
+        }),
+    };
+    let (utc, timestamp) = (flag("utc")?, flag("timestamp")?);
+
+    let now = crate::clock::now();
+    Ok(match (timestamp, utc) {
+        (true, _) => Value::from(now.timestamp()),
+        (false, true) => Value::from(now.to_rfc3339()),
+        (false, false) => Value::from(now.with_timezone(&chrono::Local).to_rfc3339()),
+    })
+}
+
+/// Format a point in time, given as a unix timestamp (like `target_mtime`) or an
+/// RFC 3339 string (like `now()`), with a strftime `fmt` (default `%Y-%m-%d %H:%M:%S`)
+/// in local time.
+fn format_date(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let invalid = |value: &Value| {
+        tera::Error::msg(format!(
+            "Function `format_date` received ts={value} but `ts` can only be a unix timestamp or an RFC 3339 date"
+        ))
+    };
+    let time = match args.get("ts") {
+        Some(Value::Number(number)) => number
+            .as_i64()
+            .and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0))
+            .ok_or_else(|| invalid(&Value::Number(number.clone())))?,
+        Some(Value::String(date)) => chrono::DateTime::parse_from_rfc3339(date)
+            .map(|date| date.to_utc())
+            .map_err(|_| invalid(&Value::String(date.clone())))?,
+        Some(value) => return Err(invalid(value)),
+        None => {
+            return Err(tera::Error::msg(
+                "Function `format_date` didn't receive a `ts` argument",
+            ));
+        }
+    };
+    let fmt = match args.get("fmt") {
+        None => "%Y-%m-%d %H:%M:%S",
+        Some(Value::String(fmt)) => fmt,
+        Some(value) => {
+            return Err(tera::Error::msg(format!(
+                "Function `format_date` received fmt={value} but `fmt` can only be a string"
+            )));
+        }
+    };
+
+    let mut items = Vec::new();
+    for item in chrono::format::StrftimeItems::new(fmt) {
+        if item == chrono::format::Item::Error {
+            return Err(tera::Error::msg(format!(
+                "Function `format_date` received an invalid format `{fmt}`"
+            )));
+        }
+        items.push(item);
+    }
+    let local = time.with_timezone(&chrono::Local);
+    Ok(Value::String(
+        local.format_with_items(items.into_iter()).to_string(),
+    ))
+}
+
+/// Longest string `random_hex` makes, so a typo can't exhaust memory.
+const MAX_RANDOM_HEX_LENGTH: u64 = 4096;
+
+/// `length` (default 16) random lowercase hex digits, e.g. for secrets or IDs.
+fn random_hex(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let length = match args.get("length") {
+        None => 16,
+        Some(value) => value
+            .as_u64()
+            .filter(|length| *length <= MAX_RANDOM_HEX_LENGTH)
+            .ok_or_else(|| {
+                tera::Error::msg(format!(
+                    "Function `random_hex` received length={value} but `length` can only be an integer from 0 to {MAX_RANDOM_HEX_LENGTH}"
+                ))
+            })?,
+    };
+    let mut rng = rand::thread_rng();
+    let hex = (0..length)
+        .map(|_| char::from_digit(rng.gen_range(0..16), 16).expect("digit is below 16"))
+        .collect();
+    Ok(Value::String(hex))
+}
+
+/// Wrap a string conversion as a Tera filter that rejects non-string values.
+fn string_filter(
+    name: &'static str,
+    convert: fn(&str) -> String,
+) -> impl Fn(&Value, &HashMap<String, Value>) -> tera::Result<Value> + Send + Sync {
+    move |value, _args| {
+        let input = value.as_str().ok_or_else(|| {
+            tera::Error::msg(format!("Filter `{name}` was called on a non-string value"))
+        })?;
+        Ok(Value::String(convert(input)))
+    }
+}
+
+/// Split an identifier-ish string into words.
+/// Words are separated by any non-alphanumeric character, by a lower-to-upper
+/// transition (`fooBar`), at the end of an acronym (`HTTPServer`) and where an
+/// uppercase letter follows a digit (`v2Api`). Digits otherwise stay attached
+/// to the word they follow or precede (`item42`, `2fast`).
+pub fn split_words(input: &str) -> Vec<String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut words = Vec::new();
+    let mut current = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+
+        if !current.is_empty() && c.is_uppercase() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_is_lower) {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(c);
+    }
+
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+pub fn to_snake_case(input: &str) -> String {
+    split_words(input)
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+pub fn to_kebab_case(input: &str) -> String {
+    split_words(input)
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+pub fn to_screaming_snake_case(input: &str) -> String {
+    split_words(input)
+        .iter()
+        .map(|word| word.to_uppercase())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+pub fn to_pascal_case(input: &str) -> String {
+    split_words(input)
+        .iter()
+        .map(|word| capitalize(word))
+        .collect()
+}
+
+pub fn to_camel_case(input: &str) -> String {
+    split_words(input)
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            if i == 0 {
+                word.to_lowercase()
+            } else {
+                capitalize(word)
+            }
+        })
+        .collect()
+}
+
+/// Characters that are not allowed in file names on at least one common platform.
+const FORBIDDEN_FILENAME_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+/// Names Windows reserves for devices, regardless of extension.
+const RESERVED_FILENAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Make a string safe to use as a single file name component on all platforms.
+/// Forbidden and control characters become `_`, trailing dots and spaces are
+/// trimmed and reserved device names get a `_` suffix. Case and spaces are kept.
+pub fn sanitize_filename(input: &str) -> String {
+    let replaced: String = input
+        .chars()
+        .map(|c| {
+            if c.is_control() || FORBIDDEN_FILENAME_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    let mut sanitized = replaced.trim_end_matches(['.', ' ']).to_string();
+    if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
+        return "_".to_string();
+    }
+
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if RESERVED_FILENAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        sanitized.insert(stem.len(), '_');
+    }
+    sanitized
+}
+
+/// Slugify the last component of `filename`, keeping its directory, a leading
+/// dot (hidden files) and the extension, e.g. `notes/My Idea!.md` becomes
+/// `notes/my-idea.md`.
+pub fn slugify_filename(filename: &str) -> String {
+    let path = Path::new(filename);
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return filename.to_string();
+    };
+
+    let (hidden, name) = match name.strip_prefix('.') {
+        Some(rest) => (true, rest),
+        None => (false, name),
+    };
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (name, None),
+    };
+
+    let mut slug = slug::slugify(stem);
+    if slug.is_empty() {
+        slug = sanitize_filename(stem);
+    }
+    if hidden {
+        slug.insert(0, '.');
+    }
+    if let Some(extension) = extension {
+        slug.push('.');
+        slug.push_str(&sanitize_filename(extension));
+    }
+
+    path.with_file_name(slug).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tera::Context;
+
+    #[test]
+    fn test_read_file_stays_in_template_directories() {
+        let root = tempfile::TempDir::new().unwrap();
+        let (user, system) = (root.path().join("user"), root.path().join("system"));
+        fs::create_dir_all(user.join("chunks")).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        fs::write(user.join("chunks").join("license.txt"), "MIT\n").unwrap();
+        fs::write(system.join("footer"), "-- {{ not rendered }}").unwrap();
+        fs::write(root.path().join("secret"), "hunter2").unwrap();
+        let dirs = [user.clone(), system];
+
+        assert_eq!(
+            read_template_file(&dirs, "chunks/license.txt").unwrap(),
+            "MIT\n"
+        );
+        assert_eq!(
+            read_template_file(&dirs, "./footer").unwrap(),
+            "-- {{ not rendered }}"
+        );
+        assert!(read_template_file(&dirs, "nope").is_err());
+
+        let secret = root.path().join("secret");
+        for escape in [
+            "../secret",
+            "chunks/../../secret",
+            "chunks/../license.txt",
+            secret.to_str().unwrap(),
+            "",
+        ] {
+            let error = read_template_file(&dirs, escape).unwrap_err();
+            assert!(
+                error.contains("only reads files below"),
+                "{escape}: {error}"
+            );
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&secret, user.join("link")).unwrap();
+            let error = read_template_file(&dirs, "link").unwrap_err();
+            assert!(error.contains("out of the template directory"), "{error}");
+        }
+    }
+
+    #[test]
+    fn test_read_file_function() {
+        let root = tempfile::TempDir::new().unwrap();
+        fs::write(root.path().join("chunk"), "shared").unwrap();
+        let mut tera = Tera::default();
+        register_read_file(&mut tera, vec![root.path().to_path_buf()]);
+        tera.add_raw_template("t", "[{{ read_file(path=\"chunk\") }}]")
+            .unwrap();
+        assert_eq!(tera.render("t", &Context::new()).unwrap(), "[shared]");
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(split_words("fooBar"), vec!["foo", "Bar"]);
+        assert_eq!(split_words("HTTPServer"), vec!["HTTP", "Server"]);
+        assert_eq!(
+            split_words("my-file name.rs"),
+            vec!["my", "file", "name", "rs"]
+        );
+        assert_eq!(split_words("  __leading__ "), vec!["leading"]);
+        assert!(split_words("--").is_empty());
+    }
+
+    #[test]
+    fn test_digit_handling() {
+        assert_eq!(split_words("v2Api"), vec!["v2", "Api"]);
+        assert_eq!(split_words("item42"), vec!["item42"]);
+        assert_eq!(split_words("2fast 4you"), vec!["2fast", "4you"]);
+        assert_eq!(to_snake_case("Item42Name"), "item42_name");
+        assert_eq!(to_camel_case("version 2 update"), "version2Update");
+        assert_eq!(to_pascal_case("http2_client"), "Http2Client");
+    }
+
+    #[test]
+    fn test_case_conversions() {
+        let input = "Hello world-fooBar";
+        assert_eq!(to_snake_case(input), "hello_world_foo_bar");
+        assert_eq!(to_kebab_case(input), "hello-world-foo-bar");
+        assert_eq!(to_screaming_snake_case(input), "HELLO_WORLD_FOO_BAR");
+        assert_eq!(to_pascal_case(input), "HelloWorldFooBar");
+        assert_eq!(to_camel_case(input), "helloWorldFooBar");
+    }
+
+    #[test]
+    fn test_unicode_handling() {
+        assert_eq!(to_snake_case("Größe Maße"), "größe_maße");
+        assert_eq!(to_screaming_snake_case("straße"), "STRASSE");
+        assert_eq!(to_pascal_case("élan vital"), "ÉlanVital");
+        assert_eq!(to_camel_case("ÜberCool"), "überCool");
+        assert_eq!(to_kebab_case("日本語 テキスト"), "日本語-テキスト");
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("Meeting: Q3/Q4?"), "Meeting_ Q3_Q4_");
+        assert_eq!(sanitize_filename("notes. "), "notes");
+        assert_eq!(sanitize_filename("tab\there"), "tab_here");
+        assert_eq!(sanitize_filename("con.txt"), "con_.txt");
+        assert_eq!(sanitize_filename("Console.txt"), "Console.txt");
+        assert_eq!(sanitize_filename(".."), "_");
+        assert_eq!(sanitize_filename(""), "_");
+    }
+
+    #[test]
+    fn test_slugify_filename() {
+        assert_eq!(slugify_filename("My Great Idea!.md"), "my-great-idea.md");
+        assert_eq!(
+            slugify_filename("notes/2024 Plans & Goals.txt"),
+            "notes/2024-plans-goals.txt"
+        );
+        assert_eq!(slugify_filename("Größe Übersicht"), "grosse-ubersicht");
+        assert_eq!(slugify_filename(".Env Local"), ".env-local");
+        assert_eq!(slugify_filename("???.md"), "___.md");
+    }
+
+    #[test]
+    fn test_filters_are_registered() {
+        let mut tera = Tera::default();
+        register(&mut tera, Vec::new());
+
+        let mut context = Context::new();
+        context.insert("name", "user profile");
+        let rendered = tera
+            .render_str(
+                "{{ name | snake_case }} {{ name | camel_case }} {{ name | pascal_case }} \
+                 {{ name | kebab_case }} {{ name | screaming_snake_case }} {{ name | upper_camel }}",
+                &context,
+            )
+            .unwrap();
+        assert_eq!(
+            rendered,
+            "user_profile userProfile UserProfile user-profile USER_PROFILE UserProfile"
+        );
+    }
+
+    #[test]
+    fn test_filter_rejects_non_strings() {
+        let mut tera = Tera::default();
+        register(&mut tera, Vec::new());
+
+        let mut context = Context::new();
+        context.insert("n", &42);
+        assert!(tera.render_str("{{ n | snake_case }}", &context).is_err());
+    }
+
+    #[test]
+    fn test_format_date() {
+        let mut tera = Tera::default();
+        register(&mut tera, Vec::new());
+        let mut context = Context::new();
+        context.insert("ts", &1_700_000_000);
+        let expected = |fmt: &str| {
+            chrono::DateTime::from_timestamp(1_700_000_000, 0)
+                .unwrap()
+                .with_timezone(&chrono::Local)
+                .format(fmt)
+                .to_string()
+        };
+
+        assert_eq!(
+            tera.render_str(r#"{{ format_date(ts=ts, fmt="%Y-%m-%d") }}"#, &context)
+                .unwrap(),
+            expected("%Y-%m-%d")
+        );
+        assert_eq!(
+            tera.render_str("{{ format_date(ts=ts) }}", &context)
+                .unwrap(),
+            expected("%Y-%m-%d %H:%M:%S")
+        );
+        assert_eq!(
+            tera.render_str(
+                r#"{{ format_date(ts="2023-11-14T22:13:20Z", fmt="%s") }}"#,
+                &context
+            )
+            .unwrap(),
+            "1700000000"
+        );
+        assert!(
+            tera.render_str(r#"{{ format_date(ts="soon") }}"#, &context)
+                .is_err()
+        );
+        assert!(
+            tera.render_str(r#"{{ format_date(ts=ts, fmt="%Q") }}"#, &context)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_random_functions() {
+        let mut tera = Tera::default();
+        register(&mut tera, Vec::new());
+        let context = Context::new();
+
+        let uuid = tera.render_str("{{ uuid4() }}", &context).unwrap();
+        let parsed = uuid::Uuid::parse_str(&uuid).unwrap();
+        assert_eq!(parsed.get_version_num(), 4);
+        assert_ne!(uuid, tera.render_str("{{ uuid4() }}", &context).unwrap());
+
+        let hex = tera.render_str("{{ random_hex() }}", &context).unwrap();
+        assert_eq!(hex.len(), 16);
+        assert!(
+            hex.chars()
+                .all(|c| c.is_ascii_hexdigit() && !c.is_uppercase())
+        );
+        let hex = tera
+            .render_str("{{ random_hex(length=40) }}", &context)
+            .unwrap();
+        assert_eq!(hex.len(), 40);
+        assert!(
+            tera.render_str("{{ random_hex(length=-1) }}", &context)
+                .is_err()
+        );
+        assert!(
+            tera.render_str("{{ random_hex(length=100000000000) }}", &context)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_env_reads_variables_with_default() {
+        let mut tera = Tera::default();
+        register_env(&mut tera, true);
+        let context = Context::new();
+
+        let path = tera
+            .render_str(r#"{{ env(name="PATH") }}"#, &context)
+            .unwrap();
+        assert_eq!(path, std::env::var("PATH").unwrap());
+        let fallback = r#"{{ env(name="ZAP_TEST_SURELY_UNSET", default="none") }}"#;
+        assert_eq!(tera.render_str(fallback, &context).unwrap(), "none");
+        assert!(
+            tera.render_str(r#"{{ env(name="ZAP_TEST_SURELY_UNSET") }}"#, &context)
+                .is_err()
+        );
+        assert!(tera.render_str("{{ env(name=1) }}", &context).is_err());
+    }
+
+    #[test]
+    fn test_env_can_be_disabled() {
+        let mut tera = Tera::default();
+        register_env(&mut tera, false);
+        let context = Context::new();
+
+        assert!(
+            tera.render_str(r#"{{ env(name="PATH") }}"#, &context)
+                .is_err()
+        );
+        assert!(
+            tera.render_str(r#"{{ get_env(name="PATH") }}"#, &context)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_now_accepts_tera_arguments() {
+        let mut tera = Tera::default();
+        register(&mut tera, Vec::new());
+        let context = Context::new();
+
+        let timestamp = tera
+            .render_str("{{ now(timestamp=true) }}", &context)
+            .unwrap();
+        assert!(timestamp.parse::<i64>().is_ok());
+        let utc = tera.render_str("{{ now(utc=true) }}", &context).unwrap();
+        assert!(utc.ends_with("+00:00"));
+        assert!(tera.render_str("{{ now(utc=1) }}", &context).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use std::env;
+use std::sync::{PoisonError, RwLock};
+
+use crate::args::Dst;
+use crate::errors::ZapError;
+
+/// Where zap takes "now" from: the system clock, or a fixed instant for
+/// reproducible output (`--now`, or `SOURCE_DATE_EPOCH` in build environments).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    System,
+    Fixed(DateTime<Utc>),
+}
+
+static CLOCK: RwLock<Clock> = RwLock::new(Clock::System);
+
+impl Clock {
+    /// The clock selected by `--now` if given, otherwise by `SOURCE_DATE_EPOCH`.
+    /// A `--now` without an offset is in `tz` (from `--tz`), or else the local zone,
+    /// with `dst` settling daylight saving changes.
+    pub fn resolve(now_override: Option<&str>, tz: Option<Tz>, dst: Dst) -> Result<Self, ZapError> {
+        if let Some(now) = now_override {
+            let parsed = crate::parsedate::parse_d_format(now, tz, dst).map_err(|e| {
+                ZapError::InvalidNow {
+                    input: now.to_string(),
+                    reason: e.to_string(),
+                }
+            })?;
+            return Ok(Clock::Fixed(parsed));
+        }
+        match env::var("SOURCE_DATE_EPOCH") {
+            Ok(epoch) if !epoch.trim().is_empty() => Self::from_source_date_epoch(&epoch),
+            _ => Ok(Clock::System),
+        }
+    }
+
+    /// Parse a `SOURCE_DATE_EPOCH` value (seconds since the Unix epoch).
+    pub fn from_source_date_epoch(epoch: &str) -> Result<Self, ZapError> {
+        let invalid = |reason: &str| ZapError::InvalidNow {
+            input: format!("SOURCE_DATE_EPOCH={epoch}"),
+            reason: reason.to_string(),
+        };
+        let seconds: i64 = epoch
+            .trim()
+            .parse()
+            .map_err(|_| invalid("expected a number of seconds since 1970-01-01"))?;
+        DateTime::from_timestamp(seconds, 0)
+            .map(Clock::Fixed)
+            .ok_or_else(|| invalid("out of range"))
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Fixed(instant) => *instant,
+        }
+    }
+
+    /// Make this the clock until another one is installed, like by the next run.
+    pub fn install(self) {
+        *CLOCK.write().unwrap_or_else(PoisonError::into_inner) = self;
+    }
+}
+
+/// The current time according to the installed clock (the system clock by default).
+pub fn now() -> DateTime<Utc> {
+    CLOCK.read().unwrap_or_else(PoisonError::into_inner).now()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_date_epoch() {
+        let clock = Clock::from_source_date_epoch("1700000000").unwrap();
+        assert_eq!(clock.now().timestamp(), 1_700_000_000);
+        assert!(Clock::from_source_date_epoch("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_now_override_wins() {
+        let clock = Clock::resolve(Some("2024-03-01T12:00:00Z"), None, Dst::Error).unwrap();
+        assert_eq!(clock.now().to_rfc3339(), "2024-03-01T12:00:00+00:00");
+        assert!(Clock::resolve(Some("not a date"), None, Dst::Error).is_err());
+    }
+}
@@ -0,0 +1,639 @@
+use anyhow::Result;
+use chrono_tz::Tz;
+use dialoguer::{Confirm, FuzzySelect};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use tera::Tera;
+
+use crate::args::{Command, ConfigCommand, PluginCommand, TemplateCommand};
+use crate::builtins;
+use crate::config::{CONFIG_TEMPLATE, Config};
+use crate::context;
+use crate::errors::ZapError;
+use crate::file_time_util::{FileTimeSpec, TimeFormat};
+use crate::fileaction::{Action, open_in_editor};
+use crate::fileops::RealFs;
+use crate::frontmatter::{self, TemplateMeta};
+use crate::journal::{JOURNAL_FILE, Journal};
+use crate::packs::{self, LockedPack, Lockfile};
+use crate::plugin_index::{self, IndexedPlugin, PluginIndex};
+use crate::plugins::Plugins;
+use crate::render::{BINARY_SUFFIX, Renderer, is_partial, template_files};
+use crate::sandbox;
+use crate::scaffold;
+use crate::timings::Timings;
+use crate::{
+    get_config_dir, get_data_dir, get_packs_dir, get_plugins_dir, get_template_path,
+    get_templates_dir, template_search_path,
+};
+
+/// Print the times of each file for `--show`, one line per file, without changing them.
+pub fn show_times(
+    filenames: &[String],
+    format: &TimeFormat,
+    symlink_only: bool,
+    tz: Option<Tz>,
+) -> Result<()> {
+    for filename in filenames {
+        let metadata = if symlink_only {
+            fs::symlink_metadata(filename)
+        } else {
+            fs::metadata(filename)
+        }
+        .map_err(|source| ZapError::ShowTimes {
+            path: filename.clone(),
+            source,
+        })?;
+        let times = FileTimeSpec::from_metadata(&metadata);
+        let mut line = filename.clone();
+        for (label, time) in [
+            ("atime", times.atime),
+            ("mtime", times.mtime),
+            ("btime", times.btime),
+        ] {
+            if let Some(time) = time {
+                line.push_str(&format!("  {label}={}", format.format(time, tz)?));
+            }
+        }
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Restore the times recorded in the undo journal for `--undo`. The journal is
+/// removed afterwards, so a second undo doesn't undo anything.
+pub fn undo() -> Result<()> {
+    let file = get_config_dir()?.join(JOURNAL_FILE);
+    let restored = Journal::load(&file)?.restore(&mut RealFs::new());
+    fs::remove_file(&file)?;
+    println!("Restored the times of {} file(s)", restored.len());
+    Ok(())
+}
+
+/// Run a subcommand instead of the default touch behavior.
+pub fn run(command: &Command) -> Result<(), anyhow::Error> {
+    match command {
+        Command::Template { action } => match action {
+            TemplateCommand::Which { name } => template_which(name),
+            TemplateCommand::Edit { name } => template_edit(name),
+            TemplateCommand::List => list_templates(),
+            TemplateCommand::Check { name, context } => template_check(name, context.as_deref()),
+            TemplateCommand::Install { source, name, rev } => {
+                pack_install(source.as_deref(), name.as_deref(), rev.as_deref())
+            }
+            TemplateCommand::Update { name } => pack_update(name.as_deref()),
+            TemplateCommand::Remove { name } => pack_remove(name),
+        },
+        Command::Config { action } => match action {
+            ConfigCommand::Init { force } => config_init(*force),
+            ConfigCommand::Show => config_show(),
+            ConfigCommand::Edit => config_edit(),
+        },
+        Command::Plugin { action } => match action {
+            PluginCommand::Install {
+                source,
+                file,
+                version,
+                force,
+            } => plugin_install(source, file.as_deref(), version.as_deref(), *force),
+            PluginCommand::List => plugin_list(),
+        },
+        Command::PluginHost { path } => sandbox::serve(path),
+    }
+}
+
+/// Print the path a template name resolves to, followed by every location
+/// that was searched (in resolution order), similar to `which -a`.
+fn template_which(name: &str) -> Result<(), anyhow::Error> {
+    let cwd = env::current_dir()?;
+    let candidates: Vec<_> = template_search_path(Some(&cwd))?
+        .into_iter()
+        .map(|location| (location.layer, location.dir.join(name)))
+        .collect();
+
+    let Some(selected) = candidates.iter().position(|(_, path)| path.exists()) else {
+        return Err(ZapError::TemplateNotFound {
+            name: name.to_string(),
+            searched: candidates.into_iter().map(|(_, path)| path).collect(),
+        }
+        .into());
+    };
+
+    println!("{}", candidates[selected].1.display());
+    println!("Searched locations:");
+    for (index, (layer, path)) in candidates.iter().enumerate() {
+        let status = if index == selected {
+            "selected"
+        } else if path.exists() {
+            "found, shadowed"
+        } else {
+            "missing"
+        };
+        println!("  {layer}: {} ({status})", path.display());
+    }
+
+    Ok(())
+}
+
+/// Print every template on the search path, grouped by location, with the
+/// description, variables and required variables from its frontmatter.
+/// Templates hidden by one of the same name in an earlier location are marked
+/// as shadowed. Partials (files starting with `_`, like the macro library) aren't listed.
+pub fn list_templates() -> Result<(), anyhow::Error> {
+    let mut seen = HashSet::new();
+    for location in template_search_path(Some(&env::current_dir()?))? {
+        let names: Vec<String> = template_files(&location.dir)?
+            .into_iter()
+            .filter(|name| !is_partial(name))
+            .collect();
+        if names.is_empty() {
+            continue;
+        }
+
+        println!("{} ({}):", location.layer, location.dir.display());
+        for name in names {
+            let path = location.dir.join(&name);
+            let meta = template_meta(&path)?;
+
+            let shadowed = if seen.insert(name.clone()) {
+                ""
+            } else {
+                " (shadowed)"
+            };
+            match meta.description {
+                Some(description) => println!("  {name}{shadowed}  {description}"),
+                None => println!("  {name}{shadowed}"),
+            }
+            if !meta.variables.is_empty() {
+                println!("    variables: {}", meta.variables.join(", "));
+            }
+            if !meta.required.is_empty() {
+                println!("    required: {}", meta.required.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn lockfile_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join(packs::LOCKFILE_NAME))
+}
+
+/// Install the pack from `source`, or, without one, every pack in the lockfile
+/// that isn't installed yet, at the revision recorded there.
+fn pack_install(source: Option<&str>, name: Option<&str>, rev: Option<&str>) -> Result<()> {
+    let packs_dir = get_packs_dir()?;
+    let lock_path = lockfile_path()?;
+    let mut lock = Lockfile::load(&lock_path)?;
+
+    let Some(source) = source else {
+        for pack in &lock.packs {
+            if packs_dir.join(&pack.name).exists() {
+                continue;
+            }
+            packs::fetch(&packs_dir, &pack.name, &pack.source, pack.rev.as_deref())?;
+            println!(
+                "Installed {} ({})",
+                pack.name,
+                packs::short_rev(pack.rev.as_deref())
+            );
+        }
+        return Ok(());
+    };
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => packs::default_name(source)?,
+    };
+    if lock.get(&name).is_some() || packs_dir.join(&name).exists() {
+        return Err(ZapError::PackExists(name).into());
+    }
+    let locked = packs::fetch(&packs_dir, &name, source, rev)?;
+    println!(
+        "Installed {name} ({}), use its templates as -T {name}/...",
+        packs::short_rev(locked.rev.as_deref())
+    );
+    lock.set(locked);
+    lock.save(&lock_path)?;
+    Ok(())
+}
+
+/// Fetch the latest version of the pack `name`, or of every pack, from where
+/// it was installed from, and record the new revisions.
+fn pack_update(name: Option<&str>) -> Result<()> {
+    let packs_dir = get_packs_dir()?;
+    let lock_path = lockfile_path()?;
+    let mut lock = Lockfile::load(&lock_path)?;
+
+    let packs: Vec<LockedPack> = match name {
+        Some(name) => vec![
+            lock.get(name)
+                .cloned()
+                .ok_or_else(|| ZapError::PackNotInstalled(name.to_string()))?,
+        ],
+        None => lock.packs.clone(),
+    };
+    for pack in packs {
+        let updated = packs::fetch(&packs_dir, &pack.name, &pack.source, None)?;
+        let (old, new) = (
+            packs::short_rev(pack.rev.as_deref()),
+            packs::short_rev(updated.rev.as_deref()),
+        );
+        if pack.rev.is_some() && pack.rev == updated.rev {
+            println!("{} is up to date ({new})", pack.name);
+        } else {
+            println!("Updated {} ({old} -> {new})", pack.name);
+        }
+        lock.set(updated);
+    }
+    lock.save(&lock_path)?;
+    Ok(())
+}
+
+fn pack_remove(name: &str) -> Result<()> {
+    let dir = get_packs_dir()?.join(name);
+    let lock_path = lockfile_path()?;
+    let mut lock = Lockfile::load(&lock_path)?;
+
+    let locked = lock.remove(name).is_some();
+    if !locked && !dir.is_dir() {
+        return Err(ZapError::PackNotInstalled(name.to_string()).into());
+    }
+    if dir.is_dir() {
+        fs::remove_dir_all(&dir)?;
+    }
+    lock.save(&lock_path)?;
+    println!("Removed {name}");
+    Ok(())
+}
+
+/// Install the plugin from `source` and record it in the plugin index.
+fn plugin_install(
+    source: &str,
+    file: Option<&str>,
+    version: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    let plugins_dir = get_plugins_dir()?;
+    let file = match file {
+        Some(file) => file.to_string(),
+        None => plugin_index::default_file_name(source)?,
+    };
+    if plugins_dir.join(&file).exists() && !force {
+        return Err(ZapError::PluginExists(file).into());
+    }
+    fs::create_dir_all(&plugins_dir)?;
+    // Relative paths are recorded as given, so make sure they still resolve later
+    let source = match Path::new(source).canonicalize() {
+        Ok(path) if !source.contains("://") => path.to_string_lossy().into_owned(),
+        _ => source.to_string(),
+    };
+    let info = plugin_index::install(&plugins_dir, &source, &file)?;
+
+    let index_path = plugins_dir.join(plugin_index::INDEX_NAME);
+    let mut index = PluginIndex::load(&index_path)?;
+    let name = match &info {
+        Some(info) => info.name.clone(),
+        None => Path::new(&file)
+            .file_stem()
+            .map_or(file.clone(), |stem| stem.to_string_lossy().into_owned()),
+    };
+    index.set(IndexedPlugin {
+        name: name.clone(),
+        file: file.clone(),
+        source,
+        version: version
+            .map(String::from)
+            .or_else(|| info.and_then(|info| info.version)),
+    });
+    index.save(&index_path)?;
+    println!("Installed {name} as {}", plugins_dir.join(&file).display());
+    Ok(())
+}
+
+/// List the plugins in the plugins directory, with what they say about
+/// themselves and, for installed ones, where they came from.
+fn plugin_list() -> Result<()> {
+    let plugins_dir = get_plugins_dir()?;
+    let paths = Plugins::plugin_paths(&plugins_dir)?;
+    if paths.is_empty() {
+        println!("No plugins in {}", plugins_dir.display());
+        return Ok(());
+    }
+    let plugins_config = Config::load()?.plugins;
+    let index = PluginIndex::load(&plugins_dir.join(plugin_index::INDEX_NAME))?;
+
+    let mut plugins = Plugins::new();
+    let mut tera = Tera::default();
+    for path in paths {
+        let file = path.file_name().unwrap_or_default().to_string_lossy();
+        let indexed = index.plugins.iter().find(|plugin| plugin.file == file);
+        if let Some(reason) = plugins_config.skip_reason(&path) {
+            println!("{file}: not loaded ({reason})");
+            continue;
+        }
+        if let Err(e) = plugins.load_plugin(&mut tera, &path) {
+            println!("{file}: failed to load ({e})");
+            continue;
+        }
+
+        let info = plugins
+            .loaded()
+            .last()
+            .and_then(|plugin| plugin.info.clone());
+        let version = info
+            .as_ref()
+            .and_then(|info| info.version.clone())
+            .or_else(|| indexed.and_then(|plugin| plugin.version.clone()));
+        let mut line = format!("{file}:");
+        if let Some(info) = &info {
+            line.push_str(&format!(" {}", info.name));
+        }
+        if let Some(version) = version {
+            line.push_str(&format!(" {version}"));
+        }
+        if let Some(author) = info.as_ref().and_then(|info| info.author.as_deref()) {
+            line.push_str(&format!(" by {author}"));
+        }
+        println!("{line}");
+        if let Some(description) = info.as_ref().and_then(|info| info.description.as_deref()) {
+            println!("  {description}");
+        }
+        if let Some(plugin) = indexed {
+            println!("  installed from {}", plugin.source);
+        }
+    }
+    Ok(())
+}
+
+/// The frontmatter of the template at `path`; binary templates have none.
+fn template_meta(path: &Path) -> Result<TemplateMeta> {
+    let bytes = fs::read(path)?;
+    match String::from_utf8(bytes) {
+        Ok(source) if !path.to_string_lossy().ends_with(BINARY_SUFFIX) => {
+            Ok(frontmatter::split(path, &source)?.0)
+        }
+        _ => Ok(TemplateMeta::default()),
+    }
+}
+
+/// Let the user choose one of the available templates by typing part of its
+/// name or description. Shadowed templates and partials aren't offered.
+pub fn pick_template() -> Result<String, anyhow::Error> {
+    if !std::io::stdin().is_terminal() {
+        return Err(ZapError::NoTerminalForPicker.into());
+    }
+    let mut names = Vec::new();
+    let mut items = Vec::new();
+    for location in template_search_path(Some(&env::current_dir()?))? {
+        for name in template_files(&location.dir)? {
+            if is_partial(&name) || names.contains(&name) {
+                continue;
+            }
+            let path = location.dir.join(&name);
+            let meta = template_meta(&path)?;
+            items.push(match meta.description {
+                Some(description) => format!("{name}  {description}"),
+                None => name.clone(),
+            });
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        return Err(ZapError::NoTemplates.into());
+    }
+
+    let index = FuzzySelect::new()
+        .with_prompt("Template")
+        .items(&items)
+        .default(0)
+        .interact()?;
+    Ok(names.swap_remove(index))
+}
+
+/// Render a template without writing anything, as if for a file named like the
+/// template in the working directory. Every file of a scaffold is checked, and
+/// each one is reported, so one run lists all the problems.
+fn template_check(name: &str, context_str: Option<&str>) -> Result<(), anyhow::Error> {
+    let cwd = env::current_dir()?;
+    let config = Config::load()?;
+    let context = context::build_context(config.context_table(), context_str, None)?;
+    let target = cwd.join(name.rsplit('/').next().unwrap_or(name));
+
+    let (context, files) = match scaffold::find_scaffold(&RealFs::new(), name, Some(&cwd)) {
+        Some(dir) => (
+            scaffold::scaffold_context(&target, &context),
+            scaffold::scaffold_files(&dir)?
+                .into_iter()
+                .map(Some)
+                .collect(),
+        ),
+        None => (context, vec![None]),
+    };
+
+    let mut renderer = Renderer::new();
+    renderer.plugins_config = config.plugins;
+    renderer.macros_file = config.macros;
+    let mut timings = Timings::new(false);
+    let mut failed = 0;
+    for file in &files {
+        let template = file
+            .as_ref()
+            .map_or(name.to_string(), |file| format!("{name}/{file}"));
+        let result = match file {
+            Some(file) => scaffold::output_path(name, file, &context)
+                .map(|relative| target.join(relative))
+                .map_err(anyhow::Error::from),
+            None => Ok(target.clone()),
+        }
+        .and_then(|path| {
+            Action::template_contents(
+                &mut renderer,
+                &path,
+                &template,
+                &context,
+                None,
+                false,
+                &mut timings,
+            )
+        });
+
+        match result {
+            Ok(_) => println!("{template}: ok"),
+            Err(e) => {
+                failed += 1;
+                println!("{template}: {e}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(ZapError::TemplateCheckFailed {
+            failed,
+            checked: files.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Open a template in the editor. Templates that can't be found anywhere on the
+/// search path are created in the user's template directory after confirmation.
+fn template_edit(name: &str) -> Result<(), anyhow::Error> {
+    let path = match get_template_path(name, Some(&env::current_dir()?)) {
+        Ok(path) => path,
+        Err(ZapError::TemplateNotFound { .. }) => {
+            let path = get_templates_dir()?.join(name);
+            let confirmation = Confirm::new()
+                .with_prompt(format!(
+                    "Template '{name}' doesn't exist. Create it at {}?",
+                    path.display()
+                ))
+                .default(true)
+                .interact()?;
+            if !confirmation {
+                return Err(ZapError::UserDeclinedTemplateCreation.into());
+            }
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&path, template_stub(name))?;
+            path
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    open_in_editor(
+        &Config::load()?.editor()?,
+        &vec![path.to_string_lossy().into_owned()],
+    )
+}
+
+/// Write the commented config template to `config.toml`.
+fn config_init(force: bool) -> Result<(), anyhow::Error> {
+    let path = Config::path()?;
+    if path.exists() && !force {
+        return Err(ZapError::ConfigExists { path }.into());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, CONFIG_TEMPLATE)?;
+    println!("Created {}", path.display());
+    Ok(())
+}
+
+/// Print where zap looks for things, followed by the effective settings as TOML.
+fn config_show() -> Result<(), anyhow::Error> {
+    let path = Config::path()?;
+    let config = Config::load()?;
+
+    let status = if path.exists() { "" } else { " (missing)" };
+    println!("# config file: {}{status}", path.display());
+    println!("# data dir: {}", get_data_dir()?.display());
+    print!("{}", config.effective().to_toml());
+    Ok(())
+}
+
+/// Open `config.toml` in the editor, creating it from the template after confirmation.
+fn config_edit() -> Result<(), anyhow::Error> {
+    let path = Config::path()?;
+    let config = Config::load()?;
+    if !path.exists() {
+        let confirmation = Confirm::new()
+            .with_prompt(format!("{} doesn't exist. Create it?", path.display()))
+            .default(true)
+            .interact()?;
+        if !confirmation {
+            return Ok(());
+        }
+        config_init(false)?;
+    }
+
+    open_in_editor(
+        &config.editor()?,
+        &vec![path.to_string_lossy().into_owned()],
+    )
+}
+
+fn template_stub(name: &str) -> String {
+    format!(
+        "{{# Template '{name}', rendered with Tera (https://keats.github.io/tera/docs/).\n   \
+         Values passed with -C/--context are available as variables, e.g. {{{{ title }}}}. #}}\n"
+    )
+}
+
+/// Print every function, filter and tester templates can use, grouped by provider.
+/// Tera can't list its own, so those are left to its documentation.
+pub fn list_functions() -> Result<(), anyhow::Error> {
+    let mut tera = Tera::default();
+
+    println!("tera (built-in):");
+    println!("  see https://keats.github.io/tera/docs/#built-ins");
+
+    let registered = builtins::register(&mut tera, Vec::new());
+    println!("zap (built-in):");
+    print_names("function", registered.functions.iter());
+    print_names("filter", registered.filters.iter());
+    print_names("tester", registered.testers.iter());
+
+    let mut plugins = Plugins::new();
+    let plugins_dir = get_plugins_dir()?;
+    let plugins_config = Config::load()?.plugins;
+    for path in Plugins::plugin_paths(&plugins_dir)? {
+        if let Some(reason) = plugins_config.skip_reason(&path) {
+            println!("{}:\n  (not loaded: {reason})", path.display());
+            continue;
+        }
+        if let Err(e) = plugins.load_plugin(&mut tera, &path) {
+            println!("{}:\n  (not loaded: {e})", path.display());
+            continue;
+        }
+        let Some(plugin) = plugins.loaded().last() else {
+            continue;
+        };
+
+        match plugin.info {
+            Some(_) => println!("{} ({}):", plugin.label(), path.display()),
+            None => println!("{}:", path.display()),
+        }
+        for (kind, names) in [
+            ("function", &plugin.functions),
+            ("filter", &plugin.filters),
+            ("tester", &plugin.testers),
+        ] {
+            match names {
+                Some(names) => print_names(kind, names.iter()),
+                None => println!("  ({kind}s not declared by plugin)"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_names<'a>(kind: &str, names: impl Iterator<Item = &'a String>) {
+    for name in names {
+        println!("  {kind:<8} {name}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_stub_renders_to_nothing() {
+        let stub = template_stub("note");
+
+        assert!(stub.contains("Template 'note'"));
+        assert_eq!(
+            Tera::one_off(&stub, &tera::Context::new(), false).unwrap(),
+            "\n"
+        );
+    }
+}
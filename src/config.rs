@@ -0,0 +1,539 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tera::Context;
+
+use crate::args::ZapCli;
+use crate::dirtemplates::DirTemplates;
+use crate::errors::ZapError;
+use crate::get_config_dir;
+use crate::notify::NotifyConfig;
+use crate::plugins::PluginsConfig;
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// What `zap config init` writes: every setting, commented out.
+pub const CONFIG_TEMPLATE: &str = r#"# zap configuration, see https://github.com/kolja/zap#usage
+# Every setting is optional. Flags given on the command line take precedence.
+
+# Template applied to new files when no -T is given (ZAP_DEFAULT_TEMPLATE wins over it)
+# default_template = "header"
+
+# Templates for new files by their extension, preferred over default_template
+# auto_template = { rs = "rust-file", md = "note" }
+
+# Always create missing parent directories, like -p
+# create_intermediate_dirs = true
+
+# Always open the files in the editor, like --open
+# open = true
+
+# Editor command used instead of $EDITOR
+# editor = "code --wait"
+
+# Keep templates from reading environment variables, like --no-env
+# no_env = true
+
+# Macro library imported into every template
+# macros = "_macros.tera"
+
+# Shorthands for flags: `zap +note today.md`
+# alias.note = "-T note -p -o"
+
+# Context passed to every template; -C overrides it per key. Instead of a table,
+# it can also be a string of pairs the way -C takes them, "author=Jane,license=MIT"
+# [context]
+# author = "Jane"
+# company = { name = "ACME", founded = 1949 }
+
+# Templates for new files by path, relative to the current directory; the first match wins
+# and they're preferred over auto_template. Patterns without a "/" match the file name.
+# [[rule]]
+# path = "src/**/*.rs"
+# template = "rust-module"
+
+# Settings bundled under a name, used with `zap --profile work`
+# [profile.work]
+# template = "memo"
+# context = "team=Platform"
+# modification_time = true
+
+# Tell a command (JSON on stdin) or a webhook (JSON POST) about every run
+# [notify]
+# command = "notify-send zap"
+# webhook = "https://example.com/hooks/zap"
+
+# Plugins to load by file name: only the allowed ones (when given), never the denied ones
+# [plugins]
+# allow = ["libzap_shout.so"]
+# deny = ["libuntrusted.so"]
+# Skip plugins that fail to load instead of failing the run, like --lenient-plugins
+# lenient = true
+# Projects whose .zap/plugins are loaded without asking first
+# trusted = ["/home/jane/code/website"]
+# Load native plugins into a separate process, so a crashing plugin can't take zap down
+# sandbox = true
+"#;
+
+/// Settings read from `config.toml` in the zap config directory.
+/// Every setting is optional; a missing file means all defaults.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    /// Template applied to newly created files when no `-T` is given.
+    pub default_template: Option<String>,
+    /// Templates for new files by extension, e.g. `{ rs = "rust-file" }`; they win over `default_template`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub auto_template: BTreeMap<String, String>,
+    /// Templates for new files by path pattern, e.g. `src/**/*.rs`; they win over `auto_template`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rule: Vec<TemplateRule>,
+    /// Always create missing parent directories, like `-p`.
+    pub create_intermediate_dirs: bool,
+    /// Context passed to every template, as pairs (`foo=bar,baz=qux`) or a `[context]`
+    /// table; `-C` overrides it per key.
+    pub context: Option<ConfigContext>,
+    /// Always open the files in the editor, like `--open`.
+    pub open: bool,
+    /// Editor command used instead of `$EDITOR`.
+    pub editor: Option<String>,
+    /// Keep templates from reading environment variables, like `--no-env`.
+    pub no_env: bool,
+    /// Macro library imported into every template (defaults to `_macros.tera`).
+    pub macros: Option<String>,
+    /// Hooks that are told about every run.
+    #[serde(skip_serializing_if = "notify_unset")]
+    pub notify: NotifyConfig,
+    /// Which plugins may be loaded.
+    #[serde(skip_serializing_if = "PluginsConfig::is_unset")]
+    pub plugins: PluginsConfig,
+    /// Named bundles of settings, selected with `--profile <name>`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub profile: BTreeMap<String, Profile>,
+    /// Shorthands for flags: `alias.note = "-T note -p"` makes `+note` expand to `-T note -p`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub alias: BTreeMap<String, String>,
+}
+
+/// The `context` setting: the pairs `-C` takes, or a `[context]` table, whose
+/// values can be numbers, lists or nested tables as well as strings.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ConfigContext {
+    Pairs(String),
+    Table(BTreeMap<String, serde_json::Value>),
+}
+
+/// A `[[rule]]` entry: new files whose path matches `path` get `template`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TemplateRule {
+    pub path: String,
+    pub template: String,
+}
+
+/// A `[profile.<name>]` section. Its settings sit between the top-level
+/// config and the command line: they override the former, flags override them.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Profile {
+    pub template: Option<String>,
+    pub context: Option<String>,
+    pub create_intermediate_dirs: bool,
+    /// Like `-a`
+    pub access_time: bool,
+    /// Like `-m`
+    pub modification_time: bool,
+    /// Like `-c`
+    pub no_create: bool,
+    /// Like `-A`, which implies `-c`
+    pub adjust: Option<String>,
+    /// Like `-d`, ignored when `-t` or `-r` is given
+    pub date: Option<String>,
+}
+
+fn notify_unset(notify: &NotifyConfig) -> bool {
+    !notify.is_configured()
+}
+
+/// Stack context pairs so that later ones override earlier ones key by key.
+fn layer_context(defaults: Option<&str>, given: Option<&str>) -> Option<String> {
+    match (defaults, given) {
+        (Some(defaults), Some(given)) => Some(format!("{defaults},{given}")),
+        (defaults, given) => given.or(defaults).map(String::from),
+    }
+}
+
+impl Config {
+    /// Where `config.toml` is read from.
+    pub fn path() -> Result<PathBuf, ZapError> {
+        Ok(get_config_dir()?.join(CONFIG_FILE))
+    }
+
+    /// Load `config.toml` from the zap config directory.
+    pub fn load() -> Result<Self, ZapError> {
+        Self::from_file(&Self::path()?)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ZapError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| ZapError::InvalidConfig {
+                path: path.to_path_buf(),
+                reason: e.message().to_string(),
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fill in the defaults from the config file, and from the profile selected
+    /// with `--profile`, for everything not given on the command line.
+    /// Flags they switch on can be switched off again with their `--no-...` (or
+    /// `--allow-env`, `--strict-plugins`) counterpart; context pairs from the command
+    /// line override those from the profile and config file key by key.
+    pub fn apply_to(&self, cli: &ZapCli) -> Result<ZapCli, ZapError> {
+        let profile = match &cli.profile {
+            Some(name) => {
+                self.profile
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| ZapError::UnknownProfile {
+                        name: name.clone(),
+                        available: self.profile.keys().cloned().collect::<Vec<_>>().join(", "),
+                    })?
+            }
+            None => Profile::default(),
+        };
+
+        let mut merged = cli.clone();
+        if !cli.no_create_intermediate_dirs {
+            merged.create_intermediate_dirs |=
+                self.create_intermediate_dirs || profile.create_intermediate_dirs;
+        }
+        if !cli.no_open {
+            merged.open |= self.open;
+        }
+        if !cli.allow_env {
+            merged.no_env |= self.no_env;
+        }
+        if !cli.strict_plugins {
+            merged.lenient_plugins |= self.plugins.lenient;
+        }
+        merged.access_time |= profile.access_time;
+        merged.modification_time |= profile.modification_time;
+        // A profile's -A would shift the times a second time after --offset
+        let profile_adjust = profile.adjust.filter(|_| cli.offset.is_none());
+        merged.no_create |= profile.no_create || profile_adjust.is_some();
+        merged.context = layer_context(
+            layer_context(self.context_pairs(), profile.context.as_deref()).as_deref(),
+            cli.context.as_deref(),
+        );
+        if merged.template.is_none() && !cli.no_template {
+            merged.template = profile.template;
+        }
+        if merged.adjust.is_none() {
+            merged.adjust = profile_adjust;
+        }
+        if cli.date.is_none()
+            && cli.timestamp.is_none()
+            && cli.unix.is_none()
+            && cli.reference.is_none()
+        {
+            merged.date = profile.date;
+        }
+        Ok(merged)
+    }
+
+    /// The `context` setting when it's given as pairs; they're stacked with those
+    /// of the profile and the command line.
+    fn context_pairs(&self) -> Option<&str> {
+        match &self.context {
+            Some(ConfigContext::Pairs(pairs)) => Some(pairs),
+            _ => None,
+        }
+    }
+
+    /// The `[context]` table, beneath every other context of a run.
+    pub fn context_table(&self) -> Context {
+        let mut context = Context::new();
+        if let Some(ConfigContext::Table(table)) = &self.context {
+            for (key, value) in table {
+                context.insert(key, value);
+            }
+        }
+        context
+    }
+
+    /// The editor command: `editor` from the config file, otherwise `$EDITOR`.
+    pub fn editor(&self) -> Result<String, ZapError> {
+        match &self.editor {
+            Some(editor) => Ok(editor.clone()),
+            None => env::var("EDITOR").map_err(|_| ZapError::EditorNotSet),
+        }
+    }
+
+    /// The settings zap actually uses, with environment variables applied.
+    pub fn effective(&self) -> Self {
+        Self {
+            default_template: self.default_template(),
+            editor: self.editor().ok(),
+            ..self.clone()
+        }
+    }
+
+    /// The settings as TOML, in the format of `config.toml`.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("config is always serializable")
+    }
+
+    /// The `[[rule]]` list, for paths relative to `dir`.
+    pub fn rules(&self, dir: &Path) -> Result<DirTemplates, ZapError> {
+        DirTemplates::from_rules(
+            &Self::path()?,
+            dir,
+            self.rule
+                .iter()
+                .map(|rule| (rule.path.clone(), rule.template.clone())),
+        )
+    }
+
+    /// The default template, with `ZAP_DEFAULT_TEMPLATE` taking precedence over the config file.
+    pub fn default_template(&self) -> Option<String> {
+        env::var("ZAP_DEFAULT_TEMPLATE")
+            .ok()
+            .filter(|name| !name.is_empty())
+            .or_else(|| self.default_template.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_missing_config_file_uses_defaults() {
+        let dir = TempDir::new().unwrap();
+        let config = Config::from_file(&dir.path().join("config.toml")).unwrap();
+        assert!(config.default_template.is_none());
+    }
+
+    #[test]
+    fn test_reads_default_template() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "default_template = \"header\"\n").unwrap();
+
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.default_template.as_deref(), Some("header"));
+    }
+
+    #[test]
+    fn test_reads_path_rules() {
+        let config: Config = toml::from_str(
+            "[[rule]]\npath = \"src/**/*.rs\"\ntemplate = \"rust-module\"\n\n[[rule]]\npath = \"tests/**\"\ntemplate = \"rust-test\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.rule.len(), 2);
+        assert_eq!(config.rule[1].template, "rust-test");
+
+        let rules = config.rules(Path::new("/work")).unwrap();
+        assert_eq!(
+            rules.template_for(Path::new("/work/src/a/b.rs")),
+            Some("rust-module")
+        );
+    }
+
+    #[test]
+    fn test_cli_overrides_config_defaults() {
+        let config: Config = toml::from_str(
+            "create_intermediate_dirs = true\ncontext = \"author=Jane,license=MIT\"\n",
+        )
+        .unwrap();
+        let cli = ZapCli {
+            context: Some("license=GPL".to_string()),
+            ..Default::default()
+        };
+
+        let merged = config.apply_to(&cli).unwrap();
+        assert!(merged.create_intermediate_dirs);
+        assert!(!merged.open);
+
+        let context = crate::context::parse_context_str(merged.context.as_deref().unwrap());
+        assert_eq!(context.get("author").unwrap(), "Jane");
+        assert_eq!(context.get("license").unwrap(), "GPL");
+    }
+
+    #[test]
+    fn test_cli_switches_off_config_flags() {
+        let config: Config = toml::from_str(
+            "create_intermediate_dirs = true\nopen = true\nno_env = true\n[plugins]\nlenient = true\n",
+        )
+        .unwrap();
+        let merged = config.apply_to(&ZapCli::default()).unwrap();
+        assert!(merged.create_intermediate_dirs && merged.open);
+        assert!(merged.no_env && merged.lenient_plugins);
+
+        let cli = ZapCli::parse_args(&[
+            "zap",
+            "--no-create-intermediate-dirs",
+            "--no-open",
+            "--allow-env",
+            "--strict-plugins",
+            "a.txt",
+        ])
+        .unwrap();
+        let merged = config.apply_to(&cli).unwrap();
+        assert!(!merged.create_intermediate_dirs && !merged.open);
+        assert!(!merged.no_env && !merged.lenient_plugins);
+
+        // On the command line, the last of a flag and its counterpart wins
+        let cli = ZapCli::parse_args(&[
+            "zap",
+            "--no-open",
+            "-o",
+            "-p",
+            "--no-create-intermediate-dirs",
+            "a.txt",
+        ])
+        .unwrap();
+        assert!(cli.open && !cli.no_open);
+        assert!(!cli.create_intermediate_dirs && cli.no_create_intermediate_dirs);
+    }
+
+    #[test]
+    fn test_config_context_used_without_cli_context() {
+        let config: Config = toml::from_str("context = \"author=Jane\"").unwrap();
+        let merged = config.apply_to(&ZapCli::default()).unwrap();
+        assert_eq!(merged.context.as_deref(), Some("author=Jane"));
+    }
+
+    #[test]
+    fn test_profile_settings_sit_between_config_and_cli() {
+        let config: Config = toml::from_str(
+            r#"
+            context = "author=Jane,license=MIT"
+
+            [profile.blog]
+            template = "blogpost"
+            context = "license=CC-BY,section=blog"
+            modification_time = true
+            adjust = "-0100"
+            "#,
+        )
+        .unwrap();
+        let cli = ZapCli {
+            profile: Some("blog".to_string()),
+            context: Some("section=news".to_string()),
+            ..Default::default()
+        };
+
+        let merged = config.apply_to(&cli).unwrap();
+        assert_eq!(merged.template.as_deref(), Some("blogpost"));
+        assert!(merged.modification_time && !merged.access_time);
+        assert_eq!(merged.adjust.as_deref(), Some("-0100"));
+        assert!(merged.no_create, "-A implies -c");
+
+        let context = crate::context::parse_context_str(merged.context.as_deref().unwrap());
+        assert_eq!(context.get("author").unwrap(), "Jane");
+        assert_eq!(context.get("license").unwrap(), "CC-BY");
+        assert_eq!(context.get("section").unwrap(), "news");
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let config: Config = toml::from_str("[profile.work]\n[profile.blog]\n").unwrap();
+        let cli = ZapCli {
+            profile: Some("home".to_string()),
+            ..Default::default()
+        };
+        let err = config.apply_to(&cli).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown profile 'home' (available: blog, work)"
+        );
+    }
+
+    #[test]
+    fn test_reads_aliases() {
+        let config: Config = toml::from_str("alias.note = \"-T note -p\"\n").unwrap();
+        assert_eq!(
+            config.alias.get("note").map(String::as_str),
+            Some("-T note -p")
+        );
+    }
+
+    #[test]
+    fn test_config_template_is_valid() {
+        assert!(toml::from_str::<Config>(CONFIG_TEMPLATE).is_ok());
+
+        // Every example is valid once uncommented
+        let uncommented: String = CONFIG_TEMPLATE
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(example) if example.contains(" = ") || example.starts_with('[') => example,
+                _ => line,
+            })
+            .map(|line| format!("{line}\n"))
+            .collect();
+        let config: Config = toml::from_str(&uncommented).unwrap();
+        assert_eq!(config.default_template.as_deref(), Some("header"));
+        assert_eq!(config.auto_template["rs"], "rust-file");
+        assert_eq!(config.alias.len(), 1);
+        assert_eq!(config.rule[0].template, "rust-module");
+        assert_eq!(config.context_table().get("author").unwrap(), "Jane");
+        assert!(config.profile["work"].modification_time);
+        assert!(config.notify.is_configured());
+        assert_eq!(config.plugins.deny, ["libuntrusted.so"]);
+        assert!(config.plugins.sandbox);
+    }
+
+    #[test]
+    fn test_context_table() {
+        let config: Config = toml::from_str(
+            "open = true\n[context]\nauthor = \"Jane\"\nyears = [2024, 2025]\n[context.company]\nname = \"ACME\"\n",
+        )
+        .unwrap();
+        let context = config.context_table();
+        assert_eq!(context.get("author").unwrap(), "Jane");
+        assert_eq!(context.get("years").unwrap()[1], 2025);
+        assert_eq!(context.get("company").unwrap()["name"], "ACME");
+
+        // Pairs from the command line aren't stacked on the table, they override it later
+        let merged = config.apply_to(&ZapCli::default()).unwrap();
+        assert_eq!(merged.context, None);
+
+        let round_tripped: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(round_tripped.context, config.context);
+    }
+
+    #[test]
+    fn test_to_toml_round_trips() {
+        let config: Config = toml::from_str(
+            "context = \"author=Jane\"\nalias.note = \"-T note\"\n[profile.work]\ntemplate = \"memo\"\n",
+        )
+        .unwrap();
+        let round_tripped: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(
+            round_tripped.context,
+            Some(ConfigContext::Pairs("author=Jane".to_string()))
+        );
+        assert_eq!(round_tripped.alias["note"], "-T note");
+        assert_eq!(
+            round_tripped.profile["work"].template.as_deref(),
+            Some("memo")
+        );
+    }
+
+    #[test]
+    fn test_invalid_config_reports_path() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "default_template = [").unwrap();
+
+        let err = Config::from_file(&path).unwrap_err();
+        assert!(matches!(err, ZapError::InvalidConfig { path: ref p, .. } if *p == path));
+    }
+}
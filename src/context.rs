@@ -0,0 +1,495 @@
+use chrono::Local;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tera::Context;
+
+use crate::clock;
+use crate::errors::ZapError;
+
+/// Parse context pairs in the format `foo=bar,baz=qux` into a Tera context.
+/// Pairs without a `=` are ignored. Dotted keys build nested objects:
+/// `author.name=Bob,author.email=b@x.io` is available as `{{ author.name }}`.
+pub fn parse_context_str(context_str: &str) -> Context {
+    let mut context = Context::new();
+    for pair in context_str.split(',') {
+        let mut parts = pair.splitn(2, '=');
+        if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+            insert_dotted(&mut context, key.trim(), value.trim());
+        }
+    }
+    context
+}
+
+/// Insert `value` at a dotted `key` like `author.name`, creating the objects on
+/// the way or adding to the ones already there. Keys with empty parts (`a..b`)
+/// are inserted as they are.
+pub fn insert_dotted(context: &mut Context, key: &str, value: &str) {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    if parts.len() == 1 || parts.iter().any(|part| part.is_empty()) {
+        context.insert(key, value);
+        return;
+    }
+
+    let root_key = parts.remove(0);
+    let leaf = parts.pop().unwrap_or_default();
+    let mut root = context
+        .get(root_key)
+        .filter(|existing| existing.is_object())
+        .cloned()
+        .unwrap_or_else(|| Value::Object(Map::new()));
+
+    let mut node = &mut root;
+    for part in parts {
+        let object = node.as_object_mut().expect("nodes are objects");
+        node = object
+            .entry(part)
+            .or_insert_with(|| Value::Object(Map::new()));
+        if !node.is_object() {
+            *node = Value::Object(Map::new());
+        }
+    }
+    node.as_object_mut()
+        .expect("nodes are objects")
+        .insert(leaf.to_string(), Value::from(value));
+    context.insert(root_key, &root);
+}
+
+/// The `--context` value that stands for "read the context from stdin".
+pub const STDIN_CONTEXT: &str = "-";
+
+/// Parse context piped into zap: either a JSON object, whose values keep their
+/// types (so nested objects and arrays work), or one `key=value` pair per line.
+/// Blank lines and lines starting with `#` are ignored.
+pub fn parse_context_input(input: &str) -> Result<Context, ZapError> {
+    let trimmed = input.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        let value: serde_json::Value =
+            serde_json::from_str(trimmed).map_err(|e| ZapError::StdinContext(e.to_string()))?;
+        if !value.is_object() {
+            return Err(ZapError::StdinContext(
+                "expected a JSON object with one entry per variable".to_string(),
+            ));
+        }
+        return Context::from_value(value).map_err(|e| ZapError::StdinContext(e.to_string()));
+    }
+
+    let mut context = Context::new();
+    for line in input.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            insert_dotted(&mut context, key.trim(), value.trim());
+        }
+    }
+    Ok(context)
+}
+
+fn read_stdin_context() -> Result<Context, ZapError> {
+    let mut stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Err(ZapError::StdinContext(
+            "nothing piped in; --context - expects key=value lines or a JSON object".to_string(),
+        ));
+    }
+    let mut input = String::new();
+    stdin.read_to_string(&mut input)?;
+    parse_context_input(&input)
+}
+
+/// Facts about the machine zap runs on, so templates can emit
+/// platform-conditional content, e.g. `{% if os == "windows" %}`.
+/// `os`, `arch` and `family` use Rust's names (`linux`, `macos`, `windows`,
+/// `x86_64`, `aarch64`, `unix`, ...). `domain` is everything after the first
+/// dot of the hostname, or empty if the hostname isn't qualified.
+pub fn platform_context() -> Context {
+    let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+    let domain = hostname
+        .split_once('.')
+        .map(|(_, domain)| domain.to_string())
+        .unwrap_or_default();
+
+    let mut context = Context::new();
+    context.insert("os", std::env::consts::OS);
+    context.insert("arch", std::env::consts::ARCH);
+    context.insert("family", std::env::consts::FAMILY);
+    context.insert("hostname", &hostname);
+    context.insert("domain", &domain);
+    context
+}
+
+/// Facts about this run: the user running zap (`$USER`, or `%USERNAME%` on Windows),
+/// the working directory `cwd`, and the local `date` (`2024-03-01`) and `time` (`14:05:09`).
+pub fn session_context() -> Context {
+    let user = env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_default();
+    let cwd = env::current_dir()
+        .map(|cwd| cwd.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let now = clock::now().with_timezone(&Local);
+
+    let mut context = Context::new();
+    context.insert("user", &user);
+    context.insert("cwd", &cwd);
+    context.insert("date", &now.format("%Y-%m-%d").to_string());
+    context.insert("time", &now.format("%H:%M:%S").to_string());
+    context
+}
+
+/// Facts about the file being created, from its path as given:
+/// `filename` (`notes.tar.gz`), `filestem` (`notes.tar`), `extension` (`gz`, or empty)
+/// and `dirname` (the directory part, or `.`).
+pub fn file_context(path: &Path) -> Context {
+    let lossy = |part: Option<&std::ffi::OsStr>| {
+        part.map(|part| part.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let dirname = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_string_lossy().into_owned(),
+        _ => ".".to_string(),
+    };
+
+    let mut context = Context::new();
+    context.insert("filename", &lossy(path.file_name()));
+    context.insert("filestem", &lossy(path.file_stem()));
+    context.insert("extension", &lossy(path.extension()));
+    context.insert("dirname", &dirname);
+    context
+}
+
+/// Facts from git when `path` is inside a repository, as the object `git`:
+/// `git.user_name` and `git.user_email` (from `git config`), the current
+/// `git.branch` (empty when HEAD is detached) and `git.repo_name`, the name of the
+/// repository's top directory. Outside a repository, or without git, it's empty.
+pub fn git_context(path: &Path) -> Context {
+    let mut context = Context::new();
+    // The file, and maybe its directory, doesn't exist yet
+    let Some(dir) = path
+        .parent()
+        .map(|parent| {
+            if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            }
+        })
+        .and_then(|parent| parent.ancestors().find(|dir| dir.is_dir()))
+    else {
+        return context;
+    };
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    let Some(toplevel) = git(&["rev-parse", "--show-toplevel"]) else {
+        return context;
+    };
+    let repo_name = Path::new(&toplevel)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut facts = Map::new();
+    for (key, value) in [
+        ("user_name", git(&["config", "user.name"])),
+        ("user_email", git(&["config", "user.email"])),
+        ("branch", git(&["symbolic-ref", "--short", "-q", "HEAD"])),
+        ("repo_name", Some(repo_name)),
+    ] {
+        facts.insert(key.to_string(), Value::from(value.unwrap_or_default()));
+    }
+    context.insert("git", &facts);
+    context
+}
+
+/// Whether a template might use the `git` facts, judging by its text: the run only
+/// asks git for them when a template does.
+pub fn uses_git(source: &str) -> bool {
+    source.contains("git") || source.contains("__tera_context")
+}
+
+/// `git_context` by repository, for a whole run: every repository is asked once,
+/// however many files in it are rendered, and files outside any are never asked.
+#[derive(Debug, Default)]
+pub struct GitContexts {
+    by_root: HashMap<PathBuf, Context>,
+}
+
+impl GitContexts {
+    pub fn get(&mut self, path: &Path) -> Context {
+        let Some(root) = repository_root(path) else {
+            return Context::new();
+        };
+        self.by_root
+            .entry(root)
+            .or_insert_with(|| git_context(path))
+            .clone()
+    }
+}
+
+/// The closest directory above `path` with a `.git` in it.
+fn repository_root(path: &Path) -> Option<PathBuf> {
+    std::path::absolute(path)
+        .ok()?
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())
+        .map(Path::to_path_buf)
+}
+
+/// Add `other` to `context`; where both have an object under the same key, the
+/// objects are merged, so `-C git.branch=main` keeps the other `git` values.
+pub fn merge(context: &mut Context, other: Context) {
+    fn merge_values(base: &mut Value, other: Value) {
+        match (base, other) {
+            (Value::Object(base), Value::Object(other)) => {
+                for (key, value) in other {
+                    match base.get_mut(&key) {
+                        Some(existing) => merge_values(existing, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, other) => *base = other,
+        }
+    }
+
+    let Value::Object(other) = other.into_json() else {
+        return;
+    };
+    for (key, value) in other {
+        let mut merged = context.get(&key).cloned().unwrap_or(Value::Null);
+        merge_values(&mut merged, value);
+        context.insert(key, &merged);
+    }
+}
+
+/// Read the current text contents of the system clipboard.
+pub fn read_clipboard() -> Result<String, ZapError> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|e| ZapError::Clipboard(e.to_string()))
+}
+
+/// Build the template context shared by every file in this run.
+/// Built-in values come first, then `defaults` (the `[context]` table of the
+/// config file); both can be overridden by `--context` pairs;
+/// values from the clipboard take precedence over both. A `-` among the pairs
+/// reads the context from stdin at that position, so later pairs override it.
+pub fn build_context(
+    defaults: Context,
+    context_str: Option<&str>,
+    clipboard_key: Option<&str>,
+) -> Result<Context, ZapError> {
+    let mut context = platform_context();
+    context.extend(session_context());
+    merge(&mut context, defaults);
+    if let Some(context_str) = context_str {
+        for pair in context_str.split(',') {
+            if pair.trim() == STDIN_CONTEXT {
+                context.extend(read_stdin_context()?);
+            } else if let Some((key, value)) = pair.split_once('=') {
+                insert_dotted(&mut context, key.trim(), value.trim());
+            }
+        }
+    }
+    if let Some(key) = clipboard_key {
+        context.insert(key, &read_clipboard()?);
+    }
+    Ok(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_context_str() {
+        let context = parse_context_str("name=Bob, title = Hello=World ,invalid");
+
+        assert_eq!(context.get("name").unwrap(), "Bob");
+        assert_eq!(context.get("title").unwrap(), "Hello=World");
+        assert!(context.get("invalid").is_none());
+    }
+
+    #[test]
+    fn test_dotted_keys_build_nested_objects() {
+        let context = parse_context_str("author.name=Bob,author.email=b@x.io,a.b.c=deep,a..b=flat");
+
+        assert_eq!(context.get("author").unwrap()["name"], "Bob");
+        assert_eq!(context.get("author").unwrap()["email"], "b@x.io");
+        assert_eq!(context.get("a").unwrap()["b"]["c"], "deep");
+        assert_eq!(context.get("a..b").unwrap(), "flat");
+    }
+
+    #[test]
+    fn test_dotted_keys_merge_across_sources() {
+        let mut context =
+            parse_context_input(r#"{"author": {"name": "Ada", "role": "dev"}}"#).unwrap();
+        insert_dotted(&mut context, "author.name", "Bob");
+        insert_dotted(&mut context, "title.main", "Report");
+
+        assert_eq!(context.get("author").unwrap()["name"], "Bob");
+        assert_eq!(context.get("author").unwrap()["role"], "dev");
+        assert_eq!(context.get("title").unwrap()["main"], "Report");
+
+        let context = build_context(
+            Context::new(),
+            Some("author.name=Bob,author.email=b@x.io"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(context.get("author").unwrap()["name"], "Bob");
+        assert_eq!(context.get("author").unwrap()["email"], "b@x.io");
+    }
+
+    #[test]
+    fn test_platform_context() {
+        let context = platform_context();
+
+        assert_eq!(context.get("os").unwrap(), std::env::consts::OS);
+        assert_eq!(context.get("arch").unwrap(), std::env::consts::ARCH);
+        assert_eq!(context.get("family").unwrap(), std::env::consts::FAMILY);
+        assert!(context.get("hostname").unwrap().is_string());
+        assert!(context.get("domain").unwrap().is_string());
+    }
+
+    #[test]
+    fn test_session_context() {
+        let context = session_context();
+
+        let date = context.get("date").unwrap().as_str().unwrap();
+        assert!(chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok());
+        let time = context.get("time").unwrap().as_str().unwrap();
+        assert!(chrono::NaiveTime::parse_from_str(time, "%H:%M:%S").is_ok());
+        assert_eq!(
+            context.get("cwd").unwrap(),
+            &std::env::current_dir()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        );
+        assert!(context.get("user").unwrap().is_string());
+    }
+
+    #[test]
+    fn test_file_context() {
+        let context = file_context(Path::new("docs/notes.tar.gz"));
+        assert_eq!(context.get("filename").unwrap(), "notes.tar.gz");
+        assert_eq!(context.get("filestem").unwrap(), "notes.tar");
+        assert_eq!(context.get("extension").unwrap(), "gz");
+        assert_eq!(context.get("dirname").unwrap(), "docs");
+
+        let context = file_context(Path::new("Makefile"));
+        assert_eq!(context.get("extension").unwrap(), "");
+        assert_eq!(context.get("dirname").unwrap(), ".");
+    }
+
+    #[test]
+    fn test_git_context() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("my-repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        assert!(git_context(&repo.join("notes.md")).get("git").is_none());
+
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(args)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "-q", "-b", "feature"]);
+        git(&["config", "user.name", "Ada Lovelace"]);
+        git(&["config", "user.email", "ada@example.com"]);
+
+        let context = git_context(&repo.join("docs").join("new").join("notes.md"));
+        let facts = context.get("git").unwrap();
+        assert_eq!(facts["user_name"], "Ada Lovelace");
+        assert_eq!(facts["user_email"], "ada@example.com");
+        assert_eq!(facts["branch"], "feature");
+        assert_eq!(facts["repo_name"], "my-repo");
+    }
+
+    #[test]
+    fn test_git_contexts_are_kept_by_repository() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().join("my-repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        let mut contexts = GitContexts::default();
+        assert!(contexts.get(&repo.join("notes.md")).get("git").is_none());
+
+        Command::new("git")
+            .arg("-C")
+            .arg(&repo)
+            .args(["init", "-q", "-b", "feature"])
+            .output()
+            .unwrap();
+        let context = contexts.get(&repo.join("src").join("main.rs"));
+        assert_eq!(context.get("git").unwrap()["branch"], "feature");
+        assert_eq!(contexts.by_root.len(), 1);
+        contexts.get(&repo.join("notes.md"));
+        assert_eq!(contexts.by_root.len(), 1);
+    }
+
+    #[test]
+    fn test_merge_keeps_nested_values() {
+        let mut context = parse_context_str("git.branch=main,git.user_name=Ada,title=Old");
+        merge(&mut context, parse_context_str("git.branch=dev,title=New"));
+
+        assert_eq!(context.get("git").unwrap()["branch"], "dev");
+        assert_eq!(context.get("git").unwrap()["user_name"], "Ada");
+        assert_eq!(context.get("title").unwrap(), "New");
+    }
+
+    #[test]
+    fn test_parse_context_input_lines() {
+        let context =
+            parse_context_input("# from a script\ntitle = Q3, revenue\n\nauthor=Ada\nnoise\n")
+                .unwrap();
+
+        assert_eq!(context.get("title").unwrap(), "Q3, revenue");
+        assert_eq!(context.get("author").unwrap(), "Ada");
+        assert!(context.get("noise").is_none());
+    }
+
+    #[test]
+    fn test_parse_context_input_json() {
+        let context =
+            parse_context_input(r#"{"title": "Q3", "total": 42, "tags": ["a", "b"]}"#).unwrap();
+
+        assert_eq!(context.get("title").unwrap(), "Q3");
+        assert_eq!(context.get("total").unwrap(), 42);
+        assert_eq!(context.get("tags").unwrap()[1], "b");
+
+        assert!(matches!(
+            parse_context_input("[1, 2]"),
+            Err(ZapError::StdinContext(_))
+        ));
+        assert!(parse_context_input("{\"unterminated\"").is_err());
+    }
+
+    #[test]
+    fn test_context_str_overrides_builtins() {
+        let context = build_context(Context::new(), Some("os=plan9"), None).unwrap();
+
+        assert_eq!(context.get("os").unwrap(), "plan9");
+        assert_eq!(context.get("arch").unwrap(), std::env::consts::ARCH);
+    }
+}
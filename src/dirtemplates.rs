@@ -0,0 +1,198 @@
+use globset::{GlobBuilder, GlobMatcher};
+use std::path::{Path, PathBuf};
+
+use crate::errors::ZapError;
+use crate::fileops::FileOps;
+
+/// Per-directory template rules, e.g. `"*.md" = "blogpost"`.
+pub const DIR_CONFIG_FILE: &str = ".zap.toml";
+
+/// Template rules by path pattern, in the order they're written: those of one
+/// `.zap.toml`, or the `[[rule]]` list of `config.toml`.
+#[derive(Debug, Clone)]
+pub struct DirTemplates {
+    dir: PathBuf,
+    rules: Vec<(GlobMatcher, String)>,
+}
+
+impl DirTemplates {
+    pub fn parse(config_path: &Path, contents: &str) -> Result<Self, ZapError> {
+        let invalid = |reason: String| ZapError::InvalidConfig {
+            path: config_path.to_path_buf(),
+            reason,
+        };
+        let table: toml::Table =
+            toml::from_str(contents).map_err(|e| invalid(e.message().to_string()))?;
+
+        let mut rules = Vec::new();
+        for (pattern, template) in table {
+            let template = template
+                .as_str()
+                .ok_or_else(|| invalid(format!("template for '{pattern}' must be a string")))?;
+            rules.push((pattern, template.to_string()));
+        }
+        let dir = config_path.parent().unwrap_or(Path::new(""));
+        Self::from_rules(config_path, dir, rules)
+    }
+
+    /// Rules for paths below `dir`, as `(pattern, template)` pairs read from `config_path`.
+    pub fn from_rules(
+        config_path: &Path,
+        dir: &Path,
+        rules: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ZapError> {
+        let mut compiled = Vec::new();
+        for (pattern, template) in rules {
+            let glob = GlobBuilder::new(&pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| ZapError::InvalidConfig {
+                    path: config_path.to_path_buf(),
+                    reason: e.to_string(),
+                })?;
+            compiled.push((glob.compile_matcher(), template));
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            rules: compiled,
+        })
+    }
+
+    /// The template of the first rule matching `path`, which has to be inside the
+    /// rules' directory. Patterns without a `/` match the file name; others match
+    /// the path relative to that directory.
+    pub fn template_for(&self, path: &Path) -> Option<&str> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+        let file_name = Path::new(path.file_name()?);
+        self.rules
+            .iter()
+            .find(|(matcher, _)| {
+                if matcher.glob().glob().contains('/') {
+                    matcher.is_match(relative)
+                } else {
+                    matcher.is_match(file_name)
+                }
+            })
+            .map(|(_, template)| template.as_str())
+    }
+}
+
+/// Find the template for a new file at `path` in the closest `.zap.toml` above it
+/// that has a matching rule.
+pub fn find_dir_template(fs: &dyn FileOps, path: &Path) -> Result<Option<String>, ZapError> {
+    let path = std::path::absolute(path)?;
+    let Some(parent) = path.parent() else {
+        return Ok(None);
+    };
+
+    for dir in parent.ancestors() {
+        let config_path = dir.join(DIR_CONFIG_FILE);
+        if !fs.exists(&config_path) {
+            continue;
+        }
+        let rules = DirTemplates::parse(&config_path, &fs.read_to_string(&config_path)?)?;
+        if let Some(template) = rules.template_for(&path) {
+            return Ok(Some(template.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::MemoryFs;
+
+    fn memory_fs(files: &[(&str, &str)]) -> MemoryFs {
+        let mut fs = MemoryFs::new();
+        for (path, contents) in files {
+            let path = Path::new(path);
+            fs.create_dir_all(path.parent().unwrap()).unwrap();
+            fs.write(path, contents.as_bytes()).unwrap();
+        }
+        fs
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let rules = DirTemplates::parse(
+            Path::new("/blog/.zap.toml"),
+            "\"drafts/*.md\" = \"draft\"\n\"*.md\" = \"blogpost\"\n\"*.{rs,toml}\" = \"code\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules.template_for(Path::new("/blog/drafts/a.md")),
+            Some("draft")
+        );
+        assert_eq!(
+            rules.template_for(Path::new("/blog/posts/a.md")),
+            Some("blogpost")
+        );
+        assert_eq!(
+            rules.template_for(Path::new("/blog/Cargo.toml")),
+            Some("code")
+        );
+        assert_eq!(
+            rules.template_for(Path::new("/blog/drafts/old/a.md")),
+            Some("blogpost")
+        );
+        assert_eq!(rules.template_for(Path::new("/blog/notes.txt")), None);
+        assert_eq!(rules.template_for(Path::new("/elsewhere/a.md")), None);
+    }
+
+    #[test]
+    fn test_closest_matching_config_is_used() {
+        let fs = memory_fs(&[
+            (
+                "/site/.zap.toml",
+                "\"*.md\" = \"page\"\n\"*.css\" = \"style\"\n",
+            ),
+            ("/site/blog/.zap.toml", "\"*.md\" = \"blogpost\"\n"),
+        ]);
+
+        let template = |path: &str| find_dir_template(&fs, Path::new(path)).unwrap();
+        assert_eq!(
+            template("/site/blog/2024/hello.md").as_deref(),
+            Some("blogpost")
+        );
+        assert_eq!(template("/site/blog/theme.css").as_deref(), Some("style"));
+        assert_eq!(template("/site/about.md").as_deref(), Some("page"));
+        assert_eq!(template("/other/about.md"), None);
+    }
+
+    #[test]
+    fn test_rules_below_a_directory() {
+        let rules = DirTemplates::from_rules(
+            Path::new("/config.toml"),
+            Path::new("/work/crate"),
+            [
+                ("src/**/*.rs".to_string(), "rust-module".to_string()),
+                ("tests/**".to_string(), "rust-test".to_string()),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules.template_for(Path::new("/work/crate/src/fs/ops.rs")),
+            Some("rust-module")
+        );
+        assert_eq!(
+            rules.template_for(Path::new("/work/crate/tests/cli.rs")),
+            Some("rust-test")
+        );
+        assert_eq!(rules.template_for(Path::new("/work/crate/build.rs")), None);
+        assert_eq!(rules.template_for(Path::new("/elsewhere/src/a.rs")), None);
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let path = Path::new("/.zap.toml");
+        assert!(matches!(
+            DirTemplates::parse(path, "\"*.md\" = 3\n"),
+            Err(ZapError::InvalidConfig { .. })
+        ));
+        assert!(DirTemplates::parse(path, "\"[*.md\" = \"page\"\n").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use serde_json::Value;
+
+use crate::errors::ZapError;
+use crate::frontmatter::Engine;
+
+/// Renders a template body with a context. `Action::render_template` renders
+/// Tera templates itself, with the partials, macros and plugins around them;
+/// the other engines only get the template body and the context.
+pub trait TemplateEngine {
+    fn render(&self, template: &str, source: &str, context: &Value) -> Result<String, ZapError>;
+}
+
+/// The renderer for templates written for `engine`, if this build includes it.
+/// Tera templates never come here, they're rendered by `Action::render_template`.
+pub fn engine_for(engine: Engine, template: &str) -> Result<Box<dyn TemplateEngine>, ZapError> {
+    match engine {
+        #[cfg(feature = "handlebars")]
+        Engine::Handlebars => Ok(Box::new(HandlebarsEngine)),
+        #[cfg(feature = "liquid")]
+        Engine::Liquid => Ok(Box::new(LiquidEngine)),
+        #[allow(unreachable_patterns)]
+        other => Err(ZapError::EngineNotEnabled {
+            template: template.to_string(),
+            engine: other.name(),
+        }),
+    }
+}
+
+#[cfg(feature = "handlebars")]
+struct HandlebarsEngine;
+
+#[cfg(feature = "handlebars")]
+impl TemplateEngine for HandlebarsEngine {
+    fn render(&self, template: &str, source: &str, context: &Value) -> Result<String, ZapError> {
+        let mut handlebars = handlebars::Handlebars::new();
+        // Files aren't HTML, so values go in as they are
+        handlebars.register_escape_fn(handlebars::no_escape);
+        handlebars
+            .render_template(source, context)
+            .map_err(|e| ZapError::EngineRender {
+                template: template.to_string(),
+                engine: Engine::Handlebars.name(),
+                reason: e.to_string(),
+            })
+    }
+}
+
+#[cfg(feature = "liquid")]
+struct LiquidEngine;
+
+#[cfg(feature = "liquid")]
+impl TemplateEngine for LiquidEngine {
+    fn render(&self, template: &str, source: &str, context: &Value) -> Result<String, ZapError> {
+        let error = |reason: String| ZapError::EngineRender {
+            template: template.to_string(),
+            engine: Engine::Liquid.name(),
+            reason,
+        };
+        let parsed = liquid::ParserBuilder::with_stdlib()
+            .build()
+            .and_then(|parser| parser.parse(source))
+            .map_err(|e| error(e.to_string()))?;
+        let globals = liquid::model::to_object(context).map_err(|e| error(e.to_string()))?;
+        parsed.render(&globals).map_err(|e| error(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "handlebars", feature = "liquid"))]
+    use serde_json::json;
+
+    #[cfg(not(feature = "liquid"))]
+    #[test]
+    fn test_disabled_engine() {
+        assert!(matches!(
+            engine_for(Engine::Liquid, "note"),
+            Err(ZapError::EngineNotEnabled {
+                engine: "liquid",
+                ..
+            })
+        ));
+    }
+
+    #[cfg(feature = "handlebars")]
+    #[test]
+    fn test_handlebars() {
+        let engine = engine_for(Engine::Handlebars, "note").unwrap();
+        let context = json!({"title": "A & B", "tags": ["x", "y"]});
+        assert_eq!(
+            engine
+                .render(
+                    "note",
+                    "# {{title}}{{#each tags}} #{{this}}{{/each}}",
+                    &context
+                )
+                .unwrap(),
+            "# A & B #x #y"
+        );
+    }
+
+    #[cfg(feature = "liquid")]
+    #[test]
+    fn test_liquid() {
+        let engine = engine_for(Engine::Liquid, "note").unwrap();
+        let context = json!({"title": "notes"});
+        assert_eq!(
+            engine
+                .render("note", "# {{ title | upcase }}", &context)
+                .unwrap(),
+            "# NOTES"
+        );
+    }
+}
@@ -23,14 +23,29 @@ pub enum PluginLoadError {
         source: libloading::Error,
     },
 
-    #[error("Entry point '{entry_point_name}' not found in plugin {plugin_path:?}: {source}")]
+    #[error("None of the entry points '{entry_point_name}' found in plugin {plugin}: {source}")]
     EntryPointNotFound {
-        plugin_path: PathBuf,
+        plugin: String,
         entry_point_name: String,
         #[source]
         source: libloading::Error,
     },
 
+    #[error(
+        "Plugin {plugin} was built for plugin ABI version {found}, but this zap needs version {expected}; rebuild it against a matching zap-plugin-sdk"
+    )]
+    AbiMismatch {
+        plugin: String,
+        found: u32,
+        expected: u32,
+    },
+
+    #[error("Failed to load plugin script {path:?}: {reason}")]
+    Script { path: PathBuf, reason: String },
+
+    #[error("Sandboxed plugin {path:?} failed: {reason}")]
+    Sandbox { path: PathBuf, reason: String },
+
     #[error("Plugin path contains invalid UTF-8: {0:?}")]
     InvalidPath(PathBuf),
 }
@@ -44,13 +59,15 @@ impl fmt::Display for TeraError {
         f.write_str("Tera templating Error: ")?;
         format_tera_error_kind(&self.0.kind, f)?;
 
-        if let Some(source) = self.0.source() {
-            if let Some(tera_source_error) = source.downcast_ref::<tera::Error>() {
-                f.write_str("\ncaused by:\n")?;
-                format_tera_error_kind(&tera_source_error.kind, f)?;
-            } else {
-                write!(f, "\ncaused by:\n{source}")?;
+        // Follow the whole chain: a failing function's own message is a few levels down
+        let mut source = self.0.source();
+        while let Some(error) = source {
+            f.write_str("\ncaused by:\n")?;
+            match error.downcast_ref::<tera::Error>() {
+                Some(tera_error) => format_tera_error_kind(&tera_error.kind, f)?,
+                None => write!(f, "{error}")?,
             }
+            source = error.source();
         }
         Ok(())
     }
@@ -75,6 +92,18 @@ fn format_tera_error_kind(kind: &tera::ErrorKind, f: &mut fmt::Formatter<'_>) ->
     }
 }
 
+fn format_line(line: Option<usize>) -> String {
+    line.map(|line| format!(", line {line}"))
+        .unwrap_or_default()
+}
+
+fn format_searched(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| format!("\n  {}", path.display()))
+        .collect()
+}
+
 #[derive(Error, Debug)]
 pub enum ZapError {
     #[error("I/O error: {0}")]
@@ -83,15 +112,147 @@ pub enum ZapError {
     #[error(transparent)]
     Tera(#[from] TeraError),
 
+    #[error("Alias '{name}' in config.toml doesn't parse: {reason}")]
+    InvalidAlias { name: String, reason: String },
+
+    #[error("Could not read argument file '{path}': {source}")]
+    ArgFile {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+
     #[error("Could not find user config directory")]
     ConfigDirNotFound,
 
-    #[error("Template file not found: {0:?}")]
-    TemplateNotFound(PathBuf),
+    #[error("Invalid config file {path:?}: {reason}")]
+    InvalidConfig { path: PathBuf, reason: String },
+
+    #[error("Config file {path:?} already exists, use --force to replace it")]
+    ConfigExists { path: PathBuf },
+
+    #[error("Invalid frontmatter in template {path:?}: {reason}")]
+    InvalidFrontmatter { path: PathBuf, reason: String },
+
+    #[error("Template '{template}' needs values for {names}; pass them with -C")]
+    MissingVariables { template: String, names: String },
+
+    #[error(
+        "Template '{template}' needs plugin '{plugin}' (function {function}), which is not loaded"
+    )]
+    MissingPlugin {
+        template: String,
+        plugin: String,
+        function: String,
+    },
+
+    #[error("Unknown profile '{name}' (available: {available})")]
+    UnknownProfile { name: String, available: String },
+
+    #[error("Template '{name}' not found, searched:{}", format_searched(searched))]
+    TemplateNotFound {
+        name: String,
+        searched: Vec<PathBuf>,
+    },
+
+    #[error("{failed} of {checked} template(s) failed the check")]
+    TemplateCheckFailed { failed: usize, checked: usize },
+
+    #[error("Formatter '{command}' failed: {reason}")]
+    Formatter { command: String, reason: String },
+
+    #[error("Scaffold '{template}' would write outside the target directory: {path}")]
+    InvalidScaffoldPath { template: String, path: String },
+
+    #[error("No template applies to '{0}'; pass one with -T to print it")]
+    NothingToRender(String),
+
+    #[error("Scaffold '{0}' renders several files and can't be printed with --stdout")]
+    ScaffoldToStdout(String),
+
+    #[error(
+        "Template '{template}' uses the {engine} engine, which this zap was built without (cargo feature '{engine}')"
+    )]
+    EngineNotEnabled {
+        template: String,
+        engine: &'static str,
+    },
+
+    #[error("Could not render template '{template}' with {engine}: {reason}")]
+    EngineRender {
+        template: String,
+        engine: &'static str,
+        reason: String,
+    },
+
+    #[error("Picking a template needs a terminal; pass its name with -T")]
+    NoTerminalForPicker,
+
+    #[error("There are no templates to pick from")]
+    NoTemplates,
+
+    #[error("Template '{0}' is binary; it can only be copied to new files or over whole files")]
+    BinaryTemplate(String),
+
+    #[error("Could not install template pack from '{from}': {reason}")]
+    PackSource { from: String, reason: String },
+
+    #[error("'{0}' can't be used as a pack name; pass one with --name")]
+    InvalidPackName(String),
+
+    #[error("Template pack '{0}' is already installed; use `zap template update {0}`")]
+    PackExists(String),
+
+    #[error("Template pack '{0}' isn't installed")]
+    PackNotInstalled(String),
+
+    #[error("Could not install plugin from '{from}': {reason}")]
+    PluginSource { from: String, reason: String },
+
+    #[error("'{0}' can't be used as a plugin file name; pass one with --file")]
+    InvalidPluginName(String),
+
+    #[error("Plugin '{0}' is already installed; pass --force to replace it")]
+    PluginExists(String),
+
+    #[error("Template '{template}'{}: {problem}", format_line(*line))]
+    StrictTemplate {
+        template: String,
+        line: Option<usize>,
+        problem: String,
+    },
+
+    #[error("Marker line '{marker}' not found")]
+    MarkerNotFound { marker: String },
+
+    #[error("--context-for {0}: no such file in this run")]
+    UnknownContextFile(String),
+
+    #[error("--map expects FILE=TEMPLATE, got '{0}'")]
+    InvalidMap(String),
+
+    #[error("Could not read items from {path:?}: {reason}")]
+    InvalidItems { path: PathBuf, reason: String },
+
+    #[error("{path:?}, line {line}: {reason}")]
+    InvalidRow {
+        path: PathBuf,
+        line: u64,
+        reason: String,
+    },
 
     #[error("Failed to set file times: {0}")]
     SetTimesError(io::Error),
 
+    #[error("Setting the birth time (--btime) isn't supported on this platform")]
+    BirthTimeUnsupported,
+
+    #[error("Could not read context from stdin: {0}")]
+    StdinContext(String),
+
+    #[error("Could not read the clipboard: {0}")]
+    Clipboard(String),
+
     #[error("Dialoguer error: {0}")]
     Dialoguer(#[from] dialoguer::Error),
 
@@ -114,6 +275,17 @@ pub enum ZapError {
     #[error("Invalid RFC3339 date-time string '{input}': {reason}")]
     ParseRfc3339 { input: String, reason: String },
 
+    #[error(
+        "Invalid date '{input}': {reason}; expected RFC3339, YYYY-MM-DDThh:mm:SS[.frac] or a relative date like \"2 hours ago\""
+    )]
+    ParseDate { input: String, reason: String },
+
+    #[error("Invalid Unix timestamp '{input}': {reason}")]
+    ParseUnix { input: String, reason: String },
+
+    #[error("Invalid time window '{input}': {reason}")]
+    ParseWindow { input: String, reason: String },
+
     #[error("Error parsing -t option with '{input}': {reason}")]
     ParseTOption { input: String, reason: String },
 
@@ -126,12 +298,20 @@ pub enum ZapError {
     #[error("The T Option was passed an invalid value for 'second': '{second}'")]
     TOptionInvalidSecondString { second: String },
 
-    #[error("Failed to convert time from option -t to local")]
+    #[error(
+        "Failed to convert time from option -t to local: a daylight saving change makes it ambiguous or skips it, see --dst"
+    )]
     TOptionConvertToLocal,
 
     #[error("Failed to convert value from -A Option to seconds: {reason}")]
     ParseAdjustment { reason: String },
 
+    #[error("Invalid current time '{input}': {reason}")]
+    InvalidNow { input: String, reason: String },
+
+    #[error("Invalid duration '{input}': {reason}")]
+    ParseDuration { input: String, reason: String },
+
     // Time adjustment errors
     #[error("Time adjustment would cause overflow")]
     TimeAdjustmentOverflow,
@@ -142,17 +322,59 @@ pub enum ZapError {
     #[error("Failed to parse time adjustment: {0}")]
     TimeAdjustmentParse(String),
 
+    #[error(
+        "Time adjustment lands on {time}, which a daylight saving change makes ambiguous or skips, see --dst"
+    )]
+    TimeAdjustmentLocal { time: String },
+
     #[error("Failed to convert between time representations")]
     TimeConversionError,
 
+    #[error("Invalid time format '{0}': use rfc3339, epoch or a strftime format")]
+    InvalidTimeFormat(String),
+
+    #[error("Could not read the times of '{path}': {source}")]
+    ShowTimes {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("The granularity for --round or --truncate has to be positive, like 2s or 1m")]
+    InvalidRounding,
+
+    #[error("--not-before is later than --not-after, so no time fits")]
+    EmptyTimeRange,
+
+    #[error("There is nothing to undo")]
+    NothingToUndo,
+
+    #[error("Invalid undo journal {path:?}: {reason}")]
+    InvalidJournal { path: PathBuf, reason: String },
+
+    #[error("Could not get the last commit of {path:?}: {reason}")]
+    GitLog { path: PathBuf, reason: String },
+
     #[error("Reference file not found: {0}")]
     ReferenceFileNotFound(String),
 
+    #[error("Not a directory: {0:?}")]
+    NotADirectory(PathBuf),
+
+    #[error("Could not download template {url}: {reason}")]
+    RemoteTemplate { url: String, reason: String },
+
+    #[error("Notification hook '{hook}' failed: {reason}")]
+    Notify { hook: String, reason: String },
+
     #[error("User declined to overwrite file")]
     UserDeclinedOverwrite,
 
     #[error("User declined to create directory")]
     UserDeclinedDirCreation,
+
+    #[error("User declined to create template")]
+    UserDeclinedTemplateCreation,
 }
 
 // Provide a direct conversion from tera::Error to ZapError for convenience
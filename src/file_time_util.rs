@@ -1,8 +1,99 @@
+use crate::args::Dst;
 use crate::errors::ZapError;
-use chrono::{DateTime, TimeDelta, Utc};
+use crate::parsedate::Adjustment;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Months, SecondsFormat, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
 use filetime::FileTime;
+use rand::Rng;
+use std::fmt::Display;
 use std::fs::Metadata;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::str::FromStr;
+
+/// Whether this platform lets zap set the birth (creation) time of files, for `--btime`.
+pub const BIRTH_TIME_SUPPORTED: bool = cfg!(any(windows, target_os = "macos"));
+
+/// The range `--not-before` and `--not-after` keep times in. Either end may be open.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeBounds {
+    pub not_before: Option<FileTime>,
+    pub not_after: Option<FileTime>,
+}
+
+impl TimeBounds {
+    pub fn new(
+        not_before: Option<FileTime>,
+        not_after: Option<FileTime>,
+    ) -> Result<Self, ZapError> {
+        match (not_before, not_after) {
+            (Some(start), Some(end)) if start > end => Err(ZapError::EmptyTimeRange),
+            _ => Ok(Self {
+                not_before,
+                not_after,
+            }),
+        }
+    }
+
+    /// `time`, moved into the range if it's outside
+    pub fn clamp(&self, time: FileTime) -> FileTime {
+        let time = self.not_before.map_or(time, |start| time.max(start));
+        self.not_after.map_or(time, |end| time.min(end))
+    }
+}
+
+/// The granularity `--round` and `--truncate` bring times to, counted from the
+/// Unix epoch in UTC. The default leaves times as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rounding {
+    step_nanos: Option<i128>,
+    /// Always round down, instead of to the nearest step
+    down: bool,
+}
+
+impl Rounding {
+    pub fn new(step: TimeDelta, down: bool) -> Result<Self, ZapError> {
+        match step.num_nanoseconds() {
+            Some(nanos) if nanos > 0 => Ok(Self {
+                step_nanos: Some(nanos.into()),
+                down,
+            }),
+            _ => Err(ZapError::InvalidRounding),
+        }
+    }
+
+    /// `time` on a multiple of the step
+    pub fn round(&self, time: FileTime) -> FileTime {
+        let Some(step) = self.step_nanos else {
+            return time;
+        };
+        let nanos =
+            i128::from(time.unix_seconds()) * 1_000_000_000 + i128::from(time.nanoseconds());
+        let nanos = if self.down { nanos } else { nanos + step / 2 };
+        let rounded = nanos - nanos.rem_euclid(step);
+        let seconds = rounded.div_euclid(1_000_000_000);
+        match i64::try_from(seconds) {
+            Ok(seconds) => {
+                FileTime::from_unix_time(seconds, rounded.rem_euclid(1_000_000_000) as u32)
+            }
+            Err(_) => time,
+        }
+    }
+}
+
+/// The window `--randomize` picks times from, both ends included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl TimeWindow {
+    /// A time in the window, each whole second equally likely
+    pub fn pick(&self, rng: &mut impl Rng) -> FileTime {
+        let seconds = rng.gen_range(self.start.timestamp()..=self.end.timestamp());
+        FileTime::from_unix_time(seconds, 0)
+    }
+}
 
 /// A specification for file times that can hold both access and modification times.
 /// Using Option allows for selective setting of either or both times.
@@ -10,6 +101,8 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 pub struct FileTimeSpec {
     pub atime: Option<FileTime>,
     pub mtime: Option<FileTime>,
+    /// Birth (creation) time, only set with `--btime`
+    pub btime: Option<FileTime>,
 }
 
 impl FileTimeSpec {
@@ -18,6 +111,7 @@ impl FileTimeSpec {
         Self {
             atime: Some(time),
             mtime: Some(time),
+            btime: None,
         }
     }
 
@@ -26,6 +120,7 @@ impl FileTimeSpec {
         Self {
             atime: Some(time),
             mtime: None,
+            btime: None,
         }
     }
 
@@ -34,6 +129,7 @@ impl FileTimeSpec {
         Self {
             atime: None,
             mtime: Some(time),
+            btime: None,
         }
     }
 
@@ -45,14 +141,15 @@ impl FileTimeSpec {
 
     /// Create from current time, setting both times
     pub fn now() -> Self {
-        Self::from_datetime(Utc::now())
+        Self::from_datetime(crate::clock::now())
     }
 
-    /// Create from a reference file's metadata
+    /// Create from a reference file's metadata, with its birth time where the platform has one
     pub fn from_metadata(metadata: &Metadata) -> Self {
         Self {
             atime: Some(FileTime::from_last_access_time(metadata)),
             mtime: Some(FileTime::from_last_modification_time(metadata)),
+            btime: FileTime::from_creation_time(metadata),
         }
     }
 
@@ -67,36 +164,78 @@ impl FileTimeSpec {
         self
     }
 
+    /// Set the birth time too (`--btime`): the one this spec has, say from a reference file,
+    /// or else the modification time. Without `set`, the birth time is left alone.
+    pub fn with_birth_time(mut self, set: bool) -> Self {
+        self.btime = if set {
+            self.btime.or(self.mtime).or(self.atime)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Each time that's set, moved into `bounds` if it's outside
+    pub fn clamped(self, bounds: &TimeBounds) -> Self {
+        Self {
+            atime: self.atime.map(|time| bounds.clamp(time)),
+            mtime: self.mtime.map(|time| bounds.clamp(time)),
+            btime: self.btime.map(|time| bounds.clamp(time)),
+        }
+    }
+
+    /// Each time that's set, brought to the granularity of `rounding`
+    pub fn rounded(self, rounding: &Rounding) -> Self {
+        Self {
+            atime: self.atime.map(|time| rounding.round(time)),
+            mtime: self.mtime.map(|time| rounding.round(time)),
+            btime: self.btime.map(|time| rounding.round(time)),
+        }
+    }
+
     /// Check if any time is set
     pub fn has_any_time(&self) -> bool {
         self.atime.is_some() || self.mtime.is_some()
     }
 
-    /// Apply adjustment to both times that are present
-    pub fn adjust_by_string(self, adjustment_str: &str) -> Result<Self, ZapError> {
-        let adjusted_atime = if let Some(atime) = self.atime {
-            Some(
-                AdjustableFileTime::from_file_time(atime)
-                    .adjust_by_string(adjustment_str)?
-                    .into_file_time(),
-            )
-        } else {
-            None
-        };
+    /// Apply adjustment to both times that are present. Calendar months are counted
+    /// in `tz` (from `--tz`), or else the local zone, with `dst` settling daylight
+    /// saving changes.
+    pub fn adjust_by_string(
+        self,
+        adjustment_str: &str,
+        tz: Option<Tz>,
+        dst: Dst,
+    ) -> Result<Self, ZapError> {
+        let adjustment = crate::parsedate::parse_adjustment(adjustment_str)
+            .map_err(|e| ZapError::TimeAdjustmentParse(e.to_string()))?;
+        self.adjust_by(adjustment, tz, dst)
+    }
 
-        let adjusted_mtime = if let Some(mtime) = self.mtime {
-            Some(
-                AdjustableFileTime::from_file_time(mtime)
-                    .adjust_by_string(adjustment_str)?
-                    .into_file_time(),
-            )
-        } else {
-            None
-        };
+    /// Move every time that's present by `delta`
+    pub fn adjust_by_delta(self, delta: TimeDelta) -> Result<Self, ZapError> {
+        self.adjust_by(Adjustment { months: 0, delta }, None, Dst::default())
+    }
 
+    /// Move every time that's present by the calendar months, then the duration, of `adjustment`
+    pub fn adjust_by(
+        self,
+        adjustment: Adjustment,
+        tz: Option<Tz>,
+        dst: Dst,
+    ) -> Result<Self, ZapError> {
+        let adjust = |time: Option<FileTime>| {
+            time.map(|time| {
+                AdjustableFileTime::from_file_time(time)
+                    .adjust_by(adjustment, tz, dst)
+                    .map(AdjustableFileTime::into_file_time)
+            })
+            .transpose()
+        };
         Ok(Self {
-            atime: adjusted_atime,
-            mtime: adjusted_mtime,
+            atime: adjust(self.atime)?,
+            mtime: adjust(self.mtime)?,
+            btime: adjust(self.btime)?,
         })
     }
 }
@@ -134,40 +273,92 @@ impl AdjustableFileTime {
 
     /// Create from the current time
     pub fn now() -> Self {
-        Self::from_datetime(Utc::now())
+        Self::from_datetime(crate::clock::now())
     }
 
     /// Adjust the time by a number of seconds (positive or negative)
     pub fn adjust_by_seconds(self, seconds: i64) -> Result<Self, ZapError> {
-        // Convert FileTime to SystemTime for easier arithmetic
-        let system_time = self.to_system_time()?;
-
-        let adjusted_time = if seconds >= 0 {
-            system_time
-                .checked_add(Duration::from_secs(seconds as u64))
-                .ok_or(ZapError::TimeAdjustmentOverflow)?
+        let delta = TimeDelta::try_seconds(seconds).ok_or(if seconds < 0 {
+            ZapError::TimeAdjustmentUnderflow
         } else {
-            system_time
-                .checked_sub(Duration::from_secs((-seconds) as u64))
+            ZapError::TimeAdjustmentOverflow
+        })?;
+        self.adjust_by_delta(delta)
+    }
+
+    /// Adjust the time by a chrono TimeDelta, keeping every nanosecond of both
+    pub fn adjust_by_delta(self, delta: TimeDelta) -> Result<Self, ZapError> {
+        let adjusted =
+            self.to_datetime()?
+                .checked_add_signed(delta)
+                .ok_or(if delta < TimeDelta::zero() {
+                    ZapError::TimeAdjustmentUnderflow
+                } else {
+                    ZapError::TimeAdjustmentOverflow
+                })?;
+        Ok(Self::from_datetime(adjusted))
+    }
+
+    /// Adjust the time by calendar months, in `tz` (or else the local zone) so the time of
+    /// day stays put. A day the month doesn't have becomes its last one (Jan 31 + 1 month is
+    /// Feb 28 or 29), and `dst` settles a time of day a daylight saving change skips or repeats.
+    pub fn adjust_by_months(self, months: i32, tz: Option<Tz>, dst: Dst) -> Result<Self, ZapError> {
+        match tz {
+            Some(tz) => self.adjust_by_months_in(months, &tz, dst),
+            None => self.adjust_by_months_in(months, &Local, dst),
+        }
+    }
+
+    fn adjust_by_months_in<Z: TimeZone>(
+        self,
+        months: i32,
+        zone: &Z,
+        dst: Dst,
+    ) -> Result<Self, ZapError> {
+        let local = self.to_datetime()?.with_timezone(zone).naive_local();
+        let shifted = Months::new(months.unsigned_abs());
+        let moved = if months < 0 {
+            local
+                .checked_sub_months(shifted)
                 .ok_or(ZapError::TimeAdjustmentUnderflow)?
+        } else {
+            local
+                .checked_add_months(shifted)
+                .ok_or(ZapError::TimeAdjustmentOverflow)?
         };
-
-        Ok(Self {
-            file_time: FileTime::from_system_time(adjusted_time),
-        })
+        let adjusted = crate::parsedate::resolve_local(zone, &moved, dst).ok_or_else(|| {
+            ZapError::TimeAdjustmentLocal {
+                time: moved.to_string(),
+            }
+        })?;
+        Ok(Self::from_datetime(adjusted.with_timezone(&Utc)))
     }
 
-    /// Adjust the time by a chrono TimeDelta
-    pub fn adjust_by_delta(self, delta: TimeDelta) -> Result<Self, ZapError> {
-        let seconds = delta.num_seconds();
-        self.adjust_by_seconds(seconds)
+    /// Adjust the time by the calendar months, then the duration, of `adjustment`
+    pub fn adjust_by(
+        self,
+        adjustment: Adjustment,
+        tz: Option<Tz>,
+        dst: Dst,
+    ) -> Result<Self, ZapError> {
+        let moved = match adjustment.months {
+            0 => self,
+            months => self.adjust_by_months(months, tz, dst)?,
+        };
+        moved.adjust_by_delta(adjustment.delta)
     }
 
-    /// Adjust the time by parsing an adjustment string (like "3600" for +1 hour or "-30" for -30 seconds)
-    pub fn adjust_by_string(self, adjustment_str: &str) -> Result<Self, ZapError> {
-        let seconds = crate::parsedate::parse_adjust(adjustment_str)
+    /// Adjust the time by parsing an adjustment string (like "3600" for +1 hour, "-30" for
+    /// -30 seconds, "1.25" for 1¼ seconds or "1month"), with months counted in `tz`
+    pub fn adjust_by_string(
+        self,
+        adjustment_str: &str,
+        tz: Option<Tz>,
+        dst: Dst,
+    ) -> Result<Self, ZapError> {
+        let adjustment = crate::parsedate::parse_adjustment(adjustment_str)
             .map_err(|e| ZapError::TimeAdjustmentParse(e.to_string()))?;
-        self.adjust_by_seconds(seconds as i64)
+        self.adjust_by(adjustment, tz, dst)
     }
 
     /// Convert to FileTime for use with filetime crate functions
@@ -180,18 +371,6 @@ impl AdjustableFileTime {
         &self.file_time
     }
 
-    /// Convert to SystemTime for easier arithmetic operations
-    fn to_system_time(self) -> Result<SystemTime, ZapError> {
-        let duration_since_epoch = Duration::new(
-            self.file_time.unix_seconds() as u64,
-            self.file_time.nanoseconds(),
-        );
-
-        UNIX_EPOCH
-            .checked_add(duration_since_epoch)
-            .ok_or(ZapError::TimeConversionError)
-    }
-
     /// Convert to DateTime<Utc> for display or further processing
     pub fn to_datetime(&self) -> Result<DateTime<Utc>, ZapError> {
         DateTime::from_timestamp(self.file_time.unix_seconds(), self.file_time.nanoseconds())
@@ -217,12 +396,72 @@ impl From<AdjustableFileTime> for FileTime {
     }
 }
 
+/// How `--show` prints a time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    #[default]
+    Rfc3339,
+    /// Seconds since the Unix epoch, with a fraction when there is one
+    Epoch,
+    Strftime(String),
+}
+
+impl FromStr for TimeFormat {
+    type Err = ZapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rfc3339" => Ok(Self::Rfc3339),
+            "epoch" => Ok(Self::Epoch),
+            _ if StrftimeItems::new(s).any(|item| item == Item::Error) => {
+                Err(ZapError::InvalidTimeFormat(s.to_string()))
+            }
+            _ => Ok(Self::Strftime(s.to_string())),
+        }
+    }
+}
+
+impl TimeFormat {
+    /// `time` in this format, in `tz` or else the local time zone
+    pub fn format(&self, time: FileTime, tz: Option<Tz>) -> Result<String, ZapError> {
+        let utc = AdjustableFileTime::from_file_time(time).to_datetime()?;
+        Ok(match tz {
+            Some(tz) => self.format_in(utc.with_timezone(&tz)),
+            None => self.format_in(utc.with_timezone(&Local)),
+        })
+    }
+
+    fn format_in<Z: TimeZone>(&self, time: DateTime<Z>) -> String
+    where
+        Z::Offset: Display,
+    {
+        match self {
+            Self::Rfc3339 => time.to_rfc3339_opts(SecondsFormat::AutoSi, false),
+            Self::Epoch => {
+                let (seconds, nanos) = (time.timestamp(), time.timestamp_subsec_nanos());
+                if nanos == 0 {
+                    return seconds.to_string();
+                }
+                // Before the epoch, the fraction counts back from the next whole second
+                let (sign, seconds, nanos) = if seconds < 0 {
+                    ("-", -(seconds + 1), 1_000_000_000 - nanos)
+                } else {
+                    ("", seconds, nanos)
+                };
+                let fraction = format!("{nanos:09}");
+                format!("{sign}{seconds}.{}", fraction.trim_end_matches('0'))
+            }
+            Self::Strftime(format) => time.format(format).to_string(),
+        }
+    }
+}
+
 /// Convenience function to adjust both access and modification times from metadata
 pub fn adjust_file_times_from_metadata(
     metadata: &Metadata,
     adjustment_str: &str,
 ) -> Result<FileTimeSpec, ZapError> {
-    FileTimeSpec::from_metadata(metadata).adjust_by_string(adjustment_str)
+    FileTimeSpec::from_metadata(metadata).adjust_by_string(adjustment_str, None, Dst::default())
 }
 
 /// Sets both atime and mtime, handling symlinks appropriately.
@@ -274,6 +513,72 @@ pub fn set_modification_time_only(
     }
 }
 
+/// Sets the access and modification times of an open file (`futimens` on Unix);
+/// the birth time of `times` is left to [`set_birth_time`].
+pub fn set_handle_times(file: &std::fs::File, times: &FileTimeSpec) -> Result<(), ZapError> {
+    if times.atime.is_none() && times.mtime.is_none() {
+        return Ok(());
+    }
+    filetime::set_file_handle_times(file, times.atime, times.mtime).map_err(ZapError::SetTimesError)
+}
+
+/// Sets the birth (creation) time, on the platforms that have a way to. It goes last, as
+/// macOS moves the birth time back when the modification time is set to before it.
+#[cfg(any(windows, target_os = "macos"))]
+pub fn set_birth_time(
+    path: &std::path::Path,
+    btime: FileTime,
+    symlink_only: bool,
+) -> Result<(), ZapError> {
+    let created = std::time::UNIX_EPOCH
+        .checked_add(std::time::Duration::new(
+            btime.unix_seconds().max(0) as u64,
+            btime.nanoseconds(),
+        ))
+        .ok_or(ZapError::TimeConversionError)?;
+    let mut options = std::fs::OpenOptions::new();
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+        // Needed to open directories, and the link itself rather than its target
+        const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+        const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x0020_0000;
+        options
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .custom_flags(if symlink_only {
+                FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT
+            } else {
+                FILE_FLAG_BACKUP_SEMANTICS
+            });
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        const O_SYMLINK: i32 = 0x0020_0000;
+        options
+            .read(true)
+            .custom_flags(if symlink_only { O_SYMLINK } else { 0 });
+    }
+    let file = options.open(path).map_err(ZapError::SetTimesError)?;
+    #[cfg(target_os = "macos")]
+    use std::os::macos::fs::FileTimesExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileTimesExt;
+    file.set_times(std::fs::FileTimes::new().set_created(created))
+        .map_err(ZapError::SetTimesError)
+}
+
+/// Sets the birth (creation) time, which this platform has no way to do.
+#[cfg(not(any(windows, target_os = "macos")))]
+pub fn set_birth_time(
+    _path: &std::path::Path,
+    _btime: FileTime,
+    _symlink_only: bool,
+) -> Result<(), ZapError> {
+    Err(ZapError::BirthTimeUnsupported)
+}
+
 /// Sets file times based on the provided FileTimeSpec and symlink mode.
 /// This function handles the logic for different combinations of atime/mtime settings,
 /// applying the appropriate filetime functions based on whether we're operating on a symlink or regular file.
@@ -283,17 +588,21 @@ pub fn set_times_with_mode(
     symlink_only: bool,
 ) -> Result<(), ZapError> {
     match (times.atime, times.mtime) {
-        (Some(atime), Some(mtime)) => set_both_times(path, atime, mtime, symlink_only),
-        (Some(atime), None) => set_access_time_only(path, atime, symlink_only),
-        (None, Some(mtime)) => set_modification_time_only(path, mtime, symlink_only),
-        (None, None) => Ok(()),
+        (Some(atime), Some(mtime)) => set_both_times(path, atime, mtime, symlink_only)?,
+        (Some(atime), None) => set_access_time_only(path, atime, symlink_only)?,
+        (None, Some(mtime)) => set_modification_time_only(path, mtime, symlink_only)?,
+        (None, None) => {}
+    }
+    match times.btime {
+        Some(btime) => set_birth_time(path, btime, symlink_only),
+        None => Ok(()),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::TimeZone;
+    use chrono::{TimeZone, Timelike};
     use std::fs::File;
     use std::path::Path;
     use tempfile::tempdir;
@@ -318,6 +627,35 @@ mod tests {
         assert_eq!(result_dt.timestamp(), dt.timestamp() + 3600);
     }
 
+    #[test]
+    fn test_adjustment_keeps_nanoseconds() {
+        let dt = Utc
+            .with_ymd_and_hms(2023, 1, 1, 12, 0, 0)
+            .unwrap()
+            .with_nanosecond(123_456_789)
+            .unwrap();
+        let spec = FileTimeSpec::from_datetime(dt)
+            .adjust_by_string("-1.000000002", None, Dst::Error)
+            .unwrap();
+        let mtime = spec.mtime.unwrap();
+        assert_eq!(mtime.unix_seconds(), dt.timestamp() - 1);
+        assert_eq!(mtime.nanoseconds(), 123_456_787);
+
+        let spec = spec.adjust_by_string("250ms", None, Dst::Error).unwrap();
+        assert_eq!(spec.atime.unwrap().nanoseconds(), 373_456_787);
+
+        // Before 1970 works as well
+        let early = AdjustableFileTime::from_file_time(FileTime::from_unix_time(-10, 500))
+            .adjust_by_string("-1.5", None, Dst::Error)
+            .unwrap()
+            .to_datetime()
+            .unwrap();
+        assert_eq!(
+            (early.timestamp(), early.timestamp_subsec_nanos()),
+            (-12, 500_000_500)
+        );
+    }
+
     #[test]
     fn test_negative_adjustment() {
         let dt = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
@@ -400,6 +738,213 @@ mod tests {
         assert!(neither.mtime.is_none());
     }
 
+    #[test]
+    fn test_file_time_spec_with_birth_time() {
+        let time = FileTime::from_unix_time(1_000_000_000, 0);
+        let born = FileTime::from_unix_time(900_000_000, 0);
+
+        assert_eq!(
+            FileTimeSpec::both(time).with_birth_time(true).btime,
+            Some(time)
+        );
+        assert_eq!(FileTimeSpec::both(time).with_birth_time(false).btime, None);
+
+        let reference = FileTimeSpec {
+            btime: Some(born),
+            ..FileTimeSpec::both(time)
+        };
+        assert_eq!(reference.with_birth_time(true).btime, Some(born));
+        assert_eq!(reference.with_birth_time(false).btime, None);
+    }
+
+    #[test]
+    fn test_time_bounds_clamp() {
+        let at = |seconds| FileTime::from_unix_time(seconds, 0);
+        let bounds = |start: Option<i64>, end: Option<i64>| {
+            TimeBounds::new(start.map(at), end.map(at)).unwrap()
+        };
+
+        for time in [50, 100, 150, 200, 250] {
+            assert_eq!(bounds(None, None).clamp(at(time)), at(time));
+        }
+        assert_eq!(bounds(Some(100), None).clamp(at(50)), at(100));
+        assert_eq!(bounds(Some(100), None).clamp(at(250)), at(250));
+        assert_eq!(bounds(None, Some(200)).clamp(at(50)), at(50));
+        assert_eq!(bounds(None, Some(200)).clamp(at(250)), at(200));
+        let range = bounds(Some(100), Some(200));
+        assert_eq!(range.clamp(at(50)), at(100));
+        assert_eq!(range.clamp(at(100)), at(100));
+        assert_eq!(range.clamp(at(150)), at(150));
+        assert_eq!(range.clamp(at(200)), at(200));
+        assert_eq!(range.clamp(at(250)), at(200));
+        assert_eq!(bounds(Some(100), Some(100)).clamp(at(250)), at(100));
+
+        assert!(matches!(
+            TimeBounds::new(Some(at(200)), Some(at(100))),
+            Err(ZapError::EmptyTimeRange)
+        ));
+    }
+
+    #[test]
+    fn test_rounding() {
+        let at = |seconds, nanos| FileTime::from_unix_time(seconds, nanos);
+        let nearest = Rounding::new(TimeDelta::seconds(2), false).unwrap();
+        let down = Rounding::new(TimeDelta::seconds(2), true).unwrap();
+
+        assert_eq!(nearest.round(at(101, 0)), at(102, 0));
+        assert_eq!(nearest.round(at(100, 999_999_999)), at(100, 0));
+        assert_eq!(down.round(at(101, 999_999_999)), at(100, 0));
+        assert_eq!(down.round(at(100, 0)), at(100, 0));
+        // Before the epoch, down is still earlier
+        assert_eq!(down.round(at(-1, 500_000_000)), at(-2, 0));
+        assert_eq!(nearest.round(at(-3, 0)), at(-2, 0));
+
+        let minute = Rounding::new(TimeDelta::minutes(1), false).unwrap();
+        assert_eq!(minute.round(at(1_700_000_029, 0)), at(1_700_000_040, 0));
+        assert_eq!(minute.round(at(1_700_000_009, 0)), at(1_699_999_980, 0));
+        assert_eq!(Rounding::default().round(at(7, 7)), at(7, 7));
+        assert!(matches!(
+            Rounding::new(TimeDelta::zero(), false),
+            Err(ZapError::InvalidRounding)
+        ));
+
+        let spec = FileTimeSpec::modification_only(at(101, 0)).rounded(&down);
+        assert_eq!((spec.atime, spec.mtime), (None, Some(at(100, 0))));
+    }
+
+    #[test]
+    fn test_file_time_spec_clamped() {
+        let at = |seconds| FileTime::from_unix_time(seconds, 0);
+        let bounds = TimeBounds::new(Some(at(100)), Some(at(200))).unwrap();
+        let spec = FileTimeSpec {
+            atime: Some(at(50)),
+            mtime: Some(at(250)),
+            btime: Some(at(150)),
+        };
+
+        let clamped = spec.clamped(&bounds);
+        assert_eq!(clamped.atime, Some(at(100)));
+        assert_eq!(clamped.mtime, Some(at(200)));
+        assert_eq!(clamped.btime, Some(at(150)));
+
+        let access_only = FileTimeSpec::access_only(at(250)).clamped(&bounds);
+        assert_eq!(access_only.atime, Some(at(200)));
+        assert_eq!(access_only.mtime, None);
+    }
+
+    #[test]
+    fn test_time_window_pick() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let window = TimeWindow {
+            start: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            end: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 10).unwrap(),
+        };
+        let picks = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..50).map(|_| window.pick(&mut rng)).collect::<Vec<_>>()
+        };
+
+        let first = picks(7);
+        assert_eq!(first, picks(7));
+        assert!(first.iter().all(|time| {
+            (window.start.timestamp()..=window.end.timestamp()).contains(&time.unix_seconds())
+        }));
+        let instant = TimeWindow {
+            end: window.start,
+            ..window
+        };
+        assert_eq!(
+            instant.pick(&mut StdRng::seed_from_u64(1)).unix_seconds(),
+            window.start.timestamp()
+        );
+    }
+
+    #[test]
+    fn test_file_time_spec_adjust_by_delta() {
+        let spec = FileTimeSpec::modification_only(FileTime::from_unix_time(1_000, 500));
+        let adjusted = spec
+            .adjust_by_delta(TimeDelta::milliseconds(-1_500))
+            .unwrap();
+        assert_eq!(adjusted.atime, None);
+        assert_eq!(
+            adjusted.mtime,
+            Some(FileTime::from_unix_time(998, 500_000_500))
+        );
+    }
+
+    #[test]
+    fn test_adjust_by_calendar_months() {
+        let at = |s: &str| {
+            let time = DateTime::parse_from_rfc3339(s).unwrap();
+            FileTimeSpec::both(FileTime::from_unix_time(time.timestamp(), 0))
+        };
+        // Month lengths and leap years, at noon so no zone moves the date
+        let adjusted = at("2024-01-31T12:00:00Z")
+            .adjust_by_string("+1month", None, Dst::Error)
+            .unwrap();
+        assert_eq!(adjusted.mtime, at("2024-02-29T12:00:00Z").mtime);
+        let adjusted = at("2024-02-29T12:00:00Z")
+            .adjust_by_string("-1year", None, Dst::Error)
+            .unwrap();
+        assert_eq!(adjusted.mtime, at("2023-02-28T12:00:00Z").mtime);
+        let adjusted = at("2023-03-31T12:00:00Z")
+            .adjust_by_string("-1month 1h", None, Dst::Error)
+            .unwrap();
+        assert_eq!(adjusted.atime, at("2023-02-28T11:00:00Z").atime);
+    }
+
+    #[test]
+    fn test_adjust_by_months_in_zone_across_dst() {
+        let at = |s: &str| {
+            let time = DateTime::parse_from_rfc3339(s).unwrap();
+            FileTimeSpec::both(FileTime::from_unix_time(time.timestamp(), 0))
+        };
+        let berlin = Some(chrono_tz::Europe::Berlin);
+        // 02:30 on 2024-03-31 is skipped when Berlin moves its clocks forward
+        let start = at("2024-01-31T02:30:00+01:00");
+        assert!(matches!(
+            start.adjust_by_string("+2months", berlin, Dst::Error),
+            Err(ZapError::TimeAdjustmentLocal { .. })
+        ));
+        let adjusted = start
+            .adjust_by_string("+2months", berlin, Dst::Latest)
+            .unwrap();
+        assert_eq!(adjusted.mtime, at("2024-03-31T03:30:00+02:00").mtime);
+        let adjusted = start
+            .adjust_by_string("+2months", berlin, Dst::Earliest)
+            .unwrap();
+        assert_eq!(adjusted.mtime, at("2024-03-31T01:30:00+01:00").mtime);
+
+        // The time of day stays put in the zone given, not the local one
+        let adjusted = at("2024-01-15T12:00:00+01:00")
+            .adjust_by_string("+6months", berlin, Dst::Error)
+            .unwrap();
+        assert_eq!(adjusted.mtime, at("2024-07-15T12:00:00+02:00").mtime);
+    }
+
+    #[test]
+    fn test_time_format() {
+        let time = FileTime::from_unix_time(1_715_769_000, 250_000_000);
+        let utc = Some(Tz::UTC);
+        let format = |s: &str| s.parse::<TimeFormat>().unwrap().format(time, utc).unwrap();
+
+        assert_eq!(format("rfc3339"), "2024-05-15T10:30:00.250+00:00");
+        assert_eq!(format("epoch"), "1715769000.25");
+        assert_eq!(format("%F %T"), "2024-05-15 10:30:00");
+        assert_eq!(
+            TimeFormat::Epoch
+                .format(FileTime::from_unix_time(-2, 500_000_000), utc)
+                .unwrap(),
+            "-1.5"
+        );
+        assert!(matches!(
+            "%Q".parse::<TimeFormat>(),
+            Err(ZapError::InvalidTimeFormat(_))
+        ));
+    }
+
     #[test]
     fn test_file_time_spec_has_any_time() {
         let dt = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
@@ -417,6 +962,7 @@ mod tests {
         let neither = FileTimeSpec {
             atime: None,
             mtime: None,
+            btime: None,
         };
         assert!(!neither.has_any_time());
     }
@@ -426,7 +972,7 @@ mod tests {
         let dt = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
         let spec = FileTimeSpec::from_datetime(dt);
 
-        let adjusted = spec.adjust_by_string("010101").unwrap(); // 01 hour 01 minute 01 second = 3661 seconds
+        let adjusted = spec.adjust_by_string("010101", None, Dst::Error).unwrap(); // 01 hour 01 minute 01 second = 3661 seconds
         assert!(adjusted.atime.is_some());
         assert!(adjusted.mtime.is_some());
         assert_eq!(
@@ -443,7 +989,9 @@ mod tests {
             dt.timestamp(),
             dt.timestamp_subsec_nanos(),
         ));
-        let adjusted_access = access_only.adjust_by_string("-3001").unwrap(); // -30 minutes 01 seconds = -1801 seconds
+        let adjusted_access = access_only
+            .adjust_by_string("-3001", None, Dst::Error)
+            .unwrap(); // -30 minutes 01 seconds = -1801 seconds
         assert!(adjusted_access.atime.is_some());
         assert!(adjusted_access.mtime.is_none());
         assert_eq!(
@@ -481,6 +1029,7 @@ mod tests {
         let neither = FileTimeSpec {
             atime: None,
             mtime: None,
+            btime: None,
         };
         assert!(set_times_with_mode(Path::new(&file_path), &neither, false).is_ok());
     }
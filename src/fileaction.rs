@@ -1,8 +1,25 @@
-use crate::errors::ZapError;
-use crate::file_time_util::{FileTimeSpec, adjust_file_times_from_metadata};
+use crate::args::{Dst, FileKind};
+use crate::dirtemplates::{DirTemplates, find_dir_template};
+use crate::engine;
+use crate::errors::{TeraError, ZapError};
+use crate::file_time_util::{FileTimeSpec, Rounding, TimeBounds};
+use crate::fileops::FileOps;
+use crate::find_template;
+use crate::frontmatter::{self, TemplateMeta};
+use crate::journal::Journal;
+use crate::media;
+use crate::render::{Body, Renderer};
+use crate::scaffold;
+use crate::timings::{Phase, Timings};
 use anyhow::Result;
-use dialoguer::Confirm;
+use chrono_tz::Tz;
+use dialoguer::{Confirm, Input, Select};
+use similar::TextDiff;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
 use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tera::Context;
 
 #[derive(Debug, Clone)]
 pub enum Action {
@@ -12,11 +29,30 @@ pub enum Action {
     CreateEmpty,
     CreateWithTemplate {
         template_name: String,
-        context_str: Option<String>,
+        context: Context,
+        format_with: Option<String>,
     },
     OverwriteWithTemplate {
         template_name: String,
-        context_str: Option<String>,
+        context: Context,
+        format_with: Option<String>,
+    },
+    /// Add the rendered template to an existing file instead of replacing it
+    ExtendWithTemplate {
+        template_name: String,
+        context: Context,
+        format_with: Option<String>,
+        placement: Placement,
+    },
+    /// Render an existing file as a template in place (`--fill`)
+    FillPlaceholders {
+        context: Context,
+    },
+    /// Render every file of a directory template into the target directory
+    CreateScaffold {
+        template_name: String,
+        context: Context,
+        format_with: Option<String>,
     },
     SetTimes {
         times: FileTimeSpec,
@@ -24,49 +60,462 @@ pub enum Action {
     },
     AdjustTimes {
         adjustment_str: String,
+        /// Zone calendar months are counted in, and how daylight saving changes are settled
+        tz: Option<Tz>,
+        dst: Dst,
         should_update_access: bool,
         should_update_modification: bool,
+        should_update_birth: bool,
+        bounds: TimeBounds,
+        rounding: Rounding,
         symlink_only: bool,
     },
 }
 
+/// Where `--append`, `--prepend` and `--insert-at` put a rendered template in an existing file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Placement {
+    Start,
+    End,
+    /// Right after the first line that reads `marker` (ignoring surrounding whitespace)
+    After(String),
+}
+
+impl Placement {
+    /// `current` with `rendered` added at this place, separated by a line break
+    /// when the part that comes first doesn't end with one.
+    pub fn insert(&self, current: &str, rendered: &str) -> Result<String, ZapError> {
+        let (first, second) = match self {
+            Placement::Start => (rendered, current),
+            Placement::End => (current, rendered),
+            Placement::After(marker) => {
+                let end =
+                    Self::marker_end(current, marker).ok_or_else(|| ZapError::MarkerNotFound {
+                        marker: marker.clone(),
+                    })?;
+                let (before, after) = current.split_at(end);
+                let head = Placement::End.insert(before, rendered)?;
+                return Placement::End.insert(&head, after);
+            }
+        };
+        let separator = if first.is_empty() || second.is_empty() || first.ends_with('\n') {
+            ""
+        } else {
+            "\n"
+        };
+        Ok(format!("{first}{separator}{second}"))
+    }
+
+    /// Whether inserting `rendered` would add nothing new: for markers, a snippet
+    /// that's already right after the marker isn't inserted again, so reruns are
+    /// harmless. It counts as there when the lines after the marker start with its
+    /// lines, or have them before the first blank line.
+    pub fn already_present(&self, current: &str, rendered: &str) -> bool {
+        let Placement::After(marker) = self else {
+            return false;
+        };
+        let snippet = rendered.trim();
+        let Some(end) = Self::marker_end(current, marker).filter(|_| !snippet.is_empty()) else {
+            return false;
+        };
+        let after: Vec<&str> = current[end..].lines().map(str::trim).collect();
+        let wanted: Vec<&str> = snippet.lines().map(str::trim).collect();
+        let block_len = after
+            .iter()
+            .position(|line| line.is_empty())
+            .unwrap_or(after.len());
+        after.starts_with(&wanted)
+            || after[..block_len]
+                .windows(wanted.len())
+                .any(|lines| lines == wanted)
+    }
+
+    /// Byte offset just past the marker line, including its line break.
+    fn marker_end(current: &str, marker: &str) -> Option<usize> {
+        let mut offset = 0;
+        for line in current.split_inclusive('\n') {
+            offset += line.len();
+            if line.trim() == marker.trim() {
+                return Some(offset);
+            }
+        }
+        None
+    }
+}
+
+/// Selects which existing files a bulk run touches, based on their current
+/// metadata and contents. Files that don't exist never pass an active filter.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    pub older_than: Option<Duration>,
+    pub newer_than: Option<Duration>,
+    pub only_type: Option<FileKind>,
+    pub mime: Option<String>,
+}
+
+impl FileFilter {
+    pub fn is_active(&self) -> bool {
+        self.older_than.is_some()
+            || self.newer_than.is_some()
+            || self.only_type.is_some()
+            || self.mime.is_some()
+    }
+
+    pub fn matches_age(&self, age: Duration) -> bool {
+        self.older_than.is_none_or(|older_than| age > older_than)
+            && self.newer_than.is_none_or(|newer_than| age < newer_than)
+    }
+
+    /// Match a sniffed MIME type against the `--mime` pattern,
+    /// which is either exact (`image/png`) or a wildcard (`image/*`, `*/*`).
+    pub fn matches_mime(&self, mime: Option<&str>) -> bool {
+        let Some(pattern) = self.mime.as_deref() else {
+            return true;
+        };
+        let Some(mime) = mime else {
+            return false;
+        };
+        match pattern.strip_suffix("/*") {
+            Some("*") => true,
+            Some(kind) => mime
+                .split_once('/')
+                .is_some_and(|(mime_kind, _)| mime_kind.eq_ignore_ascii_case(kind)),
+            None => mime.eq_ignore_ascii_case(pattern),
+        }
+    }
+
+    /// Why `path` should be skipped, or `None` if it passes every filter.
+    pub fn skip_reason(
+        &self,
+        fs: &dyn FileOps,
+        path: &Path,
+        symlink_only: bool,
+    ) -> Result<Option<String>, ZapError> {
+        let Ok(link_metadata) = fs.metadata(path, true) else {
+            return Ok(Some("File doesn't exist and a filter is set".to_string()));
+        };
+
+        if let Some(kind) = self.only_type {
+            if link_metadata.kind != Some(kind) {
+                return Ok(Some(format!("Not a {kind} (--only-type)")));
+            }
+        }
+
+        let metadata = if symlink_only {
+            link_metadata
+        } else {
+            match fs.metadata(path, false) {
+                Ok(metadata) => metadata,
+                Err(_) => return Ok(Some("Symlink target doesn't exist".to_string())),
+            }
+        };
+
+        // Ages count from `--now` or SOURCE_DATE_EPOCH too, so filtering is reproducible
+        let modified = SystemTime::UNIX_EPOCH
+            + Duration::new(
+                metadata.mtime.unix_seconds().max(0) as u64,
+                metadata.mtime.nanoseconds(),
+            );
+        let age = SystemTime::from(crate::clock::now())
+            .duration_since(modified)
+            .unwrap_or(Duration::ZERO);
+        if !self.matches_age(age) {
+            let age = Duration::from_secs(age.as_secs());
+            return Ok(Some(format!(
+                "Modified {} ago, outside the --older-than/--newer-than range",
+                humantime::format_duration(age)
+            )));
+        }
+
+        if self.mime.is_some() {
+            let sniffed = if metadata.kind == Some(FileKind::File) {
+                infer::get(&fs.read_start(path, SNIFF_LEN)?)
+            } else {
+                None
+            };
+            let mime = sniffed.map(|kind| kind.mime_type());
+            if !self.matches_mime(mime) {
+                return Ok(Some(format!(
+                    "Content type {} doesn't match --mime",
+                    mime.unwrap_or("unknown")
+                )));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// How much of a file is read to sniff its content type for `--mime`
+const SNIFF_LEN: usize = 8192;
+
+/// A file zap operates on, together with the template context for that file.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub filename: String,
+    pub context: Context,
+    /// Template for just this file (`--map`), in place of the planner's
+    pub template: Option<String>,
+    /// Times for just this file (`--reference-tree`, `--from-git`), in place of the explicit ones
+    pub times: Option<FileTimeSpec>,
+}
+
+#[derive(Clone)]
 pub struct Planner<'a> {
     pub no_create: bool,
     pub adjust: Option<&'a str>,
     pub template: Option<&'a str>,
-    pub context: Option<&'a str>,
+    /// Template used for newly created files when no `template` is given;
+    /// existing files are never overwritten with it.
+    pub default_template: Option<&'a str>,
+    /// Look up templates for new files in `.zap.toml` files above them when no
+    /// `template` is given. A matching rule wins over `auto_templates` and `default_template`.
+    pub dir_templates: bool,
+    /// Templates for new files by extension (without the dot), used when neither
+    /// `template` nor a `.zap.toml` rule applies. They win over `default_template`.
+    pub auto_templates: &'a BTreeMap<String, String>,
+    /// Templates for new files by path (`[[rule]]` in `config.toml`), used when neither
+    /// `template` nor a `.zap.toml` rule applies. They win over `auto_templates`.
+    pub path_rules: Option<&'a DirTemplates>,
+    /// Command rendered templates are piped through before they're written;
+    /// wins over `format_with` in a template's frontmatter.
+    pub format_with: Option<&'a str>,
+    /// Add the template to existing files (`--append`/`--prepend`) instead of overwriting them
+    pub placement: Option<Placement>,
+    /// Treat existing files as templates and render them in place (`--fill`)
+    pub fill: bool,
     pub should_update_access: bool,
     pub should_update_modification: bool,
+    /// Set the birth time as well (`--btime`)
+    pub should_update_birth: bool,
+    /// Range the new times are clamped to (`--not-before`, `--not-after`)
+    pub bounds: TimeBounds,
+    /// Granularity the new times end up on, after everything else (`--round`, `--truncate`)
+    pub rounding: Rounding,
+    /// Give each existing file the time it was taken, from its EXIF data or video
+    /// header (`--from-exif`), instead of `explicit_times`
+    pub from_exif: bool,
+    /// Zone of EXIF times that don't carry an offset and of the calendar months
+    /// in `adjust` (`--tz`)
+    pub tz: Option<Tz>,
+    /// How local times a daylight saving change skips or repeats are settled (`--dst`)
+    pub dst: Dst,
+    /// Give each existing file the time of the last commit that changed it (`--from-git`).
+    /// It's looked up before planning and passed in as the file's explicit times;
+    /// files without them aren't committed and are skipped.
+    pub from_git: bool,
     pub create_intermediate_dirs: bool,
     pub symlink_only: bool,
+    pub filter: FileFilter,
 }
 
 impl<'a> Planner<'a> {
+    /// The `auto_templates` entry for the extension of `path`; an exact match is
+    /// preferred, otherwise case doesn't matter (`README.MD` uses the `md` entry).
+    fn auto_template(&self, path: &Path) -> Option<&'a str> {
+        let extension = path.extension()?.to_str()?;
+        self.auto_templates
+            .get(extension)
+            .or_else(|| self.auto_templates.get(&extension.to_lowercase()))
+            .map(String::as_str)
+    }
+
+    /// The template of the first `path_rules` entry matching `path`.
+    fn rule_template(&self, path: &Path) -> Option<&'a str> {
+        let path = std::path::absolute(path).ok()?;
+        self.path_rules?.template_for(&path)
+    }
+
+    /// Where the template goes in an existing file: `placement` if given, else the
+    /// marker named by `output.insert_at` in the template's frontmatter, if any.
+    fn placement_for(
+        &self,
+        fs: &dyn FileOps,
+        template_name: &str,
+        path: &Path,
+    ) -> Option<Placement> {
+        self.placement.clone().or_else(|| {
+            let template_path = find_template(fs, template_name, path.parent()).ok()?;
+            let source = fs.read_to_string(&template_path).ok()?;
+            let (meta, _) = frontmatter::split(&template_path, &source).ok()?;
+            meta.output.insert_at.map(Placement::After)
+        })
+    }
+
+    fn is_scaffold(fs: &dyn FileOps, template_name: &str, path: &Path) -> bool {
+        scaffold::find_scaffold(fs, template_name, path.parent()).is_some()
+    }
+
+    /// `context` plus the times the file will have once the plan has run, as
+    /// `target_atime` and `target_mtime` (unix timestamps), so templates can
+    /// embed them. Times that aren't changed keep the file's current ones.
+    fn with_target_times(
+        &self,
+        fs: &dyn FileOps,
+        path: &Path,
+        file_exists: bool,
+        context: &Context,
+        explicit_times: Option<&FileTimeSpec>,
+    ) -> Result<Context, ZapError> {
+        let current = if file_exists {
+            fs.metadata(path, self.symlink_only)?.times()
+        } else {
+            FileTimeSpec::now()
+        };
+        let set = match (explicit_times, self.adjust) {
+            (Some(times), _) => *times,
+            (None, None) => FileTimeSpec::now(),
+            (None, Some(_)) => current,
+        }
+        .with_flags(self.should_update_access, self.should_update_modification);
+        let set = match self.adjust {
+            Some(_) => set,
+            None => set.clamped(&self.bounds).rounded(&self.rounding),
+        };
+        let mut target = FileTimeSpec {
+            atime: set.atime.or(current.atime),
+            mtime: set.mtime.or(current.mtime),
+            btime: None,
+        };
+        if let Some(adjustment) = self.adjust {
+            let adjusted = target
+                .adjust_by_string(adjustment, self.tz, self.dst)?
+                .with_flags(self.should_update_access, self.should_update_modification)
+                .clamped(&self.bounds)
+                .rounded(&self.rounding);
+            target = FileTimeSpec {
+                atime: adjusted.atime.or(target.atime),
+                mtime: adjusted.mtime.or(target.mtime),
+                btime: None,
+            };
+        }
+
+        let mut context = context.clone();
+        let seconds = |time: Option<filetime::FileTime>| time.map(|time| time.unix_seconds());
+        context.insert("target_atime", &seconds(target.atime));
+        context.insert("target_mtime", &seconds(target.mtime));
+        Ok(context)
+    }
+
     pub fn plan(
         &self,
+        fs: &dyn FileOps,
         path: &Path,
+        context: &Context,
         explicit_times: Option<&FileTimeSpec>,
     ) -> Result<Vec<Action>, ZapError> {
-        let file_exists = path.exists();
+        let file_exists = fs.exists(path);
+        // `.zap.toml` only matters for files that get created
+        let dir_template =
+            if !file_exists && !self.no_create && self.template.is_none() && self.dir_templates {
+                find_dir_template(fs, path)?
+            } else {
+                None
+            };
+        let creation_template = self
+            .template
+            .or(dir_template.as_deref())
+            .or_else(|| self.rule_template(path))
+            .or_else(|| self.auto_template(path))
+            .or(self.default_template);
+        // Existing files only become scaffolds when the template is given explicitly
+        let scaffold_template = if file_exists {
+            self.template
+        } else {
+            creation_template
+        }
+        .filter(|name| Self::is_scaffold(fs, name, path));
         let mut actions = Vec::new();
 
+        // Step 0: Apply filters against the existing file
+        if self.filter.is_active() {
+            if let Some(reason) = self.filter.skip_reason(fs, path, self.symlink_only)? {
+                actions.push(Action::Skip { reason });
+                return Ok(actions);
+            }
+        }
+
+        // Times that come from the file itself (`--from-exif`, `--from-git`)
+        let own_times = if !file_exists {
+            None
+        } else if self.from_exif {
+            Some((
+                media::capture_time(path, self.tz)?.map(FileTimeSpec::from_datetime),
+                "No capture time in its EXIF data or video header",
+            ))
+        } else if self.from_git {
+            Some((
+                explicit_times.copied(),
+                "Not committed to its git repository",
+            ))
+        } else {
+            None
+        };
+        let explicit_times = match own_times {
+            Some((Some(times), _)) => Some(times),
+            Some((None, reason)) => {
+                actions.push(Action::Skip {
+                    reason: reason.to_string(),
+                });
+                return Ok(actions);
+            }
+            None => explicit_times.copied(),
+        };
+        let explicit_times = explicit_times.as_ref();
+
+        let renders_template = if file_exists {
+            self.template.is_some() || self.fill
+        } else {
+            !self.no_create && creation_template.is_some()
+        };
+        let template_context;
+        let context = if renders_template {
+            template_context =
+                self.with_target_times(fs, path, file_exists, context, explicit_times)?;
+            &template_context
+        } else {
+            context
+        };
+
         // Step 1: Handle file operations
         if !file_exists && self.no_create {
             actions.push(Action::Skip {
                 reason: "File doesn't exist and --no-create flag is set".to_string(),
             });
             return Ok(actions);
-        } else if !file_exists && self.template.is_some() {
+        } else if let Some(template_name) = scaffold_template {
+            actions.push(Action::CreateScaffold {
+                template_name: template_name.to_string(),
+                context: context.clone(),
+                format_with: self.format_with.map(String::from),
+            });
+        } else if let Some(template_name) = creation_template.filter(|_| !file_exists) {
             actions.push(Action::CreateWithTemplate {
-                template_name: self.template.unwrap().to_string(),
-                context_str: self.context.map(|s| s.to_string()),
+                template_name: template_name.to_string(),
+                context: context.clone(),
+                format_with: self.format_with.map(String::from),
             });
         } else if !file_exists {
             actions.push(Action::CreateEmpty);
-        } else if file_exists && self.template.is_some() {
+        } else if self.fill {
+            actions.push(Action::FillPlaceholders {
+                context: context.clone(),
+            });
+        } else if let Some((template_name, placement)) = self
+            .template
+            .and_then(|name| Some((name, self.placement_for(fs, name, path)?)))
+        {
+            actions.push(Action::ExtendWithTemplate {
+                template_name: template_name.to_string(),
+                context: context.clone(),
+                format_with: self.format_with.map(String::from),
+                placement,
+            });
+        } else if let Some(template_name) = self.template {
             actions.push(Action::OverwriteWithTemplate {
-                template_name: self.template.unwrap().to_string(),
-                context_str: self.context.map(|s| s.to_string()),
+                template_name: template_name.to_string(),
+                context: context.clone(),
+                format_with: self.format_with.map(String::from),
             });
         }
 
@@ -74,8 +523,15 @@ impl<'a> Planner<'a> {
         match (explicit_times, self.adjust.is_some()) {
             (Some(times), _) => {
                 // Explicit times provided - always set them (with flags applied)
-                let flagged_times =
-                    times.with_flags(self.should_update_access, self.should_update_modification);
+                let flagged_times = times
+                    .with_flags(self.should_update_access, self.should_update_modification)
+                    .with_birth_time(self.should_update_birth);
+                // With -A, the adjusted times are clamped and rounded instead
+                let flagged_times = if self.adjust.is_some() {
+                    flagged_times
+                } else {
+                    flagged_times.clamped(&self.bounds).rounded(&self.rounding)
+                };
                 actions.push(Action::SetTimes {
                     times: flagged_times,
                     symlink_only: self.symlink_only,
@@ -84,7 +540,10 @@ impl<'a> Planner<'a> {
             (None, false) => {
                 // No explicit times and no adjustment - set to current time (regular touch)
                 let current_times = FileTimeSpec::now()
-                    .with_flags(self.should_update_access, self.should_update_modification);
+                    .with_flags(self.should_update_access, self.should_update_modification)
+                    .with_birth_time(self.should_update_birth)
+                    .clamped(&self.bounds)
+                    .rounded(&self.rounding);
                 actions.push(Action::SetTimes {
                     times: current_times,
                     symlink_only: self.symlink_only,
@@ -99,8 +558,13 @@ impl<'a> Planner<'a> {
         if let Some(adjustment_str) = self.adjust {
             actions.push(Action::AdjustTimes {
                 adjustment_str: adjustment_str.to_string(),
+                tz: self.tz,
+                dst: self.dst,
                 should_update_access: self.should_update_access,
                 should_update_modification: self.should_update_modification,
+                should_update_birth: self.should_update_birth,
+                bounds: self.bounds,
+                rounding: self.rounding,
                 symlink_only: self.symlink_only,
             });
         }
@@ -109,79 +573,296 @@ impl<'a> Planner<'a> {
     }
 }
 
+/// Answers to the "file already exists" prompt. `All` and `None` are
+/// remembered for the rest of the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictChoice {
+    Yes,
+    No,
+    All,
+    None,
+    ViewDiff,
+}
+
+impl ConflictChoice {
+    pub const ALL: [ConflictChoice; 5] = [
+        ConflictChoice::Yes,
+        ConflictChoice::No,
+        ConflictChoice::All,
+        ConflictChoice::None,
+        ConflictChoice::ViewDiff,
+    ];
+}
+
+impl std::fmt::Display for ConflictChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConflictChoice::Yes => "yes",
+            ConflictChoice::No => "no",
+            ConflictChoice::All => "all (overwrite every remaining file)",
+            ConflictChoice::None => "none (keep every remaining file)",
+            ConflictChoice::ViewDiff => "view diff",
+        })
+    }
+}
+
+/// Run-level state of the overwrite prompt, shared by every
+/// `OverwriteWithTemplate` action of a run.
+#[derive(Debug, Default)]
+pub struct ConflictResolver {
+    remembered: Option<bool>,
+}
+
+impl ConflictResolver {
+    /// Whether `filename` should be overwritten with `rendered`. Asks the user
+    /// unless an earlier answer applies to the rest of the run.
+    pub fn should_overwrite(
+        &mut self,
+        filename: &str,
+        current: &str,
+        rendered: &str,
+    ) -> Result<bool, ZapError> {
+        loop {
+            if let Some(answer) = self.remembered {
+                return Ok(answer);
+            }
+            let selection = Select::new()
+                .with_prompt(format!("File '{filename}' already exists. Overwrite it?"))
+                .items(&ConflictChoice::ALL)
+                .default(1)
+                .interact()?;
+            match self.answer(ConflictChoice::ALL[selection]) {
+                Some(answer) => return Ok(answer),
+                None => print!("{}", unified_diff(filename, current, rendered)),
+            }
+        }
+    }
+
+    /// Record a choice; `None` means the user wants to see the diff first.
+    pub fn answer(&mut self, choice: ConflictChoice) -> Option<bool> {
+        match choice {
+            ConflictChoice::Yes => Some(true),
+            ConflictChoice::No => Some(false),
+            ConflictChoice::All => {
+                self.remembered = Some(true);
+                Some(true)
+            }
+            ConflictChoice::None => {
+                self.remembered = Some(false);
+                Some(false)
+            }
+            ConflictChoice::ViewDiff => None,
+        }
+    }
+}
+
+/// What the actions of a run share from one file to the next.
+pub struct RunState {
+    pub renderer: Renderer,
+    pub timings: Timings,
+    pub conflicts: ConflictResolver,
+    /// Times of the files before the run changed them, for `--undo`
+    pub journal: Journal,
+    /// Put back the access time of files whose content is rewritten (`--preserve-atime`)
+    pub preserve_atime: bool,
+}
+
+impl RunState {
+    pub fn new(timings: Timings) -> Self {
+        RunState {
+            renderer: Renderer::new(),
+            timings,
+            conflicts: ConflictResolver::default(),
+            journal: Journal::default(),
+            preserve_atime: false,
+        }
+    }
+}
+
+/// Unified diff from a file's current contents to the rendered template.
+pub fn unified_diff(filename: &str, current: &str, rendered: &str) -> String {
+    TextDiff::from_lines(current, rendered)
+        .unified_diff()
+        .header(filename, &format!("{filename} (template)"))
+        .to_string()
+}
+
 impl Action {
     pub fn execute(
         self,
+        fs: &mut dyn FileOps,
         path: &Path,
         filename: &str,
         create_intermediate_dirs: bool,
+        run: &mut RunState,
     ) -> Result<(), anyhow::Error> {
+        // Reading and rewriting a file moves its access time, so it's taken beforehand
+        let preserved_atime = match &self {
+            Action::OverwriteWithTemplate { .. }
+            | Action::ExtendWithTemplate { .. }
+            | Action::FillPlaceholders { .. }
+                if run.preserve_atime =>
+            {
+                Some(fs.metadata(path, false)?.atime)
+            }
+            _ => None,
+        };
         match self {
             Action::Skip { reason } => {
                 println!("Skipping {filename}: {reason}");
             }
             Action::CreateEmpty => {
-                Self::ensure_parent_directory_exists(path, create_intermediate_dirs)?;
-                let _file = std::fs::File::create(path)?;
+                Self::ensure_parent_directory_exists(fs, path, create_intermediate_dirs)?;
+                run.timings.measure(Phase::FileWrite, || fs.create(path))?;
             }
             Action::CreateWithTemplate {
                 template_name,
-                context_str,
+                context,
+                format_with,
             } => {
-                Self::ensure_parent_directory_exists(path, create_intermediate_dirs)?;
-                Self::write_template_to_file(path, &template_name, context_str.as_deref())?;
+                Self::ensure_parent_directory_exists(fs, path, create_intermediate_dirs)?;
+                let rendered = Self::template_contents(
+                    &mut run.renderer,
+                    path,
+                    &template_name,
+                    &context,
+                    format_with.as_deref(),
+                    true,
+                    &mut run.timings,
+                )?;
+                run.timings
+                    .measure(Phase::FileWrite, || fs.write(path, &rendered))?;
+                Self::apply_file_mode(fs, path, &template_name, run)?;
             }
             Action::OverwriteWithTemplate {
                 template_name,
-                context_str,
+                context,
+                format_with,
             } => {
-                let confirmation = Confirm::new()
-                    .with_prompt(format!(
-                        "File '{filename}' already exists. Do you want to overwrite it?",
-                    ))
-                    .default(false)
-                    .interact()?;
-
-                if confirmation {
-                    Self::write_template_to_file(path, &template_name, context_str.as_deref())?;
+                let rendered = Self::template_contents(
+                    &mut run.renderer,
+                    path,
+                    &template_name,
+                    &context,
+                    format_with.as_deref(),
+                    true,
+                    &mut run.timings,
+                )?;
+                let current = fs.read_to_string(path).unwrap_or_default();
+
+                if run.conflicts.should_overwrite(
+                    filename,
+                    &current,
+                    &String::from_utf8_lossy(&rendered),
+                )? {
+                    run.timings
+                        .measure(Phase::FileWrite, || fs.write(path, &rendered))?;
+                    Self::apply_file_mode(fs, path, &template_name, run)?;
                 } else {
-                    // User declined overwrite - this will interrupt the action sequence
+                    // User declined overwrite; `execute_actions` goes on with the times
                     return Err(ZapError::UserDeclinedOverwrite.into());
                 }
             }
+            Action::ExtendWithTemplate {
+                template_name,
+                context,
+                format_with,
+                placement,
+            } => {
+                let rendered = Self::render_template(
+                    &mut run.renderer,
+                    path,
+                    &template_name,
+                    &context,
+                    format_with.as_deref(),
+                    true,
+                    &mut run.timings,
+                )?;
+                let current = fs.read_to_string(path)?;
+                if placement.already_present(&current, &rendered) {
+                    println!("Skipping {filename}: the snippet is already there");
+                } else {
+                    let extended = placement.insert(&current, &rendered)?;
+                    run.timings
+                        .measure(Phase::FileWrite, || fs.write(path, extended.as_bytes()))?;
+                }
+            }
+            Action::FillPlaceholders { context } => {
+                let current = fs.read_to_string(path)?;
+                let filled = Self::fill_placeholders(
+                    &mut run.renderer,
+                    path,
+                    &current,
+                    &context,
+                    &mut run.timings,
+                )?;
+                // The file is only touched once it rendered, and then replaced as a whole
+                if filled != current {
+                    run.timings
+                        .measure(Phase::FileWrite, || fs.replace(path, filled.as_bytes()))?;
+                }
+            }
+            Action::CreateScaffold {
+                template_name,
+                context,
+                format_with,
+            } => {
+                Self::ensure_parent_directory_exists(fs, path, create_intermediate_dirs)?;
+                Self::create_scaffold(
+                    fs,
+                    path,
+                    &template_name,
+                    &context,
+                    format_with.as_deref(),
+                    run,
+                )?;
+            }
             Action::SetTimes {
                 times,
                 symlink_only,
             } => {
-                crate::set_file_times(path, &times, symlink_only)?;
+                run.timings
+                    .measure(Phase::SetTimes, || fs.set_times(path, &times, symlink_only))?;
             }
             Action::AdjustTimes {
                 adjustment_str,
+                tz,
+                dst,
                 should_update_access,
                 should_update_modification,
+                should_update_birth,
+                bounds,
+                rounding,
                 symlink_only,
             } => {
-                let metadata = if symlink_only {
-                    std::fs::symlink_metadata(path)?
-                } else {
-                    std::fs::metadata(path)?
-                };
-                let adjusted_times = adjust_file_times_from_metadata(&metadata, &adjustment_str)?
-                    .with_flags(should_update_access, should_update_modification);
-                crate::set_file_times(path, &adjusted_times, symlink_only)?;
+                let adjusted_times = fs
+                    .metadata(path, symlink_only)?
+                    .times()
+                    .adjust_by_string(&adjustment_str, tz, dst)?
+                    .with_flags(should_update_access, should_update_modification)
+                    .with_birth_time(should_update_birth)
+                    .clamped(&bounds)
+                    .rounded(&rounding);
+                run.timings.measure(Phase::SetTimes, || {
+                    fs.set_times(path, &adjusted_times, symlink_only)
+                })?;
             }
         }
+        if let Some(atime) = preserved_atime {
+            fs.set_times(path, &FileTimeSpec::access_only(atime), false)?;
+        }
         Ok(())
     }
 
     fn ensure_parent_directory_exists(
+        fs: &mut dyn FileOps,
         path: &Path,
         create_intermediate_dirs: bool,
     ) -> Result<(), anyhow::Error> {
         if let Some(parent) = path.parent() {
-            if parent.components().next().is_some() && !parent.exists() {
+            if parent.components().next().is_some() && !fs.exists(parent) {
                 if create_intermediate_dirs {
-                    std::fs::create_dir_all(parent)?;
+                    fs.create_dir_all(parent)?;
                 } else {
                     let confirmation = Confirm::new()
                         .with_prompt(format!(
@@ -191,7 +872,7 @@ impl Action {
                         .default(false)
                         .interact()?;
                     if confirmation {
-                        std::fs::create_dir_all(parent)?;
+                        fs.create_dir_all(parent)?;
                     } else {
                         return Err(ZapError::UserDeclinedDirCreation.into());
                     }
@@ -201,68 +882,267 @@ impl Action {
         Ok(())
     }
 
-    fn write_template_to_file(
+    /// Render every file of the scaffold `template_name` into the directory `path`.
+    /// Files that already exist are only replaced after confirmation.
+    fn create_scaffold(
+        fs: &mut dyn FileOps,
         path: &Path,
         template_name: &str,
-        context_str: Option<&str>,
+        context: &Context,
+        format_with: Option<&str>,
+        run: &mut RunState,
     ) -> Result<(), anyhow::Error> {
-        use crate::{get_config_dir, get_template_path, plugins::Plugins};
-        use std::fs::File;
-        use std::io::Write;
-        use tera::{Context, Tera};
-
-        let template_path_full = get_template_path(template_name)?;
-        if !template_path_full.exists() {
-            return Err(ZapError::TemplateNotFound(template_path_full).into());
-        }
+        use crate::get_template_path;
 
-        let mut tera = Tera::default();
-        tera.add_template_file(&template_path_full, Some(template_name))?;
+        let dir = get_template_path(template_name, path.parent())?;
+        let context = scaffold::scaffold_context(path, context);
+        fs.create_dir_all(path)?;
 
-        let mut plugins = Plugins::new();
-        let plugins_dir = get_config_dir()?.join("plugins");
-        plugins.load_plugins_from_dir(&mut tera, &plugins_dir)?;
+        for file in scaffold::scaffold_files(&dir)? {
+            let target = path.join(scaffold::output_path(template_name, &file, &context)?);
+            let template = format!("{template_name}/{file}");
+            let rendered = Self::template_contents(
+                &mut run.renderer,
+                &target,
+                &template,
+                &context,
+                format_with,
+                true,
+                &mut run.timings,
+            )?;
 
-        let mut context = Context::new();
-        if let Some(ctx) = context_str {
-            for pair in ctx.split(',') {
-                let mut parts = pair.splitn(2, '=');
-                if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
-                    context.insert(key.trim(), value.trim());
+            if fs.exists(&target) {
+                let filename = target.to_string_lossy();
+                let current = fs.read_to_string(&target).unwrap_or_default();
+                if !run.conflicts.should_overwrite(
+                    &filename,
+                    &current,
+                    &String::from_utf8_lossy(&rendered),
+                )? {
+                    println!("Skipping {filename}: File already exists");
+                    continue;
                 }
+            } else if let Some(parent) = target.parent() {
+                fs.create_dir_all(parent)?;
+            }
+            run.timings
+                .measure(Phase::FileWrite, || fs.write(&target, &rendered))?;
+            Self::apply_file_mode(fs, &target, &template, run)?;
+        }
+        Ok(())
+    }
+
+    /// Give a file just written from `template_name` the permissions the template
+    /// asks for in its frontmatter, if any.
+    fn apply_file_mode(
+        fs: &mut dyn FileOps,
+        path: &Path,
+        template_name: &str,
+        run: &mut RunState,
+    ) -> Result<(), anyhow::Error> {
+        let prepared = run
+            .renderer
+            .prepare(template_name, path.parent(), &mut run.timings)?;
+        if let Some(mode) = prepared.meta.file_mode(fs.mode(path)?) {
+            fs.set_mode(path, mode)?;
+        }
+        Ok(())
+    }
+
+    /// The contents of a file created from `template_name`: binary templates are
+    /// copied as they are, all others are rendered like by `render_template`.
+    pub fn template_contents(
+        renderer: &mut Renderer,
+        path: &Path,
+        template_name: &str,
+        context: &Context,
+        format_with: Option<&str>,
+        interactive: bool,
+        timings: &mut Timings,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let prepared = renderer.prepare(template_name, path.parent(), timings)?;
+        if let Body::Binary(bytes) = &prepared.body {
+            return Ok(bytes.clone());
+        }
+        let rendered = Self::render_template(
+            renderer,
+            path,
+            template_name,
+            context,
+            format_with,
+            interactive,
+            timings,
+        )?;
+        Ok(rendered.into_bytes())
+    }
+
+    /// Render `template_name` for the file at `path`; templates are looked up
+    /// starting from the project that file belongs to. The result is piped through
+    /// `format_with`, or the formatter the template's frontmatter names, if any.
+    /// Unless `interactive` is false, missing required variables are asked for
+    /// when stdin is a terminal.
+    pub fn render_template(
+        renderer: &mut Renderer,
+        path: &Path,
+        template_name: &str,
+        context: &Context,
+        format_with: Option<&str>,
+        interactive: bool,
+        timings: &mut Timings,
+    ) -> Result<String, anyhow::Error> {
+        let eol = renderer.eol;
+        let prepared = renderer.prepare(template_name, path.parent(), timings)?;
+        let meta = &prepared.meta;
+        match &prepared.body {
+            Body::Verbatim(body) => return Ok(body.clone()),
+            Body::Binary(_) => {
+                return Err(ZapError::BinaryTemplate(template_name.to_string()).into());
             }
+            Body::Tera | Body::Engine(_) => {}
         }
-        let rendered = tera.render(template_name, &context)?;
 
-        let mut file = File::create(path)?;
-        file.write_all(rendered.as_bytes())?;
+        // The template's defaults and facts about the file come first,
+        // so context given for the run can override them
+        let mut full_context = meta.defaults_context();
+        full_context.extend(crate::context::file_context(path));
+        if prepared.uses_git {
+            full_context.extend(renderer.git_context(path));
+        }
+        crate::context::merge(&mut full_context, context.clone());
+        Self::ask_for_required(meta, template_name, &mut full_context, interactive)?;
 
+        let rendered = match &prepared.body {
+            Body::Engine(body) => timings.measure(Phase::Render, || {
+                engine::engine_for(meta.engine, template_name)?.render(
+                    template_name,
+                    body,
+                    &full_context.clone().into_json(),
+                )
+            })?,
+            _ => {
+                renderer.check_strict(&prepared.tera, template_name, &full_context, &|name| {
+                    let path = crate::get_template_path(name, path.parent()).ok()?;
+                    std::fs::read_to_string(path).ok()
+                })?;
+                timings
+                    .measure(Phase::Render, || {
+                        prepared.tera.render(template_name, &full_context)
+                    })
+                    .map_err(TeraError::from)?
+            }
+        };
+        let rendered = match format_with.or(meta.output.format_with.as_deref()) {
+            Some(command) => timings.measure(Phase::Format, || {
+                crate::formatter::format_with(command, &rendered)
+            })?,
+            None => rendered,
+        };
+        Ok(meta.finish_output(rendered, eol))
+    }
+
+    /// Render `contents`, the current contents of the file at `path`, as a template
+    /// with the same builtins, plugins and file facts as regular templates.
+    pub fn fill_placeholders(
+        renderer: &mut Renderer,
+        path: &Path,
+        contents: &str,
+        context: &Context,
+        timings: &mut Timings,
+    ) -> Result<String, anyhow::Error> {
+        let mut full_context = crate::context::file_context(path);
+        if crate::context::uses_git(contents) {
+            full_context.extend(renderer.git_context(path));
+        }
+        crate::context::merge(&mut full_context, context.clone());
+        let filled =
+            renderer.render_str(&path.to_string_lossy(), contents, &full_context, timings)?;
+        Ok(match renderer.eol {
+            Some(eol) => eol.apply(&filled),
+            None => filled,
+        })
+    }
+
+    /// Fill in required variables the context lacks by asking for them, or fail
+    /// naming all of them when nobody is there to answer.
+    fn ask_for_required(
+        meta: &TemplateMeta,
+        template_name: &str,
+        context: &mut Context,
+        interactive: bool,
+    ) -> Result<(), ZapError> {
+        let missing: Vec<String> = meta
+            .missing_required(context)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        if !interactive || !std::io::stdin().is_terminal() {
+            return Err(ZapError::MissingVariables {
+                template: template_name.to_string(),
+                names: missing.join(", "),
+            });
+        }
+        for name in missing {
+            let value: String = Input::new()
+                .with_prompt(format!("{template_name}: {name}"))
+                .interact_text()?;
+            context.insert(name, &value);
+        }
         Ok(())
     }
 }
 
+/// Identifies a file on disk by device and inode, so hard links to it can be recognised.
+pub type FileId = (u64, u64);
+
+/// The identity of the file at `path`, or `None` if it doesn't exist
+/// or the platform has no inode numbers.
+pub fn file_id(fs: &dyn FileOps, path: &Path, symlink_only: bool) -> Option<FileId> {
+    fs.metadata(path, symlink_only).ok()?.id
+}
+
 pub fn execute_actions(
+    fs: &mut dyn FileOps,
     actions: Vec<Action>,
     path: &Path,
     filename: &str,
     create_intermediate_dirs: bool,
+    run: &mut RunState,
 ) -> Result<(), anyhow::Error> {
+    let times_symlink_only = actions.iter().find_map(|action| match action {
+        Action::SetTimes { symlink_only, .. } | Action::AdjustTimes { symlink_only, .. } => {
+            Some(*symlink_only)
+        }
+        _ => None,
+    });
+    if let Some(symlink_only) = times_symlink_only {
+        if fs.exists(path) {
+            run.journal.record(fs, path, symlink_only)?;
+        }
+    }
     for action in actions {
-        action.execute(path, filename, create_intermediate_dirs)?;
+        let result = action.execute(fs, path, filename, create_intermediate_dirs, run);
+        if let Err(e) = result {
+            // Declining to overwrite keeps the contents, but the times are still set
+            if let Some(ZapError::UserDeclinedOverwrite) = e.downcast_ref::<ZapError>() {
+                println!("Not overwriting {filename}");
+                continue;
+            }
+            return Err(e);
+        }
     }
     Ok(())
 }
 
-pub fn open_in_editor(filepaths: &Vec<String>) -> Result<(), anyhow::Error> {
-    use std::env;
+pub fn open_in_editor(editor: &str, filepaths: &Vec<String>) -> Result<(), anyhow::Error> {
     use std::process::Command;
 
-    let editor_env_var = env::var("EDITOR").map_err(|_| ZapError::EditorNotSet)?;
-
-    let mut parts = editor_env_var.split_whitespace();
+    let mut parts = editor.split_whitespace();
     let editor_executable = parts
         .next()
-        .ok_or_else(|| ZapError::EditorCommandParseError(editor_env_var.clone()))?;
+        .ok_or_else(|| ZapError::EditorCommandParseError(editor.to_string()))?;
 
     let mut cmd = Command::new(editor_executable);
     cmd.args(parts);
@@ -273,9 +1153,198 @@ pub fn open_in_editor(filepaths: &Vec<String>) -> Result<(), anyhow::Error> {
             if status.success() {
                 Ok(())
             } else {
-                Err(ZapError::EditorExitedWithError(editor_env_var, status.code()).into())
+                Err(ZapError::EditorExitedWithError(editor.to_string(), status.code()).into())
             }
         }
-        Err(e) => Err(ZapError::EditorSpawnFailed(editor_env_var, e).into()),
+        Err(e) => Err(ZapError::EditorSpawnFailed(editor.to_string(), e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::RealFs;
+
+    #[test]
+    fn test_conflict_resolver_remembers_all_and_none() {
+        let mut conflicts = ConflictResolver::default();
+        assert_eq!(conflicts.answer(ConflictChoice::ViewDiff), None);
+        assert_eq!(conflicts.answer(ConflictChoice::No), Some(false));
+        assert_eq!(conflicts.answer(ConflictChoice::All), Some(true));
+        // Remembered answers are used without prompting
+        assert!(conflicts.should_overwrite("a.md", "old", "new").unwrap());
+
+        conflicts.answer(ConflictChoice::None);
+        assert!(!conflicts.should_overwrite("b.md", "old", "new").unwrap());
+    }
+
+    #[test]
+    fn test_placement_inserts_with_line_break() {
+        let insert =
+            |placement: Placement, current, rendered| placement.insert(current, rendered).unwrap();
+        assert_eq!(insert(Placement::End, "log\n", "entry\n"), "log\nentry\n");
+        assert_eq!(insert(Placement::End, "log", "entry\n"), "log\nentry\n");
+        assert_eq!(insert(Placement::End, "", "entry\n"), "entry\n");
+        assert_eq!(
+            insert(Placement::Start, "body\n", "// MIT\n"),
+            "// MIT\nbody\n"
+        );
+        assert_eq!(
+            insert(Placement::Start, "body\n", "// MIT"),
+            "// MIT\nbody\n"
+        );
+    }
+
+    #[test]
+    fn test_placement_after_marker() {
+        let marker = Placement::After("## TASKS".to_string());
+        let current = "# Plan\n\n## TASKS  \n- old\n";
+        assert_eq!(
+            marker.insert(current, "- new").unwrap(),
+            "# Plan\n\n## TASKS  \n- new\n- old\n"
+        );
+        assert_eq!(
+            marker.insert("## TASKS", "- new\n").unwrap(),
+            "## TASKS\n- new\n"
+        );
+        assert!(matches!(
+            marker.insert("# Plan\n", "- new"),
+            Err(ZapError::MarkerNotFound { .. })
+        ));
+
+        assert!(marker.already_present(current, "- old\n"));
+        assert!(!marker.already_present(current, "- new\n"));
+        assert!(!Placement::End.already_present(current, "- old\n"));
+        // Only the lines right after the marker count, and only whole lines
+        let current = "- done\n## TASKS\n- older\n- old\n\n- later\n";
+        assert!(marker.already_present(current, "- older\n- old\n"));
+        assert!(!marker.already_present(current, "- done\n"));
+        assert!(!marker.already_present(current, "- later\n"));
+        assert!(!marker.already_present(current, "- ol\n"));
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff("notes.md", "title\nold\n", "title\nnew\n");
+        assert!(diff.contains("--- notes.md\n+++ notes.md (template)\n"));
+        assert!(diff.contains("-old\n+new\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_id_identifies_hard_links() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let original = dir.path().join("original.txt");
+        let link = dir.path().join("link.txt");
+        let other = dir.path().join("other.txt");
+        std::fs::write(&original, "").unwrap();
+        std::fs::write(&other, "").unwrap();
+        std::fs::hard_link(&original, &link).unwrap();
+
+        assert!(file_id(&RealFs::new(), &original, false).is_some());
+        assert_eq!(
+            file_id(&RealFs::new(), &original, false),
+            file_id(&RealFs::new(), &link, false)
+        );
+        assert_ne!(
+            file_id(&RealFs::new(), &original, false),
+            file_id(&RealFs::new(), &other, false)
+        );
+        assert_eq!(
+            file_id(&RealFs::new(), &dir.path().join("missing.txt"), false),
+            None
+        );
+    }
+
+    #[test]
+    fn test_age_filter_matches() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let older = FileFilter {
+            older_than: Some(30 * day),
+            ..Default::default()
+        };
+        assert!(older.matches_age(31 * day));
+        assert!(!older.matches_age(29 * day));
+
+        let window = FileFilter {
+            older_than: Some(day),
+            newer_than: Some(7 * day),
+            ..Default::default()
+        };
+        assert!(window.matches_age(3 * day));
+        assert!(!window.matches_age(8 * day));
+        assert!(!window.matches_age(Duration::from_secs(60)));
+
+        assert!(!FileFilter::default().is_active());
+        assert!(FileFilter::default().matches_age(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_mime_filter_matches() {
+        let images = FileFilter {
+            mime: Some("image/*".to_string()),
+            ..Default::default()
+        };
+        assert!(images.matches_mime(Some("image/jpeg")));
+        assert!(!images.matches_mime(Some("video/mp4")));
+        assert!(!images.matches_mime(None));
+
+        let png = FileFilter {
+            mime: Some("image/png".to_string()),
+            ..Default::default()
+        };
+        assert!(png.matches_mime(Some("IMAGE/PNG")));
+        assert!(!png.matches_mime(Some("image/jpeg")));
+
+        let anything = FileFilter {
+            mime: Some("*/*".to_string()),
+            ..Default::default()
+        };
+        assert!(anything.matches_mime(Some("application/pdf")));
+        assert!(FileFilter::default().matches_mime(None));
+    }
+
+    #[test]
+    fn test_type_filter_skips_other_kinds() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("file.txt");
+        std::fs::write(&file_path, "text").unwrap();
+
+        let only_dirs = FileFilter {
+            only_type: Some(FileKind::Directory),
+            ..Default::default()
+        };
+        assert!(
+            only_dirs
+                .skip_reason(&RealFs::new(), dir.path(), false)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            only_dirs
+                .skip_reason(&RealFs::new(), &file_path, false)
+                .unwrap()
+                .is_some()
+        );
+
+        let only_images = FileFilter {
+            mime: Some("image/*".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            only_images
+                .skip_reason(&RealFs::new(), &file_path, false)
+                .unwrap()
+                .is_some()
+        );
+
+        let png_path = dir.path().join("pixel.png");
+        std::fs::write(&png_path, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        assert!(
+            only_images
+                .skip_reason(&RealFs::new(), &png_path, false)
+                .unwrap()
+                .is_none()
+        );
     }
 }
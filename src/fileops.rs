@@ -0,0 +1,1128 @@
+use filetime::FileTime;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::args::FileKind;
+use crate::errors::ZapError;
+use crate::file_time_util::{self, FileTimeSpec};
+use crate::fileaction::FileId;
+
+/// The file metadata zap's actions rely on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub atime: FileTime,
+    pub mtime: FileTime,
+    /// Birth time, where the platform and filesystem keep one
+    pub btime: Option<FileTime>,
+    /// `None` for FIFOs, sockets and devices
+    pub kind: Option<FileKind>,
+    /// Device and inode, where the platform has them
+    pub id: Option<FileId>,
+}
+
+impl FileMetadata {
+    /// All times as a spec, ready to be adjusted and written back
+    pub fn times(&self) -> FileTimeSpec {
+        FileTimeSpec {
+            atime: Some(self.atime),
+            mtime: Some(self.mtime),
+            btime: self.btime,
+        }
+    }
+}
+
+/// The filesystem operations used to plan and execute actions.
+/// `RealFs` talks to the disk; `MemoryFs` keeps everything in memory,
+/// so the planner and executor can be exercised without touching the disk.
+pub trait FileOps {
+    fn exists(&self, path: &Path) -> bool;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Create an empty file; one that appeared since it was checked is left as it is
+    fn create(&mut self, path: &Path) -> io::Result<()>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+
+    /// At most the first `len` bytes of a file, enough to sniff its content type
+    fn read_start(&self, path: &Path, len: usize) -> io::Result<Vec<u8>>;
+
+    /// Replace the contents of a file, creating it if needed
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Replace the contents of an existing file atomically: readers see either
+    /// the old or the new contents, never a half-written file. The old contents
+    /// are kept next to it first, with `BACKUP_SUFFIX` added to its name. Links
+    /// stay links: it's the file they lead to that gets the new contents.
+    fn replace(&mut self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Metadata of the file itself (`symlink_only`) or of what it points to
+    fn metadata(&self, path: &Path, symlink_only: bool) -> io::Result<FileMetadata>;
+
+    fn set_times(
+        &mut self,
+        path: &Path,
+        times: &FileTimeSpec,
+        symlink_only: bool,
+    ) -> Result<(), ZapError>;
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()>;
+
+    /// Unix permission bits of a file, like `0o644`
+    fn mode(&self, path: &Path) -> io::Result<u32>;
+
+    /// Set the Unix permission bits of a file; a no-op on Windows, which has none
+    fn set_mode(&mut self, path: &Path, mode: u32) -> io::Result<()>;
+}
+
+/// Added to the name of a file to get the name of its backup, `notes.md~`.
+pub const BACKUP_SUFFIX: &str = "~";
+
+/// Whether the file has more than one name.
+#[cfg(unix)]
+fn is_hard_linked(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn is_hard_linked(_metadata: &std::fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<FileId> {
+    None
+}
+
+/// The local filesystem. Each file is opened when its existence is checked, and the
+/// content write and the times go through that descriptor (`futimens` on Unix). A file
+/// swapped in at the same path between those steps is refused or left alone, and a
+/// file found missing is created only if nothing has appeared there since. Files that
+/// can't be opened, and links themselves, are handled by path.
+#[derive(Debug, Default)]
+pub struct RealFs {
+    /// What the last few existence checks found, newest last: the open file, or
+    /// `None` when the path was missing. Parent directories and config files are
+    /// checked in between, so one entry isn't enough.
+    checked: RefCell<Vec<(PathBuf, Option<File>)>>,
+}
+
+/// How many checked paths `RealFs` remembers, so long runs don't run out of descriptors
+const CHECKED_PATHS: usize = 8;
+
+impl RealFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn keep(&self, path: &Path, file: Option<File>) {
+        let mut checked = self.checked.borrow_mut();
+        checked.retain(|(checked_path, _)| checked_path != path);
+        if checked.len() == CHECKED_PATHS {
+            checked.remove(0);
+        }
+        checked.push((path.to_path_buf(), file));
+    }
+
+    fn forget(&self, path: &Path) {
+        self.checked
+            .borrow_mut()
+            .retain(|(checked_path, _)| checked_path != path);
+    }
+
+    /// What the last check of `path` found: `Some(None)` if it was missing
+    fn checked(&self, path: &Path) -> Option<Option<File>> {
+        let checked = self.checked.borrow();
+        let (_, file) = checked
+            .iter()
+            .find(|(checked_path, _)| checked_path == path)?;
+        Some(match file {
+            Some(file) => Some(file.try_clone().ok()?),
+            None => None,
+        })
+    }
+
+    /// Run `f` on the open file of `path`, opening it unless it was opened before.
+    /// `None` when it can't be opened.
+    fn with_file<T>(&self, path: &Path, f: impl FnOnce(&File) -> T) -> Option<T> {
+        let file = match self.checked(path) {
+            Some(Some(file)) => file,
+            _ => {
+                let file = open_existing(path).ok()?;
+                self.keep(path, Some(file.try_clone().ok()?));
+                file
+            }
+        };
+        Some(f(&file))
+    }
+}
+
+/// Open an existing file or directory just to look at it and set its times
+#[cfg(unix)]
+fn open_existing(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    // Don't wait for a writer to open a FIFO, or make a terminal the controlling one
+    File::options()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK | libc::O_NOCTTY)
+        .open(path)
+}
+
+/// Windows only sets times through handles opened for writing, so it goes by path
+#[cfg(not(unix))]
+fn open_existing(_path: &Path) -> io::Result<File> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Whether two open files are the same file.
+#[cfg(unix)]
+fn same_file(a: &File, b: &File) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (a.metadata()?, b.metadata()?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &File, _b: &File) -> io::Result<bool> {
+    Ok(true)
+}
+
+impl FileOps for RealFs {
+    fn exists(&self, path: &Path) -> bool {
+        match open_existing(path) {
+            Ok(file) => {
+                self.keep(path, Some(file));
+                true
+            }
+            // There, but not for us to open: nothing to hold on to
+            Err(_) if path.exists() => {
+                self.forget(path);
+                true
+            }
+            Err(_) => {
+                self.keep(path, None);
+                false
+            }
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn create(&mut self, path: &Path) -> io::Result<()> {
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        self.keep(path, Some(file));
+        Ok(())
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn read_start(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let mut start = Vec::with_capacity(len);
+        File::open(path)?.take(len as u64).read_to_end(&mut start)?;
+        Ok(start)
+    }
+
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = match self.checked(path) {
+            // Write to the file that was checked, not one swapped in since
+            Some(Some(checked)) => {
+                let file = File::options().write(true).open(path)?;
+                if !same_file(&file, &checked)? {
+                    return Err(io::Error::other(format!(
+                        "{} was replaced since it was checked",
+                        path.display()
+                    )));
+                }
+                file.set_len(0)?;
+                file
+            }
+            // It was missing, so whatever is there now isn't ours to write to;
+            // `create_new` doesn't follow a symlink put there either
+            Some(None) => File::options().write(true).create_new(true).open(path)?,
+            None => File::create(path)?,
+        };
+        file.write_all(contents)?;
+        self.keep(path, Some(file));
+        Ok(())
+    }
+
+    fn replace(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        // Replace what a symlink leads to, not the link
+        let target = std::fs::canonicalize(path)?;
+        let name = target
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+        let sibling = |prefix: &str, suffix: &str| {
+            let mut sibling_name = std::ffi::OsString::from(prefix);
+            sibling_name.push(name);
+            sibling_name.push(suffix);
+            target.with_file_name(sibling_name)
+        };
+        std::fs::copy(&target, sibling("", BACKUP_SUFFIX))?;
+
+        let metadata = std::fs::metadata(&target)?;
+        if is_hard_linked(&metadata) {
+            // A renamed file would lose its other names, so it's written in place;
+            // the backup has the old contents if that's interrupted
+            let mut file = File::options().write(true).truncate(true).open(&target)?;
+            file.write_all(contents)?;
+            self.keep(path, Some(file));
+            return Ok(());
+        }
+
+        // Write next to the file so the rename stays on one filesystem
+        let temp = sibling(".", ".zap-tmp");
+        let result = File::create(&temp).and_then(|mut file| {
+            file.write_all(contents)?;
+            file.set_permissions(metadata.permissions())?;
+            std::fs::rename(&temp, &target)?;
+            Ok(file)
+        });
+        match result {
+            Ok(file) => {
+                self.keep(path, Some(file));
+                Ok(())
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&temp);
+                Err(e)
+            }
+        }
+    }
+
+    fn metadata(&self, path: &Path, symlink_only: bool) -> io::Result<FileMetadata> {
+        let metadata = if symlink_only {
+            std::fs::symlink_metadata(path)?
+        } else {
+            match self.with_file(path, File::metadata) {
+                Some(metadata) => metadata?,
+                None => std::fs::metadata(path)?,
+            }
+        };
+        Ok(FileMetadata {
+            atime: FileTime::from_last_access_time(&metadata),
+            mtime: FileTime::from_last_modification_time(&metadata),
+            btime: FileTime::from_creation_time(&metadata),
+            kind: FileKind::of(&metadata.file_type()),
+            id: file_id(&metadata),
+        })
+    }
+
+    fn set_times(
+        &mut self,
+        path: &Path,
+        times: &FileTimeSpec,
+        symlink_only: bool,
+    ) -> Result<(), ZapError> {
+        if symlink_only {
+            return crate::set_file_times(path, times, symlink_only);
+        }
+        match self.with_file(path, |file| file_time_util::set_handle_times(file, times)) {
+            Some(result) => result?,
+            None => file_time_util::set_times_with_mode(
+                path,
+                &FileTimeSpec {
+                    btime: None,
+                    ..*times
+                },
+                false,
+            )?,
+        }
+        // Birth times can only be set by path
+        match times.btime {
+            Some(btime) => file_time_util::set_birth_time(path, btime, false),
+            None => Ok(()),
+        }
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    #[cfg(unix)]
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(std::fs::metadata(path)?.permissions().mode() & 0o7777)
+    }
+
+    #[cfg(not(unix))]
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        let readonly = std::fs::metadata(path)?.permissions().readonly();
+        Ok(if readonly { 0o444 } else { 0o644 })
+    }
+
+    #[cfg(unix)]
+    fn set_mode(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+    }
+
+    #[cfg(not(unix))]
+    fn set_mode(&mut self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A file held by `MemoryFs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryFile {
+    pub contents: Vec<u8>,
+    pub metadata: FileMetadata,
+    pub mode: u32,
+}
+
+/// An in-memory filesystem without symlinks. Creating a file requires its
+/// parent directory to exist, just like on disk; root and relative paths
+/// without a directory part always have an existing parent.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryFs {
+    files: HashMap<PathBuf, MemoryFile>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn file(&self, path: &Path) -> Option<&MemoryFile> {
+        self.files.get(path)
+    }
+
+    fn dir_exists(&self, path: &Path) -> bool {
+        path.as_os_str().is_empty() || path.parent().is_none() || self.dirs.contains(path)
+    }
+
+    fn ensure_parent_exists(&self, path: &Path) -> io::Result<()> {
+        match path.parent() {
+            Some(parent) if !self.dir_exists(parent) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("directory {} doesn't exist", parent.display()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} doesn't exist", path.display()),
+        )
+    }
+}
+
+impl FileOps for MemoryFs {
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path) || self.dir_exists(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.dir_exists(path)
+    }
+
+    fn create(&mut self, path: &Path) -> io::Result<()> {
+        if self.files.contains_key(path) {
+            return Ok(());
+        }
+        self.write(path, &[])
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let file = self.files.get(path).ok_or_else(|| Self::not_found(path))?;
+        String::from_utf8(file.contents.clone())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn read_start(&self, path: &Path, len: usize) -> io::Result<Vec<u8>> {
+        let file = self.files.get(path).ok_or_else(|| Self::not_found(path))?;
+        Ok(file.contents[..len.min(file.contents.len())].to_vec())
+    }
+
+    fn write(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.ensure_parent_exists(path)?;
+        let now = FileTime::now();
+        let file = self
+            .files
+            .entry(path.to_path_buf())
+            .or_insert_with(|| MemoryFile {
+                contents: Vec::new(),
+                metadata: FileMetadata {
+                    atime: now,
+                    mtime: now,
+                    btime: Some(now),
+                    kind: Some(FileKind::File),
+                    id: None,
+                },
+                mode: 0o644,
+            });
+        file.contents = contents.to_vec();
+        file.metadata.mtime = now;
+        Ok(())
+    }
+
+    fn replace(&mut self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let Some(old) = self.files.get(path).cloned() else {
+            return Err(Self::not_found(path));
+        };
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(BACKUP_SUFFIX);
+        self.files.insert(PathBuf::from(backup), old);
+        self.write(path, contents)?;
+        // Like the renamed file on disk, the replacement has a fresh access time
+        if let Some(file) = self.files.get_mut(path) {
+            file.metadata.atime = file.metadata.mtime;
+        }
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path, _symlink_only: bool) -> io::Result<FileMetadata> {
+        self.files
+            .get(path)
+            .map(|file| file.metadata)
+            .ok_or_else(|| Self::not_found(path))
+    }
+
+    fn set_times(
+        &mut self,
+        path: &Path,
+        times: &FileTimeSpec,
+        _symlink_only: bool,
+    ) -> Result<(), ZapError> {
+        let file = self
+            .files
+            .get_mut(path)
+            .ok_or_else(|| ZapError::SetTimesError(Self::not_found(path)))?;
+        if let Some(atime) = times.atime {
+            file.metadata.atime = atime;
+        }
+        if let Some(mtime) = times.mtime {
+            file.metadata.mtime = mtime;
+        }
+        if let Some(btime) = times.btime {
+            file.metadata.btime = Some(btime);
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let missing: Vec<PathBuf> = path
+            .ancestors()
+            .filter(|dir| !self.dir_exists(dir))
+            .map(Path::to_path_buf)
+            .collect();
+        self.dirs.extend(missing);
+        Ok(())
+    }
+
+    fn mode(&self, path: &Path) -> io::Result<u32> {
+        self.files
+            .get(path)
+            .map(|file| file.mode)
+            .ok_or_else(|| Self::not_found(path))
+    }
+
+    fn set_mode(&mut self, path: &Path, mode: u32) -> io::Result<()> {
+        let file = self
+            .files
+            .get_mut(path)
+            .ok_or_else(|| Self::not_found(path))?;
+        file.mode = mode;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Dst;
+    use crate::dirtemplates::DirTemplates;
+    use crate::file_time_util::{Rounding, TimeBounds};
+    use crate::fileaction::{Action, FileFilter, Placement, Planner, RunState, execute_actions};
+    use crate::timings::Timings;
+    use std::collections::BTreeMap;
+    use tera::Context;
+
+    static NO_AUTO_TEMPLATES: BTreeMap<String, String> = BTreeMap::new();
+
+    fn planner() -> Planner<'static> {
+        Planner {
+            no_create: false,
+            adjust: None,
+            template: None,
+            default_template: None,
+            dir_templates: false,
+            auto_templates: &NO_AUTO_TEMPLATES,
+            path_rules: None,
+            format_with: None,
+            placement: None,
+            fill: false,
+            should_update_access: true,
+            should_update_modification: true,
+            should_update_birth: false,
+            bounds: TimeBounds::default(),
+            rounding: Rounding::default(),
+            from_exif: false,
+            tz: None,
+            dst: Dst::default(),
+            from_git: false,
+            create_intermediate_dirs: true,
+            symlink_only: false,
+            filter: FileFilter::default(),
+        }
+    }
+
+    fn run(fs: &mut MemoryFs, planner: &Planner, path: &Path, times: Option<&FileTimeSpec>) {
+        let actions = planner.plan(fs, path, &Context::new(), times).unwrap();
+        execute_actions(
+            fs,
+            actions,
+            path,
+            &path.to_string_lossy(),
+            planner.create_intermediate_dirs,
+            &mut RunState::new(Timings::new(false)),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_creates_missing_file_with_intermediate_dirs() {
+        let mut fs = MemoryFs::new();
+        let path = Path::new("/notes/2024/today.md");
+
+        run(&mut fs, &planner(), path, None);
+
+        assert!(fs.exists(Path::new("/notes/2024")));
+        assert_eq!(fs.file(path).unwrap().contents, b"");
+    }
+
+    #[test]
+    fn test_sets_explicit_times() {
+        let mut fs = MemoryFs::new();
+        let path = Path::new("file.txt");
+        let time = FileTime::from_unix_time(1_000_000_000, 0);
+
+        run(&mut fs, &planner(), path, Some(&FileTimeSpec::both(time)));
+
+        let metadata = fs.file(path).unwrap().metadata;
+        assert_eq!(metadata.atime, time);
+        assert_eq!(metadata.mtime, time);
+    }
+
+    #[test]
+    fn test_from_git_skips_files_without_commit_times() {
+        let mut fs = MemoryFs::new();
+        let path = Path::new("file.txt");
+        let time = FileTime::from_unix_time(1_000_000_000, 0);
+        fs.create(path).unwrap();
+        let planner = Planner {
+            from_git: true,
+            ..planner()
+        };
+
+        let actions = planner.plan(&fs, path, &Context::new(), None).unwrap();
+        assert!(matches!(actions[0], Action::Skip { .. }));
+        run(&mut fs, &planner, path, Some(&FileTimeSpec::both(time)));
+        assert_eq!(fs.file(path).unwrap().metadata.mtime, time);
+    }
+
+    #[test]
+    fn test_sets_birth_time_only_when_asked() {
+        let mut fs = MemoryFs::new();
+        let path = Path::new("file.txt");
+        let time = FileTime::from_unix_time(1_000_000_000, 0);
+        fs.create(path).unwrap();
+        let created = fs.file(path).unwrap().metadata.btime;
+
+        run(&mut fs, &planner(), path, Some(&FileTimeSpec::both(time)));
+        assert_eq!(fs.file(path).unwrap().metadata.btime, created);
+
+        let planner = Planner {
+            should_update_birth: true,
+            ..planner()
+        };
+        run(&mut fs, &planner, path, Some(&FileTimeSpec::both(time)));
+        assert_eq!(fs.file(path).unwrap().metadata.btime, Some(time));
+    }
+
+    #[test]
+    fn test_clamps_adjusted_times() {
+        let mut fs = MemoryFs::new();
+        let path = Path::new("file.txt");
+        let time = FileTime::from_unix_time(1_000_000_000, 0);
+        let release = FileTime::from_unix_time(1_000_000_060, 0);
+        fs.create(path).unwrap();
+        fs.set_times(path, &FileTimeSpec::both(time), false)
+            .unwrap();
+
+        // The reference time is only clamped once -A has moved it
+        let planner = Planner {
+            adjust: Some("2m"),
+            bounds: TimeBounds::new(None, Some(release)).unwrap(),
+            ..planner()
+        };
+        run(&mut fs, &planner, path, Some(&FileTimeSpec::both(time)));
+
+        let metadata = fs.file(path).unwrap().metadata;
+        assert_eq!(metadata.mtime, release);
+        assert_eq!(metadata.atime, release);
+    }
+
+    #[test]
+    fn test_adjusts_only_modification_time_of_existing_file() {
+        let mut fs = MemoryFs::new();
+        let path = Path::new("file.txt");
+        let time = FileTime::from_unix_time(1_000_000_000, 0);
+        fs.create(path).unwrap();
+        fs.set_times(path, &FileTimeSpec::both(time), false)
+            .unwrap();
+
+        let planner = Planner {
+            no_create: true,
+            adjust: Some("-0130"),
+            should_update_access: false,
+            ..planner()
+        };
+        run(&mut fs, &planner, path, None);
+
+        let metadata = fs.file(path).unwrap().metadata;
+        assert_eq!(metadata.atime, time);
+        assert_eq!(
+            metadata.mtime,
+            FileTime::from_unix_time(1_000_000_000 - 90, 0)
+        );
+    }
+
+    #[test]
+    fn test_append_extends_existing_files_only() {
+        let mut fs = MemoryFs::new();
+        let existing = Path::new("log.md");
+        fs.create(existing).unwrap();
+        let planner = Planner {
+            template: Some("entry"),
+            placement: Some(Placement::End),
+            ..planner()
+        };
+
+        let actions = planner.plan(&fs, existing, &Context::new(), None).unwrap();
+        assert!(matches!(
+            actions[0],
+            Action::ExtendWithTemplate {
+                placement: Placement::End,
+                ..
+            }
+        ));
+        let actions = planner
+            .plan(&fs, Path::new("new.md"), &Context::new(), None)
+            .unwrap();
+        assert!(matches!(actions[0], Action::CreateWithTemplate { .. }));
+    }
+
+    #[test]
+    fn test_fill_renders_existing_files_only() {
+        let mut fs = MemoryFs::new();
+        let existing = Path::new("draft.md");
+        fs.write(existing, b"# {{ title }}\n").unwrap();
+        let planner = Planner {
+            fill: true,
+            ..planner()
+        };
+
+        let actions = planner.plan(&fs, existing, &Context::new(), None).unwrap();
+        match &actions[0] {
+            Action::FillPlaceholders { context } => assert!(context.contains_key("target_mtime")),
+            other => panic!("unexpected action {other:?}"),
+        }
+        let actions = planner
+            .plan(&fs, Path::new("new.md"), &Context::new(), None)
+            .unwrap();
+        assert!(matches!(actions[0], Action::CreateEmpty));
+    }
+
+    #[test]
+    fn test_scaffolds_are_found_through_the_fs() {
+        let mut fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/work/.zap/templates/crate/src"))
+            .unwrap();
+        let planner = Planner {
+            template: Some("crate"),
+            ..planner()
+        };
+
+        let actions = planner
+            .plan(&fs, Path::new("/work/app"), &Context::new(), None)
+            .unwrap();
+        assert!(matches!(actions[0], Action::CreateScaffold { .. }));
+        let actions = planner
+            .plan(
+                &MemoryFs::new(),
+                Path::new("/work/app"),
+                &Context::new(),
+                None,
+            )
+            .unwrap();
+        assert!(matches!(actions[0], Action::CreateWithTemplate { .. }));
+    }
+
+    #[test]
+    fn test_insert_at_is_read_through_the_fs() {
+        let mut fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/work/.zap/templates"))
+            .unwrap();
+        fs.write(
+            Path::new("/work/.zap/templates/route"),
+            b"+++\noutput = { insert_at = \"// routes\" }\n+++\nroute();\n",
+        )
+        .unwrap();
+        fs.write(Path::new("/work/app.js"), b"// routes\n").unwrap();
+        let planner = Planner {
+            template: Some("route"),
+            ..planner()
+        };
+
+        let actions = planner
+            .plan(&fs, Path::new("/work/app.js"), &Context::new(), None)
+            .unwrap();
+        assert!(matches!(
+            &actions[0],
+            Action::ExtendWithTemplate {
+                placement: Placement::After(marker),
+                ..
+            } if marker == "// routes"
+        ));
+    }
+
+    #[test]
+    fn test_preserve_atime_across_fill() {
+        let path = Path::new("draft.md");
+        let time = FileTime::from_unix_time(1_000_000_000, 0);
+        let fill = |preserve_atime| {
+            let mut fs = MemoryFs::new();
+            fs.write(path, b"{{ 6 * 7 }}\n").unwrap();
+            fs.set_times(path, &FileTimeSpec::both(time), false)
+                .unwrap();
+            let planner = Planner {
+                fill: true,
+                should_update_access: false,
+                ..planner()
+            };
+            let actions = planner.plan(&fs, path, &Context::new(), None).unwrap();
+            let mut run = RunState::new(Timings::new(false));
+            run.preserve_atime = preserve_atime;
+            execute_actions(&mut fs, actions, path, "draft.md", false, &mut run).unwrap();
+            assert_eq!(fs.file(path).unwrap().contents, b"42\n");
+            fs.file(path).unwrap().metadata.atime
+        };
+
+        assert_ne!(fill(false), time);
+        assert_eq!(fill(true), time);
+    }
+
+    #[test]
+    fn test_memory_fs_replace_requires_existing_file() {
+        let mut fs = MemoryFs::new();
+        let path = Path::new("file.txt");
+        assert!(fs.replace(path, b"new").is_err());
+        fs.write(path, b"old").unwrap();
+        fs.replace(path, b"new").unwrap();
+        assert_eq!(fs.file(path).unwrap().contents, b"new");
+        assert_eq!(fs.file(Path::new("file.txt~")).unwrap().contents, b"old");
+    }
+
+    #[test]
+    fn test_no_create_skips_missing_file() {
+        let mut fs = MemoryFs::new();
+        let path = Path::new("missing.txt");
+        let planner = Planner {
+            no_create: true,
+            ..planner()
+        };
+
+        run(&mut fs, &planner, path, None);
+
+        assert!(!fs.exists(path));
+    }
+
+    #[test]
+    fn test_filters_read_through_the_fs() {
+        let mut fs = MemoryFs::new();
+        let old_image = Path::new("old.png");
+        let new_image = Path::new("new.png");
+        let notes = Path::new("notes.txt");
+        fs.write(old_image, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR")
+            .unwrap();
+        fs.write(new_image, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR")
+            .unwrap();
+        fs.write(notes, b"text").unwrap();
+        let long_ago = FileTime::from_unix_time(0, 0);
+        let times = FileTimeSpec {
+            atime: Some(long_ago),
+            mtime: Some(long_ago),
+            btime: None,
+        };
+        fs.set_times(old_image, &times, false).unwrap();
+        fs.set_times(notes, &times, false).unwrap();
+
+        let planner = Planner {
+            filter: FileFilter {
+                older_than: Some(std::time::Duration::from_secs(86400)),
+                mime: Some("image/*".to_string()),
+                ..FileFilter::default()
+            },
+            ..planner()
+        };
+        let is_skipped = |path| {
+            matches!(
+                planner.plan(&fs, path, &Context::new(), None).unwrap()[..],
+                [Action::Skip { .. }]
+            )
+        };
+
+        assert!(!is_skipped(old_image));
+        assert!(is_skipped(new_image));
+        assert!(is_skipped(notes));
+        assert!(is_skipped(Path::new("missing.png")));
+    }
+
+    #[test]
+    fn test_dir_template_wins_over_default_template() {
+        let mut fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/blog")).unwrap();
+        fs.write(Path::new("/blog/.zap.toml"), b"\"*.md\" = \"blogpost\"\n")
+            .unwrap();
+        let planner = Planner {
+            default_template: Some("header"),
+            dir_templates: true,
+            ..planner()
+        };
+
+        assert_eq!(
+            creation_template(&fs, &planner, "/blog/hello.md"),
+            "blogpost"
+        );
+        assert_eq!(
+            creation_template(&fs, &planner, "/blog/hello.txt"),
+            "header"
+        );
+    }
+
+    #[test]
+    fn test_no_create_doesnt_read_dir_templates() {
+        let mut fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/blog")).unwrap();
+        fs.write(Path::new("/blog/.zap.toml"), b"not toml =")
+            .unwrap();
+        let planner = Planner {
+            no_create: true,
+            dir_templates: true,
+            ..planner()
+        };
+
+        run(&mut fs, &planner, Path::new("/blog/hello.md"), None);
+
+        assert!(!fs.exists(Path::new("/blog/hello.md")));
+    }
+
+    #[test]
+    fn test_auto_template_by_extension() {
+        let mut fs = MemoryFs::new();
+        fs.create_dir_all(Path::new("/blog")).unwrap();
+        fs.write(Path::new("/blog/.zap.toml"), b"\"*.md\" = \"blogpost\"\n")
+            .unwrap();
+        let auto_templates = BTreeMap::from([
+            ("md".to_string(), "note".to_string()),
+            ("rs".to_string(), "rust-file".to_string()),
+        ]);
+        let planner = Planner {
+            default_template: Some("header"),
+            dir_templates: true,
+            auto_templates: &auto_templates,
+            ..planner()
+        };
+
+        assert_eq!(
+            creation_template(&fs, &planner, "/src/main.rs"),
+            "rust-file"
+        );
+        assert_eq!(creation_template(&fs, &planner, "/README.MD"), "note");
+        assert_eq!(
+            creation_template(&fs, &planner, "/blog/post.md"),
+            "blogpost"
+        );
+        assert_eq!(creation_template(&fs, &planner, "/notes.txt"), "header");
+
+        let explicit = Planner {
+            template: Some("plain"),
+            ..planner
+        };
+        assert_eq!(creation_template(&fs, &explicit, "/src/main.rs"), "plain");
+    }
+
+    #[test]
+    fn test_path_rules_beat_auto_templates() {
+        let fs = MemoryFs::new();
+        let auto_templates = BTreeMap::from([("rs".to_string(), "rust-file".to_string())]);
+        let rules = DirTemplates::from_rules(
+            Path::new("/config.toml"),
+            Path::new("/crate"),
+            [("tests/**".to_string(), "rust-test".to_string())],
+        )
+        .unwrap();
+        let planner = Planner {
+            auto_templates: &auto_templates,
+            path_rules: Some(&rules),
+            ..planner()
+        };
+
+        assert_eq!(
+            creation_template(&fs, &planner, "/crate/tests/cli.rs"),
+            "rust-test"
+        );
+        assert_eq!(
+            creation_template(&fs, &planner, "/crate/src/lib.rs"),
+            "rust-file"
+        );
+
+        let explicit = Planner {
+            template: Some("plain"),
+            ..planner
+        };
+        assert_eq!(
+            creation_template(&fs, &explicit, "/crate/tests/cli.rs"),
+            "plain"
+        );
+    }
+
+    /// The template a new file at `path` would be created from.
+    fn creation_template(fs: &MemoryFs, planner: &Planner, path: &str) -> String {
+        match &planner
+            .plan(fs, Path::new(path), &Context::new(), None)
+            .unwrap()[0]
+        {
+            Action::CreateWithTemplate { template_name, .. } => template_name.clone(),
+            other => panic!("unexpected action {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_memory_fs_requires_parent_directory() {
+        let mut fs = MemoryFs::new();
+        let err = fs.create(Path::new("/missing/file.txt")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_real_fs_sets_times_of_the_file_it_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        let moved = dir.path().join("moved.md");
+        let time = FileTime::from_unix_time(1_700_000_000, 0);
+
+        let mut fs = RealFs::new();
+        fs.create(&path).unwrap();
+        // Another file takes its place before the times are set
+        std::fs::rename(&path, &moved).unwrap();
+        std::fs::write(&path, "swapped in").unwrap();
+        fs.set_times(&path, &FileTimeSpec::both(time), false)
+            .unwrap();
+
+        let mtime =
+            |path: &Path| FileTime::from_last_modification_time(&std::fs::metadata(path).unwrap());
+        assert_eq!(mtime(&moved), time);
+        assert_ne!(mtime(&path), time);
+    }
+
+    #[test]
+    fn test_real_fs_exists_notices_removed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        std::fs::write(&path, "# Notes\n").unwrap();
+
+        let fs = RealFs::new();
+        assert!(fs.exists(&path));
+        std::fs::remove_file(&path).unwrap();
+        assert!(!fs.exists(&path));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_real_fs_writes_only_the_file_it_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        let elsewhere = dir.path().join("elsewhere.md");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::write(&elsewhere, "keep").unwrap();
+
+        // Another file takes its place after the check
+        let mut fs = RealFs::new();
+        assert!(fs.exists(&path));
+        std::fs::rename(&path, dir.path().join("moved.md")).unwrap();
+        std::fs::write(&path, "swapped in").unwrap();
+        assert!(fs.write(&path, b"new").is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "swapped in");
+
+        assert!(fs.exists(&path));
+        fs.write(&path, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+
+        // A link put where a missing file was isn't followed
+        let missing = dir.path().join("missing.md");
+        assert!(!fs.exists(&missing));
+        std::os::unix::fs::symlink(&elsewhere, &missing).unwrap();
+        assert!(fs.write(&missing, b"new").is_err());
+        assert_eq!(std::fs::read_to_string(&elsewhere).unwrap(), "keep");
+    }
+
+    #[test]
+    fn test_real_fs_create_keeps_existing_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        std::fs::write(&path, "# Notes\n").unwrap();
+
+        RealFs::new().create(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "# Notes\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_real_fs_replace_keeps_links_and_a_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        std::fs::write(dir.path().join("notes.md"), "old").unwrap();
+        std::os::unix::fs::symlink("notes.md", dir.path().join("link.md")).unwrap();
+        std::fs::hard_link(dir.path().join("notes.md"), dir.path().join("other.md")).unwrap();
+
+        let mut fs = RealFs::new();
+        fs.replace(&dir.path().join("link.md"), b"new").unwrap();
+        assert!(dir.path().join("link.md").is_symlink());
+        assert_eq!(read("notes.md"), "new");
+        assert_eq!(read("other.md"), "new");
+        assert_eq!(read("notes.md~"), "old");
+        assert!(!dir.path().join("link.md~").exists());
+
+        // Without other names, the file is replaced by renaming
+        std::fs::remove_file(dir.path().join("other.md")).unwrap();
+        fs.replace(&dir.path().join("notes.md"), b"newer").unwrap();
+        assert_eq!(read("notes.md"), "newer");
+        assert_eq!(read("notes.md~"), "new");
+        assert!(!dir.path().join(".notes.md.zap-tmp").exists());
+    }
+}
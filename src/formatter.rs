@@ -0,0 +1,79 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::errors::ZapError;
+
+/// Pipe rendered content through an external formatter like `rustfmt --edition 2021`
+/// and return what it prints. The command is split into words the way a shell
+/// would, so quoted arguments stay whole; it gets the content on stdin and has to
+/// write the formatted content to stdout.
+pub fn format_with(command: &str, content: &str) -> Result<String, ZapError> {
+    let error = |reason: String| ZapError::Formatter {
+        command: command.to_string(),
+        reason,
+    };
+    let parts = shell_words::split(command).map_err(|e| error(e.to_string()))?;
+    let (program, args) = parts
+        .split_first()
+        .ok_or_else(|| error("the command is empty".to_string()))?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| error(e.to_string()))?;
+
+    // Write from another thread, so a formatter that prints before it has read
+    // everything can't fill the stdout pipe and block both sides
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = content.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output().map_err(|e| error(e.to_string()))?;
+    let written = writer.join().expect("the writer thread doesn't panic");
+
+    // A formatter that fails may exit before reading its input; its own
+    // complaint says more than the broken pipe that leaves behind
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(error(match stderr.trim() {
+            "" => format!("exited with {}", output.status),
+            stderr => stderr.to_string(),
+        }));
+    }
+    written.map_err(|e| error(format!("could not write to it: {e}")))?;
+    String::from_utf8(output.stdout).map_err(|_| error("its output isn't UTF-8".to_string()))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_is_piped_through_the_command() {
+        assert_eq!(
+            format_with("tr a-z A-Z", "fn main() {}\n").unwrap(),
+            "FN MAIN() {}\n"
+        );
+        assert_eq!(format_with("cat", "").unwrap(), "");
+        assert_eq!(
+            format_with("sh -c 'tr a-z A-Z'", "quoted\n").unwrap(),
+            "QUOTED\n"
+        );
+    }
+
+    #[test]
+    fn test_formatter_errors() {
+        let reason = |command: &str| match format_with(command, "x") {
+            Err(ZapError::Formatter { reason, .. }) => reason,
+            other => panic!("expected a formatter error, got {other:?}"),
+        };
+        assert_eq!(reason("  "), "the command is empty");
+        assert_eq!(reason("sh -c 'cat"), "missing closing quote");
+        assert!(reason("zap-no-such-formatter").contains("No such file"));
+        assert!(reason("false").starts_with("exited with"));
+        assert!(reason("ls /zap-no-such-dir").contains("zap-no-such-dir"));
+    }
+}
@@ -0,0 +1,411 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tera::{Context, Tera};
+
+use crate::errors::ZapError;
+
+/// Line that opens and closes a TOML frontmatter block.
+pub const TOML_DELIMITER: &str = "+++";
+
+/// Line that opens and closes a YAML frontmatter block.
+pub const YAML_DELIMITER: &str = "---";
+
+/// Key a YAML frontmatter block puts zap's metadata under. Blocks without it
+/// belong to the output, like the front matter of a Jekyll or Hugo page.
+pub const YAML_KEY: &str = "zap";
+
+/// What a template says about itself in its frontmatter, a TOML block between
+/// two `+++` lines (or a YAML block between two `---` lines with everything under
+/// a `zap:` key) at the very top of the file:
+///
+/// ```text
+/// +++
+/// description = "Meeting notes"
+/// variables = ["title", "attendees"]
+/// required = ["title"]
+/// defaults = { attendees = "everyone" }
+/// output = { final_newline = true }
+/// +++
+/// # {{ title }}
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TemplateMeta {
+    /// One line shown by `zap template list`
+    pub description: Option<String>,
+    /// Context variables the template uses
+    pub variables: Vec<String>,
+    /// Variables that must have a value; zap asks for missing ones when it can
+    pub required: Vec<String>,
+    /// Values for variables the context doesn't set
+    pub defaults: BTreeMap<String, serde_json::Value>,
+    pub output: OutputOptions,
+    /// Template language of the body; Tera unless the template says otherwise
+    pub engine: Engine,
+    /// Copy the body as it is instead of rendering it, e.g. when it contains a literal `{{`
+    pub verbatim: bool,
+    /// Plugins the template calls, as `plugin` when it calls the function of the
+    /// same name or `plugin:function`
+    pub requires_plugins: Vec<String>,
+}
+
+/// Template languages zap can render. Engines other than Tera are optional
+/// and only available when zap is built with the cargo feature of that name.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    #[default]
+    Tera,
+    Handlebars,
+    Liquid,
+}
+
+impl Engine {
+    pub fn name(self) -> &'static str {
+        match self {
+            Engine::Tera => "tera",
+            Engine::Handlebars => "handlebars",
+            Engine::Liquid => "liquid",
+        }
+    }
+}
+
+/// How the rendered template is written.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputOptions {
+    /// End the file with exactly one newline (`true`) or none at all (`false`)
+    pub final_newline: Option<bool>,
+    /// Command the rendered content is piped through, e.g. `rustfmt --edition 2021`
+    pub format_with: Option<String>,
+    /// Marker line after which the template goes in existing files, like `--insert-at`
+    pub insert_at: Option<String>,
+    /// Permissions of the written file in octal, like `"755"`
+    pub mode: Option<String>,
+    /// Make the written file executable for everyone who can read it
+    pub executable: bool,
+    /// Line endings of the written file, like `--eol`
+    pub eol: Option<Eol>,
+}
+
+/// Line endings rendered output is normalized to.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eol {
+    Lf,
+    Crlf,
+    // CRLF on Windows, LF everywhere else
+    Native,
+}
+
+impl Eol {
+    /// `text` with every line ending, LF or CRLF, replaced by this one.
+    pub fn apply(self, text: &str) -> String {
+        let lf = text.replace("\r\n", "\n");
+        match self {
+            Eol::Lf => lf,
+            Eol::Crlf => lf.replace('\n', "\r\n"),
+            Eol::Native if cfg!(windows) => Eol::Crlf.apply(&lf),
+            Eol::Native => lf,
+        }
+    }
+}
+
+impl TemplateMeta {
+    /// The defaults as a context, to be extended with the actual values.
+    pub fn defaults_context(&self) -> Context {
+        let mut context = Context::new();
+        for (key, value) in &self.defaults {
+            context.insert(key, value);
+        }
+        context
+    }
+
+    /// Required variables that `context` doesn't provide, in declaration order.
+    pub fn missing_required<'a>(&'a self, context: &Context) -> Vec<&'a str> {
+        self.required
+            .iter()
+            .filter(|name| !context.contains_key(name))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Required plugins whose function (or filter or tester) isn't registered on
+    /// `tera`, as `(plugin, function)`.
+    pub fn missing_plugins<'a>(&'a self, tera: &Tera) -> Vec<(&'a str, &'a str)> {
+        self.requires_plugins
+            .iter()
+            .map(|required| required.split_once(':').unwrap_or((required, required)))
+            .filter(|(_, function)| {
+                tera.get_function(function).is_err()
+                    && tera.get_filter(function).is_err()
+                    && tera.get_tester(function).is_err()
+            })
+            .collect()
+    }
+
+    /// The permissions a file with permissions `current` gets once the template is
+    /// written to it, if the template sets any. `mode` wins over `executable`.
+    pub fn file_mode(&self, current: u32) -> Option<u32> {
+        match &self.output.mode {
+            Some(mode) => parse_mode(mode),
+            None if self.output.executable => Some(current | (current & 0o444) >> 2),
+            None => None,
+        }
+    }
+
+    /// Apply the output options to a rendered template. `eol` (from `--eol`)
+    /// wins over the line endings the template asks for.
+    pub fn finish_output(&self, mut rendered: String, eol: Option<Eol>) -> String {
+        if let Some(final_newline) = self.output.final_newline {
+            rendered.truncate(rendered.trim_end_matches(['\n', '\r']).len());
+            if final_newline {
+                rendered.push('\n');
+            }
+        }
+        match eol.or(self.output.eol) {
+            Some(eol) => eol.apply(&rendered),
+            None => rendered,
+        }
+    }
+}
+
+/// Split a template into its frontmatter and the body that gets rendered.
+/// Templates without frontmatter have default metadata and are returned whole,
+/// and so are templates starting with a `---` block that isn't zap's.
+pub fn split<'a>(path: &Path, source: &'a str) -> Result<(TemplateMeta, &'a str), ZapError> {
+    let invalid = |reason: String| ZapError::InvalidFrontmatter {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let Some((delimiter, rest)) = [TOML_DELIMITER, YAML_DELIMITER]
+        .into_iter()
+        .find_map(|delimiter| Some((delimiter, strip_delimiter_line(source, delimiter)?)))
+    else {
+        return Ok((TemplateMeta::default(), source));
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            let block = &rest[..offset];
+            let meta = if delimiter == YAML_DELIMITER {
+                match yaml_meta(block).map_err(invalid)? {
+                    Some(meta) => meta,
+                    None => return Ok((TemplateMeta::default(), source)),
+                }
+            } else if block.trim().is_empty() {
+                TemplateMeta::default()
+            } else {
+                toml::from_str(block).map_err(|e| invalid(e.message().to_string()))?
+            };
+            if let Some(mode) = meta
+                .output
+                .mode
+                .as_deref()
+                .filter(|m| parse_mode(m).is_none())
+            {
+                return Err(invalid(format!(
+                    "mode '{mode}' isn't an octal file mode like \"755\""
+                )));
+            }
+            return Ok((meta, &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+
+    Err(invalid(format!("missing closing '{delimiter}' line")))
+}
+
+/// The metadata under the `zap:` key of a YAML block, or `None` when the block
+/// has no such key and is part of the output.
+fn yaml_meta(block: &str) -> Result<Option<TemplateMeta>, String> {
+    let Ok(serde_yaml::Value::Mapping(mut mapping)) = serde_yaml::from_str(block) else {
+        return Ok(None);
+    };
+    let Some(meta) = mapping.remove(YAML_KEY) else {
+        return Ok(None);
+    };
+    if !mapping.is_empty() {
+        return Err(format!(
+            "'{YAML_KEY}' has to be the only key of its block; \
+             put the output's own frontmatter after it"
+        ));
+    }
+    match meta {
+        serde_yaml::Value::Null => Ok(Some(TemplateMeta::default())),
+        meta => serde_yaml::from_value(meta)
+            .map(Some)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// An octal file mode like `755` or `0644`.
+fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+}
+
+/// The text after the opening delimiter line, if `source` starts with one.
+fn strip_delimiter_line<'a>(source: &'a str, delimiter: &str) -> Option<&'a str> {
+    let rest = source.strip_prefix(delimiter)?;
+    rest.strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> &'static Path {
+        Path::new("note")
+    }
+
+    #[test]
+    fn test_template_without_frontmatter() {
+        let (meta, body) = split(path(), "# {{ title }}\n").unwrap();
+        assert_eq!(meta, TemplateMeta::default());
+        assert_eq!(body, "# {{ title }}\n");
+    }
+
+    #[test]
+    fn test_frontmatter_is_parsed_and_stripped() {
+        let source =
+            "+++\ndescription = \"Meeting notes\"\nvariables = [\"title\"]\n+++\n# {{ title }}\n";
+        let (meta, body) = split(path(), source).unwrap();
+        assert_eq!(meta.description.as_deref(), Some("Meeting notes"));
+        assert_eq!(meta.variables, ["title"]);
+        assert_eq!(body, "# {{ title }}\n");
+
+        let (_, body) = split(path(), "+++\r\ndescription = \"x\"\r\n+++\r\nbody").unwrap();
+        assert_eq!(body, "body");
+    }
+
+    #[test]
+    fn test_yaml_frontmatter() {
+        let source = "---\nzap:\n  description: Meeting notes\n  required: [title]\n  defaults:\n    attendees: [Ada, Bob]\n  output:\n    final_newline: true\n---\n# {{ title }}";
+        let (meta, body) = split(path(), source).unwrap();
+        assert_eq!(meta.description.as_deref(), Some("Meeting notes"));
+        assert_eq!(meta.required, ["title"]);
+        assert_eq!(meta.defaults["attendees"][1], "Bob");
+        assert_eq!(meta.output.final_newline, Some(true));
+        assert_eq!(body, "# {{ title }}");
+
+        let (meta, body) = split(path(), "---\nzap:\n---\nbody").unwrap();
+        assert_eq!(meta, TemplateMeta::default());
+        assert_eq!(body, "body");
+    }
+
+    #[test]
+    fn test_yaml_frontmatter_of_the_output_is_kept() {
+        for source in [
+            "---\nlayout: post\ntitle: {{ title }}\n---\n# {{ title }}\n",
+            "---\n- not a mapping\n---\n",
+            "---\n\n---\n",
+        ] {
+            let (meta, body) = split(path(), source).unwrap();
+            assert_eq!(meta, TemplateMeta::default());
+            assert_eq!(body, source);
+        }
+
+        let source = "---\nzap:\n  variables: [title]\n---\n---\nlayout: post\n---\n";
+        let (meta, body) = split(path(), source).unwrap();
+        assert_eq!(meta.variables, ["title"]);
+        assert_eq!(body, "---\nlayout: post\n---\n");
+    }
+
+    #[test]
+    fn test_defaults_and_required() {
+        let (meta, _) = split(
+            path(),
+            "+++\nrequired = [\"title\", \"author\"]\n[defaults]\nauthor = \"Ada\"\n+++\n",
+        )
+        .unwrap();
+
+        let mut context = meta.defaults_context();
+        assert_eq!(meta.missing_required(&context), ["title"]);
+        context.insert("title", "Standup");
+        assert!(meta.missing_required(&context).is_empty());
+    }
+
+    #[test]
+    fn test_requires_plugins() {
+        let (meta, _) = split(
+            path(),
+            "+++\nrequires_plugins = [\"shout\", \"text:initials\", \"count:words\"]\n+++\n",
+        )
+        .unwrap();
+        let mut tera = Tera::default();
+        tera.register_filter("shout", |value: &tera::Value, _: &_| Ok(value.clone()));
+        tera.register_function("initials", |_: &_| Ok(tera::Value::Null));
+        assert_eq!(meta.missing_plugins(&tera), [("count", "words")]);
+    }
+
+    #[test]
+    fn test_final_newline() {
+        let mut meta = TemplateMeta::default();
+        assert_eq!(meta.finish_output("text\n\n".to_string(), None), "text\n\n");
+
+        meta.output.final_newline = Some(true);
+        assert_eq!(meta.finish_output("text".to_string(), None), "text\n");
+        assert_eq!(meta.finish_output("text\n\n\n".to_string(), None), "text\n");
+
+        meta.output.final_newline = Some(false);
+        assert_eq!(meta.finish_output("text\r\n".to_string(), None), "text");
+    }
+
+    #[test]
+    fn test_eol() {
+        assert_eq!(Eol::Crlf.apply("a\nb\r\nc"), "a\r\nb\r\nc");
+        assert_eq!(Eol::Lf.apply("a\r\nb\nc\r\n"), "a\nb\nc\n");
+
+        let (meta, _) = split(
+            path(),
+            "+++\noutput = { eol = \"crlf\", final_newline = true }\n+++\n",
+        )
+        .unwrap();
+        assert_eq!(meta.finish_output("a\nb".to_string(), None), "a\r\nb\r\n");
+        assert_eq!(
+            meta.finish_output("a\nb".to_string(), Some(Eol::Lf)),
+            "a\nb\n"
+        );
+        assert!(split(path(), "+++\noutput = { eol = \"cr\" }\n+++\n").is_err());
+    }
+
+    #[test]
+    fn test_engine() {
+        let (meta, _) = split(path(), "+++\n+++\n").unwrap();
+        assert_eq!(meta.engine, Engine::Tera);
+        let (meta, _) = split(path(), "+++\nengine = \"handlebars\"\n+++\n").unwrap();
+        assert_eq!(meta.engine, Engine::Handlebars);
+        assert!(split(path(), "+++\nengine = \"jinja\"\n+++\n").is_err());
+    }
+
+    #[test]
+    fn test_file_mode() {
+        let (meta, _) = split(path(), "+++\noutput = { mode = \"0750\" }\n+++\n").unwrap();
+        assert_eq!(meta.file_mode(0o644), Some(0o750));
+
+        let (meta, _) = split(path(), "---\nzap:\n  output:\n    executable: true\n---\n").unwrap();
+        assert_eq!(meta.file_mode(0o644), Some(0o755));
+        assert_eq!(meta.file_mode(0o600), Some(0o700));
+
+        assert_eq!(TemplateMeta::default().file_mode(0o644), None);
+        assert!(split(path(), "+++\noutput = { mode = \"rwx\" }\n+++\n").is_err());
+        assert!(split(path(), "+++\noutput = { mode = \"77777\" }\n+++\n").is_err());
+    }
+
+    #[test]
+    fn test_invalid_frontmatter() {
+        assert!(matches!(
+            split(path(), "+++\ndescription = \"never closed\"\n"),
+            Err(ZapError::InvalidFrontmatter { .. })
+        ));
+        assert!(split(path(), "+++\ndescripton = \"typo\"\n+++\n").is_err());
+        assert!(split(path(), "---\nzap:\n  descripton: typo\n---\n").is_err());
+        assert!(split(path(), "---\nzap: {}\nlayout: post\n---\n").is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use chrono::{DateTime, Utc};
+use std::path::Path;
+use std::process::Command;
+
+use crate::errors::ZapError;
+
+/// When `path` was last changed by a commit, for `--from-git`: the committer date
+/// of the newest commit touching it in the repository it's in, or everything under
+/// it for a directory. `None` when no commit has it yet, like an untracked file.
+pub fn last_commit_time(path: &Path) -> Result<Option<DateTime<Utc>>, ZapError> {
+    let error = |reason: String| ZapError::GitLog {
+        path: path.to_path_buf(),
+        reason,
+    };
+    // Run in the file's directory, so it's found in the repository it belongs to
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) if !dir.as_os_str().is_empty() => (dir, Path::new(name)),
+        _ => (Path::new("."), path),
+    };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["log", "-1", "--format=%ct", "--"])
+        .arg(name)
+        .output()
+        .map_err(|e| error(e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(error(match stderr.trim() {
+            "" => format!("git exited with {}", output.status),
+            stderr => stderr.to_string(),
+        }));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.trim() {
+        "" => Ok(None),
+        seconds => seconds
+            .parse()
+            .ok()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+            .map(Some)
+            .ok_or_else(|| error(format!("unexpected commit time '{seconds}'"))),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .env("GIT_COMMITTER_DATE", "1700000000 +0000")
+            .env("GIT_AUTHOR_DATE", "1600000000 +0000")
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    #[test]
+    fn test_last_commit_time() {
+        let dir = tempdir().unwrap();
+        git(dir.path(), &["init", "-q"]);
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.path().join("notes.md"), "# Notes\n").unwrap();
+        git(dir.path(), &["add", "src"]);
+        git(
+            dir.path(),
+            &[
+                "-c",
+                "user.name=zap",
+                "-c",
+                "user.email=zap@example.com",
+                "commit",
+                "-q",
+                "-m",
+                "Add main",
+            ],
+        );
+
+        let committed = DateTime::from_timestamp(1_700_000_000, 0);
+        let main = dir.path().join("src/main.rs");
+        assert_eq!(last_commit_time(&main).unwrap(), committed);
+        assert_eq!(
+            last_commit_time(&dir.path().join("src")).unwrap(),
+            committed
+        );
+        assert_eq!(
+            last_commit_time(&dir.path().join("notes.md")).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::errors::ZapError;
+use crate::file_time_util::FileTimeSpec;
+use crate::fileops::FileOps;
+
+/// File in the config directory holding the journal of the last run.
+pub const JOURNAL_FILE: &str = "undo.json";
+
+/// A file time as it's stored in the journal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Time {
+    seconds: i64,
+    nanos: u32,
+}
+
+impl From<FileTime> for Time {
+    fn from(time: FileTime) -> Self {
+        Time {
+            seconds: time.unix_seconds(),
+            nanos: time.nanoseconds(),
+        }
+    }
+}
+
+impl From<Time> for FileTime {
+    fn from(time: Time) -> Self {
+        FileTime::from_unix_time(time.seconds, time.nanos)
+    }
+}
+
+/// The times one file had before the run changed them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    atime: Time,
+    mtime: Time,
+    /// The times were those of the link itself (`--symlink`)
+    symlink: bool,
+}
+
+/// What `zap --undo` needs to put back the times the last run changed.
+/// Files the run created aren't in it, so undoing leaves them alone.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    /// Remember the times of `path` before they're changed. Only the first
+    /// record of a file counts, later ones would already see the new times.
+    pub fn record(
+        &mut self,
+        fs: &dyn FileOps,
+        path: &Path,
+        symlink_only: bool,
+    ) -> Result<(), ZapError> {
+        let absolute = std::path::absolute(path)?;
+        if self.entries.iter().any(|entry| entry.path == absolute) {
+            return Ok(());
+        }
+        let metadata = fs.metadata(path, symlink_only)?;
+        self.entries.push(Entry {
+            path: absolute,
+            atime: metadata.atime.into(),
+            mtime: metadata.mtime.into(),
+            symlink: symlink_only,
+        });
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the journal to `file`, replacing the one of the run before.
+    pub fn save(&self, file: &Path) -> Result<(), ZapError> {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            file,
+            serde_json::to_string_pretty(self).map_err(io::Error::from)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn load(file: &Path) -> Result<Self, ZapError> {
+        let content = fs::read_to_string(file).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => ZapError::NothingToUndo,
+            _ => e.into(),
+        })?;
+        serde_json::from_str(&content).map_err(|e| ZapError::InvalidJournal {
+            path: file.to_path_buf(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// Put the recorded times back. Files that are gone since are reported
+    /// and skipped; returns the files that were restored.
+    pub fn restore(&self, fs: &mut dyn FileOps) -> Vec<PathBuf> {
+        let mut restored = Vec::new();
+        for entry in &self.entries {
+            let times = FileTimeSpec {
+                atime: Some(entry.atime.into()),
+                mtime: Some(entry.mtime.into()),
+                btime: None,
+            };
+            match fs.set_times(&entry.path, &times, entry.symlink) {
+                Ok(()) => restored.push(entry.path.clone()),
+                Err(e) => eprintln!(
+                    "Warning: Could not restore the times of {}: {e}",
+                    entry.path.display()
+                ),
+            }
+        }
+        restored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fileops::MemoryFs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_restores_recorded_times() {
+        let mut fs = MemoryFs::new();
+        let path = PathBuf::from("/notes.md");
+        let before = FileTime::from_unix_time(1_000_000_000, 5);
+        fs.create(&path).unwrap();
+        fs.set_times(&path, &FileTimeSpec::both(before), false)
+            .unwrap();
+
+        let mut journal = Journal::default();
+        journal.record(&fs, &path, false).unwrap();
+        let after = FileTimeSpec::both(FileTime::from_unix_time(2_000_000_000, 0));
+        fs.set_times(&path, &after, false).unwrap();
+        // A second record of the same file keeps the first times
+        journal.record(&fs, &path, false).unwrap();
+
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("state").join(JOURNAL_FILE);
+        journal.save(&file).unwrap();
+        let restored = Journal::load(&file).unwrap().restore(&mut fs);
+
+        assert_eq!(restored, vec![path.clone()]);
+        assert_eq!(fs.file(&path).unwrap().metadata.mtime, before);
+        assert_eq!(fs.file(&path).unwrap().metadata.atime, before);
+    }
+
+    #[test]
+    fn test_missing_journal_is_nothing_to_undo() {
+        let dir = tempdir().unwrap();
+        assert!(matches!(
+            Journal::load(&dir.path().join(JOURNAL_FILE)),
+            Err(ZapError::NothingToUndo)
+        ));
+    }
+}
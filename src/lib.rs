@@ -1,40 +1,230 @@
-use dirs::home_dir;
-
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock};
+use std::time::Instant;
 
 pub mod args;
+pub mod batch;
+pub mod builtins;
+pub mod clock;
+pub mod commands;
+pub mod config;
+pub mod context;
+pub mod dirtemplates;
+pub mod engine;
 pub mod errors;
 pub mod file_time_util;
 pub mod fileaction;
+pub mod fileops;
+pub mod formatter;
+pub mod frontmatter;
+pub mod git;
+pub mod journal;
+pub mod media;
+pub mod notify;
+pub mod packs;
 pub mod parsedate;
+pub mod paths;
+pub mod plugin_index;
 pub mod plugins;
+pub mod remote;
+pub mod render;
+pub mod sandbox;
+pub mod scaffold;
+pub mod strict;
+pub mod timings;
 
 use anyhow::Result;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 
 use crate::args::ZapCli;
+use crate::clock::Clock;
+use crate::config::Config;
 use crate::errors::ZapError;
-use crate::file_time_util::FileTimeSpec;
-use crate::fileaction::{execute_actions, open_in_editor, Planner};
+use crate::file_time_util::{AdjustableFileTime, FileTimeSpec, Rounding, TimeBounds, TimeWindow};
+use crate::fileaction::{
+    Action, FileFilter, FileId, Placement, Planner, RunState, Target, execute_actions, file_id,
+    open_in_editor,
+};
+use crate::fileops::{FileOps, RealFs};
+use crate::journal::{JOURNAL_FILE, Journal};
+use crate::notify::{FailedFile, RunSummary};
+use crate::plugins::PluginsConfig;
+use crate::render::Renderer;
+use crate::timings::{Phase, Timings, TimingsFormat};
+
+/// Directory holding `config.toml`, see `paths::config_dir`.
+pub fn get_config_dir() -> Result<PathBuf, ZapError> {
+    paths::config_dir(&paths::PathEnv::from_process(), Path::exists)
+}
+
+/// Directory holding the user's `templates/` and `plugins/`, see `paths::data_dir`.
+pub fn get_data_dir() -> Result<PathBuf, ZapError> {
+    paths::data_dir(&paths::PathEnv::from_process(), Path::exists)
+}
+
+/// Directory for downloaded templates, see `paths::cache_dir`.
+pub fn get_cache_dir() -> Result<PathBuf, ZapError> {
+    paths::cache_dir(&paths::PathEnv::from_process(), Path::exists)
+}
+
+pub fn get_templates_dir() -> Result<PathBuf, ZapError> {
+    Ok(get_data_dir()?.join("templates"))
+}
+
+pub fn get_plugins_dir() -> Result<PathBuf, ZapError> {
+    Ok(get_data_dir()?.join("plugins"))
+}
+
+/// Directory holding the template packs from `zap template install`. It sits in
+/// the config dir, next to the `packs.lock` that records them.
+pub fn get_packs_dir() -> Result<PathBuf, ZapError> {
+    Ok(get_config_dir()?.join("packs"))
+}
+
+fn get_system_dir() -> Option<PathBuf> {
+    // Check for ZAP_SYSTEM_DIR environment variable first
+    if let Ok(custom_dir) = env::var("ZAP_SYSTEM_DIR") {
+        return Some(PathBuf::from(custom_dir));
+    }
+
+    // Fall back to the platform's shared data location
+    #[cfg(windows)]
+    {
+        env::var_os("ProgramData").map(|path| PathBuf::from(path).join("zap"))
+    }
+    #[cfg(not(windows))]
+    {
+        Some(PathBuf::from("/usr/share/zap"))
+    }
+}
+
+/// A directory that is searched for templates, labelled with the layer it belongs to.
+#[derive(Debug, Clone)]
+pub struct TemplateLocation {
+    pub layer: &'static str,
+    pub dir: PathBuf,
+}
+
+/// Name of the per-project directory that holds templates checked into a repository.
+pub const PROJECT_DIR_NAME: &str = ".zap";
+
+/// Find the closest project `.zap` directory in `start` or one of its ancestors.
+/// `start` doesn't need to exist yet, e.g. the directory of a file about to be created;
+/// an empty path (the parent of `notes.md`) stands for the working directory.
+pub fn find_project_dir(start: &Path) -> Option<PathBuf> {
+    find_project_dir_in(&RealFs::new(), start)
+}
+
+/// `find_project_dir`, looking through `fs`.
+fn find_project_dir_in(fs: &dyn FileOps, start: &Path) -> Option<PathBuf> {
+    let start = if start.as_os_str().is_empty() {
+        env::current_dir().ok()?
+    } else {
+        std::path::absolute(start).ok()?
+    };
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_DIR_NAME))
+        .find(|candidate| fs.is_dir(candidate))
+}
+
+/// Set by `--template-path`: directories searched before all others.
+static TEMPLATE_PATH: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+
+/// Search `dirs` for templates before the project, user and system directories,
+/// in the given order, instead of the ones set before.
+pub fn set_template_path(dirs: Vec<PathBuf>) {
+    *TEMPLATE_PATH
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = dirs;
+}
+
+/// All template directories in resolution order: directories given with
+/// `--template-path`, the project's `.zap/templates` (looked up from `start`,
+/// usually the directory of the file being created), then the user's data dir,
+/// then installed template packs (`rust/bin` is `bin` from the pack `rust`),
+/// then the system-wide directory provided by distro packages or admins.
+pub fn template_search_path(start: Option<&Path>) -> Result<Vec<TemplateLocation>, ZapError> {
+    template_search_path_in(&RealFs::new(), start)
+}
+
+/// `template_search_path`, looking for the project through `fs`.
+fn template_search_path_in(
+    fs: &dyn FileOps,
+    start: Option<&Path>,
+) -> Result<Vec<TemplateLocation>, ZapError> {
+    let mut locations: Vec<TemplateLocation> = TEMPLATE_PATH
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|dir| TemplateLocation {
+            layer: "template-path",
+            dir: dir.clone(),
+        })
+        .collect();
+    if let Some(project_dir) = start.and_then(|start| find_project_dir_in(fs, start)) {
+        locations.push(TemplateLocation {
+            layer: "project",
+            dir: project_dir.join("templates"),
+        });
+    }
+    locations.push(TemplateLocation {
+        layer: "user",
+        dir: get_templates_dir()?,
+    });
+    locations.push(TemplateLocation {
+        layer: "pack",
+        dir: get_packs_dir()?,
+    });
+    if let Some(system_dir) = get_system_dir() {
+        locations.push(TemplateLocation {
+            layer: "system",
+            dir: system_dir.join("templates"),
+        });
+    }
+    Ok(locations)
+}
+
+fn get_template_path(template_name: &str, start: Option<&Path>) -> Result<PathBuf, ZapError> {
+    find_template(&RealFs::new(), template_name, start)
+}
 
-fn get_config_dir() -> Result<PathBuf, ZapError> {
-    // Check for ZAP_CONFIG environment variable first
-    if let Ok(custom_dir) = env::var("ZAP_CONFIG") {
-        return Ok(PathBuf::from(custom_dir));
+/// `get_template_path`, looking for the template through `fs`.
+pub fn find_template(
+    fs: &dyn FileOps,
+    template_name: &str,
+    start: Option<&Path>,
+) -> Result<PathBuf, ZapError> {
+    if remote::is_remote(template_name) {
+        return remote::fetch(template_name);
     }
 
-    // Fall back to default location: $HOME/.config/zap
-    let conf_dir: Option<PathBuf> = home_dir();
-    conf_dir
-        .ok_or(ZapError::ConfigDirNotFound)
-        .map(|path| path.join(".config/zap"))
+    let candidates: Vec<PathBuf> = template_search_path_in(fs, start)?
+        .into_iter()
+        .map(|location| location.dir.join(template_name))
+        .collect();
+
+    match candidates.iter().find(|path| fs.exists(path)) {
+        Some(path) => Ok(path.clone()),
+        None => Err(ZapError::TemplateNotFound {
+            name: template_name.to_string(),
+            searched: candidates,
+        }),
+    }
 }
 
-fn get_template_path(template_name: &str) -> Result<PathBuf, ZapError> {
-    let config_dir = get_config_dir()?;
-    let mut template_path = PathBuf::from(&config_dir);
-    template_path.extend(["templates", template_name]);
-    Ok(template_path)
+/// Interpret a relative file name against `base`; absolute names are kept as they are.
+fn resolve_relative_to(base: &Path, filename: &str) -> String {
+    let path = Path::new(filename);
+    if path.is_absolute() {
+        filename.to_string()
+    } else {
+        base.join(path).to_string_lossy().into_owned()
+    }
 }
 
 pub fn set_file_times(
@@ -45,13 +235,85 @@ pub fn set_file_times(
     match (times.atime, times.mtime) {
         (Some(atime), Some(mtime)) => {
             // Both: use the combined call for efficiency (only one syscall)
-            file_time_util::set_both_times(path, atime, mtime, symlink_only)
+            file_time_util::set_both_times(path, atime, mtime, symlink_only)?
         }
-        (Some(atime), None) => file_time_util::set_access_time_only(path, atime, symlink_only),
+        (Some(atime), None) => file_time_util::set_access_time_only(path, atime, symlink_only)?,
         (None, Some(mtime)) => {
-            file_time_util::set_modification_time_only(path, mtime, symlink_only)
+            file_time_util::set_modification_time_only(path, mtime, symlink_only)?
+        }
+        (None, None) => {}
+    }
+    match times.btime {
+        Some(btime) => file_time_util::set_birth_time(path, btime, symlink_only),
+        None => Ok(()),
+    }
+}
+
+/// Prints the template the planned actions would render for `path`, for --stdout.
+/// Nothing is written and no times are changed.
+fn print_rendered(
+    actions: Vec<Action>,
+    path: &Path,
+    filename: &str,
+    renderer: &mut Renderer,
+    timings: &mut Timings,
+) -> Result<(), anyhow::Error> {
+    for action in actions {
+        match action {
+            Action::Skip { .. } => return Ok(()),
+            Action::CreateScaffold { template_name, .. } => {
+                return Err(ZapError::ScaffoldToStdout(template_name).into());
+            }
+            Action::CreateWithTemplate {
+                template_name,
+                context,
+                format_with,
+            }
+            | Action::OverwriteWithTemplate {
+                template_name,
+                context,
+                format_with,
+            }
+            | Action::ExtendWithTemplate {
+                template_name,
+                context,
+                format_with,
+                ..
+            } => {
+                let contents = Action::template_contents(
+                    renderer,
+                    path,
+                    &template_name,
+                    &context,
+                    format_with.as_deref(),
+                    true,
+                    timings,
+                )?;
+                std::io::stdout().write_all(&contents)?;
+                return Ok(());
+            }
+            Action::FillPlaceholders { context } => {
+                let current = std::fs::read_to_string(path)?;
+                print!(
+                    "{}",
+                    Action::fill_placeholders(renderer, path, &current, &context, timings)?
+                );
+                return Ok(());
+            }
+            _ => {}
         }
-        (None, None) => Ok(()),
+    }
+    Err(ZapError::NothingToRender(filename.to_string()).into())
+}
+
+/// Keep the times files had before this run for `--undo`. A run that changed no
+/// existing file leaves the previous journal in place.
+fn save_journal(journal: &Journal) {
+    if journal.is_empty() {
+        return;
+    }
+    if let Err(e) = get_config_dir().and_then(|dir| journal.save(&dir.join(JOURNAL_FILE))) {
+        eprintln!("Warning: Could not save the undo journal: {e}");
     }
 }
 
@@ -59,6 +321,36 @@ pub fn set_file_times(
 /// optionally populate it with text from a template.
 /// If the file exists, its modification and access times are updated.
 pub fn zap(cli: &ZapCli) -> Result<(), anyhow::Error> {
+    set_template_path(cli.template_path.clone());
+
+    if let Some(command) = &cli.command {
+        return commands::run(command);
+    }
+
+    if cli.list_functions {
+        return commands::list_functions();
+    }
+
+    if cli.list_templates {
+        return commands::list_templates();
+    }
+
+    if cli.undo {
+        return commands::undo();
+    }
+
+    if cli.show {
+        return commands::show_times(
+            &cli.filenames,
+            &cli.format.clone().unwrap_or_default(),
+            cli.symlink_only,
+            cli.tz,
+        );
+    }
+
+    let config = Config::load()?;
+    let cli = &config.apply_to(cli)?;
+
     let ZapCli {
         filenames,
         template,
@@ -70,16 +362,36 @@ pub fn zap(cli: &ZapCli) -> Result<(), anyhow::Error> {
         timestamp,
         reference,
         symlink_only,
+        slugify_name,
+        context_clipboard,
+        older_than,
+        newer_than,
+        only_type,
+        mime,
+        each,
+        name,
+        from_csv,
+        name_column,
+        relative_to,
         ..
     } = cli;
 
+    let mut timings = Timings::new(cli.timings.is_some());
+    let config_start = Instant::now();
+
+    let dst = cli.dst.unwrap_or_default();
+    Clock::resolve(cli.now.as_deref(), cli.tz, dst)?.install();
+    remote::set_refresh(cli.refresh);
+
     // Time calculation logic
     let explicit_times: Option<FileTimeSpec> = if let Some(date_str) = date {
-        let parsed_date = parsedate::parse_d_format(date_str)?;
+        let parsed_date = parsedate::parse_d_format(date_str, cli.tz, dst)?;
         Some(FileTimeSpec::from_datetime(parsed_date))
     } else if let Some(timestamp_str) = timestamp {
-        let parsed_date = parsedate::parse_t_format(timestamp_str)?;
+        let parsed_date = parsedate::parse_t_format(timestamp_str, cli.tz, dst)?;
         Some(FileTimeSpec::from_datetime(parsed_date))
+    } else if let Some(seconds) = &cli.unix {
+        Some(FileTimeSpec::from_datetime(parsedate::parse_unix(seconds)?))
     } else if let Some(reference_path) = reference {
         let ref_path = Path::new(reference_path);
         if !ref_path.exists() {
@@ -90,39 +402,357 @@ pub fn zap(cli: &ZapCli) -> Result<(), anyhow::Error> {
     } else {
         None
     };
+    // --adate and --mdate replace one of the times being set
+    let explicit_times = if cli.adate.is_some() || cli.mdate.is_some() {
+        let mut times = explicit_times.unwrap_or_else(FileTimeSpec::now);
+        if let Some(adate) = &cli.adate {
+            times.atime =
+                FileTimeSpec::from_datetime(parsedate::parse_d_format(adate, cli.tz, dst)?).atime;
+        }
+        if let Some(mdate) = &cli.mdate {
+            times.mtime =
+                FileTimeSpec::from_datetime(parsedate::parse_d_format(mdate, cli.tz, dst)?).mtime;
+        }
+        Some(times)
+    } else {
+        explicit_times
+    };
+    let explicit_times = match &cli.offset {
+        Some(offset) => Some(
+            explicit_times
+                .unwrap_or_else(FileTimeSpec::now)
+                .adjust_by_string(offset, cli.tz, dst)?,
+        ),
+        None => explicit_times,
+    };
 
     let (should_update_access, should_update_modification) = cli.should_update_times();
+    let should_update_access = should_update_access && !cli.preserve_atime;
+    if cli.btime && !file_time_util::BIRTH_TIME_SUPPORTED {
+        return Err(ZapError::BirthTimeUnsupported.into());
+    }
+    let window = cli
+        .randomize
+        .as_deref()
+        .map(|window| parsedate::parse_window(window, cli.tz, dst))
+        .transpose()?
+        .map(|(start, end)| TimeWindow { start, end });
+    let bound = |date: &Option<String>| {
+        date.as_deref()
+            .map(|date| {
+                parsedate::parse_d_format(date, cli.tz, dst)
+                    .map(|date| AdjustableFileTime::from_datetime(date).into_file_time())
+            })
+            .transpose()
+    };
+    let bounds = TimeBounds::new(bound(&cli.not_before)?, bound(&cli.not_after)?)?;
+    let rounding = match (&cli.round, &cli.truncate) {
+        (Some(step), _) => Rounding::new(parsedate::parse_adjust(step)?, false)?,
+        (None, Some(step)) => Rounding::new(parsedate::parse_adjust(step)?, true)?,
+        (None, None) => Rounding::default(),
+    };
+
+    let filter = FileFilter {
+        older_than: older_than
+            .as_deref()
+            .map(parsedate::parse_duration)
+            .transpose()?,
+        newer_than: newer_than
+            .as_deref()
+            .map(parsedate::parse_duration)
+            .transpose()?,
+        only_type: *only_type,
+        mime: mime.clone(),
+    };
+
+    let context = context::build_context(
+        config.context_table(),
+        context.as_deref(),
+        context_clipboard.as_deref(),
+    )?;
+
+    let template = if cli.pick_template || template.as_deref() == Some("") {
+        Some(commands::pick_template()?)
+    } else {
+        template.clone()
+    };
+
+    let default_template = if cli.no_template || template.is_some() {
+        None
+    } else {
+        config.default_template()
+    };
+
+    let path_rules = if cli.no_template || cli.no_auto_template {
+        None
+    } else {
+        Some(config.rules(&env::current_dir()?)?)
+    };
 
     // Create the planner
+    let no_auto_templates = BTreeMap::new();
     let planner = Planner {
         no_create: *no_create,
         adjust: adjust.as_deref(),
         template: template.as_deref(),
-        context: context.as_deref(),
+        default_template: default_template.as_deref(),
+        dir_templates: !cli.no_template,
+        auto_templates: if cli.no_template || cli.no_auto_template {
+            &no_auto_templates
+        } else {
+            &config.auto_template
+        },
+        path_rules: path_rules.as_ref(),
+        format_with: cli.format_with.as_deref(),
+        placement: match (cli.append, cli.prepend, &cli.insert_at) {
+            (true, _, _) => Some(Placement::End),
+            (_, true, _) => Some(Placement::Start),
+            (_, _, Some(marker)) => Some(Placement::After(marker.clone())),
+            _ => None,
+        },
+        fill: cli.fill,
         should_update_access,
         should_update_modification,
+        should_update_birth: cli.btime,
+        bounds,
+        rounding,
+        from_exif: cli.from_exif,
+        tz: cli.tz,
+        dst,
+        from_git: cli.from_git,
         create_intermediate_dirs: *create_intermediate_dirs,
         symlink_only: *symlink_only,
+        filter,
+    };
+
+    let mut targets: Vec<Target> = match (each, from_csv, &cli.reference_tree, cli.normalize) {
+        (_, _, Some(source_dir), _) => {
+            let source_dir = Path::new(source_dir);
+            let mut targets = Vec::new();
+            for filename in filenames {
+                targets.extend(batch::reference_tree_targets(
+                    source_dir,
+                    Path::new(filename),
+                    &context,
+                    *symlink_only,
+                )?);
+            }
+            targets
+        }
+        (_, _, None, Some(normalize)) => {
+            let mut targets = Vec::new();
+            for filename in filenames {
+                targets.extend(batch::normalize_targets(
+                    Path::new(filename),
+                    normalize,
+                    &context,
+                    *symlink_only,
+                )?);
+            }
+            targets
+        }
+        (Some(items_path), _, None, None) => batch::expand_each(
+            batch::load_items(Path::new(items_path))?,
+            name.as_deref().unwrap_or_default(),
+            &context,
+        )?,
+        (None, Some(csv_path), None, None) => batch::load_csv_targets(
+            Path::new(csv_path),
+            name_column.as_deref().unwrap_or_default(),
+            &context,
+        )?,
+        (None, None, None, None) => filenames
+            .iter()
+            .map(|filename| (filename.clone(), None))
+            .chain(
+                cli.mapped_files()?
+                    .into_iter()
+                    .map(|(filename, template)| (filename, Some(template))),
+            )
+            .map(|(filename, template)| Target {
+                filename,
+                context: context.clone(),
+                template,
+                times: None,
+            })
+            .collect(),
+    };
+    if let Some(offset) = &cli.offset {
+        for target in &mut targets {
+            target.times = target
+                .times
+                .map(|times| times.adjust_by_string(offset, cli.tz, dst))
+                .transpose()?;
+        }
+    }
+
+    for (filename, pairs) in cli.file_contexts() {
+        let mut matched = false;
+        for target in targets
+            .iter_mut()
+            .filter(|target| target.filename == filename)
+        {
+            context::merge(&mut target.context, context::parse_context_str(pairs));
+            matched = true;
+        }
+        if !matched {
+            return Err(ZapError::UnknownContextFile(filename.to_string()).into());
+        }
+    }
+
+    if let Some(interval) = &cli.stagger {
+        let interval = parsedate::parse_adjust(interval)?;
+        let base = explicit_times.unwrap_or_else(FileTimeSpec::now);
+        for (index, target) in targets.iter_mut().enumerate() {
+            let offset = i32::try_from(index)
+                .ok()
+                .and_then(|index| interval.checked_mul(index))
+                .ok_or(ZapError::TimeAdjustmentOverflow)?;
+            target.times = Some(base.adjust_by_delta(offset)?);
+        }
+    }
+
+    if let Some(window) = window {
+        let mut rng = match cli.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        for target in &mut targets {
+            target.times = Some(FileTimeSpec::both(window.pick(&mut rng)));
+        }
+    }
+
+    for target in &mut targets {
+        if *slugify_name {
+            target.filename = builtins::slugify_filename(&target.filename);
+        }
+        if let Some(base) = relative_to {
+            target.filename = resolve_relative_to(Path::new(base), &target.filename);
+        }
+    }
+
+    // Commit times are looked up before planning, which doesn't run git itself
+    if cli.from_git {
+        for target in &mut targets {
+            let path = Path::new(&target.filename);
+            if path.exists() {
+                target.times = git::last_commit_time(path)?.map(FileTimeSpec::from_datetime);
+            }
+        }
+    }
+
+    timings.record(Phase::Config, config_start.elapsed());
+
+    let mut fs = RealFs::new();
+    let mut run = RunState::new(timings);
+    run.renderer.eol = cli.eol;
+    run.renderer.strict = cli.strict_template;
+    run.renderer.plugins_config = PluginsConfig {
+        disabled: cli.no_plugins,
+        lenient: cli.lenient_plugins,
+        ..config.plugins.clone()
     };
+    run.renderer.verbose = cli.verbose;
+    run.renderer.macros_file = config.macros.clone();
+    run.renderer.no_env = cli.no_env;
+    run.preserve_atime = cli.preserve_atime;
+
+    // Hard links to the same file are only processed once, so adjustments aren't applied twice
+    let mut processed: HashMap<FileId, &str> = HashMap::new();
+
+    let mut summary = RunSummary::default();
 
     // Process each file
-    for filename in filenames {
-        let path = Path::new(filename);
+    for target in &targets {
+        let path = Path::new(&target.filename);
+        let existed = fs.exists(path);
+        let times = target.times.as_ref().or(explicit_times.as_ref());
 
         // Plan what actions to take
-        let actions = planner.plan(path, explicit_times.as_ref())?;
+        let planned = match file_id(&fs, path, *symlink_only).and_then(|id| processed.get(&id)) {
+            Some(first) => Ok(vec![Action::Skip {
+                reason: format!("same file as {first} (hard link), already processed"),
+            }]),
+            None => match &target.template {
+                Some(template) => Planner {
+                    template: Some(template.as_str()),
+                    ..planner.clone()
+                }
+                .plan(&fs, path, &target.context, times),
+                None => planner.plan(&fs, path, &target.context, times),
+            },
+        };
+
+        if cli.stdout {
+            print_rendered(
+                planned?,
+                path,
+                &target.filename,
+                &mut run.renderer,
+                &mut run.timings,
+            )?;
+            continue;
+        }
 
         // Execute the actions
-        execute_actions(actions, path, filename, *create_intermediate_dirs)?;
+        let result = planned.map_err(anyhow::Error::from).and_then(|actions| {
+            let skipped = actions.iter().any(|a| matches!(a, Action::Skip { .. }));
+            execute_actions(
+                &mut fs,
+                actions,
+                path,
+                &target.filename,
+                *create_intermediate_dirs,
+                &mut run,
+            )
+            .map(|()| skipped)
+        });
+
+        let filename = target.filename.clone();
+        match result {
+            Ok(true) => summary.skipped.push(filename),
+            Ok(false) if existed => summary.updated.push(filename),
+            Ok(false) => summary.created.push(filename),
+            Err(e) => {
+                save_journal(&run.journal);
+                if config.notify.is_configured() {
+                    summary.failed.push(FailedFile {
+                        file: filename,
+                        error: e.to_string(),
+                    });
+                    notify::send(&config.notify, &summary);
+                }
+                return Err(e);
+            }
+        }
+
+        if let Some(id) = file_id(&fs, path, *symlink_only) {
+            processed.entry(id).or_insert(&target.filename);
+        }
+    }
+
+    save_journal(&run.journal);
+    if config.notify.is_configured() {
+        notify::send(&config.notify, &summary);
+    }
+
+    match cli.timings {
+        Some(TimingsFormat::Text) => eprint!("{}", run.timings.report()),
+        Some(TimingsFormat::Json) => eprintln!("{}", run.timings.to_json()),
+        None => {}
     }
 
     // Open editor if requested
     if cli.open {
-        if let Err(e) = open_in_editor(&cli.filenames) {
+        let filenames: Vec<String> = targets.into_iter().map(|t| t.filename).collect();
+        if let Err(e) = config
+            .editor()
+            .map_err(anyhow::Error::from)
+            .and_then(|editor| open_in_editor(&editor, &filenames))
+        {
             eprintln!("Warning: Could not open editor: {e}");
         }
     }
 
     Ok(())
 }
-
@@ -3,7 +3,7 @@ use std::process;
 use zap::{args::ZapCli, zap};
 
 fn main() {
-    let mut cli = ZapCli::process_h_flag();
+    let mut cli = ZapCli::from_env();
 
     cli.ensure_no_create_if_symlink();
 
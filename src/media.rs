@@ -0,0 +1,230 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use exif::{In, Tag};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::errors::ZapError;
+
+/// Seconds from 1904-01-01, where QuickTime and MP4 times count from, to the Unix epoch
+const MAC_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// When a photo or video was taken, for `--from-exif`: DateTimeOriginal from the
+/// EXIF data of JPEG, HEIC, PNG, WebP and TIFF-based RAW files, or else the creation
+/// time in the header of an MP4 or QuickTime video. EXIF times without an offset are
+/// in `tz`, or else the local zone. `None` when the file has neither.
+pub fn capture_time(path: &Path, tz: Option<Tz>) -> Result<Option<DateTime<Utc>>, ZapError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    if let Some(taken) = exif_time(&mut reader, tz) {
+        return Ok(Some(taken));
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(video_creation_time(&mut reader)?)
+}
+
+fn exif_time(reader: &mut BufReader<File>, tz: Option<Tz>) -> Option<DateTime<Utc>> {
+    let exif = exif::Reader::new().read_from_container(reader).ok()?;
+    let ascii = |tag| match exif.get_field(tag, In::PRIMARY).map(|field| &field.value) {
+        Some(exif::Value::Ascii(values)) => values.first().cloned(),
+        _ => None,
+    };
+    let mut taken = exif::DateTime::from_ascii(&ascii(Tag::DateTimeOriginal)?).ok()?;
+    if let Some(subsec) = ascii(Tag::SubSecTimeOriginal) {
+        let _ = taken.parse_subsec(&subsec);
+    }
+    if let Some(offset) = ascii(Tag::OffsetTimeOriginal) {
+        let _ = taken.parse_offset(&offset);
+    }
+
+    let naive = NaiveDate::from_ymd_opt(taken.year.into(), taken.month.into(), taken.day.into())?
+        .and_hms_nano_opt(
+        taken.hour.into(),
+        taken.minute.into(),
+        taken.second.into(),
+        taken.nanosecond.unwrap_or(0),
+    )?;
+    match (taken.offset, tz) {
+        (Some(minutes), _) => in_zone(&FixedOffset::east_opt(i32::from(minutes) * 60)?, naive),
+        (None, Some(tz)) => in_zone(&tz, naive),
+        (None, None) => in_zone(&Local, naive),
+    }
+}
+
+fn in_zone<Z: TimeZone>(zone: &Z, naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    zone.from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// The creation time in the `mvhd` box of an ISO base media file (MP4, MOV, M4V, 3GP).
+fn video_creation_time(reader: &mut (impl Read + Seek)) -> io::Result<Option<DateTime<Utc>>> {
+    let Some(moov_end) = find_box(reader, b"moov", u64::MAX)? else {
+        return Ok(None);
+    };
+    if find_box(reader, b"mvhd", moov_end)?.is_none() {
+        return Ok(None);
+    }
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    let seconds = if version[0] == 1 {
+        let mut time = [0; 8];
+        reader.read_exact(&mut time)?;
+        u64::from_be_bytes(time)
+    } else {
+        let mut time = [0; 4];
+        reader.read_exact(&mut time)?;
+        u32::from_be_bytes(time).into()
+    };
+    // Zero is what cameras and encoders write when they don't know
+    if seconds == 0 {
+        return Ok(None);
+    }
+    Ok(i64::try_from(seconds)
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds - MAC_EPOCH_OFFSET, 0)))
+}
+
+/// Move `reader` to the contents of the next box named `name` before `end`, and
+/// return where that box ends. Boxes with other names are skipped.
+fn find_box(reader: &mut (impl Read + Seek), name: &[u8; 4], end: u64) -> io::Result<Option<u64>> {
+    loop {
+        let start = reader.stream_position()?;
+        if start >= end {
+            return Ok(None);
+        }
+        let mut header = [0; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let box_end = match size {
+            // The box goes on to the end of the file
+            0 => end,
+            1 => {
+                let mut large = [0; 8];
+                reader.read_exact(&mut large)?;
+                start.saturating_add(u64::from_be_bytes(large))
+            }
+            size => start + u64::from(size),
+        };
+        if box_end <= start {
+            return Ok(None);
+        }
+        if &header[4..] == name {
+            return Ok(Some(box_end));
+        }
+        reader.seek(SeekFrom::Start(box_end))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mp4_box(name: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut bytes = (8 + contents.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(contents);
+        bytes
+    }
+
+    fn video(creation_time: u32) -> Vec<u8> {
+        let mut mvhd = vec![0, 0, 0, 0];
+        mvhd.extend_from_slice(&creation_time.to_be_bytes());
+        mvhd.extend_from_slice(&[0; 16]);
+        let mut moov = mp4_box(b"udta", b"");
+        moov.extend(mp4_box(b"mvhd", &mvhd));
+        let mut file = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        file.extend(mp4_box(b"moov", &moov));
+        file.extend(mp4_box(b"mdat", &[7; 32]));
+        file
+    }
+
+    #[test]
+    fn test_video_creation_time() {
+        let taken = Utc.with_ymd_and_hms(2024, 5, 15, 10, 30, 0).unwrap();
+        let mac_seconds = (taken.timestamp() + MAC_EPOCH_OFFSET) as u32;
+
+        let found = video_creation_time(&mut Cursor::new(video(mac_seconds))).unwrap();
+        assert_eq!(found, Some(taken));
+        assert_eq!(
+            video_creation_time(&mut Cursor::new(video(0))).unwrap(),
+            None
+        );
+        let no_moov = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        assert_eq!(
+            video_creation_time(&mut Cursor::new(no_moov)).unwrap(),
+            None
+        );
+        assert_eq!(
+            video_creation_time(&mut Cursor::new(b"not a video")).unwrap(),
+            None
+        );
+    }
+
+    /// A JPEG with just the EXIF fields zap reads
+    fn photo(taken: &str, offset: Option<&str>) -> Vec<u8> {
+        let mut entries = vec![(0x9003_u16, format!("{taken}\0"))];
+        if let Some(offset) = offset {
+            entries.push((0x9011, format!("{offset}\0")));
+        }
+        // Big-endian TIFF: header, IFD0 pointing to the Exif IFD at 26, then its values
+        let mut tiff =
+            b"MM\0\x2a\0\0\0\x08\0\x01\x87\x69\0\x04\0\0\0\x01\0\0\0\x1a\0\0\0\0".to_vec();
+        let mut value_offset = 26 + 2 + 12 * entries.len() + 4;
+        let mut values = Vec::new();
+        tiff.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        for (tag, value) in &entries {
+            tiff.extend_from_slice(&tag.to_be_bytes());
+            tiff.extend_from_slice(&2_u16.to_be_bytes());
+            tiff.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            tiff.extend_from_slice(&(value_offset as u32).to_be_bytes());
+            value_offset += value.len();
+            values.extend_from_slice(value.as_bytes());
+        }
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend(values);
+
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend_from_slice(&(2 + 6 + tiff.len() as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend(tiff);
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+        jpeg
+    }
+
+    #[test]
+    fn test_exif_capture_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("IMG_0001.jpg");
+
+        std::fs::write(&path, photo("2024:05:15 10:30:00", Some("+02:00"))).unwrap();
+        let taken = capture_time(&path, None).unwrap();
+        assert_eq!(
+            taken,
+            Some(Utc.with_ymd_and_hms(2024, 5, 15, 8, 30, 0).unwrap())
+        );
+
+        std::fs::write(&path, photo("2024:05:15 10:30:00", None)).unwrap();
+        let taken = capture_time(&path, Some(chrono_tz::America::New_York)).unwrap();
+        assert_eq!(
+            taken,
+            Some(Utc.with_ymd_and_hms(2024, 5, 15, 14, 30, 0).unwrap())
+        );
+
+        std::fs::write(&path, photo("    :  :     :  :  ", None)).unwrap();
+        assert_eq!(capture_time(&path, None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_capture_time_of_other_files_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        std::fs::write(&path, "# Notes\n").unwrap();
+        assert_eq!(capture_time(&path, None).unwrap(), None);
+    }
+}
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::errors::ZapError;
+
+/// The `[notify]` section of `config.toml`: hooks that receive the run summary.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// Command that gets the JSON summary on stdin
+    pub command: Option<String>,
+    /// URL the JSON summary is POSTed to
+    pub webhook: Option<String>,
+}
+
+impl NotifyConfig {
+    pub fn is_configured(&self) -> bool {
+        self.command.is_some() || self.webhook.is_some()
+    }
+}
+
+/// What a run did to each file, reported to the notification hooks as JSON.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct RunSummary {
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub skipped: Vec<String>,
+    pub failed: Vec<FailedFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedFile {
+    pub file: String,
+    pub error: String,
+}
+
+impl RunSummary {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("run summary is always serializable")
+    }
+}
+
+/// Send the summary to every configured hook. A failing hook doesn't fail the run,
+/// so problems are only reported as warnings.
+pub fn send(config: &NotifyConfig, summary: &RunSummary) {
+    let json = summary.to_json();
+    if let Some(command) = &config.command {
+        if let Err(e) = run_command(command, &json) {
+            eprintln!("Warning: Notification command failed: {e}");
+        }
+    }
+    if let Some(url) = &config.webhook {
+        if let Err(e) = post_webhook(url, &json) {
+            eprintln!("Warning: Notification webhook failed: {e}");
+        }
+    }
+}
+
+fn run_command(command: &str, json: &str) -> Result<(), ZapError> {
+    let mut parts = command.split_whitespace();
+    let program = parts.next().ok_or_else(|| ZapError::Notify {
+        hook: command.to_string(),
+        reason: "command is empty".to_string(),
+    })?;
+
+    let mut child = Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    let written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(json.as_bytes()),
+        None => Ok(()),
+    };
+
+    // A hook that fails may exit before reading the summary; its exit status
+    // says more than the broken pipe that leaves behind
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(ZapError::Notify {
+            hook: command.to_string(),
+            reason: format!("exited with {status}"),
+        });
+    }
+    written?;
+    Ok(())
+}
+
+fn post_webhook(url: &str, json: &str) -> Result<(), ZapError> {
+    ureq::post(url)
+        .timeout(Duration::from_secs(10))
+        .set("Content-Type", "application/json")
+        .send_string(json)
+        .map(|_| ())
+        .map_err(|e| ZapError::Notify {
+            hook: url.to_string(),
+            reason: e.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+
+    fn summary() -> RunSummary {
+        RunSummary {
+            created: vec!["new.md".to_string()],
+            updated: vec!["old.md".to_string()],
+            failed: vec![FailedFile {
+                file: "locked.md".to_string(),
+                error: "Permission denied".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let json: serde_json::Value = serde_json::from_str(&summary().to_json()).unwrap();
+        assert_eq!(json["created"][0], "new.md");
+        assert_eq!(json["skipped"].as_array().unwrap().len(), 0);
+        assert_eq!(json["failed"][0]["file"], "locked.md");
+    }
+
+    #[test]
+    fn test_command_receives_summary_on_stdin() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let out = dir.path().join("summary.json");
+
+        run_command(&format!("tee {}", out.display()), &summary().to_json()).unwrap();
+
+        assert_eq!(std::fs::read_to_string(out).unwrap(), summary().to_json());
+        assert!(run_command("false", "{}").is_err());
+    }
+
+    #[test]
+    fn test_command_exiting_early_reports_its_status() {
+        // More than a pipe buffer, so writing it fails once the hook is gone
+        let json = format!("\"{}\"", "x".repeat(1 << 20));
+        match run_command("false", &json) {
+            Err(ZapError::Notify { reason, .. }) => assert!(reason.starts_with("exited with")),
+            other => panic!("expected the exit status, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_webhook_posts_summary() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                    content_length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            String::from_utf8(body).unwrap()
+        });
+
+        post_webhook(&url, &summary().to_json()).unwrap();
+        assert_eq!(server.join().unwrap(), summary().to_json());
+    }
+}
@@ -0,0 +1,307 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::errors::ZapError;
+
+/// File in the config dir recording where each installed pack came from.
+pub const LOCKFILE_NAME: &str = "packs.lock";
+
+/// Template packs installed with `zap template install`, as recorded in
+/// `packs.lock`. Keeping the lockfile next to `config.toml` means the same
+/// packs, at the same revisions, can be installed again on another machine:
+///
+/// ```text
+/// [[pack]]
+/// name = "rust"
+/// source = "https://github.com/ada/rust-templates.git"
+/// rev = "4f2c0e1..."
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default, rename = "pack")]
+    pub packs: Vec<LockedPack>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedPack {
+    pub name: String,
+    /// Git URL or local directory the pack was installed from
+    pub source: String,
+    /// Commit the pack is at; packs copied from a directory have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+}
+
+impl Lockfile {
+    /// Read the lockfile at `path`; a missing file means no packs are installed.
+    pub fn load(path: &Path) -> Result<Self, ZapError> {
+        match fs::read_to_string(path) {
+            Ok(source) => toml::from_str(&source).map_err(|e| ZapError::InvalidConfig {
+                path: path.to_path_buf(),
+                reason: e.message().to_string(),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ZapError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let toml = toml::to_string(self).expect("lockfile is always serializable");
+        fs::write(path, toml)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedPack> {
+        self.packs.iter().find(|pack| pack.name == name)
+    }
+
+    /// Record `pack`, replacing an earlier entry of the same name. Entries are
+    /// kept sorted so the lockfile diffs well.
+    pub fn set(&mut self, pack: LockedPack) {
+        self.remove(&pack.name);
+        self.packs.push(pack);
+        self.packs.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<LockedPack> {
+        let index = self.packs.iter().position(|pack| pack.name == name)?;
+        Some(self.packs.remove(index))
+    }
+}
+
+/// The name a pack installed from `source` gets unless one is given: the last
+/// path segment without `.git`, so `https://github.com/ada/rust.git` becomes `rust`.
+pub fn default_name(source: &str) -> Result<String, ZapError> {
+    let last = source
+        .trim_end_matches(['/', '\\'])
+        .rsplit(['/', '\\', ':'])
+        .next()
+        .unwrap_or_default();
+    let name = last.strip_suffix(".git").unwrap_or(last);
+    check_name(name)?;
+    Ok(name.to_string())
+}
+
+/// Pack names become the first part of template names (`-T rust/bin`), so they
+/// must be a single, ordinary path segment.
+pub fn check_name(name: &str) -> Result<(), ZapError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(ZapError::InvalidPackName(name.to_string()))
+    }
+}
+
+/// Local directories are copied; everything else is cloned with git.
+fn is_git_source(source: &str) -> bool {
+    !Path::new(source).is_dir()
+}
+
+/// Put the pack from `source` into `packs_dir/name`, at `rev` if given (the
+/// latest commit otherwise), and return its lock entry. The pack is fetched
+/// next to its final place first, so a failed download leaves an installed
+/// pack of the same name untouched.
+pub fn fetch(
+    packs_dir: &Path,
+    name: &str,
+    source: &str,
+    rev: Option<&str>,
+) -> Result<LockedPack, ZapError> {
+    check_name(name)?;
+    fs::create_dir_all(packs_dir)?;
+    let staging = packs_dir.join(format!(".{name}.zap-tmp"));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+
+    let rev = if is_git_source(source) {
+        match clone(source, rev, &staging) {
+            Ok(rev) => Some(rev),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(e);
+            }
+        }
+    } else {
+        copy_dir(Path::new(source), &staging)?;
+        None
+    };
+
+    let dir = packs_dir.join(name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    fs::rename(&staging, &dir)?;
+    Ok(LockedPack {
+        name: name.to_string(),
+        source: source.to_string(),
+        rev,
+    })
+}
+
+/// Clone `source` into `dir`, check out `rev` and return the commit it resolves to.
+/// Sources can come from a shared lockfile, so neither may pass for an option of git.
+fn clone(source: &str, rev: Option<&str>, dir: &Path) -> Result<String, ZapError> {
+    if let Some(option) = [Some(source), rev]
+        .into_iter()
+        .flatten()
+        .find(|arg| arg.starts_with('-'))
+    {
+        return Err(ZapError::PackSource {
+            from: source.to_string(),
+            reason: format!("'{option}' isn't a repository or revision"),
+        });
+    }
+    let git = |command: &mut Command| -> Result<String, ZapError> {
+        let error = |reason: String| ZapError::PackSource {
+            from: source.to_string(),
+            reason,
+        };
+        let output = command
+            .output()
+            .map_err(|e| error(format!("could not run git: {e}")))?;
+        if !output.status.success() {
+            return Err(error(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+
+    let mut command = Command::new("git");
+    command.args(["clone", "--quiet"]);
+    if rev.is_none() {
+        command.args(["--depth", "1"]);
+    }
+    git(command.arg("--").arg(source).arg(dir))?;
+    if let Some(rev) = rev {
+        git(Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["checkout", "--quiet", "--detach", rev]))?;
+    }
+    git(Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "HEAD"]))
+}
+
+/// Copy the directory `from` to `to`, leaving out `.git`.
+fn copy_dir(from: &Path, to: &Path) -> Result<(), ZapError> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let target: PathBuf = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Short form of a commit for messages.
+pub fn short_rev(rev: Option<&str>) -> &str {
+    rev.map_or("local copy", |rev| &rev[..rev.len().min(7)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_default_name() {
+        assert_eq!(
+            default_name("https://github.com/ada/rust.git").unwrap(),
+            "rust"
+        );
+        assert_eq!(
+            default_name("git@github.com:ada/web-templates").unwrap(),
+            "web-templates"
+        );
+        assert_eq!(default_name("/home/ada/packs/notes/").unwrap(), "notes");
+        assert!(default_name("https://example.com/.git").is_err());
+        assert!(check_name("..").is_err());
+        assert!(check_name("a/b").is_err());
+    }
+
+    #[test]
+    fn test_lockfile_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(LOCKFILE_NAME);
+        assert_eq!(Lockfile::load(&path).unwrap(), Lockfile::default());
+
+        let mut lock = Lockfile::default();
+        let pack = |name: &str, rev: Option<&str>| LockedPack {
+            name: name.to_string(),
+            source: format!("https://example.com/{name}.git"),
+            rev: rev.map(str::to_string),
+        };
+        lock.set(pack("web", Some("abc")));
+        lock.set(pack("rust", None));
+        lock.set(pack("web", Some("def")));
+        lock.save(&path).unwrap();
+
+        let loaded = Lockfile::load(&path).unwrap();
+        assert_eq!(loaded.packs, [pack("rust", None), pack("web", Some("def"))]);
+        assert!(!fs::read_to_string(&path).unwrap().contains("rev = \"abc\""));
+
+        lock.remove("web");
+        assert!(lock.get("web").is_none());
+        assert!(lock.get("rust").is_some());
+    }
+
+    #[test]
+    fn test_fetch_copies_local_directories() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join(".git")).unwrap();
+        fs::create_dir_all(source.join("bin")).unwrap();
+        fs::write(source.join("bin").join("main.rs"), "fn main() {}\n").unwrap();
+
+        let packs = dir.path().join("packs");
+        let locked = fetch(&packs, "rust", source.to_str().unwrap(), None).unwrap();
+        assert_eq!(locked.rev, None);
+        assert!(packs.join("rust").join("bin").join("main.rs").is_file());
+        assert!(!packs.join("rust").join(".git").exists());
+        assert!(!packs.join(".rust.zap-tmp").exists());
+    }
+
+    #[test]
+    fn test_fetch_rejects_sources_and_revisions_like_options() {
+        let dir = TempDir::new().unwrap();
+        let packs = dir.path().join("packs");
+        let marker = dir.path().join("ran");
+        let source = format!("--upload-pack=touch {}", marker.display());
+        assert!(matches!(
+            fetch(&packs, "evil", &source, None),
+            Err(ZapError::PackSource { .. })
+        ));
+        assert!(matches!(
+            fetch(
+                &packs,
+                "evil",
+                "https://example.com/pack.git",
+                Some("--orphan")
+            ),
+            Err(ZapError::PackSource { .. })
+        ));
+        assert!(!marker.exists());
+        assert!(!packs.join("evil").exists());
+    }
+}
@@ -1,32 +1,272 @@
 use crate::ZapError;
-use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+use crate::args::Dst;
+use chrono::{
+    DateTime, Datelike, Days, Local, LocalResult, Months, NaiveDate, NaiveDateTime, NaiveTime,
+    Offset, TimeDelta, TimeZone, Timelike, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use lazy_regex::{Captures, regex};
+use std::time::Duration;
 
-// Parser for -d "YYYY-MM-DDThh:mm:SS[.frac][tz]"
-pub fn parse_d_format(s: &str) -> anyhow::Result<DateTime<Utc>> {
+// What's said when `dst` leaves a local time unresolved
+const DST_PROBLEM: &str =
+    "a daylight saving change makes that local time ambiguous or skips it, see --dst";
+
+// `naive` in `zone`, with `dst` picking the instant when a daylight saving change makes
+// it ambiguous or skips it. A skipped time is read with the offsets from just before and
+// after the change, which give an instant after and one before the jump.
+pub fn resolve_local<Z: TimeZone>(
+    zone: &Z,
+    naive: &NaiveDateTime,
+    dst: Dst,
+) -> Option<DateTime<Z>> {
+    match (zone.from_local_datetime(naive), dst) {
+        (LocalResult::Single(time), _) => Some(time),
+        (_, Dst::Error) => None,
+        (LocalResult::Ambiguous(earliest, _), Dst::Earliest) => Some(earliest),
+        (LocalResult::Ambiguous(_, latest), Dst::Latest) => Some(latest),
+        (LocalResult::None, _) => {
+            // The offsets a day apart are those on either side of the change
+            let with_offset_at = |delta: TimeDelta| {
+                let offset = zone
+                    .from_local_datetime(&(*naive + delta))
+                    .earliest()?
+                    .offset()
+                    .fix();
+                Some((*naive - TimeDelta::seconds(offset.local_minus_utc().into())).and_utc())
+            };
+            let before = with_offset_at(TimeDelta::days(-1))?;
+            let after = with_offset_at(TimeDelta::days(1))?;
+            let instant = match dst {
+                Dst::Earliest => before.min(after),
+                _ => before.max(after),
+            };
+            Some(instant.with_timezone(zone))
+        }
+    }
+}
+
+// Parser for -d "YYYY-MM-DDThh:mm:SS[.frac][tz]" or a relative date like "2 hours ago".
+// Dates without an offset are in `tz` (from --tz), or else in the local zone.
+pub fn parse_d_format(s: &str, tz: Option<Tz>, dst: Dst) -> anyhow::Result<DateTime<Utc>> {
+    match tz {
+        Some(tz) => parse_d_format_in(s, &tz, dst),
+        None => parse_d_format_in(s, &Local, dst),
+    }
+}
+
+fn parse_d_format_in<Z: TimeZone>(s: &str, zone: &Z, dst: Dst) -> anyhow::Result<DateTime<Utc>> {
     // first try RFC3339 for inputs with a timezone offset.
     if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
         return Ok(dt.with_timezone(&Utc));
     }
 
     if let Ok(naive_dt) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f") {
-        let local_dt = Local
-            .from_local_datetime(&naive_dt)
-            .single()
-            .ok_or_else(|| ZapError::ParseRfc3339 {
+        let local_dt =
+            resolve_local(zone, &naive_dt, dst).ok_or_else(|| ZapError::ParseRfc3339 {
                 input: s.to_string(),
-                reason: "Failed to convert local time".to_string(),
+                reason: DST_PROBLEM.to_string(),
             })?;
         return Ok(local_dt.with_timezone(&Utc));
     }
-    Err(ZapError::ParseRfc3339 {
+
+    let now = crate::clock::now().with_timezone(zone);
+    let relative = parse_relative(s, &now, dst).map_err(|reason| ZapError::ParseDate {
         input: s.to_string(),
-        reason: "Invalid date-time format, expected RFC3339 or YYYY-MM-DDThh:mm:SS[.frac]"
-            .to_string(),
-    })?
+        reason,
+    })?;
+    Ok(relative.with_timezone(&Utc))
+}
+
+// Units of relative dates, as in "3 days ago"
+#[derive(Clone, Copy)]
+enum RelativeUnit {
+    Seconds(i64),
+    Days(i64),
+    Months(i64),
+}
+
+fn relative_unit(word: &str) -> Option<RelativeUnit> {
+    let singular = match word.strip_suffix('s') {
+        Some(stem) if stem.len() > 2 => stem,
+        _ => word,
+    };
+    Some(match singular {
+        "sec" | "second" => RelativeUnit::Seconds(1),
+        "min" | "minute" => RelativeUnit::Seconds(60),
+        "hour" => RelativeUnit::Seconds(3600),
+        "day" => RelativeUnit::Days(1),
+        "week" => RelativeUnit::Days(7),
+        "fortnight" => RelativeUnit::Days(14),
+        "month" => RelativeUnit::Months(1),
+        "year" => RelativeUnit::Months(12),
+        _ => return None,
+    })
+}
+
+fn weekday(word: &str) -> Option<Weekday> {
+    // chrono takes "mon" and "monday"; "tues" and "thurs" are ours
+    word.parse::<Weekday>().ok().or(match word {
+        "tues" => Some(Weekday::Tue),
+        "thur" | "thurs" => Some(Weekday::Thu),
+        _ => None,
+    })
+}
+
+// Calendar and clock offsets of a relative date
+#[derive(Default)]
+struct Offsets {
+    months: i64,
+    days: i64,
+    seconds: i64,
+}
+
+impl Offsets {
+    // Saturating, so huge counts end up out of range instead of overflowing
+    fn add(&mut self, unit: RelativeUnit, count: i64) {
+        let (offset, n) = match unit {
+            RelativeUnit::Seconds(n) => (&mut self.seconds, n),
+            RelativeUnit::Days(n) => (&mut self.days, n),
+            RelativeUnit::Months(n) => (&mut self.months, n),
+        };
+        *offset = offset.saturating_add(n.saturating_mul(count));
+    }
+
+    fn merge(&mut self, other: Offsets, sign: i64) {
+        self.months = self
+            .months
+            .saturating_add(other.months.saturating_mul(sign));
+        self.days = self.days.saturating_add(other.days.saturating_mul(sign));
+        self.seconds = self
+            .seconds
+            .saturating_add(other.seconds.saturating_mul(sign));
+    }
+}
+
+// Parser for relative dates like GNU date's: "yesterday", "2 hours ago", "next monday 09:00",
+// "last week" or "2024-03-01 +3 days". A date or weekday starts at midnight unless a time
+// of day is given; everything else keeps the time of `now`.
+pub fn parse_relative<Tz: TimeZone>(
+    s: &str,
+    now: &DateTime<Tz>,
+    dst: Dst,
+) -> Result<DateTime<Tz>, String> {
+    let lowercase = s.to_lowercase();
+    let mut words = lowercase.split_whitespace().peekable();
+    if words.peek().is_none() {
+        return Err("it's empty".to_string());
+    }
+
+    let local = now.naive_local();
+    let mut date = local.date();
+    let mut time = None;
+    let mut midnight = false;
+    let mut offsets = Offsets::default();
+    // Offsets since the last "ago", which turns them around
+    let mut pending = Offsets::default();
+
+    while let Some(word) = words.next() {
+        match word {
+            "now" | "today" => {}
+            "yesterday" => pending.add(RelativeUnit::Days(1), -1),
+            "tomorrow" => pending.add(RelativeUnit::Days(1), 1),
+            "noon" => time = NaiveTime::from_hms_opt(12, 0, 0),
+            "midnight" => time = NaiveTime::from_hms_opt(0, 0, 0),
+            "ago" => offsets.merge(std::mem::take(&mut pending), -1),
+            "next" | "last" | "this" => {
+                let direction = match word {
+                    "next" => 1,
+                    "last" => -1,
+                    _ => 0,
+                };
+                let Some(what) = words.next() else {
+                    return Err(format!("'{word}' needs a weekday or unit after it"));
+                };
+                if let Some(day) = weekday(what) {
+                    date = weekday_date(date, day, direction);
+                    midnight = true;
+                } else if let Some(unit) = relative_unit(what) {
+                    pending.add(unit, i64::from(direction));
+                } else {
+                    return Err(format!("'{what}' after '{word}' is no weekday or unit"));
+                }
+            }
+            _ => {
+                if let Some(day) = weekday(word) {
+                    date = weekday_date(date, day, 0);
+                    midnight = true;
+                } else if let Ok(absolute) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+                    date = absolute;
+                    midnight = true;
+                } else if let Ok(of_day) = NaiveTime::parse_from_str(word, "%H:%M:%S")
+                    .or_else(|_| NaiveTime::parse_from_str(word, "%H:%M"))
+                {
+                    time = Some(of_day);
+                } else if let Ok(count) = word.parse::<i64>().or(match word {
+                    "a" | "an" => Ok(1),
+                    _ => Err(()),
+                }) {
+                    let Some(unit) = words.next().and_then(relative_unit) else {
+                        return Err(format!("'{word}' needs a unit after it, like 'days'"));
+                    };
+                    pending.add(unit, count);
+                } else {
+                    return Err(format!("unknown word '{word}'"));
+                }
+            }
+        }
+    }
+    offsets.merge(pending, 1);
+
+    let time = time.unwrap_or(if midnight {
+        NaiveTime::MIN
+    } else {
+        local.time()
+    });
+    let overflow = || "it's out of range".to_string();
+    let months = Months::new(u32::try_from(offsets.months.unsigned_abs()).map_err(|_| overflow())?);
+    let date = if offsets.months < 0 {
+        date.checked_sub_months(months)
+    } else {
+        date.checked_add_months(months)
+    }
+    .ok_or_else(overflow)?;
+    let days = Days::new(offsets.days.unsigned_abs());
+    let date = if offsets.days < 0 {
+        date.checked_sub_days(days)
+    } else {
+        date.checked_add_days(days)
+    }
+    .ok_or_else(overflow)?;
+
+    let start = resolve_local(&now.timezone(), &date.and_time(time), dst)
+        .ok_or_else(|| DST_PROBLEM.to_string())?;
+    chrono::Duration::try_seconds(offsets.seconds)
+        .and_then(|seconds| start.checked_add_signed(seconds))
+        .ok_or_else(overflow)
+}
+
+// The `day` nearest to `date`: the next one after it (1), the last one before it (-1),
+// or the coming one, which is `date` itself if it's that day (0)
+fn weekday_date(date: NaiveDate, day: Weekday, direction: i32) -> NaiveDate {
+    let ahead = i64::from(day.days_since(date.weekday()));
+    let offset = match direction {
+        1 if ahead == 0 => 7,
+        -1 => ahead - 7,
+        _ => ahead,
+    };
+    date + chrono::Duration::days(offset)
+}
+
+// Parser for -t "[[CC]YY]MMDDhhmm[.SS]", in `tz` (from --tz) or else the local zone
+pub fn parse_t_format(s: &str, tz: Option<Tz>, dst: Dst) -> anyhow::Result<DateTime<Utc>> {
+    match tz {
+        Some(tz) => parse_t_format_in(s, &tz, dst),
+        None => parse_t_format_in(s, &Local, dst),
+    }
 }
 
-// Parser for -t "[[CC]YY]MMDDhhmm[.SS]"
-pub fn parse_t_format(s: &str) -> anyhow::Result<DateTime<Utc>> {
+fn parse_t_format_in<Z: TimeZone>(s: &str, zone: &Z, dst: Dst) -> anyhow::Result<DateTime<Utc>> {
     let parts: Vec<&str> = s.split('.').collect();
     let (date_time_str, sec_str) = match parts.as_slice() {
         [dt] => (*dt, "0"), // No seconds provided, default to 0.
@@ -49,7 +289,11 @@ pub fn parse_t_format(s: &str) -> anyhow::Result<DateTime<Utc>> {
     let naive_dt_base = match date_time_str.len() {
         // MMDDhhmm: Prepend the current year and parse.
         8 => {
-            let s_with_year = format!("{}{}", Local::now().year(), date_time_str);
+            let s_with_year = format!(
+                "{}{}",
+                crate::clock::now().with_timezone(zone).year(),
+                date_time_str
+            );
             NaiveDateTime::parse_from_str(&s_with_year, "%Y%m%d%H%M")
         }
         // YYMMDDhhmm: The %y format specifier correctly handles the 1969-2068 rule.
@@ -72,49 +316,557 @@ pub fn parse_t_format(s: &str) -> anyhow::Result<DateTime<Utc>> {
         .with_second(second)
         .ok_or_else(|| ZapError::TOptionInvalidSecond { second })?;
 
-    let local_dt = Local
-        .from_local_datetime(&naive_dt)
-        .single()
-        .ok_or_else(|| ZapError::TOptionConvertToLocal)?;
+    let local_dt =
+        resolve_local(zone, &naive_dt, dst).ok_or_else(|| ZapError::TOptionConvertToLocal)?;
 
     Ok(local_dt.with_timezone(&Utc))
 }
 
-// Parser for -A "[-][[hh]mm]SS"
-pub fn parse_adjust(s: &str) -> Result<i32, anyhow::Error> {
-    let sign = if s.chars().next().unwrap_or('+') == '-' {
-        -1
-    } else {
-        1
+// Parser for -s "[-]SECONDS[.frac]", seconds since the Unix epoch
+pub fn parse_unix(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    let invalid = |reason: &str| ZapError::ParseUnix {
+        input: s.to_string(),
+        reason: reason.to_string(),
     };
+    let (seconds, fraction) = s.split_once('.').unwrap_or((s, ""));
+    let digits = seconds.strip_prefix(['-', '+']).unwrap_or(seconds);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid("expected [-]SECONDS[.frac]").into());
+    }
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid("expected up to 9 digits after the '.'").into());
+    }
+    let seconds: i64 = seconds.parse().map_err(|_| invalid("out of range"))?;
+    let nanos: u32 = format!("{fraction:0<9}").parse().unwrap_or_default();
+    // -1.5 is half a second before -1
+    let delta = TimeDelta::try_seconds(seconds)
+        .zip(TimeDelta::new(0, nanos))
+        .map(|(whole, part)| {
+            if s.starts_with('-') {
+                whole - part
+            } else {
+                whole + part
+            }
+        })
+        .ok_or_else(|| invalid("out of range"))?;
+    DateTime::UNIX_EPOCH
+        .checked_add_signed(delta)
+        .ok_or_else(|| invalid("out of range").into())
+}
 
-    // 2, 4 or 6 digit number as string ([-][[hh]mm]SS)
-    let num = s
-        .strip_prefix('-')
-        .or_else(|| s.strip_prefix('+'))
-        .unwrap_or(s);
+// Parser for --randomize "START..END", both in any form -d takes
+pub fn parse_window(
+    s: &str,
+    tz: Option<Tz>,
+    dst: Dst,
+) -> anyhow::Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let invalid = |reason: String| ZapError::ParseWindow {
+        input: s.to_string(),
+        reason,
+    };
+    let Some((start, end)) = s.split_once("..") else {
+        return Err(invalid("expected START..END".to_string()).into());
+    };
+    let parse =
+        |date: &str| parse_d_format(date.trim(), tz, dst).map_err(|e| invalid(e.to_string()));
+    let (start, end) = (parse(start)?, parse(end)?);
+    if start > end {
+        return Err(invalid("START is later than END".to_string()).into());
+    }
+    Ok((start, end))
+}
 
-    if !num.is_ascii() || num.len() % 2 != 0 || num.is_empty() || ![2, 4, 6].contains(&num.len()) {
+// An -A adjustment: calendar months first (a year is twelve), then a fixed duration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Adjustment {
+    pub months: i32,
+    pub delta: TimeDelta,
+}
+
+// Months in one of the calendar units of a duration, "1month" or "2years"
+fn calendar_months(unit: &str) -> Option<i32> {
+    match unit {
+        "month" | "months" | "M" => Some(1),
+        "year" | "years" | "y" => Some(12),
+        _ => None,
+    }
+}
+
+// Parser for durations with a fixed length, like --stagger and --round take:
+// an -A adjustment without months or years
+pub fn parse_adjust(s: &str) -> Result<TimeDelta, anyhow::Error> {
+    let adjustment = parse_adjustment(s)?;
+    if adjustment.months != 0 {
         return Err(ZapError::ParseAdjustment {
-            reason: format!("Invalid format '{s}', expected [-][[hh]mm]SS with 2, 4, or 6 digits"),
+            reason: format!("'{s}' has months or years, which have no fixed length"),
         }
         .into());
     }
+    Ok(adjustment.delta)
+}
 
-    let sum: i32 = (0..num.len())
-        .step_by(2)
-        .map(|i| {
-            let chunk = &num[i..i + 2];
-            chunk.parse::<i32>().map_err(|e| ZapError::ParseAdjustment {
-                reason: e.to_string(),
-            })
-        })
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .rev() // Reverse the iterator of parsed numbers.
+// ISO 8601 durations without their sign, like "P1Y2M10DT2H30M" or "PT0.5S". Years and
+// months go by the calendar, weeks and days are fixed; only seconds may have a fraction.
+fn parse_iso_duration(s: &str) -> Result<Adjustment, String> {
+    let Some(rest) = s.strip_prefix('P') else {
+        return Err("doesn't start with 'P'".to_string());
+    };
+    let (date, time) = match rest.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (rest, None),
+    };
+    if rest.is_empty() || time == Some("") {
+        return Err("has no parts after 'P' or 'T'".to_string());
+    }
+
+    let too_long = || "is too long".to_string();
+    let mut adjustment = Adjustment::default();
+    for (part, units, in_time) in [(date, "YMWD", false), (time.unwrap_or(""), "HMS", true)] {
+        let mut number = String::new();
+        let mut previous = None;
+        for c in part.chars() {
+            if c.is_ascii_digit() || c == '.' || c == ',' {
+                number.push(c);
+                continue;
+            }
+            let Some(index) = units.find(c) else {
+                return Err(format!("has an unknown part '{c}'"));
+            };
+            if previous.is_some_and(|previous| previous >= index) {
+                return Err("has its parts out of order".to_string());
+            }
+            previous = Some(index);
+
+            let (whole, fraction) = match number.split_once(['.', ',']) {
+                Some((whole, fraction)) if c == 'S' && in_time => (whole, fraction),
+                Some(_) => return Err(format!("has a fraction before '{c}', only seconds may")),
+                None => (number.as_str(), ""),
+            };
+            if whole.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(format!("has no number before '{c}'"));
+            }
+            let count: i64 = whole.parse().map_err(|_| too_long())?;
+            let delta = match (in_time, c) {
+                (false, 'Y' | 'M') => {
+                    let months = count
+                        .checked_mul(if c == 'Y' { 12 } else { 1 })
+                        .and_then(|months| i32::try_from(months).ok())
+                        .ok_or_else(too_long)?;
+                    adjustment.months =
+                        adjustment.months.checked_add(months).ok_or_else(too_long)?;
+                    Some(TimeDelta::zero())
+                }
+                (false, 'W') => count.checked_mul(7).and_then(TimeDelta::try_days),
+                (false, _) => TimeDelta::try_days(count),
+                (true, 'H') => TimeDelta::try_hours(count),
+                (true, 'M') => TimeDelta::try_minutes(count),
+                (true, _) => {
+                    if fraction.len() > 9 {
+                        return Err("has more than 9 digits after the seconds' point".to_string());
+                    }
+                    let nanos = format!("{fraction:0<9}").parse().unwrap_or_default();
+                    TimeDelta::try_seconds(count)
+                        .and_then(|seconds| seconds.checked_add(&TimeDelta::nanoseconds(nanos)))
+                }
+            }
+            .ok_or_else(too_long)?;
+            adjustment.delta = adjustment.delta.checked_add(&delta).ok_or_else(too_long)?;
+            number.clear();
+        }
+        if !number.is_empty() {
+            return Err("ends in a number without a unit".to_string());
+        }
+    }
+    Ok(adjustment)
+}
+
+// Parser for -A "[-][[hh]mm]SS[.frac]": 1 to 6 digits, read in pairs from the right,
+// or a duration with units like "-2d", "1h30m" or "250ms", or an ISO 8601 one like
+// "PT1H30M". Precise to the nanosecond. Months and years ("1month", "-2years", "P1M")
+// are calendar ones, kept apart from the rest.
+pub fn parse_adjustment(s: &str) -> Result<Adjustment, anyhow::Error> {
+    let invalid = |problem: &str| ZapError::ParseAdjustment {
+        reason: format!(
+            "'{s}' {problem}, expected [-][[hh]mm]SS[.frac] or a duration like 1h30m, 1month or PT1H30M"
+        ),
+    };
+
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    if digits.starts_with('P') {
+        let adjustment = parse_iso_duration(digits).map_err(|problem| invalid(&problem))?;
+        return Ok(Adjustment {
+            months: adjustment.months * sign,
+            delta: adjustment.delta * sign,
+        });
+    }
+
+    if digits.bytes().any(|b| b.is_ascii_alphabetic()) {
+        let mut months = Some(0_i32);
+        let fixed = regex!(r"(\d+)\s*([a-zA-Z]+)").replace_all(digits, |caps: &Captures| {
+            match calendar_months(&caps[2]) {
+                Some(per_unit) => {
+                    months = caps[1]
+                        .parse::<i32>()
+                        .ok()
+                        .and_then(|count| count.checked_mul(per_unit))
+                        .zip(months)
+                        .and_then(|(these, before)| before.checked_add(these));
+                    String::new()
+                }
+                None => caps[0].to_string(),
+            }
+        });
+        let months = months.ok_or_else(|| invalid("is too long"))?;
+        let fixed = fixed.trim();
+        let delta = if fixed.is_empty() {
+            TimeDelta::zero()
+        } else {
+            let duration = humantime::parse_duration(fixed)
+                .map_err(|e| invalid(&format!("is no valid duration ({e})")))?;
+            if duration.as_secs() > i32::MAX as u64 {
+                return Err(invalid("is too long").into());
+            }
+            TimeDelta::from_std(duration).map_err(|_| invalid("is too long"))?
+        };
+        return Ok(Adjustment {
+            months: months * sign,
+            delta: delta * sign,
+        });
+    }
+
+    let (digits, fraction) = match digits.split_once('.') {
+        Some((digits, fraction)) => (digits, Some(fraction)),
+        None => (digits, None),
+    };
+    let nanos = match fraction {
+        Some(fraction)
+            if (1..=9).contains(&fraction.len())
+                && fraction.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            // Padded to nine digits, "5" is 500000000 nanoseconds
+            format!("{fraction:0<9}").parse::<u32>().unwrap_or_default()
+        }
+        Some(_) => return Err(invalid("needs 1 to 9 digits after the '.'").into()),
+        None => 0,
+    };
+
+    if digits.is_empty() {
+        return Err(invalid("has no digits").into());
+    }
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid("may only contain digits after the sign").into());
+    }
+    if digits.len() > 6 {
+        return Err(invalid("has more than 6 digits").into());
+    }
+
+    // SS, then mm, then hh; a leading single digit is its own group
+    let sum: i32 = digits
+        .as_bytes()
+        .rchunks(2)
         .zip([1, 60, 3600])
-        .map(|(val, mult)| val * mult)
+        .map(|(chunk, mult)| {
+            let value = chunk
+                .iter()
+                .fold(0, |acc, b| acc * 10 + i32::from(b - b'0'));
+            value * mult
+        })
         .sum();
 
-    Ok(sign * sum)
+    let delta = TimeDelta::new(i64::from(sum), nanos).ok_or_else(|| invalid("is too long"))?;
+    Ok(Adjustment {
+        months: 0,
+        delta: delta * sign,
+    })
+}
+
+// Parser for human readable durations like "30d", "1h30m" or "2weeks"
+pub fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    humantime::parse_duration(s.trim()).map_err(|e| {
+        ZapError::ParseDuration {
+            input: s.to_string(),
+            reason: e.to_string(),
+        }
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relative(s: &str) -> Result<String, String> {
+        // A Wednesday
+        let now = Utc.with_ymd_and_hms(2024, 5, 15, 10, 30, 0).unwrap();
+        parse_relative(s, &now, Dst::Error).map(|dt| dt.format("%a %F %T").to_string())
+    }
+
+    #[test]
+    fn test_parse_relative_dates() {
+        for (input, expected) in [
+            ("now", "Wed 2024-05-15 10:30:00"),
+            ("yesterday", "Tue 2024-05-14 10:30:00"),
+            ("Tomorrow noon", "Thu 2024-05-16 12:00:00"),
+            ("2 hours ago", "Wed 2024-05-15 08:30:00"),
+            ("an hour ago", "Wed 2024-05-15 09:30:00"),
+            ("1 day 2 hours ago", "Tue 2024-05-14 08:30:00"),
+            ("+90 minutes", "Wed 2024-05-15 12:00:00"),
+            ("-3 weeks", "Wed 2024-04-24 10:30:00"),
+            ("next monday 09:00", "Mon 2024-05-20 09:00:00"),
+            ("monday", "Mon 2024-05-20 00:00:00"),
+            ("wednesday", "Wed 2024-05-15 00:00:00"),
+            ("next wed", "Wed 2024-05-22 00:00:00"),
+            ("last wednesday", "Wed 2024-05-08 00:00:00"),
+            ("last friday 17:45:30", "Fri 2024-05-10 17:45:30"),
+            ("next week", "Wed 2024-05-22 10:30:00"),
+            ("last month", "Mon 2024-04-15 10:30:00"),
+            ("2024-01-31 +1 month", "Thu 2024-02-29 00:00:00"),
+            ("1 year ago", "Mon 2023-05-15 10:30:00"),
+        ] {
+            assert_eq!(relative(input).as_deref(), Ok(expected), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_parse_relative_rejects_invalid_input() {
+        for input in [
+            "",
+            "next",
+            "next blursday",
+            "3",
+            "3 parsecs",
+            "soon",
+            "99999999999 years",
+        ] {
+            assert!(relative(input).is_err(), "'{input}' should be rejected");
+        }
+        let err = parse_d_format("the day after", None, Dst::Error).unwrap_err();
+        assert!(err.to_string().contains("unknown word 'the'"), "{err}");
+    }
+
+    #[test]
+    fn test_dates_in_time_zone() {
+        let berlin = Some(chrono_tz::Europe::Berlin);
+        let utc = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        assert_eq!(
+            parse_d_format("2024-07-01T12:00:00", berlin, Dst::Error).unwrap(),
+            utc("2024-07-01T10:00:00Z")
+        );
+        assert_eq!(
+            parse_t_format("202401011200", berlin, Dst::Error).unwrap(),
+            utc("2024-01-01T11:00:00Z")
+        );
+        // An explicit offset wins over the zone
+        assert_eq!(
+            parse_d_format("2024-07-01T12:00:00Z", berlin, Dst::Error).unwrap(),
+            utc("2024-07-01T12:00:00Z")
+        );
+        // Doesn't exist: clocks jump from 02:00 to 03:00
+        assert!(parse_d_format("2024-03-31T02:30:00", berlin, Dst::Error).is_err());
+    }
+
+    #[test]
+    fn test_dst_resolution() {
+        let berlin = Some(chrono_tz::Europe::Berlin);
+        let utc = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        // Happens twice: clocks go back from 03:00 to 02:00
+        let twice = "2024-10-27T02:30:00";
+        assert!(parse_d_format(twice, berlin, Dst::Error).is_err());
+        assert_eq!(
+            parse_d_format(twice, berlin, Dst::Earliest).unwrap(),
+            utc("2024-10-27T00:30:00Z")
+        );
+        assert_eq!(
+            parse_d_format(twice, berlin, Dst::Latest).unwrap(),
+            utc("2024-10-27T01:30:00Z")
+        );
+        assert_eq!(
+            parse_t_format("202410270230", berlin, Dst::Latest).unwrap(),
+            utc("2024-10-27T01:30:00Z")
+        );
+        // Skipped: read as 02:30 CEST and as 02:30 CET
+        let skipped = "2024-03-31T02:30:00";
+        assert_eq!(
+            parse_d_format(skipped, berlin, Dst::Earliest).unwrap(),
+            utc("2024-03-31T00:30:00Z")
+        );
+        assert_eq!(
+            parse_d_format(skipped, berlin, Dst::Latest).unwrap(),
+            utc("2024-03-31T01:30:00Z")
+        );
+        let err = parse_t_format("202403310230", berlin, Dst::Error).unwrap_err();
+        assert!(err.to_string().contains("--dst"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_window() {
+        let (start, end) = parse_window(
+            "2023-01-01T00:00:00Z..2023-06-30T00:00:00Z",
+            None,
+            Dst::Error,
+        )
+        .unwrap();
+        assert_eq!(start, Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2023, 6, 30, 0, 0, 0).unwrap());
+
+        for input in [
+            "2023-01-01",
+            "2023-06-30T00:00:00Z..2023-01-01T00:00:00Z",
+            "soon..2023-01-01T00:00:00Z",
+        ] {
+            let err = parse_window(input, None, Dst::Error).unwrap_err();
+            assert!(
+                matches!(err.downcast_ref(), Some(ZapError::ParseWindow { .. })),
+                "{input}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_unix() {
+        let unix = |s: &str| parse_unix(s).map(|dt| (dt.timestamp(), dt.timestamp_subsec_nanos()));
+        assert_eq!(unix("1735689600").unwrap(), (1_735_689_600, 0));
+        assert_eq!(
+            unix("1735689600.123456789").unwrap(),
+            (1_735_689_600, 123_456_789)
+        );
+        assert_eq!(unix("0.5").unwrap(), (0, 500_000_000));
+        assert_eq!(unix("-1.5").unwrap(), (-2, 500_000_000));
+        for input in [
+            "",
+            "-",
+            "1.2.3",
+            "1e9",
+            "1.1234567890",
+            "99999999999999999999",
+        ] {
+            assert!(unix(input).is_err(), "'{input}' should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_adjust_digit_forms() {
+        assert_eq!(parse_adjust("5").unwrap(), TimeDelta::seconds(5));
+        assert_eq!(parse_adjust("05").unwrap(), TimeDelta::seconds(5));
+        assert_eq!(parse_adjust("130").unwrap(), TimeDelta::seconds(90));
+        assert_eq!(parse_adjust("0130").unwrap(), TimeDelta::seconds(90));
+        assert_eq!(parse_adjust("10000").unwrap(), TimeDelta::seconds(3600));
+        assert_eq!(parse_adjust("010203").unwrap(), TimeDelta::seconds(3723));
+        assert_eq!(parse_adjust("-0130").unwrap(), TimeDelta::seconds(-90));
+        assert_eq!(parse_adjust("+30").unwrap(), TimeDelta::seconds(30));
+    }
+
+    #[test]
+    fn test_parse_adjust_fractions() {
+        assert_eq!(parse_adjust("1.5").unwrap(), TimeDelta::milliseconds(1500));
+        assert_eq!(
+            parse_adjust("-0130.000000001").unwrap(),
+            -TimeDelta::new(90, 1).unwrap()
+        );
+        for input in ["1.", "1.1234567890", "1.x", ".5.5"] {
+            assert!(parse_adjust(input).is_err(), "'{input}' should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_adjust_durations() {
+        assert_eq!(parse_adjust("1h30m").unwrap(), TimeDelta::seconds(5400));
+        assert_eq!(parse_adjust("-2d").unwrap(), TimeDelta::seconds(-2 * 86400));
+        assert_eq!(parse_adjust("45s").unwrap(), TimeDelta::seconds(45));
+        assert_eq!(parse_adjust("+1h 5s").unwrap(), TimeDelta::seconds(3605));
+        assert_eq!(
+            parse_adjust("-250ms").unwrap(),
+            TimeDelta::milliseconds(-250)
+        );
+        for input in ["100years", "1month", "-1x", "h"] {
+            assert!(parse_adjust(input).is_err(), "'{input}' should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_parse_adjustment_calendar_units() {
+        let adjustment = |months, seconds| Adjustment {
+            months,
+            delta: TimeDelta::seconds(seconds),
+        };
+        assert_eq!(parse_adjustment("+1month").unwrap(), adjustment(1, 0));
+        assert_eq!(parse_adjustment("-2years").unwrap(), adjustment(-24, 0));
+        assert_eq!(
+            parse_adjustment("1y 2M 3d").unwrap(),
+            adjustment(14, 3 * 86400)
+        );
+        assert_eq!(
+            parse_adjustment("-1month 1h").unwrap(),
+            adjustment(-1, -3600)
+        );
+        assert_eq!(parse_adjustment("0130").unwrap(), adjustment(0, 90));
+        for input in ["1month 5", "9999999999years", "1 fortnight"] {
+            assert!(
+                parse_adjustment(input).is_err(),
+                "'{input}' should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_adjustment_iso_8601() {
+        let adjustment = |months, delta| Adjustment { months, delta };
+        assert_eq!(
+            parse_adjustment("PT1H30M").unwrap(),
+            adjustment(0, TimeDelta::seconds(5400))
+        );
+        assert_eq!(
+            parse_adjustment("-P2DT3H").unwrap(),
+            adjustment(0, TimeDelta::seconds(-(2 * 86400 + 3 * 3600)))
+        );
+        assert_eq!(
+            parse_adjustment("P1Y2M1W").unwrap(),
+            adjustment(14, TimeDelta::days(7))
+        );
+        assert_eq!(
+            parse_adjustment("+PT0,25S").unwrap(),
+            adjustment(0, TimeDelta::milliseconds(250))
+        );
+        // Fixed-length durations work wherever -A's do
+        assert_eq!(parse_adjust("PT2S").unwrap(), TimeDelta::seconds(2));
+        assert!(parse_adjust("P1M").is_err());
+        for input in [
+            "P",
+            "PT",
+            "P1H",
+            "PT1D",
+            "PT1M1H",
+            "P1.5D",
+            "P1",
+            "PT1.1234567890S",
+            "PX",
+        ] {
+            let err = parse_adjustment(input).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ZapError>(),
+                    Some(ZapError::ParseAdjustment { .. })
+                ),
+                "'{input}' should be rejected, got {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_adjust_rejects_invalid_input() {
+        for input in ["", "-", "1234567", "1a", "--5", "1-5", "١٢"] {
+            let err = parse_adjust(input).unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ZapError>(),
+                    Some(ZapError::ParseAdjustment { .. })
+                ),
+                "'{input}' should be rejected, got {err}"
+            );
+        }
+    }
 }
@@ -0,0 +1,353 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::errors::ZapError;
+
+/// The environment that decides where zap keeps its files, captured once so
+/// the resolution rules can be tested without touching the process environment.
+#[derive(Debug, Clone, Default)]
+pub struct PathEnv {
+    pub zap_config: Option<PathBuf>,
+    pub xdg_config_home: Option<PathBuf>,
+    pub xdg_data_home: Option<PathBuf>,
+    pub xdg_cache_home: Option<PathBuf>,
+    pub home: Option<PathBuf>,
+    /// `%APPDATA%`, only set on Windows
+    pub appdata: Option<PathBuf>,
+}
+
+impl PathEnv {
+    pub fn from_process() -> Self {
+        Self {
+            zap_config: env::var_os("ZAP_CONFIG").map(PathBuf::from),
+            xdg_config_home: env::var_os("XDG_CONFIG_HOME").map(PathBuf::from),
+            xdg_data_home: env::var_os("XDG_DATA_HOME").map(PathBuf::from),
+            xdg_cache_home: env::var_os("XDG_CACHE_HOME").map(PathBuf::from),
+            home: dirs::home_dir(),
+            appdata: appdata(),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn appdata() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(not(windows))]
+fn appdata() -> Option<PathBuf> {
+    None
+}
+
+/// On Windows everything lives in `%APPDATA%\zap`, unless that doesn't exist yet
+/// while `~/.config/zap` from older versions does.
+fn appdata_dir(env: &PathEnv, exists: &impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    let appdata_dir = env.appdata.as_ref()?.join("zap");
+    match env.home.as_ref().map(|home| home.join(".config/zap")) {
+        Some(legacy) if !exists(&appdata_dir) && exists(&legacy) => Some(legacy),
+        _ => Some(appdata_dir),
+    }
+}
+
+/// The XDG base directory spec says relative paths are invalid and must be ignored.
+fn xdg_dir(value: &Option<PathBuf>) -> Option<&Path> {
+    value.as_deref().filter(|path| path.is_absolute())
+}
+
+/// Where `config.toml` lives.
+/// `ZAP_CONFIG`, then `$XDG_CONFIG_HOME/zap`, then `%APPDATA%\zap` on Windows,
+/// then `~/.config/zap`.
+pub fn config_dir(env: &PathEnv, exists: impl Fn(&Path) -> bool) -> Result<PathBuf, ZapError> {
+    if let Some(dir) = &env.zap_config {
+        return Ok(dir.clone());
+    }
+    if let Some(config_home) = xdg_dir(&env.xdg_config_home) {
+        return Ok(config_home.join("zap"));
+    }
+    if let Some(dir) = appdata_dir(env, &exists) {
+        return Ok(dir);
+    }
+    env.home
+        .as_ref()
+        .map(|home| home.join(".config/zap"))
+        .ok_or(ZapError::ConfigDirNotFound)
+}
+
+/// Where `templates/` and `plugins/` live.
+/// `ZAP_CONFIG` keeps everything in one place. Otherwise it's `$XDG_DATA_HOME/zap`,
+/// then `%APPDATA%\zap` on Windows, then `~/.local/share/zap` - unless that doesn't exist yet while the config dir
+/// still holds templates or plugins from before zap followed XDG, in which case
+/// the config dir is used so existing setups keep working.
+pub fn data_dir(env: &PathEnv, exists: impl Fn(&Path) -> bool) -> Result<PathBuf, ZapError> {
+    if let Some(dir) = &env.zap_config {
+        return Ok(dir.clone());
+    }
+
+    if xdg_dir(&env.xdg_data_home).is_none() {
+        if let Some(dir) = appdata_dir(env, &exists) {
+            return Ok(dir);
+        }
+    }
+
+    let data_dir = match (xdg_dir(&env.xdg_data_home), &env.home) {
+        (Some(data_home), _) => data_home.join("zap"),
+        (None, Some(home)) => home.join(".local/share/zap"),
+        (None, None) => return Err(ZapError::ConfigDirNotFound),
+    };
+
+    // Without a config dir there are no old templates or plugins to keep using
+    let legacy = config_dir(env, &exists)
+        .ok()
+        .filter(|dir| exists(&dir.join("templates")) || exists(&dir.join("plugins")));
+    match legacy {
+        Some(config_dir) if !exists(&data_dir) => Ok(config_dir),
+        _ => Ok(data_dir),
+    }
+}
+
+/// Where downloaded templates are cached. `cache` inside `ZAP_CONFIG` or
+/// `%APPDATA%\zap` on Windows, otherwise `$XDG_CACHE_HOME/zap`, then `~/.cache/zap`.
+pub fn cache_dir(env: &PathEnv, exists: impl Fn(&Path) -> bool) -> Result<PathBuf, ZapError> {
+    if let Some(dir) = &env.zap_config {
+        return Ok(dir.join("cache"));
+    }
+    if let Some(cache_home) = xdg_dir(&env.xdg_cache_home) {
+        return Ok(cache_home.join("zap"));
+    }
+    if let Some(dir) = appdata_dir(env, &exists) {
+        return Ok(dir.join("cache"));
+    }
+    env.home
+        .as_ref()
+        .map(|home| home.join(".cache/zap"))
+        .ok_or(ZapError::ConfigDirNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn home_env() -> PathEnv {
+        PathEnv {
+            home: Some(PathBuf::from("/home/ada")),
+            ..Default::default()
+        }
+    }
+
+    fn nothing_exists(_: &Path) -> bool {
+        false
+    }
+
+    #[test]
+    fn test_defaults_without_overrides() {
+        let env = home_env();
+        assert_eq!(
+            config_dir(&env, nothing_exists).unwrap(),
+            PathBuf::from("/home/ada/.config/zap")
+        );
+        assert_eq!(
+            data_dir(&env, nothing_exists).unwrap(),
+            PathBuf::from("/home/ada/.local/share/zap")
+        );
+    }
+
+    #[test]
+    fn test_xdg_overrides() {
+        let env = PathEnv {
+            xdg_config_home: Some(PathBuf::from("/xdg/config")),
+            xdg_data_home: Some(PathBuf::from("/xdg/data")),
+            ..home_env()
+        };
+        assert_eq!(
+            config_dir(&env, nothing_exists).unwrap(),
+            PathBuf::from("/xdg/config/zap")
+        );
+        assert_eq!(
+            data_dir(&env, nothing_exists).unwrap(),
+            PathBuf::from("/xdg/data/zap")
+        );
+    }
+
+    #[test]
+    fn test_only_one_xdg_override() {
+        let config_only = PathEnv {
+            xdg_config_home: Some(PathBuf::from("/xdg/config")),
+            ..home_env()
+        };
+        assert_eq!(
+            data_dir(&config_only, nothing_exists).unwrap(),
+            PathBuf::from("/home/ada/.local/share/zap")
+        );
+
+        let data_only = PathEnv {
+            xdg_data_home: Some(PathBuf::from("/xdg/data")),
+            ..home_env()
+        };
+        assert_eq!(
+            config_dir(&data_only, nothing_exists).unwrap(),
+            PathBuf::from("/home/ada/.config/zap")
+        );
+        assert_eq!(
+            data_dir(&data_only, nothing_exists).unwrap(),
+            PathBuf::from("/xdg/data/zap")
+        );
+    }
+
+    #[test]
+    fn test_relative_xdg_paths_are_ignored() {
+        let env = PathEnv {
+            xdg_config_home: Some(PathBuf::from("relative/config")),
+            xdg_data_home: Some(PathBuf::from("relative/data")),
+            ..home_env()
+        };
+        assert_eq!(
+            config_dir(&env, nothing_exists).unwrap(),
+            PathBuf::from("/home/ada/.config/zap")
+        );
+        assert_eq!(
+            data_dir(&env, nothing_exists).unwrap(),
+            PathBuf::from("/home/ada/.local/share/zap")
+        );
+    }
+
+    #[test]
+    fn test_zap_config_wins_over_everything() {
+        let env = PathEnv {
+            zap_config: Some(PathBuf::from("/custom/zap")),
+            xdg_config_home: Some(PathBuf::from("/xdg/config")),
+            xdg_data_home: Some(PathBuf::from("/xdg/data")),
+            ..home_env()
+        };
+        assert_eq!(
+            config_dir(&env, nothing_exists).unwrap(),
+            PathBuf::from("/custom/zap")
+        );
+        assert_eq!(
+            data_dir(&env, nothing_exists).unwrap(),
+            PathBuf::from("/custom/zap")
+        );
+    }
+
+    #[test]
+    fn test_legacy_templates_in_config_dir() {
+        let env = home_env();
+        let legacy = |path: &Path| path == Path::new("/home/ada/.config/zap/templates");
+        assert_eq!(
+            data_dir(&env, legacy).unwrap(),
+            PathBuf::from("/home/ada/.config/zap")
+        );
+
+        // Once the data dir exists, it's used even if old templates are still around
+        let both = |path: &Path| legacy(path) || path == Path::new("/home/ada/.local/share/zap");
+        assert_eq!(
+            data_dir(&env, both).unwrap(),
+            PathBuf::from("/home/ada/.local/share/zap")
+        );
+    }
+
+    fn windows_env() -> PathEnv {
+        PathEnv {
+            home: Some(PathBuf::from(r"C:\Users\ada")),
+            appdata: Some(PathBuf::from(r"C:\Users\ada\AppData\Roaming")),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_windows_uses_appdata() {
+        let env = windows_env();
+        let appdata = PathBuf::from(r"C:\Users\ada\AppData\Roaming").join("zap");
+        assert_eq!(config_dir(&env, nothing_exists).unwrap(), appdata);
+        assert_eq!(data_dir(&env, nothing_exists).unwrap(), appdata);
+
+        // Explicit overrides still win
+        let xdg = PathEnv {
+            xdg_config_home: Some(PathBuf::from("/xdg/config")),
+            xdg_data_home: Some(PathBuf::from("/xdg/data")),
+            ..windows_env()
+        };
+        assert_eq!(
+            config_dir(&xdg, nothing_exists).unwrap(),
+            PathBuf::from("/xdg/config/zap")
+        );
+        assert_eq!(
+            data_dir(&xdg, nothing_exists).unwrap(),
+            PathBuf::from("/xdg/data/zap")
+        );
+    }
+
+    #[test]
+    fn test_windows_falls_back_to_old_config_dir() {
+        let env = windows_env();
+        let legacy = PathBuf::from(r"C:\Users\ada").join(".config/zap");
+        let appdata = PathBuf::from(r"C:\Users\ada\AppData\Roaming").join("zap");
+
+        let only_legacy = |path: &Path| path == legacy;
+        assert_eq!(config_dir(&env, only_legacy).unwrap(), legacy);
+        assert_eq!(data_dir(&env, only_legacy).unwrap(), legacy);
+
+        // Once %APPDATA%\zap exists, the old directory is ignored
+        let both = |path: &Path| path == legacy || path == appdata;
+        assert_eq!(config_dir(&env, both).unwrap(), appdata);
+        assert_eq!(data_dir(&env, both).unwrap(), appdata);
+    }
+
+    #[test]
+    fn test_cache_dir() {
+        assert_eq!(
+            cache_dir(&home_env(), nothing_exists).unwrap(),
+            PathBuf::from("/home/ada/.cache/zap")
+        );
+
+        let xdg = PathEnv {
+            xdg_cache_home: Some(PathBuf::from("/xdg/cache")),
+            ..home_env()
+        };
+        assert_eq!(
+            cache_dir(&xdg, nothing_exists).unwrap(),
+            PathBuf::from("/xdg/cache/zap")
+        );
+
+        let custom = PathEnv {
+            zap_config: Some(PathBuf::from("/custom/zap")),
+            ..xdg
+        };
+        assert_eq!(
+            cache_dir(&custom, nothing_exists).unwrap(),
+            PathBuf::from("/custom/zap/cache")
+        );
+    }
+
+    #[test]
+    fn test_no_home_and_no_overrides() {
+        let env = PathEnv::default();
+        assert!(matches!(
+            config_dir(&env, nothing_exists),
+            Err(ZapError::ConfigDirNotFound)
+        ));
+        assert!(matches!(
+            data_dir(&env, nothing_exists),
+            Err(ZapError::ConfigDirNotFound)
+        ));
+
+        let xdg_only = PathEnv {
+            xdg_config_home: Some(PathBuf::from("/xdg/config")),
+            xdg_data_home: Some(PathBuf::from("/xdg/data")),
+            ..Default::default()
+        };
+        assert_eq!(
+            data_dir(&xdg_only, nothing_exists).unwrap(),
+            PathBuf::from("/xdg/data/zap")
+        );
+
+        let data_only = PathEnv {
+            xdg_data_home: Some(PathBuf::from("/xdg/data")),
+            ..Default::default()
+        };
+        assert!(config_dir(&data_only, nothing_exists).is_err());
+        assert_eq!(
+            data_dir(&data_only, nothing_exists).unwrap(),
+            PathBuf::from("/xdg/data/zap")
+        );
+    }
+}
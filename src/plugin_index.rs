@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::errors::ZapError;
+use crate::plugins::{self, PluginInfo, Plugins};
+
+/// File in the plugins dir recording where each installed plugin came from.
+pub const INDEX_NAME: &str = "index.toml";
+
+/// Plugins installed with `zap plugin install`, as recorded in the plugins
+/// directory's `index.toml`. Plugins copied there by hand aren't listed:
+///
+/// ```text
+/// [[plugin]]
+/// name = "shout"
+/// file = "libzap_shout.so"
+/// source = "https://example.com/libzap_shout.so"
+/// version = "0.1.1"
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PluginIndex {
+    #[serde(default, rename = "plugin")]
+    pub plugins: Vec<IndexedPlugin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexedPlugin {
+    pub name: String,
+    /// File name in the plugins directory
+    pub file: String,
+    /// URL or local path the plugin was installed from
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl PluginIndex {
+    /// Read the index at `path`; a missing file means nothing was installed.
+    pub fn load(path: &Path) -> Result<Self, ZapError> {
+        match fs::read_to_string(path) {
+            Ok(source) => toml::from_str(&source).map_err(|e| ZapError::InvalidConfig {
+                path: path.to_path_buf(),
+                reason: e.message().to_string(),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ZapError> {
+        let toml = toml::to_string(self).expect("plugin index is always serializable");
+        fs::write(path, toml)?;
+        Ok(())
+    }
+
+    /// Record `plugin`, replacing an earlier entry for the same file.
+    pub fn set(&mut self, plugin: IndexedPlugin) {
+        self.plugins
+            .retain(|installed| installed.file != plugin.file);
+        self.plugins.push(plugin);
+        self.plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+}
+
+/// The file name a plugin from `source` is installed as unless one is given:
+/// the last segment of the path or URL.
+pub fn default_file_name(source: &str) -> Result<String, ZapError> {
+    let last = source
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default();
+    check_file_name(last)?;
+    Ok(last.to_string())
+}
+
+/// Plugins sit directly in the plugins directory, and hidden files there are
+/// ignored, so a name must be a single, visible path segment.
+pub fn check_file_name(name: &str) -> Result<(), ZapError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name != INDEX_NAME
+        && !name.contains(['/', '\\']);
+    if valid {
+        Ok(())
+    } else {
+        Err(ZapError::InvalidPluginName(name.to_string()))
+    }
+}
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://")
+}
+
+/// Put the plugin from `source` into `plugins_dir` as `file_name`. It's fetched
+/// into a hidden directory first and only moved in once it loaded there, so a
+/// download that isn't a working plugin never ends up being loaded by a run.
+/// Returns what the plugin says about itself, if anything.
+pub fn install(
+    plugins_dir: &Path,
+    source: &str,
+    file_name: &str,
+) -> Result<Option<PluginInfo>, ZapError> {
+    check_file_name(file_name)?;
+    let error = |reason: String| ZapError::PluginSource {
+        from: source.to_string(),
+        reason,
+    };
+    let staging = plugins_dir.join(".install.zap-tmp");
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+    let staged = staging.join(file_name);
+
+    let result = (|| {
+        if is_url(source) {
+            if !crate::remote::is_secure_url(source) {
+                return Err(error("plugins are only downloaded over https".to_string()));
+            }
+            let mut bytes = Vec::new();
+            ureq::get(source)
+                .timeout(Duration::from_secs(30))
+                .call()
+                .map_err(|e| error(e.to_string()))?
+                .into_reader()
+                .read_to_end(&mut bytes)
+                .map_err(|e| error(e.to_string()))?;
+            fs::write(&staged, bytes)?;
+        } else {
+            fs::copy(source, &staged).map_err(|e| error(e.to_string()))?;
+        }
+        // Downloads lose the executable bit exec plugins need
+        #[cfg(unix)]
+        if !plugins::is_library(&staged) && !plugins::is_script(&staged) {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+        }
+
+        // The Tera instance has to go before the library it got functions from
+        let mut plugins = Plugins::new();
+        let mut tera = tera::Tera::default();
+        plugins
+            .load_plugin(&mut tera, &staged)
+            .map_err(|e| error(e.to_string()))?;
+        let info = plugins
+            .loaded()
+            .last()
+            .and_then(|plugin| plugin.info.clone());
+        drop(tera);
+        drop(plugins);
+
+        fs::rename(&staged, plugins_dir.join(file_name))?;
+        Ok(info)
+    })();
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_default_file_name() {
+        assert_eq!(
+            default_file_name("https://example.com/v1/libzap_shout.so?download=1").unwrap(),
+            "libzap_shout.so"
+        );
+        assert_eq!(
+            default_file_name("../plugins/word-count.py").unwrap(),
+            "word-count.py"
+        );
+        assert!(default_file_name("https://example.com/").is_err());
+        assert!(check_file_name(".hidden").is_err());
+        assert!(check_file_name(INDEX_NAME).is_err());
+    }
+
+    #[test]
+    fn test_index_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(INDEX_NAME);
+        assert_eq!(PluginIndex::load(&path).unwrap(), PluginIndex::default());
+
+        let mut index = PluginIndex::default();
+        for (name, version) in [("shout", "0.1.0"), ("count", "1.0.0"), ("shout", "0.2.0")] {
+            index.set(IndexedPlugin {
+                name: name.to_string(),
+                file: format!("{name}.py"),
+                source: format!("/src/{name}.py"),
+                version: Some(version.to_string()),
+            });
+        }
+        index.save(&path).unwrap();
+        let loaded = PluginIndex::load(&path).unwrap();
+        assert_eq!(loaded, index);
+        assert_eq!(
+            loaded
+                .plugins
+                .iter()
+                .map(|plugin| (plugin.name.as_str(), plugin.version.as_deref()))
+                .collect::<Vec<_>>(),
+            [("count", Some("1.0.0")), ("shout", Some("0.2.0"))]
+        );
+    }
+
+    #[test]
+    fn test_failed_install_leaves_nothing_behind() {
+        let dir = TempDir::new().unwrap();
+        let broken = dir.path().join("libbroken.so");
+        fs::write(&broken, "not a library").unwrap();
+        let plugins_dir = dir.path().join("plugins");
+        fs::create_dir_all(&plugins_dir).unwrap();
+
+        let result = install(&plugins_dir, broken.to_str().unwrap(), "libbroken.so");
+        assert!(matches!(result, Err(ZapError::PluginSource { .. })));
+        assert_eq!(fs::read_dir(&plugins_dir).unwrap().count(), 0);
+
+        let result = install(
+            &plugins_dir,
+            "http://example.com/libzap_shout.so",
+            "libshout.so",
+        );
+        assert!(matches!(result, Err(ZapError::PluginSource { .. })));
+        assert_eq!(fs::read_dir(&plugins_dir).unwrap().count(), 0);
+    }
+}
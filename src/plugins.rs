@@ -1,15 +1,286 @@
 use libloading::{Library, Symbol};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::{CStr, c_char};
 use std::fs;
-use std::path::Path;
-use tera;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tera::{self, Value};
 
 use crate::errors::PluginLoadError;
+use crate::sandbox;
 
 type PluginRegisterFn = unsafe extern "C" fn(tera: &mut tera::Tera);
 const PLUGIN_ENTRY_POINT: &[u8] = b"register_tera_custom_functions";
 
+/// Entry points for filters and testers, with the same signature as the one for
+/// functions. A plugin needs to export at least one of the three.
+const PLUGIN_FILTERS_ENTRY_POINT: &[u8] = b"register_tera_filters";
+const PLUGIN_TESTERS_ENTRY_POINT: &[u8] = b"register_tera_testers";
+
+/// Optional entry points returning a NUL-terminated, comma-separated list of the
+/// function, filter or tester names a plugin registers. Tera can't list them itself.
+type PluginNamesFn = unsafe extern "C" fn() -> *const c_char;
+const PLUGIN_FUNCTIONS_ENTRY_POINT: &[u8] = b"zap_plugin_functions";
+const PLUGIN_FILTERS_NAMES_ENTRY_POINT: &[u8] = b"zap_plugin_filters";
+const PLUGIN_TESTERS_NAMES_ENTRY_POINT: &[u8] = b"zap_plugin_testers";
+
+/// Optional entry point returning the version of the plugin contract the plugin
+/// was built for (`zap_plugin_sdk::ABI_VERSION`). Plugins built for another
+/// version are refused rather than called into.
+type PluginAbiVersionFn = unsafe extern "C" fn() -> u32;
+const PLUGIN_ABI_VERSION_ENTRY_POINT: &[u8] = b"zap_plugin_abi_version";
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Optional entry point returning a NUL-terminated JSON object that describes
+/// the plugin, see `PluginInfo`.
+type PluginInfoFn = unsafe extern "C" fn() -> *const c_char;
+const PLUGIN_INFO_ENTRY_POINT: &[u8] = b"zap_plugin_info";
+
+/// What a plugin says about itself through `zap_plugin_info`:
+///
+/// ```text
+/// {"name": "shout", "version": "0.1.1", "author": "Kolja", "description": "Shouting"}
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PluginInfo {
+    pub name: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl PluginInfo {
+    /// Parse what `zap_plugin_info` returned. Broken metadata doesn't stop a
+    /// plugin from working, so it's only warned about.
+    fn parse(path: &Path, json: &str) -> Option<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| eprintln!("Warning: Plugin {path:?} has invalid zap_plugin_info: {e}"))
+            .ok()
+    }
+}
+
+/// The `[plugins]` section of `config.toml`, plus `--no-plugins`: which plugin
+/// libraries may be loaded, by file name (`libzap_shout.so`).
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PluginsConfig {
+    /// Only these plugins are loaded, if given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<String>>,
+    /// These plugins are never loaded, even when allowed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// Skip plugins that fail to load instead of failing the run, like `--lenient-plugins`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub lenient: bool,
+    /// Projects whose `.zap/plugins` are loaded without asking
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub trusted: Vec<PathBuf>,
+    /// Load native plugins into a helper process instead of zap itself
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub sandbox: bool,
+    /// No plugins at all, from `--no-plugins`
+    #[serde(skip)]
+    pub disabled: bool,
+}
+
+impl PluginsConfig {
+    pub fn is_unset(&self) -> bool {
+        self.allow.is_none()
+            && self.deny.is_empty()
+            && !self.lenient
+            && self.trusted.is_empty()
+            && !self.sandbox
+    }
+
+    /// Whether `project`, the directory holding a `.zap` directory, is listed
+    /// in `plugins.trusted`.
+    pub fn trusts(&self, project: &Path) -> bool {
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let project = canonical(project);
+        self.trusted
+            .iter()
+            .any(|trusted| canonical(trusted) == project)
+    }
+
+    /// Why the plugin at `path` isn't loaded, or `None` if it may be.
+    pub fn skip_reason(&self, path: &Path) -> Option<&'static str> {
+        let file_name = path.file_name()?.to_string_lossy();
+        let listed = |names: &[String]| names.iter().any(|name| *name == file_name);
+        if self.disabled {
+            Some("--no-plugins")
+        } else if listed(&self.deny) {
+            Some("listed in plugins.deny")
+        } else if self.allow.as_deref().is_some_and(|allow| !listed(allow)) {
+            Some("not listed in plugins.allow")
+        } else {
+            None
+        }
+    }
+}
+
+/// An executable in the plugins directory, registered as the Tera function named
+/// after its file (`word-count.py` is `word_count`). Every call runs it with
+/// `{"function": ..., "args": {...}}` on stdin; it prints the result as JSON.
+struct ExecFunction {
+    path: PathBuf,
+    name: String,
+}
+
+impl ExecFunction {
+    fn new(path: &Path) -> Self {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        ExecFunction {
+            path: path.to_path_buf(),
+            name: stem.replace('-', "_"),
+        }
+    }
+}
+
+impl tera::Function for ExecFunction {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let plugin = self.path.display();
+        let request = serde_json::json!({ "function": self.name, "args": args });
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| tera::Error::chain(format!("Failed to run plugin {plugin}"), e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // Plugins that don't need their arguments may exit without reading them
+            match stdin.write_all(request.to_string().as_bytes()) {
+                Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+                    return Err(tera::Error::chain(
+                        format!("Failed to write to plugin {plugin}"),
+                        e,
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        let output = child
+            .wait_with_output()
+            .map_err(|e| tera::Error::chain(format!("Failed to run plugin {plugin}"), e))?;
+        if !output.status.success() {
+            return Err(tera::Error::msg(format!(
+                "Plugin {plugin} exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        serde_json::from_slice(&output.stdout).map_err(|e| {
+            tera::Error::chain(format!("Plugin {plugin} didn't print a JSON value"), e)
+        })
+    }
+}
+
+/// Rhai scripts, whose functions are compiled once and called as Tera functions
+/// by their names and parameter names: `fn initials(name)` is `initials(name="Ada Lovelace")`.
+#[cfg(feature = "rhai")]
+struct RhaiFunction {
+    engine: std::sync::Arc<rhai::Engine>,
+    ast: std::sync::Arc<rhai::AST>,
+    path: PathBuf,
+    name: String,
+    params: Vec<String>,
+}
+
+#[cfg(feature = "rhai")]
+impl tera::Function for RhaiFunction {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let script_error = |e: Box<rhai::EvalAltResult>| {
+            tera::Error::msg(format!(
+                "Function `{}` in {} failed: {e}",
+                self.name,
+                self.path.display()
+            ))
+        };
+        let mut values = Vec::with_capacity(self.params.len());
+        for param in &self.params {
+            let value = args.get(param).ok_or_else(|| {
+                tera::Error::msg(format!(
+                    "Function `{}` requires a `{param}` argument",
+                    self.name
+                ))
+            })?;
+            values.push(rhai::serde::to_dynamic(value).map_err(script_error)?);
+        }
+        let result: rhai::Dynamic = self
+            .engine
+            .call_fn(&mut rhai::Scope::new(), &self.ast, &self.name, values)
+            .map_err(script_error)?;
+        rhai::serde::from_dynamic(&result).map_err(script_error)
+    }
+}
+
+pub fn is_script(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "rhai")
+}
+
+/// Native plugins are shared libraries; everything else is an exec plugin.
+pub fn is_library(path: &Path) -> bool {
+    let ext = path.extension().and_then(std::ffi::OsStr::to_str);
+    matches!(ext, Some("so") | Some("dylib") | Some("dll"))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    let ext = path.extension().and_then(std::ffi::OsStr::to_str);
+    path.is_file() && matches!(ext, Some("exe") | Some("bat") | Some("cmd"))
+}
+
+/// A plugin that has been loaded into a Tera instance.
+#[derive(Debug, Clone)]
+pub struct LoadedPlugin {
+    pub path: PathBuf,
+    /// Function names the plugin declares, if it exports `zap_plugin_functions`
+    pub functions: Option<Vec<String>>,
+    /// Filter names the plugin declares, if it exports `zap_plugin_filters`
+    pub filters: Option<Vec<String>>,
+    /// Tester names the plugin declares, if it exports `zap_plugin_testers`
+    pub testers: Option<Vec<String>>,
+    /// Name, version and so on, if it exports `zap_plugin_info`
+    pub info: Option<PluginInfo>,
+}
+
+impl LoadedPlugin {
+    /// How to refer to the plugin in messages: `shout 0.1.1`, or its file name.
+    pub fn label(&self) -> String {
+        plugin_label(&self.path, self.info.as_ref())
+    }
+}
+
+fn plugin_label(path: &Path, info: Option<&PluginInfo>) -> String {
+    match info {
+        Some(PluginInfo {
+            name,
+            version: Some(version),
+            ..
+        }) => format!("{name} {version}"),
+        Some(info) => info.name.clone(),
+        None => path.file_name().map_or_else(
+            || path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        ),
+    }
+}
+
 pub struct Plugins {
     libs: Vec<Library>,
+    loaded: Vec<LoadedPlugin>,
 }
 
 impl Default for Plugins {
@@ -20,7 +291,15 @@ impl Default for Plugins {
 
 impl Plugins {
     pub fn new() -> Self {
-        Plugins { libs: Vec::new() }
+        Plugins {
+            libs: Vec::new(),
+            loaded: Vec::new(),
+        }
+    }
+
+    /// The plugins loaded so far, in load order
+    pub fn loaded(&self) -> &[LoadedPlugin] {
+        &self.loaded
     }
 
     pub fn load_plugin(
@@ -28,6 +307,22 @@ impl Plugins {
         tera: &mut tera::Tera,
         plugin_path: &Path,
     ) -> Result<(), PluginLoadError> {
+        if is_script(plugin_path) {
+            return self.load_script(tera, plugin_path);
+        }
+        if !is_library(plugin_path) {
+            let function = ExecFunction::new(plugin_path);
+            self.loaded.push(LoadedPlugin {
+                path: plugin_path.to_path_buf(),
+                functions: Some(vec![function.name.clone()]),
+                filters: Some(Vec::new()),
+                testers: Some(Vec::new()),
+                info: None,
+            });
+            tera.register_function(&function.name.clone(), function);
+            return Ok(());
+        }
+
         unsafe {
             let lib = Library::new(plugin_path).map_err(|e| PluginLoadError::LibraryLoad {
                 path: plugin_path.to_path_buf(),
@@ -37,34 +332,155 @@ impl Plugins {
             self.libs.push(lib);
             let lib_ref = self.libs.last().unwrap(); // Safe as we just pushed
 
-            // For error reporting, convert the entry point name to a String
-            let entry_point_name_str = String::from_utf8_lossy(PLUGIN_ENTRY_POINT).into_owned();
+            // Read first, so problems with the plugin can be reported by its name
+            let info = lib_ref
+                .get::<PluginInfoFn>(PLUGIN_INFO_ENTRY_POINT)
+                .ok()
+                .map(|info_fn| info_fn())
+                .filter(|json| !json.is_null())
+                .and_then(|json| {
+                    PluginInfo::parse(plugin_path, &CStr::from_ptr(json).to_string_lossy())
+                });
+
+            if let Ok(abi_version_fn) =
+                lib_ref.get::<PluginAbiVersionFn>(PLUGIN_ABI_VERSION_ENTRY_POINT)
+            {
+                let found = abi_version_fn();
+                if found != PLUGIN_ABI_VERSION {
+                    return Err(PluginLoadError::AbiMismatch {
+                        plugin: plugin_label(plugin_path, info.as_ref()),
+                        found,
+                        expected: PLUGIN_ABI_VERSION,
+                    });
+                }
+            }
+
+            let register_fn: Result<Symbol<PluginRegisterFn>, _> = lib_ref.get(PLUGIN_ENTRY_POINT);
+            let filters_fn: Option<Symbol<PluginRegisterFn>> =
+                lib_ref.get(PLUGIN_FILTERS_ENTRY_POINT).ok();
+            let testers_fn: Option<Symbol<PluginRegisterFn>> =
+                lib_ref.get(PLUGIN_TESTERS_ENTRY_POINT).ok();
 
-            let register_fn: Symbol<PluginRegisterFn> =
-                lib_ref.get(PLUGIN_ENTRY_POINT).map_err(|e| {
-                    PluginLoadError::EntryPointNotFound {
-                        plugin_path: plugin_path.to_path_buf(),
-                        entry_point_name: entry_point_name_str,
+            match register_fn {
+                Ok(register_fn) => register_fn(tera),
+                Err(e) if filters_fn.is_none() && testers_fn.is_none() => {
+                    return Err(PluginLoadError::EntryPointNotFound {
+                        plugin: plugin_label(plugin_path, info.as_ref()),
+                        entry_point_name: [
+                            PLUGIN_ENTRY_POINT,
+                            PLUGIN_FILTERS_ENTRY_POINT,
+                            PLUGIN_TESTERS_ENTRY_POINT,
+                        ]
+                        .map(String::from_utf8_lossy)
+                        .join("', '"),
                         source: e,
-                    }
-                })?;
+                    });
+                }
+                Err(_) => {}
+            }
+            for register_fn in filters_fn.iter().chain(&testers_fn) {
+                register_fn(tera);
+            }
 
-            register_fn(tera);
+            let declared = |entry_point: &[u8]| {
+                lib_ref
+                    .get::<PluginNamesFn>(entry_point)
+                    .ok()
+                    .map(|names_fn| {
+                        let names = names_fn();
+                        if names.is_null() {
+                            return Vec::new();
+                        }
+                        CStr::from_ptr(names)
+                            .to_string_lossy()
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|name| !name.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+            };
+
+            self.loaded.push(LoadedPlugin {
+                path: plugin_path.to_path_buf(),
+                functions: declared(PLUGIN_FUNCTIONS_ENTRY_POINT),
+                filters: declared(PLUGIN_FILTERS_NAMES_ENTRY_POINT),
+                testers: declared(PLUGIN_TESTERS_NAMES_ENTRY_POINT),
+                info,
+            });
         }
         Ok(())
     }
 
-    pub fn load_plugins_from_dir(
+    /// Register the public top-level functions of a Rhai script.
+    #[cfg(feature = "rhai")]
+    fn load_script(
         &mut self,
         tera: &mut tera::Tera,
-        dir_path: &Path,
+        plugin_path: &Path,
+    ) -> Result<(), PluginLoadError> {
+        let engine = rhai::Engine::new();
+        let ast = engine
+            .compile_file(plugin_path.to_path_buf())
+            .map_err(|e| PluginLoadError::Script {
+                path: plugin_path.to_path_buf(),
+                reason: e.to_string(),
+            })?;
+        let mut functions: Vec<(String, Vec<String>)> = ast
+            .iter_functions()
+            // Closures are compiled to functions with generated names like `anon$1f2e`
+            .filter(|function| {
+                function.access == rhai::FnAccess::Public && !function.name.contains('$')
+            })
+            .map(|function| {
+                let params = function.params.iter().map(|p| p.to_string()).collect();
+                (function.name.to_string(), params)
+            })
+            .collect();
+        functions.sort();
+
+        let (engine, ast) = (std::sync::Arc::new(engine), std::sync::Arc::new(ast));
+        for (name, params) in &functions {
+            let function = RhaiFunction {
+                engine: engine.clone(),
+                ast: ast.clone(),
+                path: plugin_path.to_path_buf(),
+                name: name.clone(),
+                params: params.clone(),
+            };
+            tera.register_function(name, function);
+        }
+        self.loaded.push(LoadedPlugin {
+            path: plugin_path.to_path_buf(),
+            functions: Some(functions.into_iter().map(|(name, _)| name).collect()),
+            filters: Some(Vec::new()),
+            testers: Some(Vec::new()),
+            info: None,
+        });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "rhai"))]
+    fn load_script(
+        &mut self,
+        _tera: &mut tera::Tera,
+        plugin_path: &Path,
     ) -> Result<(), PluginLoadError> {
+        Err(PluginLoadError::Script {
+            path: plugin_path.to_path_buf(),
+            reason: "this zap was built without the `rhai` feature".to_string(),
+        })
+    }
 
-        // If the plugins directory doesn't exist, just return OK without loading any plugins
+    /// List the plugin libraries, scripts and executables in `dir_path`, sorted by file name.
+    /// A missing directory simply yields no plugins.
+    pub fn plugin_paths(dir_path: &Path) -> Result<Vec<PathBuf>, PluginLoadError> {
+        // If the plugins directory doesn't exist, there is nothing to load
         if !dir_path.is_dir() {
-            return Ok(());
+            return Ok(Vec::new());
         }
 
+        let mut paths = Vec::new();
         for entry in fs::read_dir(dir_path).map_err(|e| PluginLoadError::DirectoryRead {
             path: dir_path.to_path_buf(),
             source: e,
@@ -75,16 +491,208 @@ impl Plugins {
             })?;
             let path = entry.path();
 
-            let ext = path.extension().and_then(std::ffi::OsStr::to_str);
-            if !matches!(ext, Some("so") | Some("dylib") | Some("dll")) {
-                continue;
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden && (is_library(&path) || is_script(&path) || is_executable(&path)) {
+                paths.push(path);
             }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Load the native plugin at `path` in a helper process; see [`sandbox`].
+    pub fn load_sandboxed(
+        &mut self,
+        tera: &mut tera::Tera,
+        path: &Path,
+    ) -> Result<(), PluginLoadError> {
+        let plugin = sandbox::load(tera, path)?;
+        self.loaded.push(plugin);
+        Ok(())
+    }
 
-            self.load_plugin(tera, &path).map_err(|e| {
+    /// Load the plugins in `dir_path` that `config` allows. With `verbose`, the
+    /// ones it leaves out are reported on stderr. A plugin that fails to load
+    /// fails the run, unless `config` is lenient: then it's skipped with a warning.
+    pub fn load_plugins_from_dir(
+        &mut self,
+        tera: &mut tera::Tera,
+        dir_path: &Path,
+        config: &PluginsConfig,
+        verbose: bool,
+    ) -> Result<(), PluginLoadError> {
+        let mut failed = Vec::new();
+        for path in Self::plugin_paths(dir_path)? {
+            if let Some(reason) = config.skip_reason(&path) {
+                if verbose {
+                    eprintln!("Skipping plugin {path:?}: {reason}");
+                }
+                continue;
+            }
+            let result = if config.sandbox && is_library(&path) {
+                self.load_sandboxed(tera, &path)
+            } else {
+                self.load_plugin(tera, &path)
+            };
+            if let Err(e) = result {
                 eprintln!("Warning: Failed to load plugin {path:?}: {e}");
-                e
-            })?;
+                if !config.lenient {
+                    return Err(e);
+                }
+                failed.push(path);
+            }
+        }
+        if !failed.is_empty() {
+            let names: Vec<_> = failed
+                .iter()
+                .filter_map(|path| path.file_name())
+                .map(|name| name.to_string_lossy())
+                .collect();
+            eprintln!(
+                "Warning: Skipped {} plugin(s) that failed to load ({}); templates can't use what they provide",
+                failed.len(),
+                names.join(", ")
+            );
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_function_name() {
+        assert_eq!(
+            ExecFunction::new(Path::new("/plugins/word-count.py")).name,
+            "word_count"
+        );
+        assert_eq!(ExecFunction::new(Path::new("/plugins/today")).name, "today");
+    }
+
+    #[cfg(feature = "rhai")]
+    #[test]
+    fn test_rhai_functions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("names.rhai");
+        fs::write(
+            &script,
+            "fn initials(name) { let s = \"\"; for part in name.split(' ') { s += part[0] } s }\n\
+             fn greet(name, times) { let s = `Hi ${name}`; for i in 0..times { s += \"!\" } s }\n\
+             private fn helper(list) { list.map(|x| x + 1) }",
+        )
+        .unwrap();
+
+        let mut tera = tera::Tera::default();
+        let mut plugins = Plugins::new();
+        plugins.load_plugin(&mut tera, &script).unwrap();
+        assert_eq!(
+            plugins.loaded()[0].functions.as_deref(),
+            Some(&["greet".to_string(), "initials".to_string()][..])
+        );
+        let rendered = tera
+            .render_str(
+                "{{ initials(name=\"Ada Lovelace\") }} {{ greet(name=\"Bob\", times=3) }}",
+                &tera::Context::new(),
+            )
+            .unwrap();
+        assert_eq!(rendered, "AL Hi Bob!!!");
+        assert!(
+            tera.render_str("{{ greet(name=\"Bob\") }}", &tera::Context::new())
+                .is_err()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lenient_loading_skips_broken_plugins() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("libbroken.so"), "not a library").unwrap();
+        let answer = dir.path().join("answer");
+        fs::write(&answer, "#!/bin/sh\necho 42\n").unwrap();
+        fs::set_permissions(&answer, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut tera = tera::Tera::default();
+        let mut plugins = Plugins::new();
+        let strict = PluginsConfig::default();
+        assert!(
+            plugins
+                .load_plugins_from_dir(&mut tera, dir.path(), &strict, false)
+                .is_err()
+        );
+
+        let mut tera = tera::Tera::default();
+        let mut plugins = Plugins::new();
+        let lenient = PluginsConfig {
+            lenient: true,
+            ..PluginsConfig::default()
+        };
+        plugins
+            .load_plugins_from_dir(&mut tera, dir.path(), &lenient, false)
+            .unwrap();
+        assert_eq!(plugins.loaded().len(), 1);
+        assert_eq!(
+            tera.render_str("{{ answer() }}", &tera::Context::new())
+                .unwrap(),
+            "42"
+        );
+    }
+
+    #[test]
+    fn test_trusted_projects() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = PluginsConfig {
+            trusted: vec![dir.path().join("repo").join("..").join("repo")],
+            ..PluginsConfig::default()
+        };
+        fs::create_dir_all(dir.path().join("repo")).unwrap();
+        assert!(config.trusts(&dir.path().join("repo")));
+        assert!(!config.trusts(dir.path()));
+    }
+
+    #[test]
+    fn test_plugin_info() {
+        let path = Path::new("/plugins/libzap_shout.so");
+        let info = PluginInfo::parse(path, r#"{"name": "shout", "version": "0.1.1"}"#);
+        assert_eq!(plugin_label(path, info.as_ref()), "shout 0.1.1");
+        let info = PluginInfo::parse(path, r#"{"name": "shout", "author": "Kolja"}"#).unwrap();
+        assert_eq!(info.author.as_deref(), Some("Kolja"));
+        assert_eq!(plugin_label(path, Some(&info)), "shout");
+        assert_eq!(PluginInfo::parse(path, "shout 0.1.1"), None);
+        assert_eq!(plugin_label(path, None), "libzap_shout.so");
+    }
+
+    #[test]
+    fn test_abi_version_matches_sdk() {
+        assert_eq!(PLUGIN_ABI_VERSION, zap_plugin_sdk::ABI_VERSION);
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let path = Path::new("/plugins/libzap_shout.so");
+        assert_eq!(PluginsConfig::default().skip_reason(path), None);
+
+        let config: PluginsConfig =
+            toml::from_str("allow = [\"libzap_shout.so\", \"libother.so\"]").unwrap();
+        assert_eq!(config.skip_reason(path), None);
+        assert_eq!(
+            config.skip_reason(Path::new("/plugins/libunknown.so")),
+            Some("not listed in plugins.allow")
+        );
+
+        let config = PluginsConfig {
+            deny: vec!["libzap_shout.so".to_string()],
+            ..config
+        };
+        assert_eq!(config.skip_reason(path), Some("listed in plugins.deny"));
+
+        let config = PluginsConfig {
+            disabled: true,
+            ..PluginsConfig::default()
+        };
+        assert_eq!(config.skip_reason(path), Some("--no-plugins"));
+    }
+}
@@ -0,0 +1,247 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::errors::ZapError;
+use crate::get_cache_dir;
+
+/// Cached templates older than this are downloaded again.
+pub const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const GITHUB_PREFIX: &str = "github:";
+
+/// Set by `--refresh`: cached copies older than this are downloaded again.
+static REFRESH_SINCE: RwLock<Option<SystemTime>> = RwLock::new(None);
+
+/// With `refresh`, download every remote template used from now on again, even
+/// if its cached copy is still fresh; each template is only downloaded once per
+/// run. Without it, fresh cached copies are used again.
+pub fn set_refresh(refresh: bool) {
+    *REFRESH_SINCE
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = refresh.then(SystemTime::now);
+}
+
+/// Whether a template name refers to a template on the web rather than on disk.
+pub fn is_remote(name: &str) -> bool {
+    name.starts_with(GITHUB_PREFIX) || name.starts_with("https://") || name.starts_with("http://")
+}
+
+/// Whether zap downloads from `url`: over https, or plain http to this machine,
+/// where nobody in between can swap what's downloaded.
+pub fn is_secure_url(url: &str) -> bool {
+    if url.starts_with("https://") {
+        return true;
+    }
+    let Some(rest) = url.strip_prefix("http://") else {
+        return false;
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => authority,
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "[::1]")
+}
+
+/// The URL a remote template is downloaded from. `github:user/repo/path/to/template`
+/// reads the file from the default branch, `github:user/repo/path@v1` from the branch or tag `v1`.
+pub fn url(name: &str) -> Result<String, ZapError> {
+    let Some(github) = name.strip_prefix(GITHUB_PREFIX) else {
+        return Ok(name.to_string());
+    };
+
+    let mut parts = github.splitn(3, '/');
+    let (Some(user), Some(repo), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(ZapError::RemoteTemplate {
+            url: name.to_string(),
+            reason: "expected github:user/repo/path/to/template".to_string(),
+        });
+    };
+    let (path, git_ref) = path.rsplit_once('@').unwrap_or((path, "HEAD"));
+    Ok(format!(
+        "https://raw.githubusercontent.com/{user}/{repo}/{git_ref}/{path}"
+    ))
+}
+
+/// Where the download of `url` is kept below `cache_dir`: its host and path,
+/// so cached templates are easy to find and inspect.
+pub fn cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let mut path = cache_dir.join("templates");
+    for segment in without_scheme.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." {
+            continue;
+        }
+        let segment: String = segment
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "-_.@".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        path.push(segment);
+    }
+    path
+}
+
+/// The local copy of a remote template, downloaded if it isn't cached yet or is
+/// out of date. When the download fails, an existing cached copy is used instead.
+pub fn fetch(name: &str) -> Result<PathBuf, ZapError> {
+    let url = url(name)?;
+    if !is_secure_url(&url) {
+        return Err(ZapError::RemoteTemplate {
+            url,
+            reason: "templates are only downloaded over https".to_string(),
+        });
+    }
+    let path = cache_path(&get_cache_dir()?, &url);
+    let refresh_since = *REFRESH_SINCE.read().unwrap_or_else(PoisonError::into_inner);
+    fetch_to(&url, &path, refresh_since, download)
+}
+
+fn fetch_to(
+    url: &str,
+    path: &Path,
+    refresh_since: Option<SystemTime>,
+    download: impl Fn(&str) -> Result<String, ZapError>,
+) -> Result<PathBuf, ZapError> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+    let fresh = modified.is_some_and(|modified| {
+        let recent = SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|age| age < MAX_AGE);
+        recent && refresh_since.is_none_or(|since| modified >= since)
+    });
+    if fresh {
+        return Ok(path.to_path_buf());
+    }
+
+    match download(url) {
+        Ok(contents) => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+            Ok(path.to_path_buf())
+        }
+        Err(e) if modified.is_some() => {
+            eprintln!(
+                "Warning: {e}; using the cached copy from {}",
+                path.display()
+            );
+            Ok(path.to_path_buf())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn download(url: &str) -> Result<String, ZapError> {
+    let error = |reason: String| ZapError::RemoteTemplate {
+        url: url.to_string(),
+        reason,
+    };
+    ureq::get(url)
+        .timeout(Duration::from_secs(10))
+        .call()
+        .map_err(|e| error(e.to_string()))?
+        .into_string()
+        .map_err(|e| error(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_remote_names() {
+        assert!(is_remote("github:kolja/templates/note.md"));
+        assert!(is_remote("https://example.com/note.md"));
+        assert!(!is_remote("note.md"));
+        assert!(!is_remote("blog/post"));
+    }
+
+    #[test]
+    fn test_secure_urls() {
+        assert!(is_secure_url("https://example.com/note.md"));
+        assert!(is_secure_url("http://127.0.0.1:8080/note.md"));
+        assert!(is_secure_url("http://localhost/note.md"));
+        assert!(is_secure_url("http://[::1]:80"));
+        assert!(!is_secure_url("http://example.com/note.md"));
+        assert!(!is_secure_url("http://127.0.0.1.example.com/note.md"));
+        assert!(!is_secure_url("http://localhost@example.com/note.md"));
+        assert!(!is_secure_url("ftp://example.com/note.md"));
+        assert!(matches!(
+            fetch("http://example.com/note.md"),
+            Err(ZapError::RemoteTemplate { .. })
+        ));
+    }
+
+    #[test]
+    fn test_github_urls() {
+        assert_eq!(
+            url("github:kolja/templates/notes/daily.md").unwrap(),
+            "https://raw.githubusercontent.com/kolja/templates/HEAD/notes/daily.md"
+        );
+        assert_eq!(
+            url("github:kolja/templates/daily.md@v2").unwrap(),
+            "https://raw.githubusercontent.com/kolja/templates/v2/daily.md"
+        );
+        assert_eq!(
+            url("https://example.com/a.md").unwrap(),
+            "https://example.com/a.md"
+        );
+        assert!(url("github:kolja/templates").is_err());
+    }
+
+    #[test]
+    fn test_cache_path_stays_inside_cache_dir() {
+        let cache = Path::new("/cache");
+        assert_eq!(
+            cache_path(cache, "https://example.com:8080/t/note.md?v=1"),
+            Path::new("/cache/templates/example.com_8080/t/note.md_v_1")
+        );
+        assert_eq!(
+            cache_path(cache, "https://example.com/../../etc/passwd"),
+            Path::new("/cache/templates/example.com/etc/passwd")
+        );
+    }
+
+    #[test]
+    fn test_fetch_uses_fresh_cache_and_falls_back_when_offline() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("templates").join("note.md");
+        let downloads = Cell::new(0);
+        let online = |_: &str| {
+            downloads.set(downloads.get() + 1);
+            Ok(format!("version {}", downloads.get()))
+        };
+        let offline = |url: &str| {
+            Err(ZapError::RemoteTemplate {
+                url: url.to_string(),
+                reason: "offline".to_string(),
+            })
+        };
+
+        // Not cached yet and offline
+        assert!(fetch_to("u", &path, None, offline).is_err());
+
+        fetch_to("u", &path, None, online).unwrap();
+        fetch_to("u", &path, None, online).unwrap();
+        assert_eq!(downloads.get(), 1, "a fresh copy isn't downloaded again");
+
+        let refresh = Some(SystemTime::now() + Duration::from_secs(60));
+        fetch_to("u", &path, refresh, online).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "version 2");
+
+        fetch_to("u", &path, refresh, offline).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "version 2");
+    }
+}
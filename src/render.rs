@@ -0,0 +1,622 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use dialoguer::Confirm;
+use lazy_regex::regex;
+use tera::Tera;
+
+use crate::context::{self, GitContexts};
+use crate::errors::{TeraError, ZapError};
+use crate::frontmatter::{self, Engine, Eol, TemplateMeta};
+use crate::plugins::{Plugins, PluginsConfig};
+use crate::strict::Checker;
+use crate::timings::{Phase, Timings};
+use crate::{find_project_dir, get_plugins_dir, get_template_path, template_search_path};
+
+/// Macro library that is imported into every template as `macros`,
+/// unless `config.toml` names a different one.
+pub const DEFAULT_MACROS_FILE: &str = "_macros.tera";
+
+/// Names of the functions, filters and testers zap or a plugin registered on a
+/// Tera instance. Tera can't list them, so they're recorded when registering.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Registered {
+    pub functions: BTreeSet<String>,
+    pub filters: BTreeSet<String>,
+    pub testers: BTreeSet<String>,
+}
+
+impl Registered {
+    /// Register `function` on `tera` as `name`, and record it.
+    pub fn function(
+        &mut self,
+        tera: &mut Tera,
+        name: &str,
+        function: impl tera::Function + 'static,
+    ) {
+        tera.register_function(name, function);
+        self.functions.insert(name.to_string());
+    }
+
+    /// Register `filter` on `tera` as `name`, and record it.
+    pub fn filter(&mut self, tera: &mut Tera, name: &str, filter: impl tera::Filter + 'static) {
+        tera.register_filter(name, filter);
+        self.filters.insert(name.to_string());
+    }
+}
+
+/// A template ready to be rendered for any number of files.
+pub struct Prepared {
+    pub meta: TemplateMeta,
+    /// The template and everything it can include, with builtins and plugins
+    pub tera: Tera,
+    pub body: Body,
+    /// Whether the template might use the `git` facts (`context::uses_git`)
+    pub uses_git: bool,
+}
+
+/// How a prepared template turns into file contents.
+pub enum Body {
+    /// Rendered by the prepared Tera instance
+    Tera,
+    /// Written for another engine, which renders it on its own
+    Engine(String),
+    /// Copied as it is, without rendering (`verbatim = true`)
+    Verbatim(String),
+    /// Bytes copied as they are: templates named `*.bin` and files that aren't text
+    Binary(Vec<u8>),
+}
+
+/// Suffix of templates that are copied byte for byte; it's dropped from the file names
+/// of scaffolds, so `logo.png.bin` becomes `logo.png`.
+pub const BINARY_SUFFIX: &str = ".bin";
+
+/// Everything templates need that doesn't depend on the file they're rendered for,
+/// kept for a whole run: touching 500 files with a template parses it and loads
+/// the plugins once, not 500 times.
+#[derive(Default)]
+pub struct Renderer {
+    // Declared before `plugins`, so the filters and functions the plugins
+    // registered are dropped before their libraries are unloaded
+    templates: HashMap<(String, Option<PathBuf>), Rc<Prepared>>,
+    base: Option<Tera>,
+    /// `base` plus the plugins of a project's `.zap/plugins`, by `.zap` directory
+    project_bases: HashMap<PathBuf, Tera>,
+    plugins: Plugins,
+    git: GitContexts,
+    /// Check templates with `strict::Checker` before rendering them (`--strict-template`)
+    pub strict: bool,
+    /// Line endings from `--eol`, for every template whatever its frontmatter says
+    pub eol: Option<Eol>,
+    /// Which plugins to load
+    pub plugins_config: PluginsConfig,
+    /// Report the plugins `plugins_config` leaves out (`--verbose`)
+    pub verbose: bool,
+    /// Macro library named by `macros` in `config.toml`, `_macros.tera` if unset
+    pub macros_file: Option<String>,
+    /// Keep templates from reading environment variables (`--no-env`)
+    pub no_env: bool,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A Tera instance with zap's builtins and the plugins registered, but no templates.
+    pub fn base(&mut self, timings: &mut Timings) -> Result<Tera, anyhow::Error> {
+        if let Some(base) = &self.base {
+            return Ok(base.clone());
+        }
+        let mut tera = Tera::default();
+        crate::builtins::register(&mut tera, template_dirs(None)?);
+        if self.no_env {
+            crate::builtins::register_env(&mut tera, false);
+        }
+        let plugins = &mut self.plugins;
+        let (plugins_config, verbose) = (&self.plugins_config, self.verbose);
+        timings.measure(Phase::PluginLoad, || -> Result<(), anyhow::Error> {
+            let plugins_dir = get_plugins_dir()?;
+            plugins.load_plugins_from_dir(&mut tera, &plugins_dir, plugins_config, verbose)?;
+            Ok(())
+        })?;
+        self.base = Some(tera.clone());
+        Ok(tera)
+    }
+
+    /// `base`, plus the plugins in `.zap/plugins` of the project `start` is in.
+    /// Those come with the project rather than from the user, so they're only
+    /// loaded for projects in `plugins.trusted`, or after asking.
+    fn project_base(
+        &mut self,
+        start: Option<&Path>,
+        timings: &mut Timings,
+    ) -> Result<Tera, anyhow::Error> {
+        let mut tera = self.base(timings)?;
+        let Some(project_dir) = start.and_then(find_project_dir) else {
+            return Ok(tera);
+        };
+        if let Some(project_base) = self.project_bases.get(&project_dir) {
+            return Ok(project_base.clone());
+        }
+
+        let plugins_dir = project_dir.join("plugins");
+        let has_plugins =
+            !self.plugins_config.disabled && !Plugins::plugin_paths(&plugins_dir)?.is_empty();
+        if has_plugins && self.trust_project(&project_dir)? {
+            let plugins = &mut self.plugins;
+            let (plugins_config, verbose) = (&self.plugins_config, self.verbose);
+            timings.measure(Phase::PluginLoad, || {
+                plugins.load_plugins_from_dir(&mut tera, &plugins_dir, plugins_config, verbose)
+            })?;
+        }
+        self.project_bases.insert(project_dir, tera.clone());
+        Ok(tera)
+    }
+
+    /// Whether the plugins of the project with the `.zap` directory `project_dir`
+    /// may be loaded: when it's trusted in the config, or the user says so.
+    fn trust_project(&self, project_dir: &Path) -> Result<bool, anyhow::Error> {
+        let plugins_dir = project_dir.join("plugins");
+        self.ask_trust(
+            project_dir,
+            &format!(
+                "Load the plugins in {}? They run with your permissions",
+                plugins_dir.display()
+            ),
+            &format!("Not loading the plugins in {}", plugins_dir.display()),
+        )
+    }
+
+    /// Whether the project with the `.zap` directory `project_dir` is trusted in the
+    /// config, or the user answers yes to `prompt`. Says `declined` if not.
+    fn ask_trust(
+        &self,
+        project_dir: &Path,
+        prompt: &str,
+        declined: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let root = project_dir.parent().unwrap_or(project_dir);
+        if self.plugins_config.trusts(root) {
+            return Ok(true);
+        }
+        if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+            let trusted = Confirm::new()
+                .with_prompt(prompt)
+                .default(false)
+                .interact()?;
+            if trusted {
+                return Ok(true);
+            }
+        }
+        eprintln!(
+            "Warning: {declined}; add {root:?} to `trusted` under [plugins] in config.toml to trust the project"
+        );
+        Ok(false)
+    }
+
+    /// The formatter the frontmatter of the template at `path` names, if it may run.
+    /// Formatters are commands, so only those of the user's own templates (including
+    /// `--template-path` and the system's) run as they are, and those of a project
+    /// once it's trusted. Downloaded templates and template packs can't run any.
+    fn allowed_formatter(
+        &self,
+        name: &str,
+        path: &Path,
+        start: Option<&Path>,
+        command: String,
+    ) -> Result<Option<String>, anyhow::Error> {
+        let layer = template_search_path(start)?
+            .into_iter()
+            .find(|location| path.starts_with(&location.dir))
+            .map(|location| location.layer);
+        let allowed = match (layer, start.and_then(find_project_dir)) {
+            (Some("template-path" | "user" | "system"), _) => true,
+            (Some("project"), Some(project_dir)) => self.ask_trust(
+                &project_dir,
+                &format!(
+                    "Run `{command}` to format template '{name}' from {}?",
+                    path.display()
+                ),
+                &format!("Not running `{command}` from the frontmatter of template '{name}'"),
+            )?,
+            _ => {
+                eprintln!(
+                    "Warning: Not running `{command}` from the frontmatter of template '{name}', which comes from {}; pass --format-with to format it",
+                    path.display()
+                );
+                false
+            }
+        };
+        Ok(allowed.then_some(command))
+    }
+
+    /// The template `name` as found from `start`. Templates are looked up once per
+    /// project, since that's what decides which file a name resolves to.
+    pub fn prepare(
+        &mut self,
+        name: &str,
+        start: Option<&Path>,
+        timings: &mut Timings,
+    ) -> Result<Rc<Prepared>, anyhow::Error> {
+        let key = (name.to_string(), start.and_then(find_project_dir));
+        if !self.templates.contains_key(&key) {
+            let prepared = self.load(name, start, timings)?;
+            self.templates.insert(key.clone(), Rc::new(prepared));
+        }
+        Ok(Rc::clone(&self.templates[&key]))
+    }
+
+    fn load(
+        &mut self,
+        name: &str,
+        start: Option<&Path>,
+        timings: &mut Timings,
+    ) -> Result<Prepared, anyhow::Error> {
+        let mut tera = self.project_base(start, timings)?;
+        timings.measure(Phase::TemplateParse, || {
+            // The project's templates directory counts for `read_file` too
+            crate::builtins::register_read_file(&mut tera, template_dirs(start)?);
+            let path = get_template_path(name, start)?;
+            let bytes = fs::read(&path)?;
+            let binary = |bytes| Prepared {
+                meta: TemplateMeta::default(),
+                tera: Tera::default(),
+                body: Body::Binary(bytes),
+                uses_git: false,
+            };
+            let source = match String::from_utf8(bytes) {
+                Ok(source) if !name.ends_with(BINARY_SUFFIX) => source,
+                Ok(source) => return Ok(binary(source.into_bytes())),
+                Err(e) => return Ok(binary(e.into_bytes())),
+            };
+            let (mut meta, body) = frontmatter::split(&path, &source)?;
+            if let Some(command) = meta.output.format_with.take() {
+                meta.output.format_with = self.allowed_formatter(name, &path, start, command)?;
+            }
+            if meta.verbatim {
+                let body = Body::Verbatim(body.to_string());
+                return Ok(Prepared {
+                    meta,
+                    tera,
+                    body,
+                    uses_git: false,
+                });
+            }
+            // Templates for other engines are rendered from their body alone
+            if meta.engine != Engine::Tera {
+                let uses_git = context::uses_git(body);
+                let body = Body::Engine(body.to_string());
+                return Ok(Prepared {
+                    meta,
+                    tera,
+                    body,
+                    uses_git,
+                });
+            }
+
+            let macros_file = self.macros_file.as_deref();
+            let uses_git = add_template(
+                &mut tera,
+                name,
+                body,
+                macros_file.unwrap_or(DEFAULT_MACROS_FILE),
+                start,
+            )?;
+            if let Some((plugin, function)) = meta.missing_plugins(&tera).first() {
+                return Err(ZapError::MissingPlugin {
+                    template: name.to_string(),
+                    plugin: plugin.to_string(),
+                    function: function.to_string(),
+                }
+                .into());
+            }
+            Ok(Prepared {
+                meta,
+                tera,
+                body: Body::Tera,
+                uses_git,
+            })
+        })
+    }
+
+    /// Fail if the template `name` in `tera` uses anything that isn't defined, when
+    /// templates are rendered strictly. `source_of` gives the text of a template,
+    /// to point at the line with the problem.
+    pub fn check_strict(
+        &self,
+        tera: &Tera,
+        name: &str,
+        context: &tera::Context,
+        source_of: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<(), ZapError> {
+        if !self.strict {
+            return Ok(());
+        }
+        Checker::new(tera, name, context, source_of).check()
+    }
+
+    /// Render `contents` as a template named `name`, with the builtins and plugins.
+    pub fn render_str(
+        &mut self,
+        name: &str,
+        contents: &str,
+        context: &tera::Context,
+        timings: &mut Timings,
+    ) -> Result<String, anyhow::Error> {
+        let mut tera = self.base(timings)?;
+        timings
+            .measure(Phase::TemplateParse, || {
+                tera.add_raw_template(name, contents)
+            })
+            .map_err(TeraError::from)?;
+        self.check_strict(&tera, name, context, &|_| Some(contents.to_string()))?;
+        let rendered = timings
+            .measure(Phase::Render, || tera.render(name, context))
+            .map_err(TeraError::from)?;
+        Ok(rendered)
+    }
+
+    /// The `git` facts for a file at `path`, looked up once per repository.
+    pub fn git_context(&mut self, path: &Path) -> tera::Context {
+        self.git.get(path)
+    }
+}
+
+/// The template directories searched from `start`, in order.
+fn template_dirs(start: Option<&Path>) -> Result<Vec<PathBuf>, ZapError> {
+    Ok(template_search_path(start)?
+        .into_iter()
+        .map(|location| location.dir)
+        .collect())
+}
+
+/// Names of the files in a template directory, including those in subdirectories
+/// (as `sub/name`), sorted. Hidden files are skipped; a missing directory has none.
+pub fn template_files(dir: &Path) -> Result<Vec<String>, ZapError> {
+    let mut names = Vec::new();
+    if !dir.is_dir() {
+        return Ok(names);
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            names.extend(
+                template_files(&entry.path())?
+                    .into_iter()
+                    .map(|nested| format!("{name}/{nested}")),
+            );
+        } else {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Partials (`_header.html`, the macro library) are only meant to be included
+/// or imported by other templates.
+pub fn is_partial(name: &str) -> bool {
+    name.rsplit('/')
+        .next()
+        .is_some_and(|file| file.starts_with('_'))
+}
+
+/// Add the template `body`, without its frontmatter, to `tera` under `name`.
+/// The templates it includes, imports or extends are
+/// looked up on the search path (starting at `start`) and added too, along with
+/// theirs; where names clash, the earlier location wins, just like for `name`
+/// itself. If any of them call `macros::...`, the macro library `macros_file` is
+/// imported into `name` and the templates making those calls, provided there is one.
+/// Returns whether any of the added templates might use the `git` facts.
+pub fn add_template(
+    tera: &mut Tera,
+    name: &str,
+    body: &str,
+    macros_file: &str,
+    start: Option<&Path>,
+) -> Result<bool, ZapError> {
+    let dirs = template_dirs(start)?;
+    let find = |template: &str| {
+        dirs.iter()
+            .map(|dir| dir.join(template))
+            .find(|path| path.is_file())
+    };
+    let has_macros = find(macros_file).is_some();
+
+    let mut sources = BTreeMap::new();
+    let mut pending = vec![(name.to_string(), body.to_string())];
+    while let Some((template, source)) = pending.pop() {
+        let mut referenced = referenced_templates(&source);
+        if template == name && has_macros {
+            referenced.push(macros_file.to_string());
+        }
+        sources.insert(template, source);
+
+        for other in referenced {
+            if sources.contains_key(&other) || pending.iter().any(|(name, _)| *name == other) {
+                continue;
+            }
+            // Missing ones are left to Tera, which says which template it didn't find
+            let Some(other_path) = find(&other) else {
+                continue;
+            };
+            let other_source = fs::read_to_string(&other_path)?;
+            let (_, other_body) = frontmatter::split(&other_path, &other_source)?;
+            pending.push((other, other_body.to_string()));
+        }
+    }
+
+    // Tera looks macros up in the template being rendered, also for the ones it
+    // includes, and in the template a block comes from when it extends another
+    let uses_macros = |source: &String| source.contains("macros::");
+    if has_macros && sources.values().any(uses_macros) {
+        for (template, source) in sources.iter_mut() {
+            if template == name || (template != macros_file && uses_macros(source)) {
+                *source = with_macros_import(source, macros_file);
+            }
+        }
+    }
+
+    tera.add_raw_templates(
+        sources
+            .iter()
+            .map(|(template, source)| (template.as_str(), source.as_str())),
+    )?;
+    Ok(sources.values().any(|source| context::uses_git(source)))
+}
+
+/// Names of the templates `source` includes, imports or extends.
+fn referenced_templates(source: &str) -> Vec<String> {
+    regex!(r"\{%-?\s*(?:include|import|extends)\s([^%]*)%\}")
+        .captures_iter(source)
+        .flat_map(|tag| {
+            regex!(r#""([^"]+)"|'([^']+)'|`([^`]+)`"#)
+                .captures_iter(tag.get(1).map_or("", |m| m.as_str()))
+                .filter_map(|name| name.iter().skip(1).flatten().next())
+                .map(|name| name.as_str().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Insert `{% import "<macros_file>" as macros %}` into a template source.
+/// Tera only accepts imports before any content, after leading comments and the
+/// `extends` tag and with the other imports, so it goes right after those.
+/// Templates that import the library themselves are left alone.
+pub fn with_macros_import(source: &str, macros_file: &str) -> String {
+    if source.contains(&format!("\"{macros_file}\"")) {
+        return source.to_string();
+    }
+    let header_end = regex!(r"^(?:\s*(?:\{#[\s\S]*?#\}|\{%-?\s*(?:extends|import)\s[^%]*%\}))*")
+        .find(source)
+        .map_or(0, |m| m.end());
+    format!(
+        "{}{{% import \"{macros_file}\" as macros %}}{}",
+        &source[..header_end],
+        &source[header_end..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtins_record_what_they_register() {
+        let mut tera = Tera::default();
+        let registered = crate::builtins::register(&mut tera, Vec::new());
+
+        assert!(registered.filters.contains("snake_case"));
+        assert!(registered.functions.contains("read_file"));
+        for name in &registered.functions {
+            assert!(tera.get_function(name).is_ok(), "{name}");
+        }
+        for name in &registered.filters {
+            assert!(tera.get_filter(name).is_ok(), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_renderer_sets_up_tera_once() {
+        let mut renderer = Renderer::new();
+        let mut timings = Timings::new(true);
+        let mut context = tera::Context::new();
+        context.insert("name", "ada");
+
+        for _ in 0..3 {
+            let rendered = renderer
+                .render_str(
+                    "greeting",
+                    "Hi {{ name | upper_camel }}",
+                    &context,
+                    &mut timings,
+                )
+                .unwrap();
+            assert_eq!(rendered, "Hi Ada");
+        }
+        let json = timings.to_json();
+        assert_eq!(json["phases"]["plugin_load"]["count"], 1);
+        assert_eq!(json["phases"]["render"]["count"], 3);
+    }
+
+    #[test]
+    fn test_template_files_include_subdirectories() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("blog")).unwrap();
+        for name in [
+            "note",
+            "blog/post",
+            "blog/_footer",
+            "_macros.tera",
+            ".DS_Store",
+        ] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+
+        let files = template_files(dir.path()).unwrap();
+        assert_eq!(files, ["_macros.tera", "blog/_footer", "blog/post", "note"]);
+        let partials: Vec<_> = files.iter().filter(|name| is_partial(name)).collect();
+        assert_eq!(partials, ["_macros.tera", "blog/_footer"]);
+        assert!(
+            template_files(&dir.path().join("missing"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_macros_import_goes_after_leading_comments_extends_and_imports() {
+        let source = "{# a note #}\n{% extends \"base\" %}";
+        assert_eq!(
+            with_macros_import(source, DEFAULT_MACROS_FILE),
+            "{# a note #}\n{% extends \"base\" %}{% import \"_macros.tera\" as macros %}"
+        );
+        let source = "{% extends \"base\" %}\n{% import \"forms\" as forms %}\n{% block body %}{% endblock %}";
+        assert_eq!(
+            with_macros_import(source, DEFAULT_MACROS_FILE),
+            "{% extends \"base\" %}\n{% import \"forms\" as forms %}{% import \"_macros.tera\" as macros %}\n{% block body %}{% endblock %}"
+        );
+        assert_eq!(
+            with_macros_import("Hello", DEFAULT_MACROS_FILE),
+            "{% import \"_macros.tera\" as macros %}Hello"
+        );
+    }
+
+    #[test]
+    fn test_referenced_templates() {
+        let source = "{% extends 'base' %}{% import \"forms\" as forms %}\n{%- include [\"a\", `b`] ignore missing -%}{{ \"x\" }}";
+        assert_eq!(referenced_templates(source), ["base", "forms", "a", "b"]);
+        assert!(referenced_templates("{{ include }}").is_empty());
+    }
+
+    #[test]
+    fn test_macros_import_is_not_duplicated() {
+        let source = "{% import \"_macros.tera\" as m %}{{ m::hi() }}";
+        assert_eq!(with_macros_import(source, DEFAULT_MACROS_FILE), source);
+    }
+
+    #[test]
+    fn test_imported_macros_render() {
+        let mut tera = Tera::default();
+        let source = with_macros_import("{{ macros::greet(name=\"Bob\") }}", "m.tera");
+        tera.add_raw_templates(vec![
+            (
+                "m.tera",
+                "{% macro greet(name) %}Hello, {{ name }}!{% endmacro greet %}",
+            ),
+            ("note", source.as_str()),
+        ])
+        .unwrap();
+
+        let rendered = tera.render("note", &tera::Context::new()).unwrap();
+        assert_eq!(rendered, "Hello, Bob!");
+    }
+}
@@ -0,0 +1,369 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use tera::{Tera, Value};
+
+use crate::errors::PluginLoadError;
+use crate::plugins::{LoadedPlugin, PluginInfo, Plugins};
+
+/// Hidden subcommand the helper process is started with.
+pub const HOST_COMMAND: &str = "plugin-host";
+
+/// What a sandboxed plugin registered, sent by the helper once it's loaded.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Registrations {
+    /// Each is `None` when the plugin doesn't declare those names
+    functions: Option<Vec<String>>,
+    filters: Option<Vec<String>>,
+    testers: Option<Vec<String>>,
+    info: Option<PluginInfo>,
+}
+
+/// One call into the plugin, a line of JSON on the helper's stdin.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Request {
+    Function {
+        name: String,
+        args: HashMap<String, Value>,
+    },
+    Filter {
+        name: String,
+        value: Value,
+        args: HashMap<String, Value>,
+    },
+    Tester {
+        name: String,
+        value: Option<Value>,
+        args: Vec<Value>,
+    },
+}
+
+/// The answer to a request, a line of JSON on the helper's stdout.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Response {
+    Ok(Value),
+    Error(String),
+}
+
+/// The helper process side: load the plugin at `path`, report what it
+/// registered and answer requests until stdin closes. If the plugin crashes,
+/// it only takes this process down.
+pub fn serve(path: &Path) -> Result<(), anyhow::Error> {
+    // Before the plugin is loaded, so nothing it prints gets between the answers
+    let mut output = protocol_output()?;
+    let mut plugins = Plugins::new();
+    let mut tera = Tera::default();
+    plugins.load_plugin(&mut tera, path)?;
+    let loaded = plugins.loaded().last();
+    let registrations = Registrations {
+        functions: loaded.and_then(|plugin| plugin.functions.clone()),
+        filters: loaded.and_then(|plugin| plugin.filters.clone()),
+        testers: loaded.and_then(|plugin| plugin.testers.clone()),
+        info: loaded.and_then(|plugin| plugin.info.clone()),
+    };
+
+    let stdin = std::io::stdin().lock();
+    answer(&tera, &registrations, stdin, &mut output)?;
+    drop(tera);
+    Ok(())
+}
+
+/// Where the helper answers: its stdout, which from here on is only for that.
+/// What the plugin prints to stdout goes to stderr instead.
+#[cfg(unix)]
+fn protocol_output() -> io::Result<BufWriter<std::fs::File>> {
+    use std::os::unix::io::FromRawFd;
+
+    std::io::stdout().flush()?;
+    // SAFETY: the duplicate is a new descriptor owned by nothing else, and
+    // pointing descriptor 1 at stderr leaves `Stdout` usable
+    unsafe {
+        let channel = libc::dup(libc::STDOUT_FILENO);
+        if channel < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(BufWriter::new(std::fs::File::from_raw_fd(channel)))
+    }
+}
+
+/// Windows keeps stdout; plugins there mustn't print to it.
+#[cfg(not(unix))]
+fn protocol_output() -> io::Result<BufWriter<std::io::Stdout>> {
+    Ok(BufWriter::new(std::io::stdout()))
+}
+
+fn answer(
+    tera: &Tera,
+    registrations: &Registrations,
+    input: impl BufRead,
+    output: &mut impl Write,
+) -> Result<(), anyhow::Error> {
+    writeln!(output, "{}", serde_json::to_string(registrations)?)?;
+    output.flush()?;
+    for line in input.lines() {
+        let response = match serde_json::from_str::<Request>(&line?) {
+            Ok(request) => match call(tera, request) {
+                Ok(value) => Response::Ok(value),
+                Err(e) => Response::Error(message(&e)),
+            },
+            Err(e) => Response::Error(format!("invalid request: {e}")),
+        };
+        writeln!(output, "{}", serde_json::to_string(&response)?)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+fn call(tera: &Tera, request: Request) -> tera::Result<Value> {
+    match request {
+        Request::Function { name, args } => tera.get_function(&name)?.call(&args),
+        Request::Filter { name, value, args } => tera.get_filter(&name)?.filter(&value, &args),
+        Request::Tester { name, value, args } => tera
+            .get_tester(&name)?
+            .test(value.as_ref(), &args)
+            .map(Value::Bool),
+    }
+}
+
+/// A Tera error with its causes, which hold what the plugin said.
+fn message(error: &tera::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    message
+}
+
+/// The zap side of a helper process.
+struct Host {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    /// The plugin, for messages
+    label: String,
+}
+
+impl Host {
+    fn read_line(&mut self) -> Result<String, String> {
+        let mut line = String::new();
+        match self.stdout.read_line(&mut line) {
+            Ok(0) => Err(match self.child.wait() {
+                Ok(status) => format!("the plugin host exited ({status})"),
+                Err(e) => format!("the plugin host is gone: {e}"),
+            }),
+            Ok(_) => Ok(line),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn request(&mut self, request: &Request) -> tera::Result<Value> {
+        let label = self.label.clone();
+        let error = |reason: String| tera::Error::msg(format!("Plugin {label}: {reason}"));
+        let json = serde_json::to_string(request).map_err(|e| error(e.to_string()))?;
+        writeln!(self.stdin, "{json}").map_err(|e| error(e.to_string()))?;
+        let line = self.read_line().map_err(error)?;
+        match serde_json::from_str(&line).map_err(|e| error(e.to_string()))? {
+            Response::Ok(value) => Ok(value),
+            Response::Error(reason) => Err(error(reason)),
+        }
+    }
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+type SharedHost = Arc<Mutex<Host>>;
+
+fn request(host: &SharedHost, request: Request) -> tera::Result<Value> {
+    // A panic while locked can't leave the host half-way through a request
+    let mut host = host.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    host.request(&request)
+}
+
+struct SandboxedFunction(SharedHost, String);
+
+impl tera::Function for SandboxedFunction {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        request(
+            &self.0,
+            Request::Function {
+                name: self.1.clone(),
+                args: args.clone(),
+            },
+        )
+    }
+}
+
+struct SandboxedFilter(SharedHost, String);
+
+impl tera::Filter for SandboxedFilter {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        request(
+            &self.0,
+            Request::Filter {
+                name: self.1.clone(),
+                value: value.clone(),
+                args: args.clone(),
+            },
+        )
+    }
+}
+
+struct SandboxedTester(SharedHost, String);
+
+impl tera::Test for SandboxedTester {
+    fn test(&self, value: Option<&Value>, args: &[Value]) -> tera::Result<bool> {
+        let result = request(
+            &self.0,
+            Request::Tester {
+                name: self.1.clone(),
+                value: value.cloned(),
+                args: args.to_vec(),
+            },
+        )?;
+        Ok(result.as_bool().unwrap_or(false))
+    }
+}
+
+/// Start a helper process for the native plugin at `path` and register stand-ins
+/// for what it declares on `tera`. Returns the plugin as loading it in-process would.
+pub fn load(tera: &mut Tera, path: &Path) -> Result<LoadedPlugin, PluginLoadError> {
+    let error = |reason: String| PluginLoadError::Sandbox {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let exe = std::env::current_exe().map_err(|e| error(e.to_string()))?;
+    let mut child = Command::new(exe)
+        .arg(HOST_COMMAND)
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| error(format!("could not start the plugin host: {e}")))?;
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(error("the plugin host has no stdin or stdout".to_string()));
+    };
+    let mut host = Host {
+        child,
+        stdin,
+        stdout: BufReader::new(stdout),
+        label: path.display().to_string(),
+    };
+    let line = host.read_line().map_err(error)?;
+    let registrations: Registrations =
+        serde_json::from_str(&line).map_err(|e| error(e.to_string()))?;
+    if let Some(info) = &registrations.info {
+        host.label = info.name.clone();
+    }
+
+    let undeclared: Vec<&str> = [
+        ("functions", &registrations.functions),
+        ("filters", &registrations.filters),
+        ("testers", &registrations.testers),
+    ]
+    .into_iter()
+    .filter(|(_, names)| names.is_none())
+    .map(|(kind, _)| kind)
+    .collect();
+    if !undeclared.is_empty() {
+        eprintln!(
+            "Warning: Plugin {} doesn't declare its {}, so they can't be used in sandbox mode",
+            host.label,
+            undeclared.join(" or ")
+        );
+    }
+    let host = Arc::new(Mutex::new(host));
+    for name in registrations.functions.iter().flatten() {
+        tera.register_function(name, SandboxedFunction(host.clone(), name.clone()));
+    }
+    for name in registrations.filters.iter().flatten() {
+        tera.register_filter(name, SandboxedFilter(host.clone(), name.clone()));
+    }
+    for name in registrations.testers.iter().flatten() {
+        tera.register_tester(name, SandboxedTester(host.clone(), name.clone()));
+    }
+    Ok(LoadedPlugin {
+        path: path.to_path_buf(),
+        functions: registrations.functions,
+        filters: registrations.filters,
+        testers: registrations.testers,
+        info: registrations.info,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_answers_requests() {
+        let mut tera = Tera::default();
+        tera.register_function("answer", |_: &HashMap<String, Value>| Ok(Value::from(42)));
+        tera.register_filter("twice", |value: &Value, _: &HashMap<String, Value>| {
+            Ok(Value::from(format!(
+                "{0}{0}",
+                value.as_str().unwrap_or_default()
+            )))
+        });
+        let registrations = Registrations {
+            functions: Some(vec!["answer".to_string()]),
+            filters: Some(vec!["twice".to_string()]),
+            ..Registrations::default()
+        };
+        let requests = [
+            r#"{"kind": "function", "name": "answer", "args": {}}"#,
+            r#"{"kind": "filter", "name": "twice", "value": "ab", "args": {}}"#,
+            r#"{"kind": "tester", "name": "odd", "value": 3, "args": []}"#,
+            r#"{"kind": "function", "name": "missing", "args": {}}"#,
+            "not json",
+        ]
+        .join("\n");
+
+        let mut output = Vec::new();
+        answer(&tera, &registrations, requests.as_bytes(), &mut output).unwrap();
+        let lines: Vec<&str> = std::str::from_utf8(&output).unwrap().lines().collect();
+        let handshake: Registrations = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(handshake.filters, Some(vec!["twice".to_string()]));
+        assert_eq!(lines[1], r#"{"ok":42}"#);
+        assert_eq!(lines[2], r#"{"ok":"abab"}"#);
+        assert_eq!(lines[3], r#"{"ok":true}"#);
+        assert!(lines[4].starts_with(r#"{"error":"#) && lines[4].contains("missing"));
+        assert!(lines[5].contains("invalid request"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_crashed_host_is_an_error() {
+        let mut child = Command::new("sh")
+            .args(["-c", "kill -SEGV $$"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let host = Host {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            label: "shout".to_string(),
+        };
+        let host = Arc::new(Mutex::new(host));
+        let error = tera::Function::call(
+            &SandboxedFunction(host, "shout".to_string()),
+            &HashMap::new(),
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("Plugin shout: "), "{error}");
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use tera::{Context, Tera};
+
+use crate::errors::{TeraError, ZapError};
+use crate::fileops::FileOps;
+use crate::render::BINARY_SUFFIX;
+use crate::{find_template, remote};
+
+/// The directory `name` resolves to if the template is a scaffold: a directory
+/// of templates that are all rendered into the target directory at once.
+pub fn find_scaffold(fs: &dyn FileOps, name: &str, start: Option<&Path>) -> Option<PathBuf> {
+    if remote::is_remote(name) {
+        return None;
+    }
+    find_template(fs, name, start)
+        .ok()
+        .filter(|path| fs.is_dir(path))
+}
+
+/// Files of a scaffold as paths relative to `dir` (`src/main.rs`), sorted.
+/// Unlike in template directories, hidden files like `.gitignore` are included.
+pub fn scaffold_files(dir: &Path) -> Result<Vec<String>, ZapError> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() {
+            files.extend(
+                scaffold_files(&entry.path())?
+                    .into_iter()
+                    .map(|nested| format!("{name}/{nested}")),
+            );
+        } else {
+            files.push(name);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The context for the files of a scaffold created at `target`: `name` is the
+/// name of the target directory, unless the context sets it.
+pub fn scaffold_context(target: &Path, context: &Context) -> Context {
+    let mut scaffold_context = Context::new();
+    if let Some(name) = target.file_name() {
+        scaffold_context.insert("name", &name.to_string_lossy());
+    }
+    scaffold_context.extend(context.clone());
+    scaffold_context
+}
+
+/// Where the scaffold file `file` ends up below the target directory. The file
+/// name is a template itself, so `src/{{ name }}.rs` can depend on the context;
+/// the suffix of binary files is dropped (`logo.png.bin` becomes `logo.png`).
+pub fn output_path(template: &str, file: &str, context: &Context) -> Result<PathBuf, ZapError> {
+    let file = file.strip_suffix(BINARY_SUFFIX).unwrap_or(file);
+    let rendered = Tera::one_off(file, context, false).map_err(TeraError::from)?;
+    let path = PathBuf::from(&rendered);
+    let inside = path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if rendered.trim().is_empty() || !inside {
+        return Err(ZapError::InvalidScaffoldPath {
+            template: template.to_string(),
+            path: rendered,
+        });
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scaffold_files_include_hidden_and_nested_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src").join("{{ name }}.rs"), "").unwrap();
+        fs::write(dir.path().join(".gitignore"), "target\n").unwrap();
+        fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+
+        assert_eq!(
+            scaffold_files(dir.path()).unwrap(),
+            [".gitignore", "Cargo.toml", "src/{{ name }}.rs"]
+        );
+    }
+
+    #[test]
+    fn test_output_path_is_rendered() {
+        let context = scaffold_context(Path::new("projects/myproj/"), &Context::new());
+        assert_eq!(
+            output_path("rust-bin", "src/{{ name }}.rs", &context).unwrap(),
+            Path::new("src/myproj.rs")
+        );
+
+        let mut given = Context::new();
+        given.insert("name", "other");
+        let context = scaffold_context(Path::new("myproj"), &given);
+        assert_eq!(
+            output_path("rust-bin", "{{ name }}.rs", &context).unwrap(),
+            Path::new("other.rs")
+        );
+        assert_eq!(
+            output_path("rust-bin", "assets/{{ name }}.png.bin", &context).unwrap(),
+            Path::new("assets/other.png")
+        );
+    }
+
+    #[test]
+    fn test_output_path_stays_inside_target() {
+        let mut context = Context::new();
+        context.insert("name", "../escape");
+        assert!(matches!(
+            output_path("rust-bin", "{{ name }}.rs", &context),
+            Err(ZapError::InvalidScaffoldPath { .. })
+        ));
+        assert!(output_path("rust-bin", "/etc/passwd", &context).is_err());
+        context.insert("name", "");
+        assert!(output_path("rust-bin", "{{ name }}", &context).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::path::Path;
+use tera::ast::{Block, Expr, ExprVal, FunctionCall, Node};
+use tera::{Context, Tera};
+
+use crate::errors::{TeraError, ZapError};
+use crate::frontmatter;
+
+/// Variables Tera provides on its own.
+const MAGIC_VARIABLES: &[&str] = &["__tera_context"];
+
+/// Filters Tera handles itself instead of looking them up.
+const BUILTIN_FILTERS: &[&str] = &["default", "safe"];
+
+/// What `--strict-template` holds a template against: everything it could
+/// possibly use is checked up front, including branches that aren't taken, so a
+/// typo in an `{% if %}` is reported instead of quietly being false.
+pub struct Checker<'a> {
+    tera: &'a Tera,
+    /// The template being rendered, whose block definitions apply
+    name: &'a str,
+    context: &'a Context,
+    /// The text of a template by name, to tell on which line a problem is
+    source_of: &'a dyn Fn(&str) -> Option<String>,
+    visited: HashSet<String>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(
+        tera: &'a Tera,
+        name: &'a str,
+        context: &'a Context,
+        source_of: &'a dyn Fn(&str) -> Option<String>,
+    ) -> Self {
+        Checker {
+            tera,
+            name,
+            context,
+            source_of,
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Fail on the first undefined variable, unknown filter, test or function
+    /// the template `name` (or a template it extends or includes) uses.
+    pub fn check(mut self) -> Result<(), ZapError> {
+        let template = self.tera.get_template(self.name).map_err(TeraError::from)?;
+        // Rendering starts at the top of the inheritance chain
+        let start = template.parents.last().map_or(self.name, String::as_str);
+        let mut scope = Vec::new();
+        self.template(start, &mut scope)
+    }
+
+    fn template(&mut self, name: &str, scope: &mut Vec<String>) -> Result<(), ZapError> {
+        if !self.visited.insert(name.to_string()) {
+            return Ok(());
+        }
+        let tera = self.tera;
+        let Ok(template) = tera.get_template(name) else {
+            return Ok(());
+        };
+        self.nodes(name, &template.ast, scope)
+    }
+
+    fn nodes(
+        &mut self,
+        file: &str,
+        nodes: &[Node],
+        scope: &mut Vec<String>,
+    ) -> Result<(), ZapError> {
+        for node in nodes {
+            match node {
+                Node::VariableBlock(_, expr) => self.expr(file, expr, scope)?,
+                Node::Set(_, set) => {
+                    self.expr(file, &set.value, scope)?;
+                    scope.push(set.key.clone());
+                }
+                Node::FilterSection(_, section, _) => {
+                    self.filter(file, &section.filter, scope)?;
+                    self.nodes(file, &section.body, scope)?;
+                }
+                Node::Block(_, block, _) => {
+                    let (definer, block) = self.block_definition(file, block);
+                    self.nodes(&definer, &block.body, scope)?;
+                }
+                Node::Forloop(_, forloop, _) => {
+                    self.expr(file, &forloop.container, scope)?;
+                    let depth = scope.len();
+                    scope.extend(forloop.key.clone());
+                    scope.push(forloop.value.clone());
+                    scope.push("loop".to_string());
+                    self.nodes(file, &forloop.body, scope)?;
+                    scope.truncate(depth);
+                    if let Some(empty_body) = &forloop.empty_body {
+                        self.nodes(file, empty_body, scope)?;
+                    }
+                }
+                Node::If(conditions, _) => {
+                    for (_, condition, body) in &conditions.conditions {
+                        self.expr(file, condition, scope)?;
+                        // Inside `{% if x is defined %}`, `x` is
+                        let depth = scope.len();
+                        if let ExprVal::Test(test) = &condition.val {
+                            if test.name == "defined" && !test.negated && !condition.negated {
+                                scope.push(root(&test.ident).to_string());
+                            }
+                        }
+                        self.nodes(file, body, scope)?;
+                        scope.truncate(depth);
+                    }
+                    if let Some((_, body)) = &conditions.otherwise {
+                        self.nodes(file, body, scope)?;
+                    }
+                }
+                Node::Include(_, names, _) => {
+                    for name in names {
+                        self.template(name, scope)?;
+                    }
+                }
+                // Macros only see their arguments; Tera checks calls to them
+                Node::MacroDefinition(..)
+                | Node::Super
+                | Node::Text(_)
+                | Node::Extends(..)
+                | Node::ImportMacro(..)
+                | Node::Raw(..)
+                | Node::Break(_)
+                | Node::Continue(_)
+                | Node::Comment(..) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The definition of `block` that gets rendered: the one from the template
+    /// furthest down the inheritance chain, along with the template it's in.
+    fn block_definition(&self, file: &str, block: &Block) -> (String, Block) {
+        self.tera
+            .get_template(self.name)
+            .ok()
+            .and_then(|template| template.blocks_definitions.get(&block.name))
+            .and_then(|definitions| definitions.last())
+            .map_or_else(
+                || (file.to_string(), block.clone()),
+                |(definer, block)| (definer.clone(), block.clone()),
+            )
+    }
+
+    fn expr(&mut self, file: &str, expr: &Expr, scope: &[String]) -> Result<(), ZapError> {
+        // `default` is there to handle undefined values, so those are fine
+        if !expr.has_default_filter() {
+            self.value(file, &expr.val, scope)?;
+        }
+        for filter in &expr.filters {
+            self.filter(file, filter, scope)?;
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, file: &str, value: &ExprVal, scope: &[String]) -> Result<(), ZapError> {
+        match value {
+            ExprVal::Ident(ident) => self.variable(file, ident, scope),
+            ExprVal::Math(math) => {
+                self.expr(file, &math.lhs, scope)?;
+                self.expr(file, &math.rhs, scope)
+            }
+            ExprVal::Logic(logic) => {
+                self.expr(file, &logic.lhs, scope)?;
+                self.expr(file, &logic.rhs, scope)
+            }
+            ExprVal::In(test) => {
+                self.expr(file, &test.lhs, scope)?;
+                self.expr(file, &test.rhs, scope)
+            }
+            ExprVal::Test(test) => {
+                if self.tera.get_tester(&test.name).is_err() {
+                    return Err(self.problem(file, &test.name, "unknown test"));
+                }
+                // `is defined` and `is undefined` are how templates ask
+                if !["defined", "undefined"].contains(&test.name.as_str()) {
+                    self.variable(file, &test.ident, scope)?;
+                }
+                test.args
+                    .iter()
+                    .try_for_each(|arg| self.expr(file, arg, scope))
+            }
+            ExprVal::FunctionCall(call) => {
+                if self.tera.get_function(&call.name).is_err() {
+                    return Err(self.problem(file, &call.name, "unknown function"));
+                }
+                call.args
+                    .values()
+                    .try_for_each(|arg| self.expr(file, arg, scope))
+            }
+            ExprVal::MacroCall(call) => call
+                .args
+                .values()
+                .try_for_each(|arg| self.expr(file, arg, scope)),
+            ExprVal::Array(items) => items
+                .iter()
+                .try_for_each(|item| self.expr(file, item, scope)),
+            ExprVal::StringConcat(concat) => concat
+                .values
+                .iter()
+                .try_for_each(|value| self.value(file, value, scope)),
+            ExprVal::String(_) | ExprVal::Int(_) | ExprVal::Float(_) | ExprVal::Bool(_) => Ok(()),
+        }
+    }
+
+    fn filter(
+        &mut self,
+        file: &str,
+        filter: &FunctionCall,
+        scope: &[String],
+    ) -> Result<(), ZapError> {
+        let known = self.tera.get_filter(&filter.name).is_ok()
+            || BUILTIN_FILTERS.contains(&filter.name.as_str());
+        if !known {
+            return Err(self.problem(file, &filter.name, "unknown filter"));
+        }
+        filter
+            .args
+            .values()
+            .try_for_each(|arg| self.expr(file, arg, scope))
+    }
+
+    /// Check that the variable `ident` starts with exists.
+    fn variable(&self, file: &str, ident: &str, scope: &[String]) -> Result<(), ZapError> {
+        let root = root(ident);
+        let defined = scope.iter().any(|name| name == root)
+            || MAGIC_VARIABLES.contains(&root)
+            || self.context.contains_key(root);
+        if defined {
+            Ok(())
+        } else {
+            Err(self.problem(file, root, "undefined variable"))
+        }
+    }
+
+    fn problem(&self, file: &str, name: &str, what: &str) -> ZapError {
+        ZapError::StrictTemplate {
+            template: file.to_string(),
+            line: (self.source_of)(file).and_then(|source| line_of(file, &source, name)),
+            problem: format!("{what} `{name}`"),
+        }
+    }
+}
+
+/// The variable an identifier like `author.name` or `items[0]` starts with.
+fn root(ident: &str) -> &str {
+    ident.split(['.', '[']).next().unwrap_or(ident)
+}
+
+/// The first line of `source` that mentions `name` as a whole word, counted
+/// in the template file, frontmatter included.
+fn line_of(file: &str, source: &str, name: &str) -> Option<usize> {
+    let body_start = frontmatter::split(Path::new(file), source)
+        .map_or(0, |(_, body)| source.len() - body.len());
+    let skipped = source[..body_start].lines().count();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    source[body_start..]
+        .lines()
+        .position(|line| {
+            line.match_indices(name).any(|(at, _)| {
+                let before = line[..at].chars().next_back();
+                let after = line[at + name.len()..].chars().next();
+                !before.is_some_and(is_word) && !after.is_some_and(is_word)
+            })
+        })
+        .map(|index| skipped + index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(source: &str, context: &Context) -> Result<(), ZapError> {
+        let mut tera = Tera::default();
+        crate::builtins::register(&mut tera, Vec::new());
+        tera.add_raw_template("note", source).unwrap();
+        let source_of = |_: &str| Some(source.to_string());
+        Checker::new(&tera, "note", context, &source_of).check()
+    }
+
+    fn problem(source: &str) -> String {
+        match check(source, &Context::new()) {
+            Err(ZapError::StrictTemplate { line, problem, .. }) => format!("{line:?} {problem}"),
+            other => panic!("expected a strict template error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_defined_variables_pass() {
+        let mut context = Context::new();
+        context.insert("title", "Standup");
+        context.insert("people", &["Ada", "Bob"]);
+        let source = "{{ title | upper }}\n{% for person in people %}{{ loop.index }} {{ person }}{% endfor %}\n\
+            {% set n = 1 %}{{ n + 1 }} {{ missing | default(value=\"x\") }}\n\
+            {% if missing is defined %}{{ missing }}{% endif %}{{ now() }}";
+        check(source, &context).unwrap();
+    }
+
+    #[test]
+    fn test_undefined_names_are_reported_with_their_line() {
+        assert_eq!(
+            problem("# Notes\n{% if draft %}Draft{% endif %}"),
+            "Some(2) undefined variable `draft`"
+        );
+        assert_eq!(
+            problem("{{ 'a' | shout }}"),
+            "Some(1) unknown filter `shout`"
+        );
+        assert_eq!(
+            problem("\n\n{{ today() }}"),
+            "Some(3) unknown function `today`"
+        );
+        assert_eq!(
+            problem("{% for x in [1] %}{% endfor %}{{ x }}"),
+            "Some(1) undefined variable `x`"
+        );
+    }
+
+    #[test]
+    fn test_line_counts_frontmatter() {
+        assert_eq!(
+            line_of("note", "+++\n+++\nname\n{{ title }}", "title"),
+            Some(4)
+        );
+        assert_eq!(
+            line_of("note", "{{ subtitle }}\n{{ title }}", "title"),
+            Some(2)
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde_json::{Value, json};
+
+/// The phases of a run that `--timings` reports on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Config,
+    PluginLoad,
+    TemplateParse,
+    Render,
+    Format,
+    FileWrite,
+    SetTimes,
+}
+
+impl Phase {
+    fn key(&self) -> &'static str {
+        match self {
+            Phase::Config => "config",
+            Phase::PluginLoad => "plugin_load",
+            Phase::TemplateParse => "template_parse",
+            Phase::Render => "render",
+            Phase::Format => "format",
+            Phase::FileWrite => "file_write",
+            Phase::SetTimes => "set_times",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Config => "config resolution",
+            Phase::PluginLoad => "plugin loading",
+            Phase::TemplateParse => "template parse",
+            Phase::Render => "render",
+            Phase::Format => "formatter",
+            Phase::FileWrite => "file write",
+            Phase::SetTimes => "set times",
+        })
+    }
+}
+
+/// Output format for `--timings`
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimingsFormat {
+    Text,
+    Json,
+}
+
+/// Accumulated time spent per phase over a whole run.
+/// Measuring is a no-op apart from calling the closure when timings are disabled.
+#[derive(Debug, Default)]
+pub struct Timings {
+    enabled: bool,
+    phases: BTreeMap<Phase, (Duration, u32)>,
+}
+
+impl Timings {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            phases: BTreeMap::new(),
+        }
+    }
+
+    /// Run `f`, adding the time it took to `phase`.
+    pub fn measure<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    pub fn record(&mut self, phase: Phase, elapsed: Duration) {
+        let entry = self.phases.entry(phase).or_default();
+        entry.0 += elapsed;
+        entry.1 += 1;
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.values().map(|(elapsed, _)| *elapsed).sum()
+    }
+
+    /// A human readable breakdown, one phase per line.
+    pub fn report(&self) -> String {
+        let mut report = String::from("Timings:\n");
+        for (phase, (elapsed, count)) in &self.phases {
+            report.push_str(&format!(
+                "  {:<18} {:>10.3}ms  ({count}x)\n",
+                phase.to_string(),
+                elapsed.as_secs_f64() * 1000.0
+            ));
+        }
+        report.push_str(&format!(
+            "  {:<18} {:>10.3}ms\n",
+            "total",
+            self.total().as_secs_f64() * 1000.0
+        ));
+        report
+    }
+
+    /// The same breakdown as JSON, with durations in milliseconds.
+    pub fn to_json(&self) -> Value {
+        let phases: serde_json::Map<String, Value> = self
+            .phases
+            .iter()
+            .map(|(phase, (elapsed, count))| {
+                (
+                    phase.key().to_string(),
+                    json!({ "ms": elapsed.as_secs_f64() * 1000.0, "count": count }),
+                )
+            })
+            .collect();
+        json!({ "phases": phases, "total_ms": self.total().as_secs_f64() * 1000.0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_timings_record_nothing() {
+        let mut timings = Timings::new(false);
+        assert_eq!(timings.measure(Phase::Render, || 42), 42);
+        assert_eq!(timings.total(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_timings_accumulate_per_phase() {
+        let mut timings = Timings::new(true);
+        timings.record(Phase::Render, Duration::from_millis(2));
+        timings.record(Phase::Render, Duration::from_millis(3));
+        timings.record(Phase::SetTimes, Duration::from_millis(1));
+
+        assert_eq!(timings.total(), Duration::from_millis(6));
+        let json = timings.to_json();
+        assert_eq!(json["phases"]["render"]["count"], 2);
+        assert_eq!(json["phases"]["render"]["ms"], 5.0);
+        assert!(timings.report().contains("render"));
+        assert!(timings.report().contains("(2x)"));
+    }
+}
@@ -0,0 +1,77 @@
+use std::process::Command;
+use tempfile::TempDir;
+
+fn zap(config_dir: &std::path::Path, args: &[&str]) -> std::process::Output {
+    Command::new("cargo")
+        .args(["run", "--"])
+        .args(args)
+        .env("ZAP_CONFIG", config_dir)
+        .env_remove("ZAP_DEFAULT_TEMPLATE")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command")
+}
+
+#[test]
+fn test_config_init_writes_commented_template() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let config_file = config_dir.join("config.toml");
+
+    let output = zap(&config_dir, &["config", "init"]);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&config_file).expect("Failed to read config");
+    assert!(content.contains("# default_template = \"header\""));
+
+    // An existing config is only replaced with --force
+    std::fs::write(&config_file, "open = true\n").expect("Failed to write config");
+    let output = zap(&config_dir, &["config", "init"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("already exists"));
+    assert_eq!(
+        std::fs::read_to_string(&config_file).expect("Failed to read config"),
+        "open = true\n"
+    );
+
+    let output = zap(&config_dir, &["config", "init", "--force"]);
+    assert!(output.status.success());
+    let content = std::fs::read_to_string(&config_file).expect("Failed to read config");
+    assert!(content.contains("# [profile.work]"));
+}
+
+#[test]
+fn test_config_show_prints_effective_settings() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    std::fs::create_dir_all(&config_dir).expect("Failed to create config dir");
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "default_template = \"header\"\ncontext = \"author=Jane\"\n",
+    )
+    .expect("Failed to write config");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "config", "show"])
+        .env("ZAP_CONFIG", &config_dir)
+        .env("ZAP_DEFAULT_TEMPLATE", "footer")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(&format!(
+        "# config file: {}",
+        config_dir.join("config.toml").display()
+    )));
+    assert!(stdout.contains("default_template = \"footer\""));
+    assert!(stdout.contains("context = \"author=Jane\""));
+}
@@ -91,3 +91,1913 @@ fn test_create_with_template_and_context() {
     let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
     assert_eq!(content, "Hello, Bob!");
 }
+
+#[test]
+fn test_create_with_system_template_fallback() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("templated.txt");
+
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let system_dir = temp_dir.path().join("share").join("zap");
+    let system_template_dir = system_dir.join("templates");
+
+    std::fs::create_dir_all(config_dir.join("templates"))
+        .expect("Failed to create user template directory");
+    std::fs::create_dir_all(&system_template_dir)
+        .expect("Failed to create system template directory");
+    std::fs::write(system_template_dir.join("org-header"), "Provided by ACME")
+        .expect("Failed to create template");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--template",
+            "org-header",
+            test_file.to_str().unwrap(),
+        ])
+        .env("ZAP_CONFIG", &config_dir)
+        .env("ZAP_SYSTEM_DIR", &system_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(content, "Provided by ACME");
+}
+
+#[test]
+fn test_missing_template_lists_searched_locations() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("templated.txt");
+
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let system_dir = temp_dir.path().join("share").join("zap");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--template",
+            "missing",
+            test_file.to_str().unwrap(),
+        ])
+        .env("ZAP_CONFIG", &config_dir)
+        .env("ZAP_SYSTEM_DIR", &system_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let user_candidate = config_dir.join("templates").join("missing");
+    let system_candidate = system_dir.join("templates").join("missing");
+    let user_pos = stderr
+        .find(user_candidate.to_str().unwrap())
+        .expect("user location should be listed");
+    let system_pos = stderr
+        .find(system_candidate.to_str().unwrap())
+        .expect("system location should be listed");
+    assert!(user_pos < system_pos, "user dir must be searched first");
+}
+
+#[test]
+fn test_create_with_case_conversion_filters() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("user_profile.rs");
+
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("rust-struct"),
+        "pub struct {{ name | pascal_case }};\nconst {{ name | screaming_snake_case }}: &str = \"{{ name | kebab_case }}\";",
+    )
+    .expect("Failed to create template");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-T",
+            "rust-struct",
+            "-C",
+            "name=user profile",
+            test_file.to_str().unwrap(),
+        ])
+        .env("ZAP_CONFIG", config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(
+        content,
+        "pub struct UserProfile;\nconst USER_PROFILE: &str = \"user-profile\";"
+    );
+}
+
+#[test]
+fn test_each_renders_one_file_per_item() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let out_dir = temp_dir.path().join("team");
+    std::fs::create_dir_all(&out_dir).expect("Failed to create output directory");
+
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("member"),
+        "# {{ item.name }} ({{ item.role }})",
+    )
+    .expect("Failed to create template");
+
+    let items_file = temp_dir.path().join("members.json");
+    std::fs::write(
+        &items_file,
+        r#"[{"name": "Ada", "role": "admin"}, {"name": "Linus", "role": "maintainer"}]"#,
+    )
+    .expect("Failed to write items");
+
+    let name_pattern = format!("{}/{{{{ item.name | lower }}}}.md", out_dir.display());
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-T",
+            "member",
+            "--each",
+            items_file.to_str().unwrap(),
+            "--name",
+            &name_pattern,
+        ])
+        .env("ZAP_CONFIG", config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let ada = std::fs::read_to_string(out_dir.join("ada.md")).expect("Failed to read ada.md");
+    let linus = std::fs::read_to_string(out_dir.join("linus.md")).expect("Failed to read linus.md");
+    assert_eq!(ada, "# Ada (admin)");
+    assert_eq!(linus, "# Linus (maintainer)");
+}
+
+#[test]
+fn test_default_template_from_config() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("header"), "# header\n").expect("Failed to create template");
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "default_template = \"header\"\n",
+    )
+    .expect("Failed to write config");
+
+    let new_file = temp_dir.path().join("new.txt");
+    let existing_file = temp_dir.path().join("existing.txt");
+    let plain_file = temp_dir.path().join("plain.txt");
+    std::fs::write(&existing_file, "keep me").expect("Failed to create existing file");
+
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .env_remove("ZAP_DEFAULT_TEMPLATE")
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    run(&[new_file.to_str().unwrap(), existing_file.to_str().unwrap()]);
+    run(&["--no-template", plain_file.to_str().unwrap()]);
+
+    assert_eq!(std::fs::read_to_string(&new_file).unwrap(), "# header\n");
+    assert_eq!(std::fs::read_to_string(&existing_file).unwrap(), "keep me");
+    assert_eq!(std::fs::read_to_string(&plain_file).unwrap(), "");
+}
+
+#[test]
+fn test_shared_macros_are_imported() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("greeting.txt");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("_macros.tera"),
+        "{% macro greet(name) %}Hello, {{ name }}!{% endmacro greet %}",
+    )
+    .expect("Failed to create macros");
+    std::fs::write(
+        template_dir.join("greeting"),
+        "{# uses the shared macros #}\n{{ macros::greet(name=name) }}",
+    )
+    .expect("Failed to create template");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-T",
+            "greeting",
+            "-C",
+            "name=Ada",
+            test_file.to_str().unwrap(),
+        ])
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(content, "Hello, Ada!");
+}
+
+#[test]
+fn test_source_date_epoch_and_now_override() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("stamp"), "{{ now(utc=true) }}")
+        .expect("Failed to create template");
+
+    let run = |file: &std::path::Path, extra_args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "-T", "stamp"])
+            .args(extra_args)
+            .arg(file)
+            .env("ZAP_CONFIG", &config_dir)
+            .env("SOURCE_DATE_EPOCH", "1700000000")
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    let from_epoch = temp_dir.path().join("epoch.txt");
+    run(&from_epoch, &[]);
+    assert_eq!(
+        std::fs::read_to_string(&from_epoch).unwrap(),
+        "2023-11-14T22:13:20+00:00"
+    );
+    let modified = std::fs::metadata(&from_epoch).unwrap().modified().unwrap();
+    assert_eq!(
+        modified,
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000)
+    );
+
+    let overridden = temp_dir.path().join("override.txt");
+    run(&overridden, &["--now", "2024-03-01T12:00:00Z"]);
+    assert_eq!(
+        std::fs::read_to_string(&overridden).unwrap(),
+        "2024-03-01T12:00:00+00:00"
+    );
+}
+
+#[test]
+fn test_from_csv_creates_one_file_per_row() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("service"),
+        "name = \"{{ service }}\"\nport = {{ port }}",
+    )
+    .expect("Failed to create template");
+
+    let out_dir = temp_dir.path().join("out");
+    let csv_file = temp_dir.path().join("services.csv");
+    std::fs::write(
+        &csv_file,
+        format!(
+            "service,port,path\napi,8080,{0}/api.toml\nweb,80,{0}/web.toml\n",
+            out_dir.display()
+        ),
+    )
+    .expect("Failed to write CSV");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-p",
+            "-T",
+            "service",
+            "--from-csv",
+            csv_file.to_str().unwrap(),
+            "--name-column",
+            "path",
+        ])
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("api.toml")).unwrap(),
+        "name = \"api\"\nport = 8080"
+    );
+    assert_eq!(
+        std::fs::read_to_string(out_dir.join("web.toml")).unwrap(),
+        "name = \"web\"\nport = 80"
+    );
+}
+
+#[test]
+fn test_config_supplies_default_options() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("signed"), "{{ title }} by {{ author }}")
+        .expect("Failed to create template");
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "create_intermediate_dirs = true\ncontext = \"author=Jane,title=Untitled\"\n",
+    )
+    .expect("Failed to write config");
+
+    let test_file = temp_dir.path().join("nested").join("dir").join("post.md");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-T",
+            "signed",
+            "-C",
+            "title=Hello",
+            test_file.to_str().unwrap(),
+        ])
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(content, "Hello by Jane");
+}
+
+#[test]
+fn test_project_templates_take_precedence() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    std::fs::create_dir_all(config_dir.join("templates")).expect("Failed to create template dir");
+    std::fs::write(config_dir.join("templates").join("note"), "user")
+        .expect("Failed to create user template");
+    std::fs::write(config_dir.join("templates").join("other"), "user only")
+        .expect("Failed to create user template");
+
+    let project_dir = temp_dir.path().join("project");
+    let project_templates = project_dir.join(".zap").join("templates");
+    std::fs::create_dir_all(&project_templates).expect("Failed to create project templates");
+    std::fs::write(project_templates.join("note"), "project")
+        .expect("Failed to create project template");
+
+    let run = |template: &str, file: &std::path::Path| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "-p", "-T", template, file.to_str().unwrap()])
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        std::fs::read_to_string(file).expect("Failed to read file")
+    };
+
+    let nested = project_dir.join("docs").join("new").join("a.md");
+    assert_eq!(run("note", &nested), "project");
+    assert_eq!(run("other", &project_dir.join("b.md")), "user only");
+    assert_eq!(run("note", &temp_dir.path().join("outside.md")), "user");
+}
+
+#[test]
+fn test_profile_bundles_template_and_context() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("memo"), "{{ team }}: {{ title }}")
+        .expect("Failed to create template");
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "[profile.work]\ntemplate = \"memo\"\ncontext = \"team=Platform,title=Untitled\"\n",
+    )
+    .expect("Failed to write config");
+
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let test_file = temp_dir.path().join("notes.md");
+    let output = run(&[
+        "--profile",
+        "work",
+        "-C",
+        "title=Standup",
+        test_file.to_str().unwrap(),
+    ]);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(content, "Platform: Standup");
+
+    let other_file = temp_dir.path().join("other.md");
+    let output = run(&["--profile", "home", other_file.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown profile 'home'"));
+    assert!(!other_file.exists());
+}
+
+#[test]
+fn test_alias_expands_to_flags() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("note"), "# {{ title }}").expect("Failed to create template");
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "alias.note = \"-T note -p -C 'title=Good morning'\"\n",
+    )
+    .expect("Failed to write config");
+
+    let test_file = temp_dir.path().join("journal").join("today.md");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "+note", test_file.to_str().unwrap()])
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(content, "# Good morning");
+}
+
+#[test]
+fn test_dir_config_selects_template_by_glob() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("blogpost"), "---\ntitle: post\n---\n")
+        .expect("Failed to create template");
+
+    let blog_dir = temp_dir.path().join("blog");
+    std::fs::create_dir_all(&blog_dir).expect("Failed to create blog directory");
+    std::fs::write(blog_dir.join(".zap.toml"), "\"*.md\" = \"blogpost\"\n")
+        .expect("Failed to write .zap.toml");
+
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    let post = blog_dir.join("hello.md");
+    let plain = blog_dir.join("notes.txt");
+    let skipped = blog_dir.join("empty.md");
+    run(&[post.to_str().unwrap(), plain.to_str().unwrap()]);
+    run(&["--no-template", skipped.to_str().unwrap()]);
+
+    let read = |path: &std::path::Path| std::fs::read_to_string(path).expect("Failed to read file");
+    assert_eq!(read(&post), "---\ntitle: post\n---\n");
+    assert_eq!(read(&plain), "");
+    assert_eq!(read(&skipped), "");
+}
+
+#[test]
+fn test_builtin_file_and_session_variables() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("header"),
+        "{{ filename }}|{{ filestem }}|{{ extension }}|{{ dirname }}|{{ date }}|{{ user }}",
+    )
+    .expect("Failed to create template");
+
+    let test_file = temp_dir.path().join("report.md");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "header", "-C", "user=Ada"])
+        .args(["--now", "2024-03-01T12:00:00"])
+        .arg(&test_file)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(
+        content,
+        format!(
+            "report.md|report|md|{}|2024-03-01|Ada",
+            temp_dir.path().display()
+        )
+    );
+}
+
+#[test]
+fn test_context_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("report"),
+        "{{ title }}: {{ stats.total }} by {{ author }}",
+    )
+    .expect("Failed to create template");
+
+    let test_file = temp_dir.path().join("out.md");
+
+    let mut child = Command::new("cargo")
+        .args(["run", "--", "-T", "report", "--context=-,author=Ada"])
+        .arg(&test_file)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute zap command");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(br#"{"title": "Q3", "stats": {"total": 42}, "author": "Bob"}"#)
+        .expect("Failed to write to stdin");
+    let output = child.wait_with_output().expect("Failed to wait for zap");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(content, "Q3: 42 by Ada");
+}
+
+#[test]
+fn test_frontmatter_requires_plugins() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("loud"),
+        "+++\nrequires_plugins = [\"shout\"]\n+++\n{{ shout(input=\"hi\") }}\n",
+    )
+    .expect("Failed to create template");
+    // Any registered filter satisfies a requirement, here one of Tera's own
+    std::fs::write(
+        template_dir.join("quiet"),
+        "+++\nrequires_plugins = [\"case:lower\"]\n+++\n{{ \"HI\" | lower }}\n",
+    )
+    .expect("Failed to create template");
+
+    let run = |template: &str, path: &std::path::Path| {
+        Command::new("cargo")
+            .args(["run", "--", "-T", template])
+            .arg(path)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let loud = temp_dir.path().join("loud.txt");
+    let output = run("loud", &loud);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Template 'loud' needs plugin 'shout' (function shout), which is not loaded"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!loud.exists());
+
+    let quiet = temp_dir.path().join("quiet.txt");
+    let output = run("quiet", &quiet);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read_to_string(&quiet).unwrap(), "hi\n");
+}
+
+#[test]
+fn test_frontmatter_defaults_required_and_output() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("meeting"),
+        "---\nzap:\n  required: [title]\n  defaults:\n    room: Lobby\n  output:\n    final_newline: true\n---\n# {{ title }} in {{ room }}\n\n\n",
+    )
+    .expect("Failed to create template");
+
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", "-T", "meeting"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let missing = temp_dir.path().join("missing.md");
+    let output = run(&[missing.to_str().unwrap()]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("Template 'meeting' needs values for title")
+    );
+
+    let test_file = temp_dir.path().join("standup.md");
+    let output = run(&["-C", "title=Standup", test_file.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(content, "# Standup in Lobby\n");
+}
+
+#[test]
+fn test_templates_can_include_and_extend_each_other() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(template_dir.join("partials"))
+        .expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("base.html"),
+        "<h1>{% block title %}{% endblock title %}</h1>{% include \"partials/_footer.html\" %}",
+    )
+    .expect("Failed to create base template");
+    std::fs::write(
+        template_dir.join("partials").join("_footer.html"),
+        "<footer>{{ macros::sign(name=author) }}</footer>",
+    )
+    .expect("Failed to create partial");
+    std::fs::write(
+        template_dir.join("_macros.tera"),
+        "{% macro sign(name) %}by {{ name }}{% endmacro sign %}",
+    )
+    .expect("Failed to create macros");
+    std::fs::write(
+        template_dir.join("page.html"),
+        "+++\ndescription = \"A page\"\n+++\n{% extends \"base.html\" %}{% block title %}{{ title }}{% endblock title %}",
+    )
+    .expect("Failed to create page template");
+
+    let test_file = temp_dir.path().join("index.html");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-T",
+            "page.html",
+            "-C",
+            "title=Home,author=Ada",
+        ])
+        .arg(&test_file)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let content = std::fs::read_to_string(&test_file).expect("Failed to read file");
+    assert_eq!(content, "<h1>Home</h1><footer>by Ada</footer>");
+}
+
+#[test]
+fn test_unrelated_templates_dont_break_rendering() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("post.md"),
+        "+++\nlayout = \"post\"\n+++\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(template_dir.join("broken"), "{% if %}").expect("Failed to create template");
+    std::fs::write(template_dir.join("plain"), "{{ 1 + 1 }}").expect("Failed to create template");
+
+    let test_file = temp_dir.path().join("a.txt");
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "plain"])
+        .arg(&test_file)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read_to_string(&test_file).unwrap(), "2");
+}
+
+#[test]
+fn test_auto_template_by_extension() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("rust-file"), "// {{ filename }}\n")
+        .expect("Failed to create template");
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "auto_template = { rs = \"rust-file\" }\n",
+    )
+    .expect("Failed to write config");
+
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    let auto = temp_dir.path().join("new.rs");
+    let plain = temp_dir.path().join("plain.rs");
+    run(&[auto.to_str().unwrap()]);
+    run(&["--no-auto-template", plain.to_str().unwrap()]);
+
+    let read = |path: &std::path::Path| std::fs::read_to_string(path).expect("Failed to read file");
+    assert_eq!(read(&auto), "// new.rs\n");
+    assert_eq!(read(&plain), "");
+}
+
+#[test]
+fn test_path_rules_pick_templates() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("rust-file"), "// {{ filename }}\n")
+        .expect("Failed to create template");
+    std::fs::write(template_dir.join("rust-test"), "#[test]\n").expect("Failed to create template");
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "auto_template = { rs = \"rust-file\" }\n\n[[rule]]\npath = \"*_test.rs\"\ntemplate = \"rust-test\"\n",
+    )
+    .expect("Failed to write config");
+
+    // Rules apply below the directory zap runs in.
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--manifest-path"])
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+            .arg("--")
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(temp_dir.path())
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+
+    let ruled = temp_dir.path().join("cli_test.rs");
+    let explicit = temp_dir.path().join("other_test.rs");
+    let by_extension = temp_dir.path().join("lib.rs");
+    run(&[ruled.to_str().unwrap(), by_extension.to_str().unwrap()]);
+    run(&["-T", "rust-file", explicit.to_str().unwrap()]);
+
+    let read = |path: &std::path::Path| std::fs::read_to_string(path).expect("Failed to read file");
+    assert_eq!(read(&ruled), "#[test]\n");
+    assert_eq!(read(&by_extension), "// lib.rs\n");
+    assert_eq!(read(&explicit), "// other_test.rs\n");
+}
+
+#[test]
+fn test_remote_template_is_cached() {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    std::fs::create_dir_all(&config_dir).expect("Failed to create config dir");
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+        let body = "Remote {{ title }}";
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+    });
+
+    let url = format!("http://127.0.0.1:{port}/templates/note");
+    let run = |args: &[&str], file: &std::path::Path| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "-T", &url, "-C", "title=Notes"])
+            .args(args)
+            .arg(file)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output
+    };
+
+    let first = temp_dir.path().join("first.md");
+    run(&[], &first);
+    server.join().unwrap();
+    let cached = config_dir
+        .join("cache")
+        .join("templates")
+        .join(format!("127.0.0.1_{port}"))
+        .join("templates")
+        .join("note");
+    assert_eq!(
+        std::fs::read_to_string(&cached).expect("Template should be cached"),
+        "Remote {{ title }}"
+    );
+
+    // The server is gone: the cached copy is used, and --refresh falls back to it
+    let second = temp_dir.path().join("second.md");
+    run(&[], &second);
+    let third = temp_dir.path().join("third.md");
+    let output = run(&["--refresh"], &third);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("using the cached copy"));
+
+    for file in [first, second, third] {
+        let content = std::fs::read_to_string(&file).expect("Failed to read file");
+        assert_eq!(content, "Remote Notes");
+    }
+}
+
+#[test]
+fn test_directory_template_creates_scaffold() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let scaffold_dir = config_dir.join("templates").join("rust-bin");
+    std::fs::create_dir_all(scaffold_dir.join("src")).expect("Failed to create scaffold");
+    std::fs::write(
+        scaffold_dir.join("Cargo.toml"),
+        "[package]\nname = \"{{ name }}\"\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(
+        scaffold_dir.join("src").join("{{ name }}.rs"),
+        "// {{ filename }} by {{ author }}\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(scaffold_dir.join(".gitignore"), "/target\n")
+        .expect("Failed to create template");
+
+    let project = temp_dir.path().join("myproj");
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "rust-bin", "-C", "author=Ada"])
+        .arg(format!("{}/", project.display()))
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let read =
+        |path: std::path::PathBuf| std::fs::read_to_string(path).expect("Failed to read file");
+    assert_eq!(
+        read(project.join("Cargo.toml")),
+        "[package]\nname = \"myproj\"\n"
+    );
+    assert_eq!(
+        read(project.join("src").join("myproj.rs")),
+        "// myproj.rs by Ada\n"
+    );
+    assert_eq!(read(project.join(".gitignore")), "/target\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_rendered_template_is_piped_through_formatter() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("shout"),
+        "+++\noutput = { format_with = \"tr a-z A-Z\" }\n+++\nhello {{ name }}\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(template_dir.join("plain"), "hello {{ name }}\n")
+        .expect("Failed to create template");
+
+    let run = |args: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+    let read = |path: &std::path::Path| std::fs::read_to_string(path).expect("Failed to read file");
+
+    let from_frontmatter = temp_dir.path().join("a.txt");
+    let output = run(&[
+        "-T",
+        "shout",
+        "-C",
+        "name=ada",
+        from_frontmatter.to_str().unwrap(),
+    ]);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(read(&from_frontmatter), "HELLO ADA\n");
+
+    let from_flag = temp_dir.path().join("b.txt");
+    let output = run(&[
+        "-T",
+        "plain",
+        "-C",
+        "name=ada",
+        "--format-with",
+        "rev",
+        from_flag.to_str().unwrap(),
+    ]);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(read(&from_flag), "ada olleh\n");
+
+    let failing = temp_dir.path().join("c.txt");
+    let output = run(&[
+        "-T",
+        "plain",
+        "-C",
+        "name=ada",
+        "--format-with",
+        "false",
+        failing.to_str().unwrap(),
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Formatter 'false' failed"));
+    assert!(
+        !failing.exists(),
+        "nothing is written when the formatter fails"
+    );
+}
+
+#[test]
+fn test_template_sees_target_times() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("stamped"),
+        "{{ target_mtime }} {{ format_date(ts=target_mtime, fmt=\"%s\") }}\n",
+    )
+    .expect("Failed to create template");
+
+    let file = temp_dir.path().join("stamped.txt");
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "stamped", "-d", "2023-11-14T22:13:20Z"])
+        .arg(&file)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert_eq!(
+        std::fs::read_to_string(&file).expect("Failed to read file"),
+        "1700000000 1700000000\n"
+    );
+}
+
+#[test]
+fn test_append_and_prepend_to_existing_file() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("entry"), "- {{ text }}\n")
+        .expect("Failed to create template");
+    std::fs::write(
+        template_dir.join("license"),
+        "// SPDX-License-Identifier: MIT",
+    )
+    .expect("Failed to create template");
+
+    let log = temp_dir.path().join("log.md");
+    let source = temp_dir.path().join("main.rs");
+    std::fs::write(&log, "# Log\n").expect("Failed to create file");
+    std::fs::write(&source, "fn main() {}\n").expect("Failed to create file");
+
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    run(&[
+        "-T",
+        "entry",
+        "-C",
+        "text=one",
+        "--append",
+        log.to_str().unwrap(),
+    ]);
+    run(&[
+        "-T",
+        "entry",
+        "-C",
+        "text=two",
+        "--append",
+        log.to_str().unwrap(),
+    ]);
+    run(&["-T", "license", "--prepend", source.to_str().unwrap()]);
+
+    let read = |path: &std::path::Path| std::fs::read_to_string(path).expect("Failed to read file");
+    assert_eq!(read(&log), "# Log\n- one\n- two\n");
+    assert_eq!(
+        read(&source),
+        "// SPDX-License-Identifier: MIT\nfn main() {}\n"
+    );
+}
+
+#[test]
+fn test_stdout_prints_template_without_creating_file() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("greeting"),
+        "Hello {{ name }} in {{ filename }}\n",
+    )
+    .expect("Failed to create template");
+
+    let target = temp_dir.path().join("greeting.txt");
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "greeting", "-C", "name=ada", "--stdout"])
+        .arg(&target)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Hello ada in greeting.txt\n"
+    );
+    assert!(!target.exists(), "--stdout should not create the file");
+}
+
+#[test]
+fn test_fill_renders_placeholders_in_place() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    std::fs::create_dir_all(&config_dir).expect("Failed to create config directory");
+
+    let draft = temp_dir.path().join("draft.md");
+    std::fs::write(&draft, "# {{ title }}\n\nBy {{ author | upper }}\n")
+        .expect("Failed to create file");
+
+    let run = |context: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "--fill", "-C", context])
+            .arg(&draft)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    // A failed render leaves the file as it was
+    let output = run("title=Notes");
+    assert!(!output.status.success(), "missing author should fail");
+    assert_eq!(
+        std::fs::read_to_string(&draft).unwrap(),
+        "# {{ title }}\n\nBy {{ author | upper }}\n"
+    );
+
+    let output = run("title=Notes,author=ada");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&draft).unwrap(),
+        "# Notes\n\nBy ADA\n"
+    );
+}
+
+#[test]
+fn test_insert_at_marker_is_idempotent() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("task"), "- [ ] {{ text }}\n")
+        .expect("Failed to create template");
+    std::fs::write(
+        template_dir.join("route"),
+        "+++\noutput = { insert_at = \"// routes\" }\n+++\nroute(\"/{{ path }}\");\n",
+    )
+    .expect("Failed to create template");
+
+    let plan = temp_dir.path().join("plan.md");
+    let app = temp_dir.path().join("app.js");
+    std::fs::write(&plan, "# Plan\n## TASKS\n- [x] done\n").expect("Failed to create file");
+    std::fs::write(&app, "// routes\nlisten();\n").expect("Failed to create file");
+
+    let run = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    let task = [
+        "-T",
+        "task",
+        "-C",
+        "text=ship it",
+        "--insert-at",
+        "## TASKS",
+        plan.to_str().unwrap(),
+    ];
+    run(&task);
+    run(&task);
+    run(&["-T", "route", "-C", "path=users", app.to_str().unwrap()]);
+
+    assert_eq!(
+        std::fs::read_to_string(&plan).unwrap(),
+        "# Plan\n## TASKS\n- [ ] ship it\n- [x] done\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&app).unwrap(),
+        "// routes\nroute(\"/users\");\nlisten();\n"
+    );
+}
+
+#[test]
+fn test_template_is_loaded_once_per_run() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("note"), "# {{ filename }}\n")
+        .expect("Failed to create template");
+
+    let files: Vec<_> = ["a.md", "b.md", "c.md"]
+        .iter()
+        .map(|name| temp_dir.path().join(name))
+        .collect();
+    let output = Command::new("cargo")
+        .args(["run", "--", "--timings=json", "-T", "note"])
+        .args(&files)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read_to_string(&files[2]).unwrap(), "# c.md\n");
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let report = stderr.lines().last().expect("timings should be printed");
+    let json: serde_json::Value = serde_json::from_str(report).expect("report should be JSON");
+    assert_eq!(json["phases"]["plugin_load"]["count"], 1);
+    assert_eq!(json["phases"]["template_parse"]["count"], 1);
+    assert_eq!(json["phases"]["render"]["count"], 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_template_sets_file_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("script"),
+        "+++\noutput = { executable = true }\n+++\n#!/bin/sh\necho hi\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(
+        template_dir.join("secret"),
+        "+++\noutput = { mode = \"600\" }\n+++\ntoken=\n",
+    )
+    .expect("Failed to create template");
+
+    let run = |template: &str, path: &std::path::Path| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "-T", template])
+            .arg(path)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    let script = temp_dir.path().join("run.sh");
+    let secret = temp_dir.path().join(".env");
+    run("script", &script);
+    run("secret", &secret);
+
+    let mode =
+        |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_ne!(mode(&script) & 0o100, 0);
+    assert_eq!(mode(&secret), 0o600);
+}
+
+#[test]
+fn test_binary_and_verbatim_templates_are_copied_as_is() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    let scaffold_dir = template_dir.join("site");
+    std::fs::create_dir_all(&scaffold_dir).expect("Failed to create template directory");
+    let image = [0x89, b'P', b'N', b'G', 0xff, 0x00, b'{', b'{'];
+    std::fs::write(template_dir.join("logo.bin"), image).expect("Failed to create template");
+    std::fs::write(scaffold_dir.join("favicon.ico.bin"), image).expect("Failed to create template");
+    std::fs::write(
+        template_dir.join("mustache"),
+        "+++\nverbatim = true\n+++\nHello {{ name }}\n",
+    )
+    .expect("Failed to create template");
+
+    let run = |template: &str, path: &std::path::Path| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "-T", template])
+            .arg(path)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    let logo = temp_dir.path().join("logo.png");
+    let mustache = temp_dir.path().join("hello.mustache");
+    let site = temp_dir.path().join("site");
+    run("logo.bin", &logo);
+    run("mustache", &mustache);
+    run("site", &site.join(""));
+
+    assert_eq!(std::fs::read(&logo).unwrap(), image);
+    assert_eq!(
+        std::fs::read_to_string(&mustache).unwrap(),
+        "Hello {{ name }}\n"
+    );
+    assert_eq!(std::fs::read(site.join("favicon.ico")).unwrap(), image);
+}
+
+#[test]
+fn test_eol_normalizes_line_endings() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("bat"),
+        "+++\noutput = { eol = \"crlf\" }\n+++\n@echo off\necho {{ filename }}\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(template_dir.join("mixed"), "one\r\ntwo\nthree\r\n")
+        .expect("Failed to create template");
+
+    let run = |args: &[&str], path: &std::path::Path| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .arg(path)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        std::fs::read_to_string(path).expect("Failed to read file")
+    };
+
+    assert_eq!(
+        run(&["-T", "bat"], &temp_dir.path().join("run.bat")),
+        "@echo off\r\necho run.bat\r\n"
+    );
+    assert_eq!(
+        run(
+            &["-T", "mixed", "--eol", "lf"],
+            &temp_dir.path().join("a.txt")
+        ),
+        "one\ntwo\nthree\n"
+    );
+    assert_eq!(
+        run(
+            &["-T", "bat", "--eol", "lf"],
+            &temp_dir.path().join("b.bat")
+        ),
+        "@echo off\necho b.bat\n"
+    );
+}
+
+#[test]
+fn test_strict_template_reports_undefined_variables() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("post"),
+        "+++\ndescription = \"Blog post\"\n+++\n# {{ title }}\n{% if draft %}DRAFT{% endif %}\n",
+    )
+    .expect("Failed to create template");
+
+    let run = |strict: bool, path: &std::path::Path| {
+        let mut command = Command::new("cargo");
+        command.args(["run", "--", "-T", "post", "-C", "title=Hello"]);
+        if strict {
+            command.arg("--strict-template");
+        }
+        command
+            .arg(path)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let lenient = temp_dir.path().join("lenient.md");
+    let output = run(false, &lenient);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read_to_string(&lenient).unwrap(), "# Hello\n\n");
+
+    let strict = temp_dir.path().join("strict.md");
+    let output = run(true, &strict);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Template 'post', line 5: undefined variable `draft`"),
+        "{stderr}"
+    );
+    assert!(!strict.exists(), "nothing should be written");
+}
+
+#[test]
+fn test_config_context_table_is_merged_beneath_cli_context() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        config_dir.join("config.toml"),
+        "[context]\nauthor = \"Jane\"\ncompany = { name = \"ACME\", founded = 1949 }\n",
+    )
+    .expect("Failed to write config");
+    std::fs::write(
+        template_dir.join("header"),
+        "{{ author }}, {{ company.name }} (since {{ company.founded + 1 }})\n",
+    )
+    .expect("Failed to create template");
+
+    let run = |context: &str, path: &std::path::Path| {
+        let output = Command::new("cargo")
+            .args(["run", "--", "-T", "header", "-C", context])
+            .arg(path)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        std::fs::read_to_string(path).expect("Failed to read file")
+    };
+
+    assert_eq!(
+        run("unused=1", &temp_dir.path().join("a.txt")),
+        "Jane, ACME (since 1950)\n"
+    );
+    assert_eq!(
+        run(
+            "author=Bob,company.name=Initech",
+            &temp_dir.path().join("b.txt")
+        ),
+        "Bob, Initech (since 1950)\n"
+    );
+}
+
+#[test]
+fn test_read_file_embeds_shared_chunks() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(template_dir.join("_chunks")).expect("Failed to create chunks");
+    std::fs::write(
+        template_dir.join("_chunks").join("license.txt"),
+        "SPDX-License-Identifier: {{ license }}\n",
+    )
+    .expect("Failed to create chunk");
+    std::fs::write(
+        template_dir.join("module"),
+        "// {{ read_file(path=\"_chunks/license.txt\") }}// {{ filename }}\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(
+        template_dir.join("sneaky"),
+        "{{ read_file(path=\"../config.toml\") }}",
+    )
+    .expect("Failed to create template");
+
+    let run = |template: &str, path: &std::path::Path| {
+        Command::new("cargo")
+            .args(["run", "--", "-T", template])
+            .arg(path)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let module = temp_dir.path().join("lib.rs");
+    let output = run("module", &module);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&module).unwrap(),
+        "// SPDX-License-Identifier: {{ license }}\n// lib.rs\n"
+    );
+
+    let output = run("sneaky", &temp_dir.path().join("out.txt"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("only reads files below"));
+}
+
+#[test]
+fn test_map_creates_each_file_from_its_own_template() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template dir");
+    std::fs::write(template_dir.join("rust-bin"), "fn main() {}\n")
+        .expect("Failed to create template");
+    std::fs::write(template_dir.join("readme"), "# {{ filename }}\n")
+        .expect("Failed to create template");
+
+    let main_rs = temp_dir.path().join("main.rs");
+    let readme = temp_dir.path().join("README.md");
+    let plain = temp_dir.path().join("plain.txt");
+    let output = Command::new("cargo")
+        .args(["run", "--", "--map"])
+        .arg(format!("{}=rust-bin", main_rs.display()))
+        .arg("--map")
+        .arg(format!("{}=readme", readme.display()))
+        .arg(&plain)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read_to_string(&main_rs).unwrap(), "fn main() {}\n");
+    assert_eq!(std::fs::read_to_string(&readme).unwrap(), "# README.md\n");
+    assert_eq!(std::fs::read_to_string(&plain).unwrap(), "");
+}
+
+#[test]
+fn test_context_for_overrides_context_per_file() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template dir");
+    std::fs::write(template_dir.join("day"), "{{ title }} with {{ team }}\n")
+        .expect("Failed to create template");
+
+    let monday = temp_dir.path().join("monday.md");
+    let tuesday = temp_dir.path().join("tuesday.md");
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "day", "-C", "title=Standup,team=Core"])
+        .arg("--context-for")
+        .arg(&monday)
+        .arg("title=Planning")
+        .arg(&monday)
+        .arg(&tuesday)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&monday).unwrap(),
+        "Planning with Core\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&tuesday).unwrap(),
+        "Standup with Core\n"
+    );
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "day", "-C", "title=Standup,team=Core"])
+        .args(["--context-for", "wednesday.md", "title=Retro"])
+        .arg(temp_dir.path().join("thursday.md"))
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no such file in this run"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_plugins_are_called_with_json() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    let plugins_dir = config_dir.join("plugins");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template dir");
+    std::fs::create_dir_all(&plugins_dir).expect("Failed to create plugins dir");
+    // Answers with the request it got
+    let echo = plugins_dir.join("echo-request.sh");
+    std::fs::write(&echo, "#!/bin/sh\ncat\n").expect("Failed to create plugin");
+    let broken = plugins_dir.join("broken");
+    std::fs::write(&broken, "#!/bin/sh\necho 'no such word' >&2\nexit 3\n")
+        .expect("Failed to create plugin");
+    for plugin in [&echo, &broken] {
+        std::fs::set_permissions(plugin, std::fs::Permissions::from_mode(0o755))
+            .expect("Failed to make plugin executable");
+    }
+    std::fs::write(
+        template_dir.join("echo"),
+        "{% set reply = echo_request(word=\"hi\", count=2) %}{{ reply.function }} {{ reply.args.word }} {{ reply.args.count + 1 }}\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(template_dir.join("broken"), "{{ broken() }}")
+        .expect("Failed to create template");
+
+    let run = |template: &str, path: &std::path::Path| {
+        Command::new("cargo")
+            .args(["run", "--", "-T", template])
+            .arg(path)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let out = temp_dir.path().join("out.txt");
+    let output = run("echo", &out);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&out).unwrap(),
+        "echo_request hi 3\n"
+    );
+
+    let output = run("broken", &temp_dir.path().join("broken.txt"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no such word"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_project_plugins_load_only_when_trusted() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let project = temp_dir.path().join("project");
+    let project_zap = project.join(".zap");
+    std::fs::create_dir_all(project_zap.join("templates")).expect("Failed to create templates");
+    std::fs::create_dir_all(project_zap.join("plugins")).expect("Failed to create plugins");
+    std::fs::create_dir_all(&config_dir).expect("Failed to create config dir");
+    let plugin = project_zap.join("plugins").join("answer");
+    std::fs::write(&plugin, "#!/bin/sh\necho 42\n").expect("Failed to create plugin");
+    std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755))
+        .expect("Failed to make plugin executable");
+    std::fs::write(
+        project_zap.join("templates").join("answer"),
+        "{{ answer() }}\n",
+    )
+    .expect("Failed to create template");
+
+    let run = |file: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "-T", "answer"])
+            .arg(project.join(file))
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let output = run("untrusted.txt");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Not loading the plugins"));
+
+    std::fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "[plugins]\ntrusted = [{:?}]\n",
+            project.display().to_string()
+        ),
+    )
+    .expect("Failed to write config");
+    let output = run("trusted.txt");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(project.join("trusted.txt")).unwrap(),
+        "42\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_project_template_formatter_runs_only_when_trusted() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let project = temp_dir.path().join("project");
+    let templates = project.join(".zap").join("templates");
+    std::fs::create_dir_all(&templates).expect("Failed to create templates");
+    std::fs::create_dir_all(&config_dir).expect("Failed to create config dir");
+    std::fs::write(
+        templates.join("shout"),
+        "+++\noutput = { format_with = \"tr a-z A-Z\" }\n+++\nhello\n",
+    )
+    .expect("Failed to create template");
+
+    let run = |file: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "-T", "shout"])
+            .arg(project.join(file))
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let output = run("untrusted.txt");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Not running `tr a-z A-Z`"));
+    assert_eq!(
+        std::fs::read_to_string(project.join("untrusted.txt")).unwrap(),
+        "hello\n"
+    );
+
+    std::fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "[plugins]\ntrusted = [{:?}]\n",
+            project.display().to_string()
+        ),
+    )
+    .expect("Failed to write config");
+    let output = run("trusted.txt");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(project.join("trusted.txt")).unwrap(),
+        "HELLO\n"
+    );
+}
+
+#[test]
+fn test_fill_with_preserve_atime() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    std::fs::create_dir_all(&config_dir).expect("Failed to create config directory");
+
+    let draft = temp_dir.path().join("draft.md");
+    std::fs::write(&draft, "{{ 6 * 7 }}\n").expect("Failed to create file");
+    let read_long_ago = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+    filetime::set_file_atime(&draft, read_long_ago).expect("Failed to set atime");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--fill", "--preserve-atime"])
+        .arg(&draft)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Before reading the file here, which moves the access time itself
+    let metadata = std::fs::metadata(&draft).unwrap();
+    assert_eq!(
+        filetime::FileTime::from_last_access_time(&metadata),
+        read_long_ago
+    );
+    assert_ne!(
+        filetime::FileTime::from_last_modification_time(&metadata),
+        read_long_ago
+    );
+    assert_eq!(std::fs::read_to_string(&draft).unwrap(), "42\n");
+}
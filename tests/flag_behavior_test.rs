@@ -446,3 +446,955 @@ fn test_no_time_operations_when_skipping() {
     let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(stdout.contains("Skipping"));
 }
+
+#[test]
+fn test_slugify_name_flag() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let requested = temp_dir.path().join("Meeting Notes: Q3 Plans!.md");
+    let expected = temp_dir.path().join("meeting-notes-q3-plans.md");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--slugify-name", requested.to_str().unwrap()])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert!(expected.exists(), "slugified file should have been created");
+    assert!(!requested.exists(), "original name should not be used");
+}
+
+#[test]
+fn test_older_than_only_touches_stale_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let stale_file = temp_dir.path().join("stale.sentinel");
+    let fresh_file = temp_dir.path().join("fresh.sentinel");
+
+    File::create(&stale_file).expect("Failed to create stale file");
+    File::create(&fresh_file).expect("Failed to create fresh file");
+
+    let forty_days_ago = SystemTime::now() - Duration::from_secs(40 * 24 * 60 * 60);
+    filetime::set_file_mtime(
+        &stale_file,
+        filetime::FileTime::from_system_time(forty_days_ago),
+    )
+    .expect("Failed to backdate stale file");
+    let (_, fresh_mtime) = get_file_times(&fresh_file);
+
+    sleep_for_time_resolution();
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--older-than",
+            "30d",
+            stale_file.to_str().unwrap(),
+            fresh_file.to_str().unwrap(),
+        ])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, stale_mtime) = get_file_times(&stale_file);
+    assert!(
+        stale_mtime > SystemTime::now() - Duration::from_secs(60),
+        "Stale file should have been touched"
+    );
+    assert_eq!(
+        get_file_times(&fresh_file).1,
+        fresh_mtime,
+        "Fresh file should not have been touched"
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Skipping"));
+}
+
+#[test]
+fn test_age_filters_measure_from_now_flag() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("old.sentinel");
+    File::create(&test_file).expect("Failed to create test file");
+    // 2020-01-01T00:00:00Z, a week before the --now below
+    filetime::set_file_mtime(
+        &test_file,
+        filetime::FileTime::from_unix_time(1_577_836_800, 0),
+    )
+    .expect("Failed to backdate test file");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--now",
+            "2020-01-08T00:00:00Z",
+            "--newer-than",
+            "30d",
+            test_file.to_str().unwrap(),
+        ])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let (_, mtime) = get_file_times(&test_file);
+    assert_eq!(
+        mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        1_578_441_600
+    );
+}
+
+#[test]
+fn test_relative_to_resolves_operands_against_base() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let base_dir = temp_dir.path().join("project");
+    fs::create_dir_all(&base_dir).expect("Failed to create base dir");
+    let absolute_file = temp_dir.path().join("absolute.txt");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--relative-to",
+            base_dir.to_str().unwrap(),
+            "notes.md",
+            absolute_file.to_str().unwrap(),
+        ])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert!(base_dir.join("notes.md").exists());
+    assert!(absolute_file.exists(), "absolute paths are used as given");
+    assert!(
+        !Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("notes.md")
+            .exists()
+    );
+}
+
+#[test]
+fn test_timings_json_reports_phases() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("timed.txt");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--timings=json", test_file.to_str().unwrap()])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let report = stderr
+        .lines()
+        .last()
+        .expect("timings should be printed to stderr");
+    let json: serde_json::Value = serde_json::from_str(report).expect("report should be JSON");
+    assert_eq!(json["phases"]["file_write"]["count"], 1);
+    assert_eq!(json["phases"]["set_times"]["count"], 1);
+    assert!(json["phases"]["config"]["ms"].is_number());
+    assert!(json["total_ms"].is_number());
+}
+
+#[test]
+fn test_argfile_supplies_arguments() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let first = temp_dir.path().join("first.txt");
+    let second = temp_dir.path().join("second.txt");
+    let argfile = temp_dir.path().join("args.txt");
+    fs::write(
+        &argfile,
+        format!(
+            "# batch of files\n{}\n\n{}\n",
+            first.display(),
+            second.display()
+        ),
+    )
+    .expect("Failed to write argument file");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", &format!("@{}", argfile.display())])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(first.exists());
+    assert!(second.exists());
+}
+
+#[test]
+fn test_hard_links_are_adjusted_once() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let original = temp_dir.path().join("original.txt");
+    let link = temp_dir.path().join("link.txt");
+    File::create(&original).expect("Failed to create test file");
+    fs::hard_link(&original, &link).expect("Failed to create hard link");
+
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    filetime::set_file_times(
+        &original,
+        filetime::FileTime::from_system_time(start),
+        filetime::FileTime::from_system_time(start),
+    )
+    .expect("Failed to set file times");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-A",
+            "10",
+            original.to_str().unwrap(),
+            link.to_str().unwrap(),
+        ])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, mtime) = get_file_times(&original);
+    assert_eq!(mtime, start + Duration::from_secs(10));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("hard link"));
+}
+
+#[test]
+fn test_notify_command_receives_summary() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join("config");
+    fs::create_dir_all(&config_dir).expect("Failed to create config dir");
+    let summary_file = temp_dir.path().join("summary.json");
+    fs::write(
+        config_dir.join("config.toml"),
+        format!("[notify]\ncommand = \"tee {}\"\n", summary_file.display()),
+    )
+    .expect("Failed to write config");
+
+    let existing = temp_dir.path().join("existing.txt");
+    let created = temp_dir.path().join("created.txt");
+    File::create(&existing).expect("Failed to create test file");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            existing.to_str().unwrap(),
+            created.to_str().unwrap(),
+        ])
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&summary_file).unwrap())
+            .expect("summary should be JSON");
+    assert_eq!(summary["created"][0], created.to_str().unwrap());
+    assert_eq!(summary["updated"][0], existing.to_str().unwrap());
+    assert_eq!(summary["failed"].as_array().unwrap().len(), 0);
+}
+
+#[test]
+fn test_relative_date() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("relative.txt");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--now",
+            "2024-05-15T10:30:00Z",
+            "-d",
+            "2 hours ago",
+        ])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, mtime) = get_file_times(&test_file);
+    assert_eq!(
+        mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        1_715_769_000 - 2 * 60 * 60
+    );
+}
+
+#[test]
+fn test_adjust_by_duration() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("adjusted.txt");
+    File::create(&test_file).expect("Failed to create test file");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-d", "2023-01-01T12:00:00Z", "-A", "-1h30m"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, mtime) = get_file_times(&test_file);
+    assert_eq!(
+        mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        1_672_574_400 - 90 * 60
+    );
+}
+
+#[test]
+fn test_tz_interprets_dates_without_offset() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("tokyo.txt");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--tz",
+            "Asia/Tokyo",
+            "-d",
+            "2024-01-01T09:00:00",
+        ])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Midnight UTC
+    let (_, mtime) = get_file_times(&test_file);
+    assert_eq!(
+        mtime
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+        1_704_067_200
+    );
+}
+
+#[test]
+fn test_nanoseconds_are_kept() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("precise.txt");
+    File::create(&test_file).expect("Failed to create test file");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-d",
+            "2023-01-01T12:00:00.123456789Z",
+            "-A",
+            "-0.000000089",
+        ])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (atime, mtime) = get_file_times(&test_file);
+    for time in [atime, mtime] {
+        let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(
+            (since_epoch.as_secs(), since_epoch.subsec_nanos()),
+            (1_672_574_400, 123_456_700)
+        );
+    }
+}
+
+#[test]
+fn test_unix_timestamp() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("epoch.txt");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-s", "1735689600.25"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, mtime) = get_file_times(&test_file);
+    let since_epoch = mtime.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    assert_eq!(
+        (since_epoch.as_secs(), since_epoch.subsec_nanos()),
+        (1_735_689_600, 250_000_000)
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_btime_is_refused_where_unsupported() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("born.txt");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--btime"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--btime"));
+    assert!(!test_file.exists());
+}
+
+#[test]
+fn test_reference_with_offset() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let reference = temp_dir.path().join("reference.txt");
+    let test_file = temp_dir.path().join("newer.txt");
+    fs::write(&reference, "").unwrap();
+    let ref_time = filetime::FileTime::from_unix_time(1_700_000_000, 0);
+    filetime::set_file_times(&reference, ref_time, ref_time).unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-r"])
+        .arg(&reference)
+        .args(["--offset", "1h"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (atime, mtime) = get_file_times(&test_file);
+    for time in [atime, mtime] {
+        let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(since_epoch.as_secs(), 1_700_003_600);
+    }
+}
+
+#[test]
+fn test_reference_tree() {
+    let source = TempDir::new().expect("Failed to create temp dir");
+    let target = TempDir::new().expect("Failed to create temp dir");
+    for dir in [source.path(), target.path()] {
+        fs::create_dir(dir.join("docs")).unwrap();
+        fs::write(dir.join("docs/guide.md"), "").unwrap();
+    }
+    fs::write(target.path().join("only-here.md"), "").unwrap();
+    let time = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+    filetime::set_file_times(source.path().join("docs/guide.md"), time, time).unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--reference-tree"])
+        .arg(source.path())
+        .arg(target.path())
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, mtime) = get_file_times(&target.path().join("docs/guide.md"));
+    assert_eq!(
+        mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+    );
+    let (_, untouched) = get_file_times(&target.path().join("only-here.md"));
+    assert_ne!(untouched, mtime);
+}
+
+#[test]
+fn test_show_prints_times_without_touching() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("shown.txt");
+    fs::write(&test_file, "").unwrap();
+    let time = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+    filetime::set_file_times(&test_file, time, time).unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--show", "--format", "epoch"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("atime=1600000000  mtime=1600000000"),
+        "{stdout}"
+    );
+    let (_, mtime) = get_file_times(&test_file);
+    assert_eq!(
+        mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+    );
+}
+
+#[test]
+fn test_undo_restores_previous_times() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join("config");
+    let test_file = temp_dir.path().join("oops.txt");
+    fs::write(&test_file, "").unwrap();
+    let time = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+    filetime::set_file_times(&test_file, time, time).unwrap();
+
+    let zap = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .env("ZAP_CONFIG", &config_dir)
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    zap(&["-t", "202001010000", test_file.to_str().unwrap()]);
+    zap(&["--undo"]);
+
+    let (_, mtime) = get_file_times(&test_file);
+    assert_eq!(
+        mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+    );
+    assert!(!config_dir.join("undo.json").exists());
+}
+
+#[test]
+fn test_not_after_clamps_reference_times() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let reference = temp_dir.path().join("reference.txt");
+    let test_file = temp_dir.path().join("archived.txt");
+    fs::write(&reference, "").unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-r"])
+        .arg(&reference)
+        .args(["--not-after", "2024-01-01T00:00:00Z"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (atime, mtime) = get_file_times(&test_file);
+    let release = SystemTime::UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+    assert_eq!(atime, release);
+    assert_eq!(mtime, release);
+}
+
+#[test]
+fn test_randomize_with_seed_is_reproducible() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let files: Vec<_> = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|name| temp_dir.path().join(name))
+        .collect();
+
+    let run = || {
+        let output = Command::new("cargo")
+            .args(["run", "--", "--randomize"])
+            .arg("2023-01-01T00:00:00Z..2023-06-30T00:00:00Z")
+            .args(["--seed", "42"])
+            .args(&files)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        files
+            .iter()
+            .map(|file| get_file_times(file).1)
+            .collect::<Vec<_>>()
+    };
+
+    let first = run();
+    assert_eq!(first, run());
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_672_531_200);
+    let end = SystemTime::UNIX_EPOCH + Duration::from_secs(1_688_083_200);
+    assert!(first.iter().all(|mtime| (start..=end).contains(mtime)));
+}
+
+#[test]
+fn test_normalize_to_oldest() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let tree = temp_dir.path().join("extracted");
+    fs::create_dir_all(tree.join("lib")).unwrap();
+    fs::write(tree.join("lib/old.c"), "").unwrap();
+    fs::write(tree.join("fresh.c"), "").unwrap();
+    let old = filetime::FileTime::from_unix_time(1_500_000_000, 0);
+    filetime::set_file_times(tree.join("lib/old.c"), old, old).unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--normalize", "oldest"])
+        .arg(&tree)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    for path in [tree.join("fresh.c"), tree.join("lib"), tree.clone()] {
+        assert_eq!(get_file_times(&path).1, expected, "{}", path.display());
+    }
+}
+
+#[test]
+fn test_stagger_orders_files() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let files: Vec<_> = ["first.txt", "second.txt", "third.txt"]
+        .iter()
+        .map(|name| temp_dir.path().join(name))
+        .collect();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-s", "1700000000", "--stagger", "2s"])
+        .args(&files)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mtimes: Vec<_> = files.iter().map(|file| get_file_times(file).1).collect();
+    let at = |seconds| SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+    assert_eq!(
+        mtimes,
+        [at(1_700_000_000), at(1_700_000_002), at(1_700_000_004)]
+    );
+}
+
+#[test]
+fn test_round_to_two_seconds() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("fat.txt");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-s", "1700000001.25", "--truncate", "2s"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, mtime) = get_file_times(&test_file);
+    assert_eq!(
+        mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    );
+}
+
+#[test]
+fn test_from_exif_sets_video_creation_time() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let video = temp_dir.path().join("clip.mp4");
+    let notes = temp_dir.path().join("notes.txt");
+    let missing = temp_dir.path().join("missing.mp4");
+
+    // ftyp, then moov holding an mvhd created at 1700000000 (counted from 1904)
+    let created = (1_700_000_000_u64 + 2_082_844_800) as u32;
+    let mut mvhd = vec![0, 0, 0, 0];
+    mvhd.extend_from_slice(&created.to_be_bytes());
+    mvhd.extend_from_slice(&[0; 16]);
+    let mut bytes = b"\0\0\0\x10ftypisom\0\0\0\0".to_vec();
+    bytes.extend_from_slice(&(16 + mvhd.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(b"moov");
+    bytes.extend_from_slice(&(8 + mvhd.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(b"mvhd");
+    bytes.extend_from_slice(&mvhd);
+    fs::write(&video, bytes).unwrap();
+    fs::write(&notes, "no metadata here\n").unwrap();
+    let (_, notes_mtime) = get_file_times(&notes);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--from-exif"])
+        .args([&video, &notes, &missing])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, mtime) = get_file_times(&video);
+    assert_eq!(
+        mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    );
+    assert_eq!(get_file_times(&notes).1, notes_mtime);
+    assert!(!missing.exists(), "--from-exif implies -c");
+}
+
+#[test]
+fn test_from_git_sets_last_commit_time() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let repo = temp_dir.path();
+    let committed = repo.join("Makefile");
+    let untracked = repo.join("build.log");
+    fs::write(&committed, "all:\n").unwrap();
+    fs::write(&untracked, "ok\n").unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["-c", "user.name=zap", "-c", "user.email=zap@example.com"])
+            .args(args)
+            .env("GIT_COMMITTER_DATE", "1700000000 +0000")
+            .status()
+            .expect("Failed to run git");
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    git(&["add", "Makefile"]);
+    git(&["commit", "-q", "-m", "Add Makefile"]);
+    let (_, untracked_mtime) = get_file_times(&untracked);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--from-git"])
+        .args([&committed, &untracked])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let (_, mtime) = get_file_times(&committed);
+    assert_eq!(
+        mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    );
+    assert_eq!(get_file_times(&untracked).1, untracked_mtime);
+}
+
+#[test]
+fn test_adate_and_mdate_set_different_times() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let both = temp_dir.path().join("both.txt");
+    let mtime_only = temp_dir.path().join("mtime_only.txt");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--adate", "2024-05-01T00:00:00Z"])
+        .args(["--mdate", "2023-01-01T00:00:00Z"])
+        .arg(&both)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        get_file_times(&both),
+        (
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_521_600),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_672_531_200)
+        )
+    );
+
+    // On its own, --mdate only replaces the modification time of -s
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "-s",
+            "1700000000",
+            "--mdate",
+            "2023-01-01T00:00:00Z",
+        ])
+        .arg(&mtime_only)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        get_file_times(&mtime_only),
+        (
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_672_531_200)
+        )
+    );
+}
+
+#[test]
+fn test_dst_picks_ambiguous_local_time() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("night.log");
+    let zap = |dst: Option<&str>| {
+        let mut command = Command::new("cargo");
+        command
+            .args([
+                "run",
+                "--",
+                "--tz",
+                "Europe/Berlin",
+                "-d",
+                "2024-10-27T02:30:00",
+            ])
+            .arg(&test_file)
+            .current_dir(env!("CARGO_MANIFEST_DIR"));
+        if let Some(dst) = dst {
+            command.args(["--dst", dst]);
+        }
+        command.output().expect("Failed to execute zap command")
+    };
+
+    let output = zap(None);
+    assert!(!output.status.success(), "an ambiguous time is refused");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--dst"));
+
+    let output = zap(Some("latest"));
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let (_, mtime) = get_file_times(&test_file);
+    // 2024-10-27T01:30:00Z, the second 02:30 in Berlin
+    assert_eq!(
+        mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_729_992_600)
+    );
+}
+
+#[test]
+fn test_adjust_by_calendar_month() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let test_file = temp_dir.path().join("invoice.pdf");
+    // 2024-01-31T12:00:00Z
+    let output = Command::new("cargo")
+        .args(["run", "--", "-s", "1706702400"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(output.status.success());
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-A", "+1month"])
+        .arg(&test_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // 2024-02-29T12:00:00Z, the last day of a leap February
+    let (_, mtime) = get_file_times(&test_file);
+    assert_eq!(
+        mtime,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_208_000)
+    );
+}
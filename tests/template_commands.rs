@@ -0,0 +1,475 @@
+use std::process::Command;
+use tempfile::TempDir;
+
+#[test]
+fn test_template_which_lists_all_locations() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let system_dir = temp_dir.path().join("share").join("zap");
+
+    for dir in [&config_dir, &system_dir] {
+        let template_dir = dir.join("templates");
+        std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+        std::fs::write(template_dir.join("note"), "# note").expect("Failed to create template");
+    }
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "template", "which", "note"])
+        .env("ZAP_CONFIG", &config_dir)
+        .env("ZAP_SYSTEM_DIR", &system_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let user_template = config_dir.join("templates").join("note");
+    let system_template = system_dir.join("templates").join("note");
+
+    assert_eq!(
+        stdout.lines().next().unwrap(),
+        user_template.to_str().unwrap(),
+        "first line should be the resolved path"
+    );
+    assert!(stdout.contains(&format!("user: {} (selected)", user_template.display())));
+    assert!(stdout.contains(&format!(
+        "system: {} (found, shadowed)",
+        system_template.display()
+    )));
+}
+
+#[test]
+fn test_template_which_fails_for_unknown_template() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "template", "which", "nope"])
+        .env("ZAP_CONFIG", temp_dir.path().join("config"))
+        .env("ZAP_SYSTEM_DIR", temp_dir.path().join("system"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Template 'nope' not found"));
+}
+
+#[test]
+fn test_list_functions_without_plugins() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--list-functions"])
+        .env("ZAP_CONFIG", temp_dir.path())
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("tera (built-in):"));
+    assert!(stdout.contains("keats.github.io/tera/docs/#built-ins"));
+    assert!(stdout.contains("zap (built-in):"));
+    assert!(stdout.contains("function uuid4"));
+    assert!(stdout.contains("filter   snake_case"));
+}
+
+#[test]
+fn test_list_functions_skips_broken_plugins() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let plugins_dir = temp_dir.path().join("plugins");
+    std::fs::create_dir_all(&plugins_dir).expect("Failed to create plugins directory");
+    let broken = plugins_dir.join(format!("broken.{}", std::env::consts::DLL_EXTENSION));
+    std::fs::write(&broken, "not a library").expect("Failed to write plugin");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--list-functions"])
+        .env("ZAP_CONFIG", temp_dir.path())
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("function uuid4"));
+    assert!(stdout.contains(&format!("{}:\n  (not loaded: ", broken.display())));
+}
+
+#[test]
+fn test_template_edit_opens_existing_template() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("note"), "# note").expect("Failed to create template");
+
+    // `echo` stands in for the editor and prints the path it was asked to open
+    let output = Command::new("cargo")
+        .args(["run", "--", "template", "edit", "note"])
+        .env("ZAP_CONFIG", &config_dir)
+        .env("ZAP_SYSTEM_DIR", temp_dir.path().join("system"))
+        .env("EDITOR", "echo")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        template_dir.join("note").to_str().unwrap()
+    );
+}
+
+#[test]
+fn test_template_list_shows_frontmatter() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("meeting"),
+        "+++\ndescription = \"Meeting notes\"\nvariables = [\"title\", \"attendees\"]\n+++\n# {{ title }}\n",
+    )
+    .expect("Failed to create template");
+    std::fs::write(template_dir.join("plain"), "plain").expect("Failed to create template");
+
+    for args in [
+        ["template", "list"].as_slice(),
+        ["--list-templates"].as_slice(),
+    ] {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .env("ZAP_SYSTEM_DIR", temp_dir.path().join("no-system-dir"))
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(
+            stdout,
+            format!(
+                "user ({}):\n  meeting  Meeting notes\n    variables: title, attendees\n  plain\n",
+                template_dir.display()
+            )
+        );
+    }
+
+    // The frontmatter isn't part of the rendered file
+    let test_file = temp_dir.path().join("standup.md");
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "meeting", "-C", "title=Standup"])
+        .arg(&test_file)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(&test_file).expect("Failed to read file"),
+        "# Standup\n"
+    );
+}
+
+#[test]
+fn test_template_path_is_searched_first() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+
+    let config_dir = temp_dir.path().join("config");
+    let extra_dir = temp_dir.path().join("checkout");
+    for dir in [config_dir.join("templates"), extra_dir.clone()] {
+        std::fs::create_dir_all(&dir).expect("Failed to create template directory");
+        std::fs::write(dir.join("note"), "# note").expect("Failed to create template");
+    }
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "template", "which", "--template-path"])
+        .arg(&extra_dir)
+        .arg("note")
+        .env("ZAP_CONFIG", &config_dir)
+        .env("ZAP_SYSTEM_DIR", temp_dir.path().join("system"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.lines().next().unwrap(),
+        extra_dir.join("note").to_str().unwrap()
+    );
+    assert!(stdout.contains(&format!(
+        "user: {} (found, shadowed)",
+        config_dir.join("templates").join("note").display()
+    )));
+}
+
+#[test]
+fn test_template_check_reports_broken_templates() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join("config");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(template_dir.join("good"), "# {{ title }}\n")
+        .expect("Failed to create template");
+    std::fs::write(template_dir.join("typo"), "# {{ title | uppercse }}\n")
+        .expect("Failed to create template");
+    std::fs::write(template_dir.join("broken"), "{% if %}\n").expect("Failed to create template");
+
+    let check = |name: &str| {
+        Command::new("cargo")
+            .args(["run", "--", "template", "check", name, "-C", "title=Hello"])
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let output = check("good");
+    assert!(
+        output.status.success(),
+        "a broken neighbour shouldn't fail the check: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "good: ok\n");
+
+    let output = check("typo");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("FilterNotFound(\"uppercse\")"));
+
+    let output = check("broken");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Failed to parse 'broken'"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 of 1 template(s) failed"));
+}
+
+#[cfg(not(feature = "handlebars"))]
+#[test]
+fn test_disabled_engine_is_reported() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join("config");
+    let template_dir = config_dir.join("templates");
+    std::fs::create_dir_all(&template_dir).expect("Failed to create template directory");
+    std::fs::write(
+        template_dir.join("legacy"),
+        "+++\nengine = \"handlebars\"\n+++\n# {{#if title}}{{title}}{{/if}}\n",
+    )
+    .expect("Failed to create template");
+
+    let target = temp_dir.path().join("legacy.md");
+    let output = Command::new("cargo")
+        .args(["run", "--", "-T", "legacy"])
+        .arg(&target)
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("cargo feature 'handlebars'"),
+        "unexpected error: {stderr}"
+    );
+}
+
+#[test]
+fn test_pick_template_needs_a_terminal() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join("config");
+    std::fs::create_dir_all(config_dir.join("templates")).expect("Failed to create directory");
+
+    let target = temp_dir.path().join("notes.md");
+    let output = Command::new("cargo")
+        .args(["run", "--"])
+        .arg(&target)
+        .arg("-T")
+        .env("ZAP_CONFIG", &config_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(std::process::Stdio::null())
+        .output()
+        .expect("Failed to execute zap command");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("needs a terminal"));
+    assert!(!target.exists());
+}
+
+#[test]
+fn test_template_packs_are_installed_updated_and_removed() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join("config");
+    let source = temp_dir.path().join("rust-templates");
+    std::fs::create_dir_all(&source).expect("Failed to create pack");
+
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Ada", "-c", "user.email=ada@example.com"])
+            .args(args)
+            .current_dir(&source)
+            .output()
+            .expect("Failed to run git")
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    let commit = |contents: &str| {
+        std::fs::write(source.join("bin"), contents).expect("Failed to write template");
+        git(&["add", "bin"]);
+        git(&["commit", "-q", "-m", "template"]);
+    };
+    git(&["init", "-q"]);
+    commit("// v1 {{ filename }}\n");
+
+    let zap = |args: &[&str]| {
+        let output = Command::new("cargo")
+            .args(["run", "--"])
+            .args(args)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command");
+        assert!(
+            output.status.success(),
+            "zap command failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let url = format!("file://{}", source.display());
+    let lockfile = config_dir.join("packs.lock");
+    let lock = || std::fs::read_to_string(&lockfile).expect("Failed to read lockfile");
+    let render = || {
+        let target = temp_dir.path().join("main.rs");
+        let _ = std::fs::remove_file(&target);
+        zap(&["-T", "rust/bin", target.to_str().unwrap()]);
+        std::fs::read_to_string(&target).expect("Failed to read file")
+    };
+
+    let stdout = zap(&["template", "install", &url, "--name", "rust"]);
+    assert!(stdout.contains("Installed rust"), "{stdout}");
+    assert!(lock().contains(&format!("source = \"{url}\"")));
+    assert_eq!(render(), "// v1 main.rs\n");
+    let first = lock();
+
+    commit("// v2 {{ filename }}\n");
+    let stdout = zap(&["template", "update", "rust"]);
+    assert!(stdout.contains("Updated rust"), "{stdout}");
+    assert_ne!(lock(), first);
+    assert_eq!(render(), "// v2 main.rs\n");
+
+    // On another machine, the lockfile brings back the same revision
+    std::fs::write(&lockfile, &first).expect("Failed to write lockfile");
+    std::fs::remove_dir_all(config_dir.join("packs")).expect("Failed to remove packs");
+    zap(&["template", "install"]);
+    assert_eq!(render(), "// v1 main.rs\n");
+
+    zap(&["template", "remove", "rust"]);
+    assert!(!config_dir.join("packs").join("rust").exists());
+    assert!(!lock().contains("rust"));
+}
+
+#[test]
+fn test_plugin_install_records_plugin_in_index() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let source = temp_dir.path().join("answer.sh");
+    std::fs::write(&source, "#!/bin/sh\necho 42\n").expect("Failed to create plugin");
+
+    let install = |extra: &[&str]| {
+        Command::new("cargo")
+            .args(["run", "--", "plugin", "install"])
+            .arg(&source)
+            .args(extra)
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+
+    let output = install(&["--version", "1.0.0"]);
+    assert!(
+        output.status.success(),
+        "zap command failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let plugins_dir = config_dir.join("plugins");
+    assert!(plugins_dir.join("answer.sh").is_file());
+    let index = std::fs::read_to_string(plugins_dir.join("index.toml")).unwrap();
+    assert!(index.contains("name = \"answer\""));
+    assert!(index.contains("version = \"1.0.0\""));
+
+    let output = install(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--force"));
+    assert!(install(&["--force"]).status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_plugin_list_shows_index_details() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_dir = temp_dir.path().join(".config").join("zap");
+    let plugins_dir = config_dir.join("plugins");
+    std::fs::create_dir_all(&plugins_dir).expect("Failed to create plugins dir");
+
+    let list = || {
+        Command::new("cargo")
+            .args(["run", "--", "plugin", "list"])
+            .env("ZAP_CONFIG", &config_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .output()
+            .expect("Failed to execute zap command")
+    };
+    let output = list();
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No plugins in"));
+
+    let plugin = plugins_dir.join("answer.sh");
+    std::fs::write(&plugin, "#!/bin/sh\necho 42\n").expect("Failed to create plugin");
+    std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755))
+        .expect("Failed to make plugin executable");
+    std::fs::write(
+        plugins_dir.join("index.toml"),
+        "[[plugin]]\nname = \"answer\"\nfile = \"answer.sh\"\nsource = \"https://example.com/answer.sh\"\nversion = \"1.2.0\"\n",
+    )
+    .expect("Failed to write index");
+    let stdout = String::from_utf8_lossy(&list().stdout).to_string();
+    assert!(stdout.contains("answer.sh: 1.2.0"), "{stdout}");
+    assert!(stdout.contains("installed from https://example.com/answer.sh"));
+}